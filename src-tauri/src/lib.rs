@@ -1,6 +1,6 @@
 use std::{sync::{mpsc, Arc}, thread};
 use tokio::sync::Mutex;
-use crate::orchestrator::{Orchestrator, TaskResult};
+use crate::orchestrator::{Orchestrator, SessionContext, TaskResult};
 
 use tauri::{
     menu::{Menu, MenuItem},
@@ -12,6 +12,7 @@ use tauri_plugin_global_shortcut::{Code, GlobalShortcutExt, Modifiers, Shortcut,
 pub mod modules;
 pub mod orchestrator;
 use modules::io_controller;
+use modules::macro_editor::MacroEditor;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
@@ -40,11 +41,67 @@ pub fn run() {
             // Orchestrator Commands
             commands::execute_task_command,
             commands::get_app_state_command,
+            commands::get_session_context_command,
             commands::start_recording_command,
+            commands::start_listening_command,
+            commands::stop_listening_command,
+            commands::get_voice_settings_command,
+            commands::set_voice_settings_command,
+            commands::train_wake_word_command,
+            commands::speak_command,
+            commands::set_execution_recording_command,
+            commands::list_trajectories_command,
+            commands::get_redaction_settings_command,
+            commands::set_redaction_settings_command,
+            commands::get_redaction_audit_command,
+            commands::get_cognition_settings_command,
+            commands::set_cognition_settings_command,
             commands::stop_recording_command,
             // New Macro Commands
             commands::play_macro_command,
+            commands::dry_run_macro_command,
+            commands::take_screenshot_command,
+            commands::find_image_command,
+            commands::read_screen_command,
+            commands::find_text_on_screen_command,
+            commands::read_ui_map_command,
+            commands::accessibility_tree_command,
+            commands::perform_accessible_action_command,
+            commands::list_windows_command,
+            commands::active_window_command,
+            commands::focus_window_command,
+            commands::move_resize_window_command,
+            commands::minimize_window_command,
+            commands::close_window_command,
+            commands::wait_for_window_command,
+            commands::generate_plan_command,
+            commands::execute_plan_command,
+            commands::dry_run_plan_command,
             commands::list_macros_command,
+            commands::search_macros_command,
+            commands::set_macro_metadata_command,
+            commands::export_macro_command,
+            commands::import_macro_command,
+            commands::macro_history_command,
+            commands::restore_macro_revision_command,
+            commands::diff_macro_revisions_command,
+            // Macro Editor Commands
+            commands::open_macro_editor_command,
+            commands::delete_macro_events_command,
+            commands::insert_macro_events_command,
+            commands::set_macro_event_delay_command,
+            commands::move_macro_click_command,
+            commands::split_macro_command,
+            commands::concatenate_macros_command,
+            commands::undo_macro_edit_command,
+            commands::save_edited_macro_command,
+            commands::close_macro_editor_command,
+            // Scheduler Commands
+            commands::list_scheduled_jobs_command,
+            commands::add_scheduled_job_command,
+            commands::remove_scheduled_job_command,
+            commands::set_scheduled_job_enabled_command,
+            commands::scheduled_job_history_command,
             // Gemini API Commands
             commands::set_gemini_api_key,
             commands::test_gemini_api
@@ -59,9 +116,18 @@ pub fn run() {
             }
 
             // Setup the orchestrator and add it to the managed state
-            let orchestrator = Orchestrator::new(app.handle().clone());
+            let mut orchestrator = Orchestrator::new(app.handle().clone());
+            orchestrator.scheduler.notify_app_started();
             let orchestrator_state = Arc::new(Mutex::new(orchestrator));
             app.manage(orchestrator_state.clone());
+            let wake_word_state = orchestrator_state.clone();
+            tauri::async_runtime::spawn(async move {
+                let mut orchestrator = wake_word_state.lock().await;
+                if let Err(e) = orchestrator.restart_wake_word_listener(wake_word_state.clone()) {
+                    log::error!("Could not start the wake word listener: {}", e);
+                }
+            });
+            app.manage(Arc::new(Mutex::new(MacroEditor::default())));
 
             // Create a channel to send events from the listener to the processor
             let (tx, rx) = mpsc::channel::<rdev::Event>();
@@ -78,6 +144,7 @@ pub fn run() {
             });
 
             tauri::async_runtime::spawn(orchestrator::event_processor_task(orchestrator_state.clone(), rx));
+            tauri::async_runtime::spawn(orchestrator::scheduler_task(orchestrator_state.clone()));
 
             app.global_shortcut()
                 .register(Shortcut::new(None, Code::F4))?;
@@ -185,8 +252,26 @@ fn toggle_window_visibility(window: &tauri::WebviewWindow) {
 
 mod commands {
     use super::*;
-    use crate::modules::macro_engine;
-    use crate::modules::cognition;
+    use crate::modules::macro_engine::{self, PlaybackReport};
+    use crate::modules::macro_formats::MacroFormat;
+    use crate::modules::macro_history;
+    use crate::modules::macro_index::{self, MacroMetadata, MacroSort};
+    use crate::modules::accessibility::{AccessibleAction, AccessibleId, AccessibleNode};
+    use crate::modules::cognition::{self, CognitionSettings, Gemini};
+    use crate::modules::dry_run;
+    use crate::modules::io_controller::LiveInput;
+    use crate::modules::tooling::{PlannedCall, ToolCall, Tooling};
+    use crate::modules::perception::{
+        self, CaptureTarget, FindImageOptions, ImageMatch, Perception, Rect, ScreenText, Screenshot, TextMatch, UiMap,
+    };
+    use crate::modules::self_correction;
+    use crate::modules::voice::{self, VoiceInput, VoiceSettings};
+    use crate::modules::knowledge::{self, TrajectoryEntry};
+    use crate::modules::redaction::{self, RedactionAudit, RedactionSettings, Redactor};
+    use crate::modules::screen_recording::RecordingOptions;
+    use crate::modules::wake_word::WakeWordModel;
+    use crate::modules::window::{WindowContext, WindowInfo, WindowManager};
+    use crate::modules::scheduler::{BusyPolicy, JobAction, JobTrigger, RunRecord, ScheduledJob};
     use tauri::State;
 
     #[tauri::command]
@@ -204,29 +289,166 @@ mod commands {
         Ok(serde_json::to_string(&orchestrator.state).unwrap_or_default())
     }
 
+    /// The current task and the window that was focused when it started.
+    #[tauri::command]
+    pub async fn get_session_context_command(
+        orchestrator_state: State<'_, Arc<Mutex<Orchestrator>>>,
+    ) -> Result<Option<SessionContext>, String> {
+        let orchestrator = orchestrator_state.lock().await;
+        Ok(orchestrator.session_context().cloned())
+    }
+
     #[tauri::command]
     pub async fn start_recording_command(
+        ai_assisted: Option<bool>,
         orchestrator_state: State<'_, Arc<Mutex<Orchestrator>>>,
     ) -> Result<(), String> {
         let mut orchestrator = orchestrator_state.lock().await;
+        orchestrator.set_ai_assisted(ai_assisted.unwrap_or(false));
         orchestrator.start_recording().map_err(|e| e.to_string())
     }
 
+    /// Listens for one spoken command, from the microphone or a WAV file, and executes it.
+    /// `model` defaults to the one in the voice settings.
     #[tauri::command]
-    pub async fn stop_recording_command(
+    pub async fn start_listening_command(
+        input: Option<VoiceInput>,
+        model: Option<String>,
+        app_handle: tauri::AppHandle,
+        orchestrator_state: State<'_, Arc<Mutex<Orchestrator>>>,
+    ) -> Result<(), String> {
+        let (model, stop) = {
+            let mut orchestrator = orchestrator_state.lock().await;
+            let model = match model {
+                Some(model) => std::path::PathBuf::from(model),
+                None => orchestrator.voice_settings.whisper_model_path(&app_handle).map_err(|e| e.to_string())?,
+            };
+            (model, orchestrator.start_voice_input().map_err(|e| e.to_string())?)
+        };
+        let orchestrator_state = orchestrator_state.inner().clone();
+        thread::spawn(move || {
+            orchestrator::voice_listener(orchestrator_state, app_handle, input.unwrap_or_default(), model, stop, None)
+        });
+        Ok(())
+    }
+
+    #[tauri::command]
+    pub async fn stop_listening_command(orchestrator_state: State<'_, Arc<Mutex<Orchestrator>>>) -> Result<(), String> {
+        let mut orchestrator = orchestrator_state.lock().await;
+        if orchestrator.state != orchestrator::AppState::LISTENING {
+            return Err("The agent is not listening.".to_string());
+        }
+        orchestrator.stop().map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn get_voice_settings_command(
+        orchestrator_state: State<'_, Arc<Mutex<Orchestrator>>>,
+    ) -> Result<VoiceSettings, String> {
+        Ok(orchestrator_state.lock().await.voice_settings.clone())
+    }
+
+    /// Saves the push-to-talk key, wake word switch and whisper model, and restarts the
+    /// wake word listener to match.
+    #[tauri::command]
+    pub async fn set_voice_settings_command(
+        settings: VoiceSettings,
+        app_handle: tauri::AppHandle,
+        orchestrator_state: State<'_, Arc<Mutex<Orchestrator>>>,
+    ) -> Result<(), String> {
+        settings.save(&app_handle).map_err(|e| e.to_string())?;
+        let mut orchestrator = orchestrator_state.lock().await;
+        orchestrator.voice_settings = settings;
+        orchestrator.restart_wake_word_listener(orchestrator_state.inner().clone()).map_err(|e| e.to_string())
+    }
+
+    /// Trains the wake word from WAV recordings of it, a few seconds each, and restarts
+    /// the wake word listener with it.
+    #[tauri::command]
+    pub async fn train_wake_word_command(
         name: String,
+        clips: Vec<String>,
+        app_handle: tauri::AppHandle,
         orchestrator_state: State<'_, Arc<Mutex<Orchestrator>>>,
     ) -> Result<(), String> {
+        let path = voice::wake_word_model_path(&app_handle).map_err(|e| e.to_string())?;
+        let clips: Vec<std::path::PathBuf> = clips.into_iter().map(std::path::PathBuf::from).collect();
+        tokio::task::spawn_blocking(move || WakeWordModel::train_from_files(&name, &clips)?.save(&path))
+            .await
+            .map_err(|e| format!("Task join error: {}", e))?
+            .map_err(|e| e.to_string())?;
         let mut orchestrator = orchestrator_state.lock().await;
-        orchestrator.stop_recording(name).map_err(|e| e.to_string())
+        orchestrator.restart_wake_word_listener(orchestrator_state.inner().clone()).map_err(|e| e.to_string())
+    }
+
+    /// Says `text`, such as a question the UI puts to the user, with the voice settings' speech
+    /// options. Muted settings say nothing.
+    #[tauri::command]
+    pub async fn speak_command(text: String, orchestrator_state: State<'_, Arc<Mutex<Orchestrator>>>) -> Result<(), String> {
+        let speech = orchestrator_state.lock().await.voice_settings.speech.clone();
+        tokio::task::spawn_blocking(move || speech.speak(&text))
+            .await
+            .map_err(|e| format!("Task join error: {}", e))?
+            .map_err(|e| e.to_string())
+    }
+
+    /// Records the screen during every following execution, or stops doing so when
+    /// `options` is unset. Recordings are linked from the trajectory log.
+    #[tauri::command]
+    pub async fn set_execution_recording_command(
+        options: Option<RecordingOptions>,
+        orchestrator_state: State<'_, Arc<Mutex<Orchestrator>>>,
+    ) -> Result<(), String> {
+        orchestrator_state.lock().await.execution_recording = options;
+        Ok(())
+    }
+
+    /// Lists executed tasks, newest first.
+    #[tauri::command]
+    pub fn list_trajectories_command(app_handle: tauri::AppHandle) -> Result<Vec<TrajectoryEntry>, String> {
+        let dir = knowledge::trajectory_dir(&app_handle).map_err(|e| e.to_string())?;
+        knowledge::list_trajectories(&dir).map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub fn get_redaction_settings_command(app_handle: tauri::AppHandle) -> RedactionSettings {
+        RedactionSettings::load(&app_handle)
+    }
+
+    /// Saves the windows to blur and the patterns to mask before anything reaches the
+    /// model; invalid patterns are refused.
+    #[tauri::command]
+    pub fn set_redaction_settings_command(settings: RedactionSettings, app_handle: tauri::AppHandle) -> Result<(), String> {
+        settings.save(&app_handle).map_err(|e| e.to_string())
+    }
+
+    /// What was redacted from each request to the model, oldest first.
+    #[tauri::command]
+    pub fn get_redaction_audit_command(app_handle: tauri::AppHandle) -> Result<Vec<RedactionAudit>, String> {
+        let path = redaction::audit_log_path(&app_handle).map_err(|e| e.to_string())?;
+        redaction::read_audit_log(&path).map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn stop_recording_command(
+        name: String,
+        orchestrator_state: State<'_, Arc<Mutex<Orchestrator>>>,
+    ) -> Result<(), String> {
+        let recording = orchestrator_state.lock().await.stop_recording(name).map_err(|e| e.to_string())?;
+        tokio::task::spawn_blocking(move || recording.save())
+            .await
+            .map_err(|e| format!("Task join error: {}", e))?
+            .map_err(|e| e.to_string())
     }
 
     #[tauri::command]
     pub async fn play_macro_command(
         name: String,
+        confidence_threshold: Option<f32>,
+        coordinate_fallback: Option<bool>,
         app_handle: tauri::AppHandle,
         orchestrator_state: State<'_, Arc<Mutex<Orchestrator>>>,
-    ) -> Result<(), String> {
+    ) -> Result<PlaybackReport, String> {
         // Set state to EXECUTING
         {
             let mut orchestrator = orchestrator_state.lock().await;
@@ -243,32 +465,504 @@ mod commands {
             macro_engine::load_macro(&name, &app_handle).map_err(|e| e.to_string())?;
 
         // Play the macro in a blocking thread to not freeze the UI
-        let play_result = tokio::task::spawn_blocking(move || macro_engine::play_macro(&macro_data))
-            .await
-            .map_err(|e| format!("Task join error: {}", e))?;
+        let threshold = confidence_threshold.unwrap_or(self_correction::DEFAULT_CONFIDENCE_THRESHOLD);
+        let coordinate_fallback = coordinate_fallback.unwrap_or(false);
+        let played_name = name.clone();
+        let play_result = tokio::task::spawn_blocking(move || {
+            let result =
+                self_correction::play_macro_self_correcting(&macro_data, &app_handle, threshold, coordinate_fallback);
+            // Only completed plays count towards the macro's usage.
+            if result.is_ok() {
+                macro_index::record_play(&played_name, &app_handle);
+            }
+            result
+        })
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?;
 
         // Set state back to IDLE
         {
+            let message = match &play_result {
+                Ok(_) => format!("Played macro: {}", name),
+                Err(e) => e.to_string(),
+            };
             let mut orchestrator = orchestrator_state.lock().await;
-            orchestrator.stop().map_err(|e| e.to_string())?;
+            orchestrator.stop_executing(play_result.is_ok(), &message).map_err(|e| e.to_string())?;
         }
 
         play_result.map_err(|e| e.to_string())
     }
 
+    #[tauri::command]
+    pub async fn take_screenshot_command(target: Option<CaptureTarget>, save_to: Option<String>) -> Result<Screenshot, String> {
+        tokio::task::spawn_blocking(move || {
+            Perception::new().take_screenshot(target.unwrap_or_default(), save_to.as_deref().map(std::path::Path::new))
+        })
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+        .map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn find_image_command(template_path: String, options: Option<FindImageOptions>) -> Result<Vec<ImageMatch>, String> {
+        tokio::task::spawn_blocking(move || {
+            let template = perception::load_image(std::path::Path::new(&template_path))?;
+            Perception::new().find_image(&template, &options.unwrap_or_default())
+        })
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+        .map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn read_screen_command(region: Option<Rect>) -> Result<ScreenText, String> {
+        tokio::task::spawn_blocking(move || Perception::new().read_screen(region))
+            .await
+            .map_err(|e| format!("Task join error: {}", e))?
+            .map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn find_text_on_screen_command(query: String) -> Result<Vec<TextMatch>, String> {
+        tokio::task::spawn_blocking(move || Perception::new().find_text_on_screen(&query))
+            .await
+            .map_err(|e| format!("Task join error: {}", e))?
+            .map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn read_ui_map_command() -> Result<UiMap, String> {
+        tokio::task::spawn_blocking(|| Perception::new().ui_map())
+            .await
+            .map_err(|e| format!("Task join error: {}", e))?
+            .map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn accessibility_tree_command() -> Result<AccessibleNode, String> {
+        tokio::task::spawn_blocking(|| Perception::new().accessibility_tree())
+            .await
+            .map_err(|e| format!("Task join error: {}", e))?
+            .map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn perform_accessible_action_command(target: AccessibleId, action: AccessibleAction) -> Result<(), String> {
+        tokio::task::spawn_blocking(move || Perception::new().perform_accessible_action(&target, &action))
+            .await
+            .map_err(|e| format!("Task join error: {}", e))?
+            .map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn list_windows_command() -> Result<Vec<WindowInfo>, String> {
+        tokio::task::spawn_blocking(|| WindowManager::connect()?.list_windows())
+            .await
+            .map_err(|e| format!("Task join error: {}", e))?
+            .map_err(|e| e.to_string())
+    }
+
+    /// The focused window's title, class and pid.
+    #[tauri::command]
+    pub async fn active_window_command() -> Result<Option<WindowContext>, String> {
+        tokio::task::spawn_blocking(|| Perception::new().active_window())
+            .await
+            .map_err(|e| format!("Task join error: {}", e))?
+            .map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn focus_window_command(id: u32) -> Result<(), String> {
+        tokio::task::spawn_blocking(move || WindowManager::connect()?.focus_window(id))
+            .await
+            .map_err(|e| format!("Task join error: {}", e))?
+            .map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn move_resize_window_command(id: u32, rect: Rect) -> Result<(), String> {
+        tokio::task::spawn_blocking(move || WindowManager::connect()?.move_resize(id, rect))
+            .await
+            .map_err(|e| format!("Task join error: {}", e))?
+            .map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn minimize_window_command(id: u32) -> Result<(), String> {
+        tokio::task::spawn_blocking(move || WindowManager::connect()?.minimize(id))
+            .await
+            .map_err(|e| format!("Task join error: {}", e))?
+            .map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn close_window_command(id: u32) -> Result<(), String> {
+        tokio::task::spawn_blocking(move || WindowManager::connect()?.close(id))
+            .await
+            .map_err(|e| format!("Task join error: {}", e))?
+            .map_err(|e| e.to_string())
+    }
+
+    /// Waits for a window whose title or class contains `pattern`.
+    #[tauri::command]
+    pub async fn wait_for_window_command(pattern: String, timeout_ms: Option<u64>) -> Result<WindowInfo, String> {
+        let timeout = std::time::Duration::from_millis(timeout_ms.unwrap_or(10_000));
+        tokio::task::spawn_blocking(move || WindowManager::connect()?.wait_for_window(&pattern, timeout))
+            .await
+            .map_err(|e| format!("Task join error: {}", e))?
+            .map_err(|e| e.to_string())
+    }
+
+    /// Plans `task` against a UI map from `read_ui_map_command`; pass the same map when executing
+    /// the plan so element ids resolve. The focused window is included so the task can
+    /// refer to it, and a screenshot shows what the map leaves out. All are redacted
+    /// before they are sent.
+    #[tauri::command]
+    pub async fn generate_plan_command(
+        task: String,
+        ui_map: UiMap,
+        app_handle: tauri::AppHandle,
+        orchestrator_state: State<'_, Arc<Mutex<Orchestrator>>>,
+    ) -> Result<Vec<ToolCall>, String> {
+        let window = orchestrator_state.lock().await.task_window();
+        tokio::task::spawn_blocking(move || {
+            orchestrator::plan_with_cognition(&app_handle, &task, &ui_map, window.as_ref())
+        })
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+    }
+
+    #[tauri::command]
+    pub fn dry_run_macro_command(name: String, app_handle: tauri::AppHandle) -> Result<Vec<String>, String> {
+        let macro_data = macro_engine::load_macro(&name, &app_handle).map_err(|e| e.to_string())?;
+        Ok(dry_run::dry_run_macro(&macro_data))
+    }
+
+    #[tauri::command]
+    pub async fn execute_plan_command(
+        steps: Vec<ToolCall>,
+        ui_map: Option<UiMap>,
+        app_handle: tauri::AppHandle,
+        orchestrator_state: State<'_, Arc<Mutex<Orchestrator>>>,
+    ) -> Result<(), String> {
+        {
+            let mut orchestrator = orchestrator_state.lock().await;
+            if orchestrator.state != orchestrator::AppState::IDLE {
+                return Err("Cannot execute a plan while the agent is not idle.".to_string());
+            }
+            orchestrator
+                .start_executing(format!("Executing plan with {} steps", steps.len()))
+                .map_err(|e| e.to_string())?;
+        }
+
+        let run_result = tokio::task::spawn_blocking(move || {
+            let load_macro = move |name: &str| macro_engine::load_macro(name, &app_handle);
+            let tooling = match ui_map {
+                Some(ui_map) => Tooling::new().with_ui_map(ui_map),
+                None => Tooling::new(),
+            };
+            tooling.execute_plan(&steps, &mut LiveInput, &load_macro)
+        })
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?;
+
+        {
+            let message = match &run_result {
+                Ok(()) => "Plan executed".to_string(),
+                Err(e) => e.to_string(),
+            };
+            let mut orchestrator = orchestrator_state.lock().await;
+            orchestrator.stop_executing(run_result.is_ok(), &message).map_err(|e| e.to_string())?;
+        }
+
+        run_result.map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub fn dry_run_plan_command(
+        steps: Vec<ToolCall>,
+        ui_map: Option<UiMap>,
+        app_handle: tauri::AppHandle,
+    ) -> Result<Vec<PlannedCall>, String> {
+        let load_macro = move |name: &str| macro_engine::load_macro(name, &app_handle);
+        let tooling = match ui_map {
+            Some(ui_map) => Tooling::new().with_ui_map(ui_map),
+            None => Tooling::new(),
+        };
+        tooling.dry_run_plan(&steps, &load_macro).map_err(|e| e.to_string())
+    }
+
     #[tauri::command]
     pub fn list_macros_command(app_handle: tauri::AppHandle) -> Result<Vec<String>, String> {
         macro_engine::list_macros(&app_handle).map_err(|e| e.to_string())
     }
 
+    #[tauri::command]
+    pub fn search_macros_command(
+        query: Option<String>,
+        tags: Option<Vec<String>>,
+        sort: Option<MacroSort>,
+        app_handle: tauri::AppHandle,
+    ) -> Result<Vec<MacroMetadata>, String> {
+        macro_index::search_macros(
+            query.as_deref().unwrap_or(""),
+            &tags.unwrap_or_default(),
+            sort.unwrap_or_default(),
+            &app_handle,
+        )
+        .map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub fn set_macro_metadata_command(
+        name: String,
+        tags: Vec<String>,
+        description: String,
+        app_handle: tauri::AppHandle,
+    ) -> Result<MacroMetadata, String> {
+        macro_index::set_macro_metadata(&name, tags, description, &app_handle).map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub fn export_macro_command(
+        name: String,
+        format: MacroFormat,
+        destination: Option<String>,
+        app_handle: tauri::AppHandle,
+    ) -> Result<String, String> {
+        macro_engine::export_macro(&name, format, destination.map(Into::into), &app_handle)
+            .map(|path| path.to_string_lossy().into_owned())
+            .map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub fn import_macro_command(
+        path: String,
+        format: Option<MacroFormat>,
+        app_handle: tauri::AppHandle,
+    ) -> Result<macro_engine::Macro, String> {
+        macro_engine::import_macro(std::path::Path::new(&path), format, &app_handle)
+            .map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub fn macro_history_command(
+        name: String,
+        app_handle: tauri::AppHandle,
+    ) -> Result<Vec<macro_history::RevisionInfo>, String> {
+        macro_history::macro_history(&name, &app_handle).map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub fn restore_macro_revision_command(
+        name: String,
+        revision: u32,
+        app_handle: tauri::AppHandle,
+    ) -> Result<macro_engine::Macro, String> {
+        macro_history::restore_macro_revision(&name, revision, &app_handle).map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub fn diff_macro_revisions_command(
+        name: String,
+        from_revision: u32,
+        to_revision: u32,
+        app_handle: tauri::AppHandle,
+    ) -> Result<macro_history::MacroDiff, String> {
+        macro_history::diff_revisions(&name, from_revision, to_revision, &app_handle).map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn open_macro_editor_command(
+        name: String,
+        editor_state: State<'_, Arc<Mutex<MacroEditor>>>,
+        app_handle: tauri::AppHandle,
+    ) -> Result<macro_engine::Macro, String> {
+        let mut editor = editor_state.lock().await;
+        editor.open(&name, &app_handle).cloned().map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn delete_macro_events_command(
+        name: String,
+        start: usize,
+        end: usize,
+        editor_state: State<'_, Arc<Mutex<MacroEditor>>>,
+    ) -> Result<macro_engine::Macro, String> {
+        let mut editor = editor_state.lock().await;
+        let session = editor.session(&name).map_err(|e| e.to_string())?;
+        session.delete_range(start, end).map_err(|e| e.to_string())?;
+        Ok(session.macro_data.clone())
+    }
+
+    #[tauri::command]
+    pub async fn insert_macro_events_command(
+        name: String,
+        index: usize,
+        events: Vec<macro_engine::TimedEvent>,
+        editor_state: State<'_, Arc<Mutex<MacroEditor>>>,
+    ) -> Result<macro_engine::Macro, String> {
+        let mut editor = editor_state.lock().await;
+        let session = editor.session(&name).map_err(|e| e.to_string())?;
+        session.insert(index, events).map_err(|e| e.to_string())?;
+        Ok(session.macro_data.clone())
+    }
+
+    #[tauri::command]
+    pub async fn set_macro_event_delay_command(
+        name: String,
+        index: usize,
+        delay_secs: f64,
+        editor_state: State<'_, Arc<Mutex<MacroEditor>>>,
+    ) -> Result<macro_engine::Macro, String> {
+        let mut editor = editor_state.lock().await;
+        let session = editor.session(&name).map_err(|e| e.to_string())?;
+        session.set_delay(index, delay_secs).map_err(|e| e.to_string())?;
+        Ok(session.macro_data.clone())
+    }
+
+    #[tauri::command]
+    pub async fn move_macro_click_command(
+        name: String,
+        index: usize,
+        x: f64,
+        y: f64,
+        editor_state: State<'_, Arc<Mutex<MacroEditor>>>,
+    ) -> Result<macro_engine::Macro, String> {
+        let mut editor = editor_state.lock().await;
+        let session = editor.session(&name).map_err(|e| e.to_string())?;
+        session.move_click_target(index, x, y).map_err(|e| e.to_string())?;
+        Ok(session.macro_data.clone())
+    }
+
+    #[tauri::command]
+    pub async fn split_macro_command(
+        name: String,
+        index: usize,
+        new_name: String,
+        editor_state: State<'_, Arc<Mutex<MacroEditor>>>,
+        app_handle: tauri::AppHandle,
+    ) -> Result<(macro_engine::Macro, macro_engine::Macro), String> {
+        let mut editor = editor_state.lock().await;
+        editor.split(&name, index, &new_name, &app_handle).map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn concatenate_macros_command(
+        name: String,
+        other_name: String,
+        editor_state: State<'_, Arc<Mutex<MacroEditor>>>,
+        app_handle: tauri::AppHandle,
+    ) -> Result<macro_engine::Macro, String> {
+        let mut editor = editor_state.lock().await;
+        editor.concatenate(&name, &other_name, &app_handle).cloned().map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn undo_macro_edit_command(
+        name: String,
+        editor_state: State<'_, Arc<Mutex<MacroEditor>>>,
+    ) -> Result<macro_engine::Macro, String> {
+        let mut editor = editor_state.lock().await;
+        let session = editor.session(&name).map_err(|e| e.to_string())?;
+        session.undo().map_err(|e| e.to_string())?;
+        Ok(session.macro_data.clone())
+    }
+
+    #[tauri::command]
+    pub async fn save_edited_macro_command(
+        name: String,
+        editor_state: State<'_, Arc<Mutex<MacroEditor>>>,
+        app_handle: tauri::AppHandle,
+    ) -> Result<(), String> {
+        let mut editor = editor_state.lock().await;
+        editor.save(&name, &app_handle).map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn close_macro_editor_command(
+        name: String,
+        editor_state: State<'_, Arc<Mutex<MacroEditor>>>,
+    ) -> Result<(), String> {
+        let mut editor = editor_state.lock().await;
+        editor.close(&name).map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn list_scheduled_jobs_command(
+        orchestrator_state: State<'_, Arc<Mutex<Orchestrator>>>,
+    ) -> Result<Vec<ScheduledJob>, String> {
+        let orchestrator = orchestrator_state.lock().await;
+        Ok(orchestrator.scheduler.jobs().to_vec())
+    }
+
+    #[tauri::command]
+    pub async fn add_scheduled_job_command(
+        name: String,
+        trigger: JobTrigger,
+        action: JobAction,
+        busy_policy: Option<BusyPolicy>,
+        orchestrator_state: State<'_, Arc<Mutex<Orchestrator>>>,
+    ) -> Result<ScheduledJob, String> {
+        let mut orchestrator = orchestrator_state.lock().await;
+        orchestrator
+            .scheduler
+            .add_job(name, trigger, action, busy_policy.unwrap_or_default())
+            .map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn remove_scheduled_job_command(
+        id: u64,
+        orchestrator_state: State<'_, Arc<Mutex<Orchestrator>>>,
+    ) -> Result<(), String> {
+        let mut orchestrator = orchestrator_state.lock().await;
+        orchestrator.scheduler.remove_job(id).map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn set_scheduled_job_enabled_command(
+        id: u64,
+        enabled: bool,
+        orchestrator_state: State<'_, Arc<Mutex<Orchestrator>>>,
+    ) -> Result<(), String> {
+        let mut orchestrator = orchestrator_state.lock().await;
+        orchestrator.scheduler.set_enabled(id, enabled).map_err(|e| e.to_string())
+    }
+
+    #[tauri::command]
+    pub async fn scheduled_job_history_command(
+        job_id: Option<u64>,
+        orchestrator_state: State<'_, Arc<Mutex<Orchestrator>>>,
+    ) -> Result<Vec<RunRecord>, String> {
+        let orchestrator = orchestrator_state.lock().await;
+        Ok(orchestrator.scheduler.history(job_id))
+    }
+
+    #[tauri::command]
+    pub fn get_cognition_settings_command(app_handle: tauri::AppHandle) -> CognitionSettings {
+        CognitionSettings::load(&app_handle)
+    }
+
+    /// Saves the token budget and encoding of screenshots sent to the model.
+    #[tauri::command]
+    pub fn set_cognition_settings_command(settings: CognitionSettings, app_handle: tauri::AppHandle) -> Result<(), String> {
+        settings.save(&app_handle).map_err(|e| e.to_string())
+    }
+
     #[tauri::command]
     pub fn set_gemini_api_key(api_key: String) -> Result<(), String> {
         cognition::set_api_key(&api_key).map_err(|e| e.to_string())
     }
 
+    /// Sends a prompt, redacted like every other request, with the saved cognition settings.
     #[tauri::command]
-    pub async fn test_gemini_api(prompt: Option<String>) -> Result<String, String> {
+    pub async fn test_gemini_api(prompt: Option<String>, app_handle: tauri::AppHandle) -> Result<String, String> {
         let test_prompt = prompt.unwrap_or_else(|| "Say hello in a friendly way!".to_string());
-        cognition::ask_gemini(&test_prompt).await.map_err(|e| e.to_string())
+        let (redactor, settings) =
+            tokio::task::spawn_blocking(move || (Redactor::load(&app_handle), CognitionSettings::load(&app_handle)))
+                .await
+                .map_err(|e| format!("Task join error: {}", e))?;
+        cognition::ask_gemini(&test_prompt, None, &redactor, &Gemini::new(settings)).await.map_err(|e| e.to_string())
     }
 }
@@ -0,0 +1,354 @@
+// Accessibility module
+// Reads the AT-SPI accessibility tree that GTK and Qt apps expose over D-Bus, and performs
+// accessible actions (click, set text, focus) without moving the mouse.
+
+use crate::modules::perception::Rect;
+use atspi::proxy::{
+    accessible::AccessibleProxyBlocking, action::ActionProxyBlocking, bus::BusProxyBlocking,
+    component::ComponentProxyBlocking, editable_text::EditableTextProxyBlocking,
+};
+use atspi::{CoordType, Interface, State};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use thiserror::Error;
+use zbus::blocking::{connection, Connection};
+
+const REGISTRY_BUS: &str = "org.a11y.atspi.Registry";
+const ROOT_PATH: &str = "/org/a11y/atspi/accessible/root";
+/// Limits on how much of an application's tree is read, since some apps expose huge or
+/// cyclic trees.
+const MAX_TREE_DEPTH: usize = 32;
+const MAX_TREE_NODES: usize = 2000;
+/// Longest wait for a D-Bus reply, so a hung application cannot stall a lookup.
+const DBUS_TIMEOUT: Duration = Duration::from_secs(2);
+/// Names toolkits give the action that activates a widget.
+const CLICK_ACTIONS: [&str; 4] = ["click", "press", "activate", "toggle"];
+
+#[derive(Error, Debug)]
+pub enum AccessibilityError {
+    #[error("Accessibility bus is unavailable: {0}")]
+    Unavailable(String),
+    #[error("D-Bus call failed: {0}")]
+    DBus(String),
+    #[error("No application has an active window")]
+    NoFocusedApplication,
+    #[error("No application named '{0}'")]
+    ApplicationNotFound(String),
+    #[error("No accessible application runs as pid {0}")]
+    NoApplicationForPid(u32),
+    #[error("{0} does not support {1}")]
+    Unsupported(String, String),
+    #[error("{0} refused the action")]
+    ActionFailed(String),
+}
+
+impl From<zbus::Error> for AccessibilityError {
+    fn from(e: zbus::Error) -> Self {
+        AccessibilityError::DBus(e.to_string())
+    }
+}
+
+/// Address of an accessible object on the accessibility bus.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AccessibleId {
+    pub bus: String,
+    pub path: String,
+}
+
+impl std::fmt::Display for AccessibleId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.bus, self.path)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AccessibleNode {
+    pub id: AccessibleId,
+    /// AT-SPI role name, e.g. "push button" or "entry".
+    pub role: String,
+    pub name: String,
+    /// State names, e.g. "focused", "editable" or "showing".
+    pub states: Vec<String>,
+    /// Area on screen, for objects that are visible components.
+    pub extents: Option<Rect>,
+    /// Names of the actions it supports, e.g. "click".
+    pub actions: Vec<String>,
+    /// Whether its text can be set.
+    pub editable: bool,
+    pub children: Vec<AccessibleNode>,
+}
+
+impl AccessibleNode {
+    pub fn has_state(&self, state: &str) -> bool {
+        self.states.iter().any(|s| s == state)
+    }
+
+    /// This node and all its descendants, breadth first.
+    pub fn descendants(&self) -> Vec<&AccessibleNode> {
+        let mut nodes = vec![self];
+        let mut index = 0;
+        while index < nodes.len() {
+            let node = nodes[index];
+            nodes.extend(node.children.iter());
+            index += 1;
+        }
+        nodes
+    }
+}
+
+/// An action performed through the accessibility API instead of synthetic input.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum AccessibleAction {
+    Click,
+    SetText { text: String },
+    Focus,
+}
+
+/// A connection to the AT-SPI accessibility bus.
+pub struct Accessibility {
+    connection: Connection,
+}
+
+impl Accessibility {
+    /// Connects to the accessibility bus announced on the session bus.
+    pub fn connect() -> Result<Self, AccessibilityError> {
+        let unavailable = |e: zbus::Error| AccessibilityError::Unavailable(e.to_string());
+        let session = connection::Builder::session()
+            .and_then(|builder| builder.method_timeout(DBUS_TIMEOUT).build())
+            .map_err(unavailable)?;
+        let address = BusProxyBlocking::new(&session).and_then(|bus| bus.get_address()).map_err(unavailable)?;
+        let connection = connection::Builder::address(address.as_str())
+            .and_then(|builder| builder.method_timeout(DBUS_TIMEOUT).build())
+            .map_err(unavailable)?;
+        Ok(Self { connection })
+    }
+
+    fn accessible(&self, id: &AccessibleId) -> Result<AccessibleProxyBlocking<'_>, AccessibilityError> {
+        Ok(AccessibleProxyBlocking::builder(&self.connection)
+            .destination(id.bus.clone())?
+            .path(id.path.clone())?
+            .build()?)
+    }
+
+    fn children(&self, id: &AccessibleId) -> Result<Vec<AccessibleId>, AccessibilityError> {
+        Ok(self
+            .accessible(id)?
+            .get_children()?
+            .into_iter()
+            .filter(|child| !child.is_null())
+            .filter_map(|child| {
+                Some(AccessibleId { bus: child.name_as_str()?.to_string(), path: child.path_as_str().to_string() })
+            })
+            .collect())
+    }
+
+    /// The applications registered on the accessibility bus.
+    pub fn applications(&self) -> Result<Vec<AccessibleId>, AccessibilityError> {
+        self.children(&AccessibleId { bus: REGISTRY_BUS.to_string(), path: ROOT_PATH.to_string() })
+    }
+
+    /// The tree of the application whose window is active.
+    pub fn focused_application(&self) -> Result<AccessibleNode, AccessibilityError> {
+        for application in self.applications()? {
+            // Unresponsive applications are skipped rather than failing the lookup.
+            let Ok(windows) = self.children(&application) else {
+                continue;
+            };
+            let active = windows
+                .iter()
+                .any(|window| self.accessible(window).and_then(|w| Ok(w.get_state()?)).is_ok_and(|s| s.contains(State::Active)));
+            if active {
+                return self.read_tree(&application);
+            }
+        }
+        Err(AccessibilityError::NoFocusedApplication)
+    }
+
+    /// The tree of the application with the given accessible name, e.g. "gedit".
+    pub fn application(&self, name: &str) -> Result<AccessibleNode, AccessibilityError> {
+        for application in self.applications()? {
+            if self.accessible(&application).and_then(|a| Ok(a.name()?)).is_ok_and(|n| n == name) {
+                return self.read_tree(&application);
+            }
+        }
+        Err(AccessibilityError::ApplicationNotFound(name.to_string()))
+    }
+
+    /// The tree of the application running as process `pid`, e.g. a window's owner.
+    pub fn application_for_pid(&self, pid: u32) -> Result<AccessibleNode, AccessibilityError> {
+        let dbus = zbus::blocking::fdo::DBusProxy::new(&self.connection)?;
+        for application in self.applications()? {
+            let Ok(bus_name) = zbus::names::BusName::try_from(application.bus.as_str()) else {
+                continue;
+            };
+            if dbus.get_connection_unix_process_id(bus_name).is_ok_and(|owner| owner == pid) {
+                return self.read_tree(&application);
+            }
+        }
+        Err(AccessibilityError::NoApplicationForPid(pid))
+    }
+
+    /// Reads `root` and its descendants.
+    pub fn read_tree(&self, root: &AccessibleId) -> Result<AccessibleNode, AccessibilityError> {
+        let mut budget = MAX_TREE_NODES;
+        self.read_node(root, 0, &mut budget)
+    }
+
+    fn read_node(&self, id: &AccessibleId, depth: usize, budget: &mut usize) -> Result<AccessibleNode, AccessibilityError> {
+        *budget = budget.saturating_sub(1);
+        let accessible = self.accessible(id)?;
+        let interfaces = accessible.get_interfaces()?;
+        let states = accessible.get_state()?;
+
+        let extents = if interfaces.contains(Interface::Component) && states.contains(State::Showing) {
+            let component = ComponentProxyBlocking::builder(&self.connection)
+                .destination(id.bus.clone())?
+                .path(id.path.clone())?
+                .build()?;
+            let (x, y, width, height) = component.get_extents(CoordType::Screen)?;
+            (width > 0 && height > 0).then_some(Rect { x, y, width: width as u32, height: height as u32 })
+        } else {
+            None
+        };
+        let actions = if interfaces.contains(Interface::Action) {
+            self.action(id)?.get_actions()?.into_iter().map(|action| action.name).collect()
+        } else {
+            Vec::new()
+        };
+
+        let mut children = Vec::new();
+        if depth < MAX_TREE_DEPTH {
+            for child in self.children(id)? {
+                if *budget == 0 {
+                    break;
+                }
+                // Objects can disappear while the tree is read.
+                match self.read_node(&child, depth + 1, budget) {
+                    Ok(node) => children.push(node),
+                    Err(e) => log::debug!("Skipping accessible {}: {}", child, e),
+                }
+            }
+        }
+
+        Ok(AccessibleNode {
+            id: id.clone(),
+            role: accessible.get_role()?.name().to_string(),
+            name: accessible.name()?,
+            states: states.iter().map(|state| state.to_static_str().to_string()).collect(),
+            extents,
+            actions,
+            editable: interfaces.contains(Interface::EditableText),
+            children,
+        })
+    }
+
+    fn action(&self, id: &AccessibleId) -> Result<ActionProxyBlocking<'_>, AccessibilityError> {
+        Ok(ActionProxyBlocking::builder(&self.connection)
+            .destination(id.bus.clone())?
+            .path(id.path.clone())?
+            .build()?)
+    }
+
+    fn require(&self, id: &AccessibleId, interface: Interface) -> Result<(), AccessibilityError> {
+        if self.accessible(id)?.get_interfaces()?.contains(interface) {
+            Ok(())
+        } else {
+            Err(AccessibilityError::Unsupported(id.to_string(), format!("{:?}", interface)))
+        }
+    }
+
+    /// Performs `action` on the object, without moving the mouse or sending keys.
+    pub fn perform(&self, id: &AccessibleId, action: &AccessibleAction) -> Result<(), AccessibilityError> {
+        let done = match action {
+            AccessibleAction::Click => {
+                self.require(id, Interface::Action)?;
+                let proxy = self.action(id)?;
+                let names: Vec<String> = proxy.get_actions()?.into_iter().map(|a| a.name.to_lowercase()).collect();
+                // Toolkits name the default action differently; never guess at another one.
+                let index = names
+                    .iter()
+                    .position(|name| CLICK_ACTIONS.contains(&name.as_str()))
+                    .ok_or_else(|| AccessibilityError::Unsupported(id.to_string(), "click".to_string()))?;
+                proxy.do_action(index as i32)?
+            }
+            AccessibleAction::SetText { text } => {
+                self.require(id, Interface::EditableText)?;
+                EditableTextProxyBlocking::builder(&self.connection)
+                    .destination(id.bus.clone())?
+                    .path(id.path.clone())?
+                    .build()?
+                    .set_text_contents(text)?
+            }
+            AccessibleAction::Focus => {
+                self.require(id, Interface::Component)?;
+                ComponentProxyBlocking::builder(&self.connection)
+                    .destination(id.bus.clone())?
+                    .path(id.path.clone())?
+                    .build()?
+                    .grab_focus()?
+            }
+        };
+        if done {
+            Ok(())
+        } else {
+            Err(AccessibilityError::ActionFailed(id.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        path::Path,
+        process::{Child, Command},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    struct FixtureApp(Child);
+
+    impl Drop for FixtureApp {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    fn find<'a>(tree: &'a AccessibleNode, role: &str, name: &str) -> Option<&'a AccessibleNode> {
+        tree.descendants().into_iter().find(|node| node.role == role && node.name == name)
+    }
+
+    /// Runs against tests/fixtures/atspi_form.py, a small GTK window. Needs an X server
+    /// and a session bus, e.g.
+    /// `xvfb-run -a dbus-run-session -- cargo test accessibility -- --ignored`.
+    #[test]
+    #[ignore = "needs Xvfb, a session bus and python3-gi"]
+    fn test_gtk_fixture_tree_and_actions() {
+        let script = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/atspi_form.py");
+        let _app = FixtureApp(Command::new("python3").arg(script).spawn().unwrap());
+
+        let started = Instant::now();
+        let (accessibility, tree) = loop {
+            let found = Accessibility::connect().and_then(|a| a.application("atspi_form").map(|tree| (a, tree)));
+            match found {
+                Ok(found) if find(&found.1, "push button", "Save as").is_some() => break found,
+                _ if started.elapsed() > Duration::from_secs(10) => panic!("fixture app did not appear: {:?}", found.err()),
+                _ => thread::sleep(Duration::from_millis(200)),
+            }
+        };
+
+        let entry = tree.descendants().into_iter().find(|node| node.editable).unwrap();
+        assert!(entry.extents.is_some());
+        let button = find(&tree, "push button", "Save as").unwrap();
+        assert!(button.actions.iter().any(|action| action == "click"));
+
+        let text = AccessibleAction::SetText { text: "Acme Corp".to_string() };
+        accessibility.perform(&entry.id, &text).unwrap();
+        accessibility.perform(&button.id, &AccessibleAction::Click).unwrap();
+
+        let tree = accessibility.read_tree(&tree.id).unwrap();
+        assert!(find(&tree, "label", "Saved: Acme Corp").is_some());
+    }
+}
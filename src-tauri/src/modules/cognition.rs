@@ -1,9 +1,25 @@
 // Cognition module for task planning, decision making, and cognitive processing
 // This module handles communication with the Gemini LLM API
 
+use crate::modules::perception::{self, OcrWord, UiMap};
+use crate::modules::persistence::{self, PersistenceError};
+use crate::modules::redaction::{RedactionAudit, Redactor};
+use crate::modules::tooling::ToolCall;
+use crate::modules::window::WindowContext;
+use base64::Engine;
+use image::{codecs::jpeg::JpegEncoder, imageops, DynamicImage, RgbaImage};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use tauri::Manager;
 use thiserror::Error;
 
+const GEMINI_URL: &str = "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.5-flash:generateContent";
+/// Gemini counts an image as 258 tokens per 768x768 tile, or one tile if it fits in 384x384.
+const TOKENS_PER_TILE: u32 = 258;
+const TILE_SIZE: u32 = 768;
+const SMALL_IMAGE_SIZE: u32 = 384;
+
 #[derive(Error, Debug)]
 pub enum CognitionError {
     #[error("API key not found. Please set your Gemini API key.")]
@@ -20,6 +36,12 @@ pub enum CognitionError {
     
     #[error("Failed to parse API response: {0}")]
     ParseError(String),
+
+    #[error("Cannot encode image: {0}")]
+    ImageError(String),
+
+    #[error("Cognition settings error: {0}")]
+    SettingsError(String),
 }
 
 pub struct Cognition;
@@ -41,9 +63,20 @@ struct Content {
     parts: Vec<Part>,
 }
 
-#[derive(Debug, Serialize)]
-struct Part {
-    text: String,
+/// One piece of a request: text, or an image.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Part {
+    Text(String),
+    InlineData(InlineData),
+}
+
+/// An image in a request, base64-encoded.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct InlineData {
+    /// "image/png" or "image/jpeg".
+    pub mime_type: String,
+    pub data: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -99,67 +132,566 @@ pub fn set_api_key(api_key: &str) -> Result<(), CognitionError> {
     Ok(())
 }
 
-/// Sends a prompt to the Gemini API and returns the response
-pub async fn ask_gemini(prompt: &str) -> Result<String, CognitionError> {
-    // 1. Get API key from keyring
-    let api_key = get_api_key()?;
+/// How images are encoded for requests.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "format", rename_all = "snake_case")]
+pub enum ImageEncoding {
+    Png,
+    /// Smaller than PNG for screenshots with photos or gradients; `quality` is 1 to 100.
+    Jpeg { quality: u8 },
+}
+
+impl Default for ImageEncoding {
+    fn default() -> Self {
+        ImageEncoding::Jpeg { quality: 80 }
+    }
+}
+
+/// How the model is asked.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct CognitionSettings {
+    /// Tokens each screenshot may cost; larger ones are scaled down to fit. 0 sends no
+    /// screenshots at all.
+    pub image_token_budget: u32,
+    pub image_encoding: ImageEncoding,
+}
+
+impl Default for CognitionSettings {
+    fn default() -> Self {
+        Self { image_token_budget: 4 * TOKENS_PER_TILE, image_encoding: ImageEncoding::default() }
+    }
+}
+
+impl CognitionSettings {
+    pub fn validate(&self) -> Result<(), CognitionError> {
+        match self.image_encoding {
+            ImageEncoding::Jpeg { quality } if !(1..=100).contains(&quality) => {
+                Err(CognitionError::SettingsError(format!("JPEG quality {} is not between 1 and 100", quality)))
+            }
+            _ => Ok(()),
+        }
+    }
 
-    // 2. Create HTTP client
-    let client = reqwest::Client::new();
+    /// Loads the saved settings, or the defaults if there are none or they are unreadable.
+    pub fn load(app_handle: &tauri::AppHandle) -> Self {
+        let path = match config_path(app_handle) {
+            Ok(path) => path,
+            Err(e) => {
+                log::error!("Failed to load cognition settings: {}", e);
+                return Self::default();
+            }
+        };
+        match persistence::read_json::<Self>(&path) {
+            Ok(loaded) => {
+                if let Some(warning) = &loaded.recovery {
+                    persistence::report_recovery(app_handle, warning);
+                }
+                loaded.value
+            }
+            Err(PersistenceError::NotFound(_)) => Self::default(),
+            Err(e) => {
+                log::error!("Failed to load cognition settings: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, app_handle: &tauri::AppHandle) -> Result<(), CognitionError> {
+        self.validate()?;
+        persistence::write_json(&config_path(app_handle)?, self).map_err(|e| CognitionError::SettingsError(e.to_string()))
+    }
+}
+
+fn config_path(app_handle: &tauri::AppHandle) -> Result<PathBuf, CognitionError> {
+    let config_dir = app_handle
+        .path()
+        .app_config_dir()
+        .map_err(|e| CognitionError::SettingsError(format!("No config directory: {}", e)))?;
+    Ok(config_dir.join("nyx-agent/cognition.json"))
+}
 
-    // 3. Build request body
-    let request_body = GeminiRequest {
-        contents: vec![Content {
-            parts: vec![Part {
-                text: prompt.to_string(),
-            }],
-        }],
+/// The tokens Gemini counts for an image of this size.
+pub fn image_tokens(width: u32, height: u32) -> u32 {
+    if width <= SMALL_IMAGE_SIZE && height <= SMALL_IMAGE_SIZE {
+        return TOKENS_PER_TILE;
+    }
+    width.div_ceil(TILE_SIZE) * height.div_ceil(TILE_SIZE) * TOKENS_PER_TILE
+}
+
+/// The largest size with the image's aspect ratio that costs at most `budget` tokens, and
+/// is no larger than the image.
+fn size_within_budget(width: u32, height: u32, budget: u32) -> (u32, u32) {
+    if image_tokens(width, height) <= budget {
+        return (width, height);
+    }
+    let tiles = (budget / TOKENS_PER_TILE).max(1);
+    // Try every grid of tiles the budget allows and keep the one losing the least detail.
+    let scale = (1..=tiles)
+        .map(|columns| {
+            let rows = tiles / columns;
+            f64::min(
+                (columns * TILE_SIZE) as f64 / width as f64,
+                (rows * TILE_SIZE) as f64 / height as f64,
+            )
+        })
+        .fold(0.0, f64::max)
+        .min(1.0);
+    (((width as f64 * scale).floor() as u32).max(1), ((height as f64 * scale).floor() as u32).max(1))
+}
+
+/// Scales `image` down to the token budget and encodes it.
+pub fn encode_image(image: &RgbaImage, settings: &CognitionSettings) -> Result<InlineData, CognitionError> {
+    let (width, height) = size_within_budget(image.width(), image.height(), settings.image_token_budget);
+    let scaled;
+    let image = if (width, height) == image.dimensions() {
+        image
+    } else {
+        scaled = imageops::resize(image, width, height, imageops::FilterType::Triangle);
+        &scaled
     };
+    let (mime_type, bytes) = match settings.image_encoding {
+        ImageEncoding::Png => {
+            ("image/png", perception::encode_png(image).map_err(|e| CognitionError::ImageError(e.to_string()))?)
+        }
+        ImageEncoding::Jpeg { quality } => {
+            let mut bytes = Vec::new();
+            // JPEG has no alpha channel.
+            JpegEncoder::new_with_quality(&mut bytes, quality.clamp(1, 100))
+                .encode_image(&DynamicImage::ImageRgba8(image.clone()).to_rgb8())
+                .map_err(|e| CognitionError::ImageError(e.to_string()))?;
+            ("image/jpeg", bytes)
+        }
+    };
+    Ok(InlineData { mime_type: mime_type.to_string(), data: base64::engine::general_purpose::STANDARD.encode(bytes) })
+}
 
-    // 4. Send the POST request with header-based authentication
-    let url = "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.5-flash:generateContent";
-    
-    let res = client
-        .post(url)
-        .header("x-goog-api-key", api_key) // Use header for auth
-        .json(&request_body)
-        .send()
-        .await?;
-
-    // 5. Await and handle potential HTTP errors
-    if !res.status().is_success() {
-        let status = res.status();
-        let error_text = res.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(CognitionError::ApiError(format!(
-            "HTTP {}: {}",
-            status, error_text
-        )));
-    }
-
-    // 6. Parse the response
-    let response: GeminiResponse = res.json().await.map_err(|e| {
-        CognitionError::ParseError(format!("Failed to parse JSON response: {}", e))
-    })?;
-
-    // 7. Check for API-level errors
-    if let Some(error) = response.error {
-        return Err(CognitionError::ApiError(format!(
-            "Gemini API error: {} (code: {:?})",
-            error.message,
-            error.code
-        )));
-    }
-
-    // 8. Extract the text from the response
-    if let Some(candidates) = response.candidates {
-        if let Some(candidate) = candidates.first() {
-            if let Some(part) = candidate.content.parts.first() {
-                return Ok(part.text.clone());
+/// A Gemini endpoint and how requests to it are made.
+pub struct Gemini {
+    endpoint: String,
+    /// Read from the keyring for each request if unset.
+    api_key: Option<String>,
+    settings: CognitionSettings,
+}
+
+impl Gemini {
+    /// The Gemini API, with the key from the keyring.
+    pub fn new(settings: CognitionSettings) -> Self {
+        Self { endpoint: GEMINI_URL.to_string(), api_key: None, settings }
+    }
+
+    /// Another endpoint serving the same API, e.g. a proxy.
+    pub fn with_endpoint(mut self, endpoint: &str, api_key: &str) -> Self {
+        self.endpoint = endpoint.to_string();
+        self.api_key = Some(api_key.to_string());
+        self
+    }
+
+    /// Whether screenshots are sent at all.
+    fn sends_images(&self) -> bool {
+        self.settings.image_token_budget > 0
+    }
+
+    /// The size a screenshot of `width` by `height` is sent at.
+    fn sent_size(&self, width: u32, height: u32) -> (u32, u32) {
+        size_within_budget(width, height, self.settings.image_token_budget)
+    }
+
+    /// Sends a prompt with images, each scaled down to the token budget, and returns the
+    /// response text. Nothing is redacted here; everything goes through `send_redacted`.
+    async fn ask_with_images(&self, prompt: &str, images: &[RgbaImage]) -> Result<String, CognitionError> {
+        let api_key = match &self.api_key {
+            Some(api_key) => api_key.clone(),
+            None => get_api_key()?,
+        };
+
+        let mut parts = Vec::new();
+        if self.settings.image_token_budget > 0 {
+            for image in images {
+                parts.push(Part::InlineData(encode_image(image, &self.settings)?));
             }
         }
+        parts.push(Part::Text(prompt.to_string()));
+        let request_body = GeminiRequest { contents: vec![Content { parts }] };
+
+        let res = reqwest::Client::new()
+            .post(&self.endpoint)
+            .header("x-goog-api-key", api_key)
+            .json(&request_body)
+            .send()
+            .await?;
+
+        if !res.status().is_success() {
+            let status = res.status();
+            let error_text = res.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            return Err(CognitionError::ApiError(format!(
+                "HTTP {}: {}",
+                status, error_text
+            )));
+        }
+
+        let response: GeminiResponse = res.json().await.map_err(|e| {
+            CognitionError::ParseError(format!("Failed to parse JSON response: {}", e))
+        })?;
+
+        if let Some(error) = response.error {
+            return Err(CognitionError::ApiError(format!(
+                "Gemini API error: {} (code: {:?})",
+                error.message,
+                error.code
+            )));
+        }
+
+        if let Some(candidates) = response.candidates {
+            if let Some(candidate) = candidates.first() {
+                if let Some(part) = candidate.content.parts.first() {
+                    return Ok(part.text.clone());
+                }
+            }
+        }
+
+        Err(CognitionError::ParseError(
+            "No text content found in API response".to_string(),
+        ))
+    }
+}
+
+/// Sends a prompt to the Gemini API, with a screenshot and the words recognized on it if
+/// given, and returns the response. Secrets in the prompt and screenshot are redacted.
+pub async fn ask_gemini(
+    prompt: &str,
+    screen: Option<(&RgbaImage, &[OcrWord])>,
+    redactor: &Redactor,
+    gemini: &Gemini,
+) -> Result<String, CognitionError> {
+    send_redacted(prompt, screen, RedactionAudit::new("prompt"), redactor, gemini).await
+}
+
+/// Redacts the prompt and the screenshot, if screenshots are sent, adding to `audit`,
+/// records the audit and sends the request.
+async fn send_redacted(
+    prompt: &str,
+    screen: Option<(&RgbaImage, &[OcrWord])>,
+    mut audit: RedactionAudit,
+    redactor: &Redactor,
+    gemini: &Gemini,
+) -> Result<String, CognitionError> {
+    let prompt = redactor.redact_text(prompt, &mut audit);
+    let images: Vec<RgbaImage> = screen
+        .filter(|_| gemini.sends_images())
+        .map(|(image, words)| redactor.redact_image(image, words, &mut audit))
+        .into_iter()
+        .collect();
+    redactor.record(&audit);
+    gemini.ask_with_images(&prompt, &images).await
+}
+
+/// Tells the model what the attached screenshot is.
+fn screenshot_note(screen: (u32, u32), sent: (u32, u32)) -> String {
+    if screen == sent {
+        format!("The attached image shows the {}x{} screen. ", screen.0, screen.1)
+    } else {
+        format!("The attached image shows the {}x{} screen scaled to {}x{}. ", screen.0, screen.1, sent.0, sent.1)
+    }
+}
+
+/// Scales the position of a suggested click from pixels of the screenshot as sent to
+/// screen pixels.
+fn click_to_screen(call: &mut ToolCall, screen: (u32, u32), sent: (u32, u32)) {
+    if call.tool != "click" {
+        return;
+    }
+    for (axis, scale) in [("x", screen.0 as f64 / sent.0 as f64), ("y", screen.1 as f64 / sent.1 as f64)] {
+        if let Some(value) = call.params.get(axis).and_then(Value::as_f64) {
+            call.params[axis] = json!((value * scale).round());
+        }
+    }
+}
+
+/// Asks Gemini for one action that could bring a missing click target back into view,
+/// e.g. scrolling or closing a dialog. Returns `None` if it suggests nothing. `words` are
+/// the words recognized on `screenshot`, if there is one; the prompt and the screenshot
+/// are redacted before sending. The model places clicks on the screenshot as sent, and
+/// they are scaled back to screen pixels.
+pub async fn suggest_recovery(
+    target_description: &str,
+    words: &[OcrWord],
+    screenshot: Option<&RgbaImage>,
+    tried: &[String],
+    redactor: &Redactor,
+    gemini: &Gemini,
+) -> Result<Option<ToolCall>, CognitionError> {
+    let mut audit = RedactionAudit::new("recovery");
+    let target_description = redactor.redact_text(target_description, &mut audit);
+    let screen_text = perception::words_to_text(&redactor.redact_words(words, &mut audit));
+    let screenshot = screenshot.filter(|_| gemini.sends_images());
+    let sizes = screenshot.map(|image| (image.dimensions(), gemini.sent_size(image.width(), image.height())));
+    let note = match sizes {
+        Some((screen, sent)) => {
+            format!("{}Give click coordinates in pixels of the attached image. ", screenshot_note(screen, sent))
+        }
+        None => String::new(),
+    };
+    let prompt = format!(
+        "A desktop automation macro wants to click: {}.\n\
+         The target is not visible. {}Text currently on screen: \"{}\".\n\
+         Actions already tried: {}.\n\
+         Reply with a single JSON object and nothing else: either one tool call such as \
+         {{\"tool\": \"scroll\", \"params\": {{\"dx\": 0, \"dy\": -3}}}}, \
+         {{\"tool\": \"press_key\", \"params\": {{\"key\": \"Escape\"}}}} or \
+         {{\"tool\": \"click\", \"params\": {{\"x\": 100, \"y\": 200}}}}, \
+         or {{\"tool\": \"none\"}} if nothing would help.",
+        target_description,
+        note,
+        screen_text,
+        if tried.is_empty() { "none".to_string() } else { tried.join(", ") }
+    );
+    let screen = screenshot.map(|image| (image, words));
+    let response = send_redacted(&prompt, screen, audit, redactor, gemini).await?;
+    let mut call = parse_recovery(&response)?;
+    if let (Some(call), Some((screen, sent))) = (call.as_mut(), sizes) {
+        click_to_screen(call, screen, sent);
+    }
+    Ok(call)
+}
+
+/// The JSON in a model reply, without the Markdown code fence it is often wrapped in.
+fn strip_code_fence(response: &str) -> &str {
+    response
+        .trim()
+        .trim_start_matches("```json")
+        .trim_start_matches("```")
+        .trim_end_matches("```")
+        .trim()
+}
+
+fn parse_recovery(response: &str) -> Result<Option<ToolCall>, CognitionError> {
+    let json = strip_code_fence(response);
+    let call: ToolCall = serde_json::from_str(json)
+        .map_err(|e| CognitionError::ParseError(format!("Invalid recovery action '{}': {}", json, e)))?;
+    Ok((call.tool != "none").then_some(call))
+}
+
+/// Asks Gemini for the tool calls that accomplish `task` on the screen described by
+/// `ui_map`. Elements are referred to by id, as `click_element` and `set_element_text`
+/// calls. The focused window, if known, lets the task refer to e.g. "the current document".
+/// A screenshot, with the words recognized on it, shows the model what the map cannot
+/// describe. The task, map, window and screenshot are redacted before sending.
+pub async fn generate_plan(
+    task: &str,
+    ui_map: &UiMap,
+    window: Option<&WindowContext>,
+    screen: Option<(&RgbaImage, &[OcrWord])>,
+    redactor: &Redactor,
+    gemini: &Gemini,
+) -> Result<Vec<ToolCall>, CognitionError> {
+    let mut audit = RedactionAudit::new("plan");
+    let ui_map = redactor.redact_ui_map(ui_map, &mut audit);
+    let window = window.map(|window| redactor.redact_window(window, &mut audit));
+    let screen = screen.filter(|_| gemini.sends_images());
+    let note = match screen {
+        Some((image, _)) => format!(
+            "{}Coordinates are screen pixels, as in the element list. ",
+            screenshot_note(image.dimensions(), gemini.sent_size(image.width(), image.height()))
+        ),
+        None => String::new(),
+    };
+    let prompt = format!(
+        "You control a desktop computer. Task: {}.\n\
+         {}{}{}\n\
+         Reply with a JSON array of tool calls and nothing else. Tools: \
+         click_element {{\"id\"}}, set_element_text {{\"id\", \"text\"}}, click {{\"x\", \"y\"}}, \
+         type_text {{\"text\"}}, press_key {{\"key\", \"modifiers\"}}, scroll {{\"dx\", \"dy\"}}, \
+         wait {{\"ms\"}}, wait_for_stable {{\"ms\"}}, focus_window {{\"window\"}}, \
+         move_window {{\"window\", \"x\", \"y\", \"width\", \"height\"}}, minimize_window {{\"window\"}}, \
+         close_window {{\"window\"}}, wait_for_window {{\"window\"}}, play_macro {{\"name\"}}; \
+         \"window\" is part of a window title or class. \
+         Prefer the element tools with an id from the list above, e.g. \
+         [{{\"tool\": \"set_element_text\", \"params\": {{\"id\": 3, \"text\": \"Acme Corp\"}}}}, \
+         {{\"tool\": \"click_element\", \"params\": {{\"id\": 6}}}}].",
+        task,
+        window_prompt(window.as_ref()),
+        note,
+        ui_map.to_prompt()
+    );
+    let response = send_redacted(&prompt, screen, audit, redactor, gemini).await?;
+    parse_plan(&response)
+}
+
+/// The prompt line about the focused window, or nothing when it is unknown.
+fn window_prompt(window: Option<&WindowContext>) -> String {
+    match window {
+        Some(window) => format!(
+            "Focused window: {}; \"the current document\" or \"this window\" means this one.\n",
+            window.describe()
+        ),
+        None => String::new(),
+    }
+}
+
+fn parse_plan(response: &str) -> Result<Vec<ToolCall>, CognitionError> {
+    let json = strip_code_fence(response);
+    serde_json::from_str(json).map_err(|e| CognitionError::ParseError(format!("Invalid plan '{}': {}", json, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::perception::{FixtureOcr, OcrEngine};
+    use crate::modules::redaction::RedactionSettings;
+    use image::Rgba;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        path::Path,
+        sync::mpsc,
+    };
+
+    fn fixture_path(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+    }
+
+    /// Answers one request with a recorded Gemini response and hands back the request's
+    /// headers and body.
+    fn mock_server(response: &str) -> (String, mpsc::Receiver<(String, String)>) {
+        let response = std::fs::read_to_string(fixture_path(&format!("gemini/{}", response))).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1beta/models/gemini-2.5-flash:generateContent", listener.local_addr().unwrap());
+        let (sender, requests) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 8192];
+            let (head, body_start) = loop {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                    break (String::from_utf8_lossy(&request[..end]).into_owned(), end + 4);
+                }
+            };
+            let length: usize = head
+                .lines()
+                .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|value| value.trim().parse().unwrap()))
+                .unwrap();
+            while request.len() < body_start + length {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                response.len(),
+                response
+            )
+            .unwrap();
+            sender.send((head, String::from_utf8_lossy(&request[body_start..]).into_owned())).unwrap();
+        });
+        (endpoint, requests)
+    }
+
+    /// The parts of the request the mock server received.
+    fn request_parts(requests: &mpsc::Receiver<(String, String)>) -> Vec<Part> {
+        let (head, body) = requests.recv().unwrap();
+        assert!(head.to_lowercase().contains("x-goog-api-key: test-key"), "{}", head);
+        let request: serde_json::Value = serde_json::from_str(&body).unwrap();
+        serde_json::from_value(request["contents"][0]["parts"].clone()).unwrap()
+    }
+
+    fn decode(data: &InlineData) -> RgbaImage {
+        let bytes = base64::engine::general_purpose::STANDARD.decode(&data.data).unwrap();
+        image::load_from_memory(&bytes).unwrap().to_rgba8()
     }
 
-    Err(CognitionError::ParseError(
-        "No text content found in API response".to_string(),
-    ))
+    #[test]
+    fn test_image_budget() {
+        assert_eq!(image_tokens(384, 300), 258);
+        assert_eq!(image_tokens(1920, 1080), 1548);
+        assert_eq!(size_within_budget(1920, 1080, 1032), (1536, 864));
+        assert_eq!(size_within_budget(1920, 1080, 258), (768, 432));
+        assert_eq!(size_within_budget(480, 220, 258), (480, 220));
+
+        assert_eq!(serde_json::to_value(Part::Text("Hi".to_string())).unwrap(), serde_json::json!({"text": "Hi"}));
+        let image = RgbaImage::from_pixel(1920, 1080, Rgba([30, 60, 90, 255]));
+        let data = encode_image(&image, &CognitionSettings::default()).unwrap();
+        assert_eq!(data.mime_type, "image/jpeg");
+        assert_eq!(decode(&data).dimensions(), (1536, 864));
+        assert_eq!(
+            serde_json::to_value(Part::InlineData(data.clone())).unwrap(),
+            serde_json::json!({"inline_data": {"mime_type": "image/jpeg", "data": data.data}})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_plan_with_screenshot() {
+        let image = image::open(fixture_path("invoice_dialog.png")).unwrap().to_rgba8();
+        let words = FixtureOcr::from_tsv(&fixture_path("invoice_dialog.tsv")).unwrap().recognize(&image, None).unwrap();
+        let map = UiMap::build(&image, &words);
+        // The customer's name counts as a secret here.
+        let settings = RedactionSettings { patterns: vec!["Acme Corp".to_string()], ..Default::default() };
+        let redactor = Redactor::new(&settings).unwrap();
+        let (endpoint, requests) = mock_server("plan_response.json");
+        let gemini = Gemini::new(CognitionSettings::default()).with_endpoint(&endpoint, "test-key");
+
+        let task = "Change the customer to Globex Inc and save";
+        let plan = generate_plan(task, &map, None, Some((&image, &words)), &redactor, &gemini).await.unwrap();
+        let steps: Vec<(&str, String)> = plan.iter().map(|call| (call.tool.as_str(), call.params.to_string())).collect();
+        assert_eq!(
+            steps,
+            [("set_element_text", r#"{"id":3,"text":"Globex Inc"}"#.to_string()), ("click_element", r#"{"id":6}"#.to_string())]
+        );
+
+        let parts = request_parts(&requests);
+        let [Part::InlineData(screenshot), Part::Text(prompt)] = &parts[..] else { panic!("{:?}", parts) };
+        assert!(prompt.contains("shows the 480x220 screen.") && prompt.contains("#3 text_field \"[redacted]\""), "{}", prompt);
+        assert!(!prompt.contains("\"Acme Corp\" ["), "{}", prompt);
+
+        // The dialog fits one tile, so it is sent at full size, with the secret blacked out.
+        assert_eq!(screenshot.mime_type, "image/jpeg");
+        let sent = decode(screenshot);
+        assert_eq!(sent.dimensions(), (480, 220));
+        let acme = words.iter().find(|word| word.text == "Acme").unwrap().bbox;
+        let (x, y) = acme.center();
+        assert!(sent.get_pixel(x as u32, y as u32).0[..3].iter().all(|&channel| channel < 40));
+    }
+
+    #[tokio::test]
+    async fn test_recovery_with_screenshot() {
+        let screen = RgbaImage::from_pixel(1920, 1080, Rgba([240, 240, 240, 255]));
+        let redactor = Redactor::new(&RedactionSettings::default()).unwrap();
+        let (endpoint, requests) = mock_server("recovery_response.json");
+        let settings = CognitionSettings { image_token_budget: 258, image_encoding: ImageEncoding::Png };
+        let gemini = Gemini::new(settings).with_endpoint(&endpoint, "test-key");
+
+        let call = suggest_recovery("Element with text 'Submit'", &[], Some(&screen), &[], &redactor, &gemini)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((call.tool.as_str(), call.params.to_string()), ("press_key", r#"{"key":"Escape"}"#.to_string()));
+
+        let parts = request_parts(&requests);
+        let [Part::InlineData(screenshot), Part::Text(prompt)] = &parts[..] else { panic!("{:?}", parts) };
+        assert!(prompt.contains("Element with text 'Submit'"), "{}", prompt);
+        assert!(prompt.contains("1920x1080 screen scaled to 768x432"), "{}", prompt);
+        assert_eq!(screenshot.mime_type, "image/png");
+        assert_eq!(decode(screenshot).dimensions(), (768, 432));
+    }
+
+    #[tokio::test]
+    async fn test_recovery_click_is_mapped_to_screen() {
+        let screen = RgbaImage::from_pixel(1920, 1080, Rgba([240, 240, 240, 255]));
+        let redactor = Redactor::new(&RedactionSettings::default()).unwrap();
+        let (endpoint, requests) = mock_server("recovery_click_response.json");
+        let settings = CognitionSettings { image_token_budget: 258, image_encoding: ImageEncoding::Png };
+        let gemini = Gemini::new(settings).with_endpoint(&endpoint, "test-key");
+
+        let call = suggest_recovery("Element with text 'Submit'", &[], Some(&screen), &[], &redactor, &gemini)
+            .await
+            .unwrap()
+            .unwrap();
+        request_parts(&requests);
+        // (400, 300) on the 768x432 image is (1000, 750) on the screen.
+        assert_eq!(call.tool, "click");
+        assert_eq!((call.params["x"].as_f64(), call.params["y"].as_f64()), (Some(1000.0), Some(750.0)));
+        assert!(call.resolve().is_ok());
+    }
 }
@@ -0,0 +1,321 @@
+// Dry-run input backend
+// Accepts the same input as the live backend but only writes a human-readable
+// transcript, e.g. "t=1.20s click left at (812, 440)" or "type 'invoice'".
+
+use crate::modules::accessibility::{AccessibleAction, AccessibleId};
+use crate::modules::io_controller::InputBackend;
+use crate::modules::macro_engine::{self, Macro};
+use crate::modules::perception::ScreenWait;
+use crate::modules::window::{WindowAction, WindowContext};
+use rdev::{Button, EventType, Key};
+use std::time::Duration;
+
+/// A press whose line is rewritten to a click/keystroke if the matching release follows.
+enum PendingPress {
+    Button { line: usize, button: Button },
+    Key { line: usize, key: Key },
+}
+
+#[derive(Default)]
+pub struct DryRunInput {
+    elapsed: Duration,
+    cursor: Option<(f64, f64)>,
+    /// Cursor position last mentioned in the transcript.
+    reported_cursor: Option<(f64, f64)>,
+    pending: Option<PendingPress>,
+    lines: Vec<String>,
+    /// Focused window to report to recorded window checks; unknown by default.
+    active_window: Option<WindowContext>,
+}
+
+fn button_name(button: &Button) -> String {
+    match button {
+        Button::Left => "left".to_string(),
+        Button::Right => "right".to_string(),
+        Button::Middle => "middle".to_string(),
+        Button::Unknown(code) => format!("button {}", code),
+    }
+}
+
+impl DryRunInput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pretends `window` is focused, so recorded window checks run.
+    pub fn with_active_window(mut self, window: WindowContext) -> Self {
+        self.active_window = Some(window);
+        self
+    }
+
+    fn position(&self) -> String {
+        match self.cursor {
+            Some((x, y)) => format!("({}, {})", x.round(), y.round()),
+            None => "the current position".to_string(),
+        }
+    }
+
+    fn push(&mut self, action: String) -> usize {
+        self.lines.push(format!("t={:.2}s {}", self.elapsed.as_secs_f64(), action));
+        self.lines.len() - 1
+    }
+
+    /// Mentions mouse movement that no click or scroll has reported yet.
+    fn flush_movement(&mut self) {
+        if self.cursor != self.reported_cursor {
+            let position = self.position();
+            self.push(format!("move to {}", position));
+            self.reported_cursor = self.cursor;
+        }
+    }
+
+    fn record(&mut self, event_type: &EventType) {
+        match event_type {
+            EventType::MouseMove { x, y } => {
+                // Moving with a button held turns the click into a drag.
+                if matches!(self.pending, Some(PendingPress::Button { .. })) {
+                    self.pending = None;
+                }
+                self.cursor = Some((*x, *y));
+                return;
+            }
+            EventType::ButtonRelease(button) => {
+                if let Some(PendingPress::Button { line, button: pressed }) = &self.pending {
+                    if pressed == button {
+                        self.lines[*line] = self.lines[*line].replacen("press", "click", 1);
+                        self.pending = None;
+                        return;
+                    }
+                }
+            }
+            EventType::KeyRelease(key) => {
+                if let Some(PendingPress::Key { line, key: pressed }) = &self.pending {
+                    if pressed == key {
+                        self.lines[*line] = self.lines[*line].replacen("press key", "key", 1);
+                        self.pending = None;
+                        return;
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        self.pending = None;
+        match event_type {
+            EventType::ButtonPress(button) => {
+                let action = format!("press {} at {}", button_name(button), self.position());
+                let line = self.push(action);
+                self.reported_cursor = self.cursor;
+                self.pending = Some(PendingPress::Button { line, button: *button });
+            }
+            EventType::ButtonRelease(button) => {
+                let action = format!("release {} at {}", button_name(button), self.position());
+                self.push(action);
+                self.reported_cursor = self.cursor;
+            }
+            EventType::Wheel { delta_x, delta_y } => {
+                let action = format!("scroll ({}, {}) at {}", delta_x, delta_y, self.position());
+                self.push(action);
+                self.reported_cursor = self.cursor;
+            }
+            EventType::KeyPress(key) => {
+                self.flush_movement();
+                let line = self.push(format!("press key {:?}", key));
+                self.pending = Some(PendingPress::Key { line, key: *key });
+            }
+            EventType::KeyRelease(key) => {
+                self.flush_movement();
+                self.push(format!("release key {:?}", key));
+            }
+            EventType::MouseMove { .. } => {}
+        }
+    }
+
+    /// Takes the transcript written so far, including any trailing mouse movement.
+    pub fn take_transcript(&mut self) -> Vec<String> {
+        self.flush_movement();
+        self.pending = None;
+        std::mem::take(&mut self.lines)
+    }
+}
+
+impl InputBackend for DryRunInput {
+    fn send_event(&mut self, event_type: &EventType) -> Result<(), String> {
+        self.record(event_type);
+        Ok(())
+    }
+
+    fn type_text(&mut self, text: &str) -> Result<(), String> {
+        self.flush_movement();
+        self.pending = None;
+        self.push(format!("type '{}'", text.replace('\'', "\\'")));
+        Ok(())
+    }
+
+    fn type_secret(&mut self, _macro_name: &str, secret_id: &str) -> Result<(), String> {
+        // The keyring is never read, so a dry run cannot leak the secret.
+        self.flush_movement();
+        self.pending = None;
+        self.push(format!("type secret '{}'", secret_id));
+        Ok(())
+    }
+
+    fn wait(&mut self, duration: Duration) {
+        self.elapsed += duration;
+    }
+
+    fn wait_for_screen(&mut self, wait: &ScreenWait) -> Result<(), String> {
+        // How long the screen takes is unknown, so the clock does not advance.
+        self.flush_movement();
+        self.pending = None;
+        let action = match wait {
+            ScreenWait::Stable { stable_ms, timeout_ms } => {
+                format!("wait until the screen is stable for {}ms (timeout {}ms)", stable_ms, timeout_ms)
+            }
+            ScreenWait::RegionChanges { rect, timeout_ms } => format!(
+                "wait until ({}, {}, {}x{}) changes (timeout {}ms)",
+                rect.x, rect.y, rect.width, rect.height, timeout_ms
+            ),
+        };
+        self.push(action);
+        Ok(())
+    }
+
+    fn find_image(&mut self, template: &std::path::Path, threshold: f32) -> Result<Option<(f64, f64)>, String> {
+        self.flush_movement();
+        self.pending = None;
+        self.push(format!("find image '{}' (threshold {:.2})", template.display(), threshold));
+        // Where it would be found is unknown.
+        Ok(None)
+    }
+
+    fn accessible_action(&mut self, target: &AccessibleId, action: &AccessibleAction) -> Result<(), String> {
+        self.flush_movement();
+        self.pending = None;
+        let action = match action {
+            AccessibleAction::Click => "click".to_string(),
+            AccessibleAction::SetText { text } => format!("set text '{}' of", text.replace('\'', "\\'")),
+            AccessibleAction::Focus => "focus".to_string(),
+        };
+        self.push(format!("{} {} via accessibility", action, target));
+        Ok(())
+    }
+
+    fn window_action(&mut self, action: &WindowAction) -> Result<(), String> {
+        self.flush_movement();
+        self.pending = None;
+        self.push(match action {
+            WindowAction::Focus { window } => format!("focus window '{}'", window),
+            WindowAction::MoveResize { window, rect } => format!(
+                "move window '{}' to ({}, {}) {}x{}",
+                window, rect.x, rect.y, rect.width, rect.height
+            ),
+            WindowAction::Minimize { window } => format!("minimize window '{}'", window),
+            WindowAction::Close { window } => format!("close window '{}'", window),
+            WindowAction::WaitFor { window, timeout_ms } => {
+                format!("wait for window '{}' (timeout {}ms)", window, timeout_ms)
+            }
+        });
+        Ok(())
+    }
+
+    fn active_window(&mut self) -> Result<Option<WindowContext>, String> {
+        Ok(self.active_window.clone())
+    }
+}
+
+/// Simulates a macro and returns what it would do, without sending any input.
+pub fn dry_run_macro(macro_data: &Macro) -> Vec<String> {
+    let mut backend = DryRunInput::new();
+    if let Err(e) = macro_engine::play_macro_with(macro_data, &mut backend) {
+        backend.lines.push(format!("error: {}", e));
+    }
+    backend.take_transcript()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::macro_engine::{MacroEvent, TimedEvent};
+    use crate::modules::macro_secrets::SecretRef;
+
+    fn step(event: MacroEvent, millis: u64) -> TimedEvent {
+        TimedEvent { event, time_since_previous: Duration::from_millis(millis), text: None, target: None, window: None }
+    }
+
+    #[test]
+    fn test_macro_transcript() {
+        let input = |event_type, millis| step(MacroEvent::Input(event_type), millis);
+        let macro_data = Macro {
+            name: "invoice".to_string(),
+            events: vec![
+                input(EventType::MouseMove { x: 500.0, y: 300.0 }, 0),
+                input(EventType::MouseMove { x: 812.2, y: 439.6 }, 1000),
+                input(EventType::ButtonPress(Button::Left), 200),
+                input(EventType::ButtonRelease(Button::Left), 80),
+                step(MacroEvent::TypeText("invoice".to_string()), 500),
+                step(MacroEvent::Secret(SecretRef { id: "secret-1".to_string() }), 100),
+                input(EventType::KeyPress(Key::ControlLeft), 100),
+                input(EventType::KeyPress(Key::KeyS), 50),
+                input(EventType::KeyRelease(Key::KeyS), 50),
+                input(EventType::KeyRelease(Key::ControlLeft), 20),
+                input(EventType::MouseMove { x: 10.0, y: 10.0 }, 100),
+            ],
+        };
+
+        assert_eq!(
+            dry_run_macro(&macro_data),
+            vec![
+                "t=1.20s click left at (812, 440)",
+                "t=1.78s type 'invoice'",
+                "t=1.88s type secret 'secret-1'",
+                "t=1.98s press key ControlLeft",
+                "t=2.03s key KeyS",
+                "t=2.10s release key ControlLeft",
+                "t=2.20s move to (10, 10)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_refuses_to_play_into_another_app() {
+        let gedit = WindowContext { title: "notes.txt - gedit".to_string(), class: "Gedit".to_string(), pid: Some(4242) };
+        let macro_data = Macro {
+            name: "save".to_string(),
+            events: vec![
+                TimedEvent { window: Some(gedit.clone()), ..step(MacroEvent::TypeText("hello".to_string()), 0) },
+                step(MacroEvent::Input(EventType::KeyPress(Key::Return)), 100),
+            ],
+        };
+
+        // Another document in the same app is fine.
+        let renamed = WindowContext { title: "todo.txt - gedit".to_string(), pid: Some(5151), ..gedit };
+        let mut backend = DryRunInput::new().with_active_window(renamed);
+        macro_engine::play_macro_with(&macro_data, &mut backend).unwrap();
+        assert_eq!(backend.take_transcript(), vec!["t=0.00s type 'hello'", "t=0.10s press key Return"]);
+
+        let firefox = WindowContext { title: "Mozilla Firefox".to_string(), class: "firefox".to_string(), pid: None };
+        let mut backend = DryRunInput::new().with_active_window(firefox);
+        let err = macro_engine::play_macro_with(&macro_data, &mut backend).unwrap_err();
+        assert!(matches!(err, macro_engine::MacroError::WrongWindow { step: 1, .. }), "{}", err);
+        assert!(backend.take_transcript().is_empty());
+        assert_eq!(backend.elapsed, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_drag_is_not_reported_as_click() {
+        let mut backend = DryRunInput::new();
+        for event_type in [
+            EventType::MouseMove { x: 0.0, y: 0.0 },
+            EventType::ButtonPress(Button::Left),
+            EventType::MouseMove { x: 50.0, y: 0.0 },
+            EventType::ButtonRelease(Button::Left),
+        ] {
+            backend.send_event(&event_type).unwrap();
+        }
+        assert_eq!(
+            backend.take_transcript(),
+            vec!["t=0.00s press left at (0, 0)", "t=0.00s release left at (50, 0)"]
+        );
+    }
+}
@@ -1,4 +1,9 @@
+use enigo::{Enigo, Keyboard, Settings};
 use rdev::{Button, EventType, Key};
+use crate::modules::accessibility::{AccessibleAction, AccessibleId};
+use crate::modules::macro_secrets;
+use crate::modules::perception::{self, FindImageOptions, Perception, ScreenWait};
+use crate::modules::window::{WindowAction, WindowContext, WindowManager};
 use std::sync::Mutex;
 use std::{thread, time};
 use lazy_static::lazy_static;
@@ -7,6 +12,34 @@ use lazy_static::lazy_static;
 // which is not thread-safe, is only called by one thread at a time.
 lazy_static! {
     static ref EVENT_LOCK: Mutex<()> = Mutex::new(());
+    static ref INPUT_OBSERVER: Mutex<Option<InputObserver>> = Mutex::new(None);
+}
+
+/// Synthetic input as reported to the input observer. Typed text is only counted, so
+/// observers never see it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntheticInput {
+    Event(EventType),
+    Text { chars: usize },
+}
+
+pub type InputObserver = Box<dyn Fn(&SyntheticInput) + Send>;
+
+/// Reports all synthetic input sent from now on to `observer`, e.g. to mark it in a
+/// screen recording, replacing any previous observer. `None` removes it.
+pub fn set_input_observer(observer: Option<InputObserver>) {
+    match INPUT_OBSERVER.lock() {
+        Ok(mut current) => *current = observer,
+        Err(e) => log::error!("Failed to set the input observer: {}", e),
+    }
+}
+
+fn notify_observer(input: SyntheticInput) {
+    if let Ok(observer) = INPUT_OBSERVER.lock() {
+        if let Some(observer) = observer.as_ref() {
+            observer(&input);
+        }
+    }
 }
 
 /// A centralized function to send system events.
@@ -21,9 +54,79 @@ lazy_static! {
 pub fn send_event(event_type: &EventType) -> Result<(), String> {
     let _lock = EVENT_LOCK.lock().map_err(|e| format!("Failed to acquire event lock: {}", e))?;
     rdev::simulate(event_type).map_err(|e| format!("Failed to simulate event: {:?}", e))?;
+    notify_observer(SyntheticInput::Event(*event_type));
     Ok(())
 }
 
+/// Destination for synthetic input. Macro playback and plan execution send everything
+/// through a backend so they can be simulated instead of touching the desktop.
+pub trait InputBackend {
+    fn send_event(&mut self, event_type: &EventType) -> Result<(), String>;
+    fn type_text(&mut self, text: &str) -> Result<(), String>;
+    /// Types a secret stored in the keyring for the given macro.
+    fn type_secret(&mut self, macro_name: &str, secret_id: &str) -> Result<(), String>;
+    fn wait(&mut self, duration: time::Duration);
+    /// Blocks until the screen condition is met or its timeout passes.
+    fn wait_for_screen(&mut self, wait: &ScreenWait) -> Result<(), String>;
+    /// Finds the template image on screen and returns its center, or `None` when the
+    /// backend cannot see the screen.
+    fn find_image(&mut self, template: &std::path::Path, threshold: f32) -> Result<Option<(f64, f64)>, String>;
+    /// Acts on an accessibility object directly instead of through mouse and keyboard.
+    fn accessible_action(&mut self, target: &AccessibleId, action: &AccessibleAction) -> Result<(), String>;
+    /// Focuses, arranges, closes or waits for a window through the window manager.
+    fn window_action(&mut self, action: &WindowAction) -> Result<(), String>;
+    /// The focused window, or `None` when the backend cannot tell.
+    fn active_window(&mut self) -> Result<Option<WindowContext>, String>;
+}
+
+/// Sends input to the real desktop.
+pub struct LiveInput;
+
+impl InputBackend for LiveInput {
+    fn send_event(&mut self, event_type: &EventType) -> Result<(), String> {
+        send_event(event_type)
+    }
+
+    fn type_text(&mut self, text: &str) -> Result<(), String> {
+        type_text(text)
+    }
+
+    fn type_secret(&mut self, macro_name: &str, secret_id: &str) -> Result<(), String> {
+        let value = macro_secrets::load_secret(macro_name, secret_id).map_err(|e| e.to_string())?;
+        type_text(&value)
+    }
+
+    fn wait(&mut self, duration: time::Duration) {
+        thread::sleep(duration);
+    }
+
+    fn wait_for_screen(&mut self, wait: &ScreenWait) -> Result<(), String> {
+        Perception::new().wait_for(wait).map_err(|e| e.to_string())
+    }
+
+    fn find_image(&mut self, template: &std::path::Path, threshold: f32) -> Result<Option<(f64, f64)>, String> {
+        let image = perception::load_image(template).map_err(|e| e.to_string())?;
+        let options = FindImageOptions { threshold, ..FindImageOptions::default() };
+        let found = Perception::new().find_image(&image, &options).map_err(|e| e.to_string())?;
+        match found.first() {
+            Some(found) => Ok(Some(found.center)),
+            None => Err(format!("{} is not on screen", template.display())),
+        }
+    }
+
+    fn accessible_action(&mut self, target: &AccessibleId, action: &AccessibleAction) -> Result<(), String> {
+        Perception::new().perform_accessible_action(target, action).map_err(|e| e.to_string())
+    }
+
+    fn window_action(&mut self, action: &WindowAction) -> Result<(), String> {
+        WindowManager::connect().and_then(|manager| manager.perform(action)).map_err(|e| e.to_string())
+    }
+
+    fn active_window(&mut self) -> Result<Option<WindowContext>, String> {
+        Perception::new().active_window().map_err(|e| e.to_string())
+    }
+}
+
 // --- Helper Functions for Common Actions ---
 
 /// Moves the mouse to the specified screen coordinates.
@@ -57,6 +160,18 @@ pub fn type_string(text: &str) -> Result<(), String> {
     Ok(())
 }
 
+/// Types arbitrary Unicode text independent of the active keyboard layout.
+pub fn type_text(text: &str) -> Result<(), String> {
+    let _lock = EVENT_LOCK.lock().map_err(|e| format!("Failed to acquire event lock: {}", e))?;
+    let mut enigo = Enigo::new(&Settings::default())
+        .map_err(|e| format!("Failed to connect to the input backend: {}", e))?;
+    enigo
+        .text(text)
+        .map_err(|e| format!("Failed to type text: {}", e))?;
+    notify_observer(SyntheticInput::Text { chars: text.chars().count() });
+    Ok(())
+}
+
 // --- Utility Functions ---
 
 /// Converts a string representation of a mouse button to a `rdev::Button`.
@@ -1,5 +1,18 @@
 // Placeholder module for Knowledge
-// This module will handle logging, memory, and knowledge base operations
+// This module will handle logging, memory, and knowledge base operations. So far it keeps
+// the trajectory log: one entry per task execution, with what was asked, how it ended and
+// the screen recording of it, if one was made.
+
+use crate::modules::persistence::{self, PersistenceError};
+use crate::modules::screen_recording::ScreenRecording;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tauri::Manager;
+use thiserror::Error;
+
+/// Trajectory entries kept; older ones are removed with their recordings.
+pub const MAX_TRAJECTORIES: usize = 200;
 
 pub struct Knowledge;
 
@@ -9,3 +22,112 @@ impl Knowledge {
     }
 }
 
+#[derive(Error, Debug)]
+pub enum KnowledgeError {
+    #[error("File system error: {0}")]
+    FileSystem(String),
+}
+
+/// One task execution.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TrajectoryEntry {
+    /// Also the name of the entry's folder, which holds its recording.
+    pub id: String,
+    pub task: String,
+    pub started_at: NaiveDateTime,
+    pub finished_at: NaiveDateTime,
+    pub success: bool,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recording: Option<ScreenRecording>,
+}
+
+/// The trajectory log's folder in the app config, `nyx-agent/trajectories`.
+pub fn trajectory_dir(app_handle: &tauri::AppHandle) -> Result<PathBuf, KnowledgeError> {
+    let config_dir = app_handle
+        .path()
+        .app_config_dir()
+        .map_err(|e| KnowledgeError::FileSystem(format!("No config directory: {}", e)))?;
+    Ok(config_dir.join("nyx-agent/trajectories"))
+}
+
+/// Writes an entry to `<dir>/<id>.json`, creating `dir` if needed.
+pub fn save_trajectory(dir: &Path, entry: &TrajectoryEntry) -> Result<(), KnowledgeError> {
+    std::fs::create_dir_all(dir).map_err(|e| KnowledgeError::FileSystem(e.to_string()))?;
+    persistence::write_json(&dir.join(format!("{}.json", entry.id)), entry)
+        .map_err(|e| KnowledgeError::FileSystem(e.to_string()))
+}
+
+/// Reads every entry in `dir`, newest first. Unreadable entries are skipped.
+pub fn list_trajectories(dir: &Path) -> Result<Vec<TrajectoryEntry>, KnowledgeError> {
+    let files = match std::fs::read_dir(dir) {
+        Ok(files) => files,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(KnowledgeError::FileSystem(e.to_string())),
+    };
+    let mut entries: Vec<TrajectoryEntry> = files
+        .filter_map(Result::ok)
+        .map(|file| file.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+        .filter_map(|path| match persistence::read_json::<TrajectoryEntry>(&path) {
+            Ok(loaded) => Some(loaded.value),
+            Err(PersistenceError::NotFound(_)) => None,
+            Err(e) => {
+                log::warn!("Skipping unreadable trajectory entry: {}", e);
+                None
+            }
+        })
+        .collect();
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.started_at));
+    Ok(entries)
+}
+
+/// Removes all but the `keep` newest entries in `dir`, with their recordings. Returns how
+/// many were removed.
+pub fn prune_trajectories(dir: &Path, keep: usize) -> Result<usize, KnowledgeError> {
+    let old = list_trajectories(dir)?.into_iter().skip(keep);
+    let mut removed = 0;
+    for entry in old {
+        std::fs::remove_file(dir.join(format!("{}.json", entry.id)))
+            .map_err(|e| KnowledgeError::FileSystem(e.to_string()))?;
+        match std::fs::remove_dir_all(dir.join(&entry.id)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                log::warn!("Could not remove the recording of trajectory entry {}: {}", entry.id, e)
+            }
+            _ => {}
+        }
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_old_trajectories_are_pruned() {
+        let dir = std::env::temp_dir().join(format!("nyx-trajectories-{}", std::process::id()));
+        for minute in 0..4 {
+            let started_at = chrono::NaiveDate::from_ymd_opt(2026, 10, 19).unwrap().and_hms_opt(9, minute, 0).unwrap();
+            let entry = TrajectoryEntry {
+                id: format!("task-{}", minute),
+                task: "Open the invoices".to_string(),
+                started_at,
+                finished_at: started_at,
+                success: true,
+                message: String::new(),
+                recording: None,
+            };
+            save_trajectory(&dir, &entry).unwrap();
+            std::fs::create_dir_all(dir.join(&entry.id).join("frames")).unwrap();
+        }
+
+        assert_eq!(prune_trajectories(&dir, 2).unwrap(), 2);
+        let ids: Vec<String> = list_trajectories(&dir).unwrap().into_iter().map(|entry| entry.id).collect();
+        assert_eq!(ids, ["task-3", "task-2"]);
+        assert!(dir.join("task-2").exists());
+        assert!(!dir.join("task-1").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
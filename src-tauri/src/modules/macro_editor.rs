@@ -0,0 +1,515 @@
+// Macro editor backend
+// Keeps loaded macros in edit sessions with an undo stack. Every edit is checked so it
+// cannot leave a key or mouse button pressed without a matching release.
+
+use crate::modules::macro_engine::{self, Macro, MacroError, MacroEvent, TimedEvent};
+use crate::modules::macro_secrets::{self, secret_ids, SecretError, SecretRef};
+use rdev::{Button, EventType, Key};
+use std::{collections::HashMap, time::Duration};
+use thiserror::Error;
+
+/// Maximum number of undo snapshots kept per session.
+const MAX_UNDO: usize = 100;
+
+#[derive(Error, Debug)]
+pub enum EditorError {
+    #[error("Macro '{0}' is not open for editing")]
+    NotOpen(String),
+    #[error("Macro '{0}' is already open for editing")]
+    AlreadyOpen(String),
+    #[error("Index {index} is out of range for a macro with {len} events")]
+    IndexOutOfRange { index: usize, len: usize },
+    #[error("Edit rejected: {0}")]
+    Unbalanced(String),
+    #[error("Event {0} is not a mouse button press")]
+    NotAClick(usize),
+    #[error("Invalid delay: {0}")]
+    InvalidDelay(f64),
+    #[error("Nothing to undo")]
+    NothingToUndo,
+    #[error("Macro error: {0}")]
+    Macro(#[from] MacroError),
+    #[error("Secret error: {0}")]
+    Secret(#[from] SecretError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pressable {
+    Key(Key),
+    Button(Button),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum IssueKind {
+    PressedWhileHeld,
+    ReleasedWithoutPress,
+    NeverReleased,
+}
+
+/// A press/release problem. `index` is left out of comparisons, since edits shift it.
+#[derive(Debug, Clone, Copy)]
+struct BalanceIssue {
+    index: Option<usize>,
+    pressable: Pressable,
+    kind: IssueKind,
+}
+
+impl std::fmt::Display for BalanceIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.kind, self.index) {
+            (IssueKind::PressedWhileHeld, Some(index)) => {
+                write!(f, "event {}: {:?} pressed while already held", index, self.pressable)
+            }
+            (IssueKind::ReleasedWithoutPress, Some(index)) => {
+                write!(f, "event {}: {:?} released without a press", index, self.pressable)
+            }
+            _ => write!(f, "{:?} is never released", self.pressable),
+        }
+    }
+}
+
+/// Describes every press without a release, release without a press, and press that is
+/// still held at the end of the macro.
+pub fn balance_issues(events: &[TimedEvent]) -> Vec<String> {
+    find_balance_issues(events).iter().map(ToString::to_string).collect()
+}
+
+fn find_balance_issues(events: &[TimedEvent]) -> Vec<BalanceIssue> {
+    let mut issues = Vec::new();
+    let mut held: Vec<Pressable> = Vec::new();
+
+    for (index, timed_event) in events.iter().enumerate() {
+        let (pressable, is_press) = match timed_event.event.as_input() {
+            Some(EventType::KeyPress(key)) => (Pressable::Key(*key), true),
+            Some(EventType::KeyRelease(key)) => (Pressable::Key(*key), false),
+            Some(EventType::ButtonPress(button)) => (Pressable::Button(*button), true),
+            Some(EventType::ButtonRelease(button)) => (Pressable::Button(*button), false),
+            _ => continue,
+        };
+        let is_held = held.contains(&pressable);
+        let index = Some(index);
+        match (is_press, is_held) {
+            (true, true) => issues.push(BalanceIssue { index, pressable, kind: IssueKind::PressedWhileHeld }),
+            (true, false) => held.push(pressable),
+            (false, true) => held.retain(|p| *p != pressable),
+            (false, false) => issues.push(BalanceIssue { index, pressable, kind: IssueKind::ReleasedWithoutPress }),
+        }
+    }
+    for pressable in held {
+        issues.push(BalanceIssue { index: None, pressable, kind: IssueKind::NeverReleased });
+    }
+    issues
+}
+
+/// Returns the first issue in `after` that has no counterpart in `before`.
+fn new_balance_issue(before: &[BalanceIssue], after: &[BalanceIssue]) -> Option<BalanceIssue> {
+    let mut unmatched = before.to_vec();
+    after.iter().copied().find(|issue| {
+        match unmatched.iter().position(|old| old.pressable == issue.pressable && old.kind == issue.kind) {
+            Some(position) => {
+                unmatched.remove(position);
+                false
+            }
+            None => true,
+        }
+    })
+}
+
+/// A macro being edited, with the snapshots needed to undo each change.
+pub struct EditSession {
+    pub macro_data: Macro,
+    undo_stack: Vec<Vec<TimedEvent>>,
+    dirty: bool,
+    /// Secrets brought in by concatenating or splitting: id here -> (macro, id) whose
+    /// keyring entry is copied when this macro is saved.
+    secret_sources: HashMap<String, (String, String)>,
+}
+
+impl EditSession {
+    pub fn new(macro_data: Macro) -> Self {
+        Self {
+            macro_data,
+            undo_stack: Vec::new(),
+            dirty: false,
+            secret_sources: HashMap::new(),
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    /// Replaces the event list if the change does not add press/release problems.
+    /// Recordings are often unbalanced to begin with (e.g. the click that stopped the
+    /// recording), so only newly introduced problems are rejected.
+    fn apply(&mut self, events: Vec<TimedEvent>) -> Result<(), EditorError> {
+        let before = find_balance_issues(&self.macro_data.events);
+        if let Some(issue) = new_balance_issue(&before, &find_balance_issues(&events)) {
+            return Err(EditorError::Unbalanced(issue.to_string()));
+        }
+
+        let previous = std::mem::replace(&mut self.macro_data.events, events);
+        self.undo_stack.push(previous);
+        if self.undo_stack.len() > MAX_UNDO {
+            self.undo_stack.remove(0);
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn check_index(&self, index: usize, allow_end: bool) -> Result<(), EditorError> {
+        let len = self.macro_data.events.len();
+        if index > len || (index == len && !allow_end) {
+            return Err(EditorError::IndexOutOfRange { index, len });
+        }
+        Ok(())
+    }
+
+    /// Deletes events in `start..end`.
+    pub fn delete_range(&mut self, start: usize, end: usize) -> Result<(), EditorError> {
+        self.check_index(end, true)?;
+        if start > end {
+            return Err(EditorError::IndexOutOfRange { index: start, len: end });
+        }
+        let mut events = self.macro_data.events.clone();
+        events.drain(start..end);
+        self.apply(events)
+    }
+
+    /// Inserts events before `index` (or at the end when `index == len`).
+    pub fn insert(&mut self, index: usize, new_events: Vec<TimedEvent>) -> Result<(), EditorError> {
+        self.check_index(index, true)?;
+        let mut events = self.macro_data.events.clone();
+        events.splice(index..index, new_events);
+        self.apply(events)
+    }
+
+    /// Changes the wait before the event at `index`.
+    pub fn set_delay(&mut self, index: usize, delay_secs: f64) -> Result<(), EditorError> {
+        self.check_index(index, false)?;
+        if !delay_secs.is_finite() || delay_secs < 0.0 {
+            return Err(EditorError::InvalidDelay(delay_secs));
+        }
+        let delay = Duration::try_from_secs_f64(delay_secs).map_err(|_| EditorError::InvalidDelay(delay_secs))?;
+        let mut events = self.macro_data.events.clone();
+        events[index].time_since_previous = delay;
+        self.apply(events)
+    }
+
+    /// Moves the click whose press is at `index` to `(x, y)` by updating the mouse move
+    /// that positions it, or inserting one if the press has none.
+    pub fn move_click_target(&mut self, index: usize, x: f64, y: f64) -> Result<(), EditorError> {
+        self.check_index(index, false)?;
+        if !matches!(self.macro_data.events[index].event.as_input(), Some(EventType::ButtonPress(_))) {
+            return Err(EditorError::NotAClick(index));
+        }
+
+        let mut events = self.macro_data.events.clone();
+        // The cursor position at the press is set by the last move since the previous click.
+        let positioning_move = events[..index]
+            .iter()
+            .rposition(|e| {
+                matches!(
+                    e.event.as_input(),
+                    Some(EventType::MouseMove { .. }) | Some(EventType::ButtonRelease(_))
+                )
+            })
+            .filter(|&i| matches!(events[i].event.as_input(), Some(EventType::MouseMove { .. })));
+
+        match positioning_move {
+            Some(move_index) => events[move_index].event = MacroEvent::Input(EventType::MouseMove { x, y }),
+            None => events.insert(index, TimedEvent::input(EventType::MouseMove { x, y }, Duration::ZERO)),
+        }
+        self.apply(events)
+    }
+
+    /// Appends another macro's events.
+    /// Secrets from `other` whose ids are already used here are given fresh ids. Returns
+    /// `(id in other, id here)` for every appended secret, whose keyring entries must be
+    /// copied on save since playback looks secrets up by macro name.
+    pub fn concatenate(&mut self, other: &Macro) -> Result<Vec<(String, String)>, EditorError> {
+        let mut used: Vec<String> = secret_ids(&self.macro_data.events);
+        let mut copies = Vec::new();
+        let mut events = self.macro_data.events.clone();
+        for timed_event in &other.events {
+            let mut timed_event = timed_event.clone();
+            if let MacroEvent::Secret(secret) = &timed_event.event {
+                let mut id = secret.id.clone();
+                let mut n = 1;
+                while used.contains(&id) {
+                    n += 1;
+                    id = format!("{}-{}", secret.id, n);
+                }
+                copies.push((secret.id.clone(), id.clone()));
+                used.push(id.clone());
+                timed_event.event = MacroEvent::Secret(SecretRef { id });
+            }
+            events.push(timed_event);
+        }
+        self.apply(events)?;
+        Ok(copies)
+    }
+
+    /// Splits the macro at `index`. This session keeps the events before it; the events
+    /// from `index` on are returned as a new macro named `new_name`.
+    pub fn split(&mut self, index: usize, new_name: String) -> Result<Macro, EditorError> {
+        self.check_index(index, true)?;
+        let mut head = self.macro_data.events.clone();
+        let tail = head.split_off(index);
+
+        let before = find_balance_issues(&self.macro_data.events);
+        let mut after = find_balance_issues(&head);
+        after.extend(find_balance_issues(&tail));
+        if new_balance_issue(&before, &after).is_some() {
+            return Err(EditorError::Unbalanced(format!(
+                "splitting at event {} would separate a press from its release",
+                index
+            )));
+        }
+
+        self.undo_stack.push(std::mem::replace(&mut self.macro_data.events, head));
+        self.dirty = true;
+        Ok(Macro {
+            name: new_name,
+            events: tail,
+        })
+    }
+
+    pub fn undo(&mut self) -> Result<(), EditorError> {
+        let previous = self.undo_stack.pop().ok_or(EditorError::NothingToUndo)?;
+        self.macro_data.events = previous;
+        self.dirty = true;
+        Ok(())
+    }
+}
+
+/// All open edit sessions, keyed by macro name.
+#[derive(Default)]
+pub struct MacroEditor {
+    sessions: HashMap<String, EditSession>,
+}
+
+impl MacroEditor {
+    /// Opens a saved macro for editing. Reopening returns the existing session unchanged.
+    pub fn open(&mut self, name: &str, app_handle: &tauri::AppHandle) -> Result<&Macro, EditorError> {
+        if !self.sessions.contains_key(name) {
+            let macro_data = macro_engine::load_macro(name, app_handle)?;
+            self.sessions.insert(name.to_string(), EditSession::new(macro_data));
+        }
+        Ok(&self.sessions[name].macro_data)
+    }
+
+    pub fn session(&mut self, name: &str) -> Result<&mut EditSession, EditorError> {
+        self.sessions
+            .get_mut(name)
+            .ok_or_else(|| EditorError::NotOpen(name.to_string()))
+    }
+
+    /// Appends `other_name` (its open session if any, else the saved file) to `name`.
+    pub fn concatenate(&mut self, name: &str, other_name: &str, app_handle: &tauri::AppHandle) -> Result<&Macro, EditorError> {
+        let other = match self.sessions.get(other_name) {
+            Some(session) => session.macro_data.clone(),
+            None => macro_engine::load_macro(other_name, app_handle)?,
+        };
+        // Secrets the other session has not saved yet still live under their source macro.
+        let other_sources = self
+            .sessions
+            .get(other_name)
+            .map(|session| session.secret_sources.clone())
+            .unwrap_or_default();
+        let session = self.session(name)?;
+        for (from_id, to_id) in session.concatenate(&other)? {
+            let source = other_sources.get(&from_id).cloned().unwrap_or((other_name.to_string(), from_id));
+            session.secret_sources.insert(to_id, source);
+        }
+        Ok(&session.macro_data)
+    }
+
+    /// Splits `name` at `index`, opening the second half as a new unsaved macro.
+    pub fn split(
+        &mut self,
+        name: &str,
+        index: usize,
+        new_name: &str,
+        app_handle: &tauri::AppHandle,
+    ) -> Result<(Macro, Macro), EditorError> {
+        if self.sessions.contains_key(new_name) {
+            return Err(EditorError::AlreadyOpen(new_name.to_string()));
+        }
+        if macro_engine::macro_exists(new_name, app_handle)? {
+            return Err(MacroError::AlreadyExists(new_name.to_string()).into());
+        }
+        let session = self.session(name)?;
+        let tail = session.split(index, new_name.to_string())?;
+        let head = session.macro_data.clone();
+
+        let mut tail_session = EditSession::new(tail.clone());
+        tail_session.dirty = true;
+        // Playback looks secrets up by macro name, so the new macro needs its own entries.
+        for id in secret_ids(&tail.events) {
+            let source = session.secret_sources.get(&id).cloned().unwrap_or((name.to_string(), id.clone()));
+            tail_session.secret_sources.insert(id, source);
+        }
+        self.sessions.insert(new_name.to_string(), tail_session);
+        Ok((head, tail))
+    }
+
+    /// Copies the secrets the macro picked up from other macros, writes the macro to disk
+    /// and clears its undo history.
+    pub fn save(&mut self, name: &str, app_handle: &tauri::AppHandle) -> Result<(), EditorError> {
+        let session = self.session(name)?;
+        // Secrets removed again by later edits or undo are not copied.
+        let in_use = secret_ids(&session.macro_data.events);
+        for (id, (from_macro, from_id)) in &session.secret_sources {
+            if in_use.contains(id) {
+                macro_secrets::copy_secret(from_macro, from_id, name, id)?;
+            }
+        }
+        session.secret_sources.clear();
+        macro_engine::save_macro(&session.macro_data, app_handle)?;
+        session.undo_stack.clear();
+        session.dirty = false;
+        Ok(())
+    }
+
+    /// Closes a session, discarding unsaved changes.
+    pub fn close(&mut self, name: &str) -> Result<(), EditorError> {
+        self.sessions
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| EditorError::NotOpen(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ev(event_type: EventType) -> TimedEvent {
+        TimedEvent::input(event_type, Duration::from_millis(100))
+    }
+
+    fn sample() -> EditSession {
+        EditSession::new(Macro {
+            name: "sample".to_string(),
+            events: vec![
+                ev(EventType::MouseMove { x: 10.0, y: 10.0 }),
+                ev(EventType::ButtonPress(Button::Left)),
+                ev(EventType::ButtonRelease(Button::Left)),
+                ev(EventType::KeyPress(Key::KeyA)),
+                ev(EventType::KeyRelease(Key::KeyA)),
+            ],
+        })
+    }
+
+    #[test]
+    fn test_delete_and_undo() {
+        let mut session = sample();
+        session.delete_range(3, 5).unwrap();
+        assert_eq!(session.macro_data.events.len(), 3);
+        assert!(session.is_dirty());
+
+        session.undo().unwrap();
+        assert_eq!(session.macro_data.events.len(), 5);
+        assert!(matches!(session.undo(), Err(EditorError::NothingToUndo)));
+    }
+
+    #[test]
+    fn test_unbalancing_edits_are_rejected() {
+        let mut session = sample();
+        assert!(matches!(session.delete_range(3, 4), Err(EditorError::Unbalanced(_))));
+        assert!(matches!(
+            session.insert(0, vec![ev(EventType::ButtonRelease(Button::Right))]),
+            Err(EditorError::Unbalanced(_))
+        ));
+        assert!(matches!(session.split(4, "tail".into()), Err(EditorError::Unbalanced(_))));
+        assert_eq!(session.macro_data.events.len(), 5);
+        assert!(!session.can_undo());
+    }
+
+    #[test]
+    fn test_swapping_one_issue_for_another_is_rejected() {
+        let mut session = sample();
+        session.macro_data.events.push(ev(EventType::ButtonPress(Button::Right)));
+        // Releasing the held button but adding a stray release keeps the count at one.
+        let swap = vec![ev(EventType::ButtonRelease(Button::Middle)), ev(EventType::ButtonRelease(Button::Right))];
+        assert!(matches!(session.insert(6, swap), Err(EditorError::Unbalanced(_))));
+        // Fixing the existing issue is fine even though event indices shift.
+        session.insert(0, vec![ev(EventType::MouseMove { x: 1.0, y: 1.0 })]).unwrap();
+        session.delete_range(6, 7).unwrap();
+        assert!(balance_issues(&session.macro_data.events).is_empty());
+    }
+
+    #[test]
+    fn test_already_unbalanced_recordings_stay_editable() {
+        let mut session = sample();
+        // A trailing press without release, like the click that stopped the recording.
+        session.macro_data.events.push(ev(EventType::ButtonPress(Button::Left)));
+        session.set_delay(0, 0.5).unwrap();
+        assert_eq!(session.macro_data.events[0].time_since_previous, Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_move_click_target() {
+        let mut session = sample();
+        session.move_click_target(1, 300.0, 400.0).unwrap();
+        assert_eq!(session.macro_data.events[0].event, MacroEvent::Input(EventType::MouseMove { x: 300.0, y: 400.0 }));
+        assert!(matches!(session.move_click_target(3, 0.0, 0.0), Err(EditorError::NotAClick(3))));
+
+        // A second click without its own move gets one inserted.
+        session.insert(5, vec![ev(EventType::ButtonPress(Button::Left)), ev(EventType::ButtonRelease(Button::Left))]).unwrap();
+        session.move_click_target(5, 5.0, 6.0).unwrap();
+        assert_eq!(session.macro_data.events[5].event, MacroEvent::Input(EventType::MouseMove { x: 5.0, y: 6.0 }));
+        assert_eq!(session.macro_data.events.len(), 8);
+    }
+
+    #[test]
+    fn test_split_and_concatenate() {
+        let mut session = sample();
+        let tail = session.split(3, "typing".into()).unwrap();
+        assert_eq!(session.macro_data.events.len(), 3);
+        assert_eq!(tail.events.len(), 2);
+
+        session.concatenate(&tail).unwrap();
+        assert_eq!(session.macro_data.events.len(), 5);
+    }
+
+    #[test]
+    fn test_concatenate_renames_clashing_secrets() {
+        let secret = |id: &str| TimedEvent {
+            event: MacroEvent::Secret(SecretRef { id: id.to_string() }),
+            time_since_previous: Duration::ZERO,
+            text: None,
+            target: None,
+            window: None,
+        };
+        let mut session = EditSession::new(Macro {
+            name: "login".to_string(),
+            events: vec![secret("secret-1")],
+        });
+        let other = Macro {
+            name: "vpn".to_string(),
+            events: vec![secret("secret-1"), secret("secret-2")],
+        };
+
+        let copies = session.concatenate(&other).unwrap();
+        assert_eq!(
+            copies,
+            vec![
+                ("secret-1".to_string(), "secret-1-2".to_string()),
+                ("secret-2".to_string(), "secret-2".to_string()),
+            ]
+        );
+        assert_eq!(secret_ids(&session.macro_data.events), vec!["secret-1", "secret-1-2", "secret-2"]);
+    }
+
+    #[test]
+    fn test_huge_delay_is_rejected() {
+        let mut session = sample();
+        assert!(matches!(session.set_delay(1, 1e30), Err(EditorError::InvalidDelay(_))));
+    }
+}
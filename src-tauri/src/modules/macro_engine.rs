@@ -1,10 +1,15 @@
-use crate::modules::io_controller;
+use crate::modules::io_controller::{self, InputBackend};
+use crate::modules::macro_formats::{self, MacroFormat};
+use crate::modules::macro_history;
+use crate::modules::macro_secrets::SecretRef;
+use crate::modules::perception::{Rect, ScreenWait};
+use crate::modules::persistence::{self, PersistenceError};
+use crate::modules::window::WindowContext;
 use rdev::{Button, EventType, Key};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::{
     fs,
-    path::PathBuf,
-    thread,
+    path::{Path, PathBuf},
     time::Duration,
 };
 use tauri::Manager;
@@ -18,6 +23,18 @@ pub enum MacroError {
     Json(#[from] serde_json::Error),
     #[error("I/O Controller error: {0}")]
     Io(String),
+    #[error("Line {line}: {message}")]
+    Parse { line: usize, message: String },
+    #[error("Unsupported format: {0}")]
+    UnsupportedFormat(String),
+    #[error("A macro named '{0}' already exists")]
+    AlreadyExists(String),
+    #[error("Revision {revision} of macro '{name}' does not exist")]
+    RevisionNotFound { name: String, revision: u32 },
+    #[error("Step {step} was recorded in {expected}, but {found} is focused")]
+    WrongWindow { step: usize, expected: String, found: String },
+    #[error("Step {step}: '{target}' was not found on screen")]
+    TargetNotFound { step: usize, target: String },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -26,6 +43,29 @@ pub struct Macro {
     pub events: Vec<TimedEvent>,
 }
 
+/// A single step of a macro.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MacroEvent {
+    /// A raw input event replayed through rdev.
+    Input(EventType),
+    /// Text typed through Unicode-aware typing, independent of the keyboard layout.
+    TypeText(String),
+    /// Secret text kept in the OS keyring, typed like `TypeText` at playback.
+    Secret(SecretRef),
+    /// Waits for the screen to settle or change instead of a fixed delay.
+    WaitFor(ScreenWait),
+}
+
+impl MacroEvent {
+    /// Returns the raw input event, if this step is one.
+    pub fn as_input(&self) -> Option<&EventType> {
+        match self {
+            MacroEvent::Input(event_type) => Some(event_type),
+            _ => None,
+        }
+    }
+}
+
 // Custom serializable representation of EventType
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -36,6 +76,9 @@ enum SerializableEventType {
     KeyPress { key: String },
     KeyRelease { key: String },
     Wheel { delta_x: i64, delta_y: i64 },
+    TypeText { text: String },
+    Secret { id: String },
+    WaitFor { wait: ScreenWait },
 }
 
 impl From<&EventType> for SerializableEventType {
@@ -49,10 +92,10 @@ impl From<&EventType> for SerializableEventType {
                 button: format!("{:?}", button).to_lowercase(),
             },
             EventType::KeyPress(key) => SerializableEventType::KeyPress {
-                key: format!("{:?}", key),
+                key: key_name(*key),
             },
             EventType::KeyRelease(key) => SerializableEventType::KeyRelease {
-                key: format!("{:?}", key),
+                key: key_name(*key),
             },
             EventType::Wheel { delta_x, delta_y } => SerializableEventType::Wheel {
                 delta_x: *delta_x as i64,
@@ -62,9 +105,20 @@ impl From<&EventType> for SerializableEventType {
     }
 }
 
-impl From<SerializableEventType> for EventType {
+impl From<&MacroEvent> for SerializableEventType {
+    fn from(event: &MacroEvent) -> Self {
+        match event {
+            MacroEvent::Input(event_type) => event_type.into(),
+            MacroEvent::TypeText(text) => SerializableEventType::TypeText { text: text.clone() },
+            MacroEvent::Secret(secret) => SerializableEventType::Secret { id: secret.id.clone() },
+            MacroEvent::WaitFor(wait) => SerializableEventType::WaitFor { wait: *wait },
+        }
+    }
+}
+
+impl From<SerializableEventType> for MacroEvent {
     fn from(ser: SerializableEventType) -> Self {
-        match ser {
+        let event_type = match ser {
             SerializableEventType::MouseMove { x, y } => EventType::MouseMove { x, y },
             SerializableEventType::ButtonPress { button } => {
                 EventType::ButtonPress(parse_button(&button))
@@ -77,7 +131,11 @@ impl From<SerializableEventType> for EventType {
             SerializableEventType::Wheel { delta_x, delta_y } => {
                 EventType::Wheel { delta_x, delta_y }
             }
-        }
+            SerializableEventType::TypeText { text } => return MacroEvent::TypeText(text),
+            SerializableEventType::Secret { id } => return MacroEvent::Secret(SecretRef { id }),
+            SerializableEventType::WaitFor { wait } => return MacroEvent::WaitFor(wait),
+        };
+        MacroEvent::Input(event_type)
     }
 }
 
@@ -91,88 +149,123 @@ fn parse_button(s: &str) -> Button {
 }
 
 fn parse_key(s: &str) -> Key {
-    match s {
-        // Alphanumeric
-        "KeyA" => Key::KeyA, "KeyB" => Key::KeyB, "KeyC" => Key::KeyC, "KeyD" => Key::KeyD,
-        "KeyE" => Key::KeyE, "KeyF" => Key::KeyF, "KeyG" => Key::KeyG, "KeyH" => Key::KeyH,
-        "KeyI" => Key::KeyI, "KeyJ" => Key::KeyJ, "KeyK" => Key::KeyK, "KeyL" => Key::KeyL,
-        "KeyM" => Key::KeyM, "KeyN" => Key::KeyN, "KeyO" => Key::KeyO, "KeyP" => Key::KeyP,
-        "KeyQ" => Key::KeyQ, "KeyR" => Key::KeyR, "KeyS" => Key::KeyS, "KeyT" => Key::KeyT,
-        "KeyU" => Key::KeyU, "KeyV" => Key::KeyV, "KeyW" => Key::KeyW, "KeyX" => Key::KeyX,
-        "KeyY" => Key::KeyY, "KeyZ" => Key::KeyZ,
-        "Num1" => Key::Num1, "Num2" => Key::Num2, "Num3" => Key::Num3, "Num4" => Key::Num4,
-        "Num5" => Key::Num5, "Num6" => Key::Num6, "Num7" => Key::Num7, "Num8" => Key::Num8,
-        "Num9" => Key::Num9, "Num0" => Key::Num0,
-
-        // Special Keys
-        "Return" => Key::Return, // <-- FIX for Enter key
-        "Enter" => Key::Return,  // Alias for Enter
-        "Escape" => Key::Escape,
-        "Backspace" => Key::Backspace,
-        "Tab" => Key::Tab,
-        "Space" => Key::Space,
-        "Delete" => Key::Delete,
-
-        // Modifier Keys
-        "ShiftLeft" => Key::ShiftLeft,
-        "ShiftRight" => Key::ShiftRight,
-        "ControlLeft" => Key::ControlLeft,
-        "ControlRight" => Key::ControlRight,
-        "Alt" => Key::Alt,
-        "AltGr" => Key::AltGr,
-        "MetaLeft" => Key::MetaLeft,   // <-- FIX for Windows key
-        "MetaRight" => Key::MetaRight, // <-- FIX for Windows key
-
-        // Arrow Keys
-        "UpArrow" => Key::UpArrow,
-        "DownArrow" => Key::DownArrow,
-        "LeftArrow" => Key::LeftArrow,
-        "RightArrow" => Key::RightArrow,
-
-        // Function Keys
-        "F1" => Key::F1, "F2" => Key::F2, "F3" => Key::F3, "F4" => Key::F4,
-        "F5" => Key::F5, "F6" => Key::F6, "F7" => Key::F7, "F8" => Key::F8,
-        "F9" => Key::F9, "F10" => Key::F10, "F11" => Key::F11, "F12" => Key::F12,
-
-        // Navigation
-        "Home" => Key::Home,
-        "End" => Key::End,
-        "PageUp" => Key::PageUp,
-        "PageDown" => Key::PageDown,
-        
-        // Default case
-        _ => {
-            if let Some(num_str) = s.strip_prefix("Unknown(").and_then(|s| s.strip_suffix(")")) {
-                if let Ok(num) = num_str.parse::<u32>() {
-                    return Key::Unknown(num);
-                }
-            }
-            log::warn!("Unhandled key in macro playback: {}. Defaulting to Unknown(0).", s);
-            Key::Unknown(0)
-        }
+    try_parse_key(s).unwrap_or_else(|| {
+        log::warn!("Unhandled key in macro playback: {}. Defaulting to Unknown(0).", s);
+        Key::Unknown(0)
+    })
+}
+
+/// Every named key with the name macros store it under, which is its `Debug` name so
+/// older macro files keep loading. `Key::Unknown(code)` is written as `Unknown(code)`.
+#[rustfmt::skip]
+pub(crate) const KEY_NAMES: &[(Key, &str)] = &[
+    (Key::Alt, "Alt"), (Key::AltGr, "AltGr"), (Key::Backspace, "Backspace"), (Key::CapsLock, "CapsLock"),
+    (Key::ControlLeft, "ControlLeft"), (Key::ControlRight, "ControlRight"), (Key::Delete, "Delete"),
+    (Key::DownArrow, "DownArrow"), (Key::End, "End"), (Key::Escape, "Escape"), (Key::F1, "F1"), (Key::F2, "F2"),
+    (Key::F3, "F3"), (Key::F4, "F4"), (Key::F5, "F5"), (Key::F6, "F6"), (Key::F7, "F7"), (Key::F8, "F8"),
+    (Key::F9, "F9"), (Key::F10, "F10"), (Key::F11, "F11"), (Key::F12, "F12"), (Key::Home, "Home"),
+    (Key::LeftArrow, "LeftArrow"), (Key::MetaLeft, "MetaLeft"), (Key::MetaRight, "MetaRight"),
+    (Key::PageDown, "PageDown"), (Key::PageUp, "PageUp"), (Key::Return, "Return"),
+    (Key::RightArrow, "RightArrow"), (Key::ShiftLeft, "ShiftLeft"), (Key::ShiftRight, "ShiftRight"),
+    (Key::Space, "Space"), (Key::Tab, "Tab"), (Key::UpArrow, "UpArrow"), (Key::PrintScreen, "PrintScreen"),
+    (Key::ScrollLock, "ScrollLock"), (Key::Pause, "Pause"), (Key::NumLock, "NumLock"),
+    (Key::BackQuote, "BackQuote"), (Key::Num1, "Num1"), (Key::Num2, "Num2"), (Key::Num3, "Num3"),
+    (Key::Num4, "Num4"), (Key::Num5, "Num5"), (Key::Num6, "Num6"), (Key::Num7, "Num7"), (Key::Num8, "Num8"),
+    (Key::Num9, "Num9"), (Key::Num0, "Num0"), (Key::Minus, "Minus"), (Key::Equal, "Equal"), (Key::KeyQ, "KeyQ"),
+    (Key::KeyW, "KeyW"), (Key::KeyE, "KeyE"), (Key::KeyR, "KeyR"), (Key::KeyT, "KeyT"), (Key::KeyY, "KeyY"),
+    (Key::KeyU, "KeyU"), (Key::KeyI, "KeyI"), (Key::KeyO, "KeyO"), (Key::KeyP, "KeyP"),
+    (Key::LeftBracket, "LeftBracket"), (Key::RightBracket, "RightBracket"), (Key::KeyA, "KeyA"),
+    (Key::KeyS, "KeyS"), (Key::KeyD, "KeyD"), (Key::KeyF, "KeyF"), (Key::KeyG, "KeyG"), (Key::KeyH, "KeyH"),
+    (Key::KeyJ, "KeyJ"), (Key::KeyK, "KeyK"), (Key::KeyL, "KeyL"), (Key::SemiColon, "SemiColon"),
+    (Key::Quote, "Quote"), (Key::BackSlash, "BackSlash"), (Key::IntlBackslash, "IntlBackslash"),
+    (Key::KeyZ, "KeyZ"), (Key::KeyX, "KeyX"), (Key::KeyC, "KeyC"), (Key::KeyV, "KeyV"), (Key::KeyB, "KeyB"),
+    (Key::KeyN, "KeyN"), (Key::KeyM, "KeyM"), (Key::Comma, "Comma"), (Key::Dot, "Dot"), (Key::Slash, "Slash"),
+    (Key::Insert, "Insert"), (Key::KpReturn, "KpReturn"), (Key::KpMinus, "KpMinus"), (Key::KpPlus, "KpPlus"),
+    (Key::KpMultiply, "KpMultiply"), (Key::KpDivide, "KpDivide"), (Key::Kp0, "Kp0"), (Key::Kp1, "Kp1"),
+    (Key::Kp2, "Kp2"), (Key::Kp3, "Kp3"), (Key::Kp4, "Kp4"), (Key::Kp5, "Kp5"), (Key::Kp6, "Kp6"),
+    (Key::Kp7, "Kp7"), (Key::Kp8, "Kp8"), (Key::Kp9, "Kp9"), (Key::KpDelete, "KpDelete"),
+    (Key::Function, "Function"),
+];
+
+/// The name a key is stored under in macro files and the text DSL; read back by `try_parse_key`.
+pub(crate) fn key_name(key: Key) -> String {
+    match KEY_NAMES.iter().find(|(named, _)| *named == key) {
+        Some((_, name)) => name.to_string(),
+        None => format!("{:?}", key),
+    }
+}
+
+/// Parses a key name as written by `key_name`, returning `None` if it is not recognised.
+pub(crate) fn try_parse_key(s: &str) -> Option<Key> {
+    if s == "Enter" {
+        return Some(Key::Return);
+    }
+    if let Some((key, _)) = KEY_NAMES.iter().find(|(_, name)| *name == s) {
+        return Some(*key);
     }
+    let num_str = s.strip_prefix("Unknown(")?.strip_suffix(')')?;
+    Some(Key::Unknown(num_str.parse::<u32>().ok()?))
 }
 
 #[serde_with::serde_as]
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct TimedEvent {
     // Store only the event_type, not the full Event, to avoid serialization issues
     // with UnicodeInfo and other non-serializable fields
-    #[serde(serialize_with = "serialize_event_type", deserialize_with = "deserialize_event_type")]
-    pub event_type: EventType,
+    #[serde(rename = "event_type", serialize_with = "serialize_event", deserialize_with = "deserialize_event")]
+    pub event: MacroEvent,
     #[serde_as(as = "serde_with::DurationSecondsWithFrac<f64>")]
     pub time_since_previous: Duration,
+    /// The character produced by a key press, as reported by the OS while recording.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// What was clicked, for button presses recorded in AI-assisted mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<ClickTarget>,
+    /// The focused window, sampled for clicks and the first key of each typing burst.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window: Option<WindowContext>,
+}
+
+impl TimedEvent {
+    pub fn input(event_type: EventType, time_since_previous: Duration) -> Self {
+        Self {
+            event: MacroEvent::Input(event_type),
+            time_since_previous,
+            text: None,
+            target: None,
+            window: None,
+        }
+    }
 }
 
-fn serialize_event_type<S>(event_type: &EventType, serializer: S) -> Result<S::Ok, S::Error>
+/// Screen context captured for a click in AI-assisted recordings. Stored in the macro
+/// as `{"action": "click", "target_description", "target_crop_path", "original_xy", ...}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "action", rename = "click")]
+pub struct ClickTarget {
+    pub target_description: String,
+    /// Crop around the cursor, relative to the macro's assets folder.
+    pub target_crop_path: String,
+    pub original_xy: (f64, f64),
+    /// Screen area of the crop; the click was at `original_xy` inside it.
+    pub crop_rect: Rect,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_title: Option<String>,
+    /// Text of the clicked element as read by OCR, used to find it again if it moved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_text: Option<String>,
+}
+
+fn serialize_event<S>(event: &MacroEvent, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
-    let ser: SerializableEventType = event_type.into();
+    let ser: SerializableEventType = event.into();
     ser.serialize(serializer)
 }
 
-fn deserialize_event_type<'de, D>(deserializer: D) -> Result<EventType, D::Error>
+fn deserialize_event<'de, D>(deserializer: D) -> Result<MacroEvent, D::Error>
 where
     D: Deserializer<'de>,
 {
@@ -180,8 +273,109 @@ where
     Ok(ser.into())
 }
 
+/// Typing pauses longer than this split recorded text into separate `TypeText` steps.
+const TYPE_TEXT_MAX_GAP: Duration = Duration::from_millis(1000);
+
+/// Modifiers that only change which character a key produces, and are therefore
+/// absorbed into `TypeText` steps.
+fn is_text_modifier(key: &Key) -> bool {
+    matches!(key, Key::ShiftLeft | Key::ShiftRight | Key::AltGr)
+}
+
+/// Returns the printable text captured for a key press, if any.
+fn printable_text(timed_event: &TimedEvent) -> Option<&str> {
+    match (&timed_event.event, timed_event.text.as_deref()) {
+        (MacroEvent::Input(EventType::KeyPress(key)), Some(text))
+            if !is_text_modifier(key) && !text.is_empty() && !text.chars().any(char::is_control) =>
+        {
+            Some(text)
+        }
+        _ => None,
+    }
+}
+
+/// Collapses runs of printable key presses (with their releases and any Shift/AltGr
+/// presses) into `TypeText` steps. Keys pressed while Ctrl, Alt or Meta is held are
+/// shortcuts and are left untouched.
+pub fn collapse_typed_text(events: Vec<TimedEvent>) -> Vec<TimedEvent> {
+    let mut result = Vec::with_capacity(events.len());
+    let mut held_shortcut_modifiers: Vec<Key> = Vec::new();
+    let mut i = 0;
+
+    while i < events.len() {
+        let run = if held_shortcut_modifiers.is_empty() {
+            text_run_at(&events, i)
+        } else {
+            None
+        };
+
+        if let Some((end, text)) = run {
+            result.push(TimedEvent {
+                event: MacroEvent::TypeText(text),
+                time_since_previous: events[i].time_since_previous,
+                text: None,
+                target: None,
+                window: events[i].window.clone(),
+            });
+            i = end;
+            continue;
+        }
+
+        match events[i].event.as_input() {
+            Some(EventType::KeyPress(key)) if is_shortcut_modifier(key) => held_shortcut_modifiers.push(*key),
+            Some(EventType::KeyRelease(key)) => held_shortcut_modifiers.retain(|k| k != key),
+            _ => {}
+        }
+        result.push(events[i].clone());
+        i += 1;
+    }
+    result
+}
+
+fn is_shortcut_modifier(key: &Key) -> bool {
+    matches!(
+        key,
+        Key::ControlLeft | Key::ControlRight | Key::Alt | Key::MetaLeft | Key::MetaRight
+    )
+}
+
+/// Finds the longest typing run starting at `start`. A run may only end where every key
+/// it pressed has been released again, so the raw events around it stay balanced.
+/// Returns the index after the run and the typed text.
+fn text_run_at(events: &[TimedEvent], start: usize) -> Option<(usize, String)> {
+    let mut pressed: Vec<Key> = Vec::new();
+    let mut text = String::new();
+    let mut balanced = None;
+
+    for (j, timed_event) in events.iter().enumerate().skip(start) {
+        if j > start && timed_event.time_since_previous > TYPE_TEXT_MAX_GAP {
+            break;
+        }
+        match timed_event.event.as_input() {
+            Some(EventType::KeyPress(key)) => {
+                if let Some(typed) = printable_text(timed_event) {
+                    text.push_str(typed);
+                } else if !is_text_modifier(key) {
+                    break;
+                }
+                if !pressed.contains(key) {
+                    pressed.push(*key);
+                }
+            }
+            Some(EventType::KeyRelease(key)) if pressed.contains(key) => {
+                pressed.retain(|k| k != key);
+            }
+            _ => break,
+        }
+        if pressed.is_empty() && !text.is_empty() {
+            balanced = Some((j + 1, text.clone()));
+        }
+    }
+    balanced
+}
+
 /// Returns the path to the macros directory, creating it if it doesn't exist.
-fn get_macros_dir(app_handle: &tauri::AppHandle) -> Result<PathBuf, MacroError> {
+pub(crate) fn get_macros_dir(app_handle: &tauri::AppHandle) -> Result<PathBuf, MacroError> {
     let config_dir = app_handle
         .path()
         .app_config_dir()
@@ -194,24 +388,186 @@ fn get_macros_dir(app_handle: &tauri::AppHandle) -> Result<PathBuf, MacroError>
     Ok(macros_dir)
 }
 
-/// Loads a macro from a JSON file.
+/// Returns the folder holding a macro's assets (screenshots, crops, ...).
+pub fn macro_assets_dir(name: &str, app_handle: &tauri::AppHandle) -> Result<PathBuf, MacroError> {
+    Ok(get_macros_dir(app_handle)?.join(name))
+}
+
+/// Returns true if a macro with the given name has been saved.
+pub fn macro_exists(name: &str, app_handle: &tauri::AppHandle) -> Result<bool, MacroError> {
+    Ok(get_macros_dir(app_handle)?.join(format!("{}.json", name)).is_file())
+}
+
+/// Saves a macro to `{name}.json` in the macros directory and returns the file path.
+/// The saved version is also kept as a new revision in the macro's history.
+pub fn save_macro(macro_data: &Macro, app_handle: &tauri::AppHandle) -> Result<PathBuf, MacroError> {
+    let macros_dir = get_macros_dir(app_handle)?;
+    macro_history::record_save(macro_data, app_handle)?;
+    let file_path = macros_dir.join(format!("{}.json", macro_data.name));
+    persistence::write_json(&file_path, macro_data)
+        .map_err(|e| MacroError::FileSystem(format!("Failed to write macro file: {}", e)))?;
+
+    log::info!("Macro saved successfully to: {:?}", file_path);
+    Ok(file_path)
+}
+
+/// Loads a macro from a JSON file, falling back to its backup if the file is corrupt.
 pub fn load_macro(name: &str, app_handle: &tauri::AppHandle) -> Result<Macro, MacroError> {
     let macros_dir = get_macros_dir(app_handle)?;
     let file_path = macros_dir.join(format!("{}.json", name));
 
     log::info!("Loading macro from: {:?}", file_path);
-    let json_string = fs::read_to_string(file_path)
-        .map_err(|e| MacroError::FileSystem(format!("Failed to read macro file: {}", e)))?;
-
-    let macro_data: Macro = serde_json::from_str(&json_string)?;
+    let loaded = persistence::read_json::<Macro>(&file_path).map_err(|e| match e {
+        PersistenceError::Corrupt { message, .. } => MacroError::FileSystem(format!("Macro file is corrupt: {}", message)),
+        e => MacroError::FileSystem(format!("Failed to read macro file: {}", e)),
+    })?;
+    if let Some(warning) = &loaded.recovery {
+        persistence::report_recovery(app_handle, warning);
+    }
 
-    Ok(macro_data)
+    Ok(loaded.value)
 }
 
 /// Executes the events in a given Macro struct.
 pub fn play_macro(macro_data: &Macro) -> Result<(), MacroError> {
+    play_macro_with(macro_data, &mut io_controller::LiveInput)
+}
+
+/// How a recorded click target was found during playback.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LocateMethod {
+    /// The stored crop was found on screen.
+    Template,
+    /// The target's text was found on screen.
+    Text,
+    /// Nothing matched confidently enough and the locator was allowed to use the
+    /// recorded position.
+    Coordinates,
+}
+
+/// Where a recorded click landed during playback.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ClickResolution {
+    pub event_index: usize,
+    pub original_xy: (f64, f64),
+    pub resolved_xy: (f64, f64),
+    pub method: LocateMethod,
+    pub confidence: f32,
+    /// Recovery actions tried before the target was found, e.g. "scroll (0, -3)".
+    #[serde(default)]
+    pub recovery_actions: Vec<String>,
+}
+
+/// Clicks that were relocated or recovered while playing a macro.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct PlaybackReport {
+    pub macro_name: String,
+    pub clicks: Vec<ClickResolution>,
+}
+
+impl PlaybackReport {
+    /// Clicks that did not land on their recorded position.
+    pub fn relocated(&self) -> impl Iterator<Item = &ClickResolution> {
+        self.clicks.iter().filter(|click| click.resolved_xy != click.original_xy)
+    }
+}
+
+/// Finds a recorded click target on the current screen before it is clicked.
+pub trait TargetLocator {
+    /// Locates `target` for the event at `event_index`. Recovery input, if any, is sent
+    /// through `backend`. An error stops playback before the click.
+    fn locate(
+        &mut self,
+        event_index: usize,
+        target: &ClickTarget,
+        backend: &mut dyn InputBackend,
+    ) -> Result<ClickResolution, MacroError>;
+}
+
+/// Plays a macro through the given input backend.
+pub fn play_macro_with(macro_data: &Macro, backend: &mut dyn InputBackend) -> Result<(), MacroError> {
+    play_macro_inner(macro_data, backend, None).map(|_| ())
+}
+
+/// Plays a macro, re-finding each recorded click target with `locator` before clicking.
+pub fn play_macro_corrected(
+    macro_data: &Macro,
+    backend: &mut dyn InputBackend,
+    locator: &mut dyn TargetLocator,
+) -> Result<PlaybackReport, MacroError> {
+    play_macro_inner(macro_data, backend, Some(locator))
+}
+
+/// Clicks closer than this to their recorded position are not moved.
+const RELOCATE_MIN_DISTANCE: f64 = 2.0;
+/// How long playback waits for the recorded application to come to the front, e.g. after
+/// a click that opens it, before refusing to continue.
+const WINDOW_MATCH_TIMEOUT: Duration = Duration::from_secs(2);
+const WINDOW_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Fails when a different application than the one recorded for step `index` is focused.
+/// Backends that cannot tell which window is focused are not checked, and neither are
+/// steps recorded into Nyx's own window, which playback never brings to the front.
+fn check_window(index: usize, recorded: &WindowContext, backend: &mut dyn InputBackend) -> Result<(), MacroError> {
+    if recorded.pid == Some(std::process::id()) {
+        return Ok(());
+    }
+    let mut waited = Duration::ZERO;
+    loop {
+        let current = match backend.active_window() {
+            Ok(Some(current)) => current,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                log::warn!("Could not read the active window, playing step {} unchecked: {}", index + 1, e);
+                return Ok(());
+            }
+        };
+        if recorded.same_app(&current) {
+            return Ok(());
+        }
+        if waited >= WINDOW_MATCH_TIMEOUT {
+            return Err(MacroError::WrongWindow {
+                step: index + 1,
+                expected: recorded.describe(),
+                found: current.describe(),
+            });
+        }
+        backend.wait(WINDOW_POLL_INTERVAL);
+        waited += WINDOW_POLL_INTERVAL;
+    }
+}
+
+fn play_macro_inner(
+    macro_data: &Macro,
+    backend: &mut dyn InputBackend,
+    locator: Option<&mut dyn TargetLocator>,
+) -> Result<PlaybackReport, MacroError> {
     log::info!("--- Starting macro playback: {} ---", macro_data.name);
     log::info!("Total events to play: {}", macro_data.events.len());
+    let mut held = Vec::new();
+    let result = play_events(macro_data, backend, locator, &mut held);
+    // A step that failed part way must not leave keys or buttons pressed. Macros that
+    // finish with a press held were recorded that way and are played as recorded.
+    if result.is_err() {
+        for release in held.iter().rev() {
+            log::info!("Releasing {:?} held when playback stopped", release);
+            if let Err(e) = backend.send_event(release) {
+                log::error!("Failed to release {:?}: {}", release, e);
+            }
+        }
+    }
+    result
+}
+
+/// Plays every event, keeping in `held` the releases for presses that are still down.
+fn play_events(
+    macro_data: &Macro,
+    backend: &mut dyn InputBackend,
+    mut locator: Option<&mut dyn TargetLocator>,
+    held: &mut Vec<EventType>,
+) -> Result<PlaybackReport, MacroError> {
+    let mut report = PlaybackReport { macro_name: macro_data.name.clone(), clicks: Vec::new() };
 
     for (index, timed_event) in macro_data.events.iter().enumerate() {
         // Wait for the recorded duration
@@ -221,22 +577,64 @@ pub fn play_macro(macro_data: &Macro) -> Result<(), MacroError> {
                 macro_data.events.len(),
                 timed_event.time_since_previous.as_secs_f64()
             );
-            thread::sleep(timed_event.time_since_previous);
+            backend.wait(timed_event.time_since_previous);
+        }
+
+        if let Some(recorded) = &timed_event.window {
+            check_window(index, recorded, backend)?;
+        }
+
+        if let (Some(locator), Some(target)) = (locator.as_deref_mut(), &timed_event.target) {
+            let resolution = locator.locate(index, target, backend)?;
+            let (x, y) = resolution.resolved_xy;
+            let (original_x, original_y) = resolution.original_xy;
+            if (x - original_x).hypot(y - original_y) > RELOCATE_MIN_DISTANCE {
+                log::info!(
+                    "[Step {}/{}] Target '{}' moved from ({}, {}) to ({}, {}) ({:?}, confidence {:.2})",
+                    index + 1,
+                    macro_data.events.len(),
+                    target.target_description,
+                    original_x,
+                    original_y,
+                    x,
+                    y,
+                    resolution.method,
+                    resolution.confidence
+                );
+                if let Err(e) = backend.send_event(&EventType::MouseMove { x, y }) {
+                    log::error!("Failed to move to relocated target: {}", e);
+                }
+            }
+            report.clicks.push(resolution);
         }
 
         // Execute the event
         log::info!("[Step {}/{}] Executing: {:?}",
             index + 1,
             macro_data.events.len(),
-            timed_event.event_type
+            timed_event.event
         );
-        if let Err(e) = io_controller::send_event(&timed_event.event_type) {
-            let error_msg = format!("Failed to send event during macro playback: {}", e);
-            log::error!("{}", error_msg);
+        let result = match &timed_event.event {
+            MacroEvent::Input(event_type) => backend.send_event(event_type),
+            MacroEvent::TypeText(text) => backend.type_text(text),
+            MacroEvent::Secret(secret) => backend.type_secret(&macro_data.name, &secret.id),
+            MacroEvent::WaitFor(wait) => backend.wait_for_screen(wait),
+        };
+        match (result, timed_event.event.as_input()) {
+            (Err(e), _) => {
+                let error_msg = format!("Failed to send event during macro playback: {}", e);
+                log::error!("{}", error_msg);
+            }
+            (Ok(()), Some(EventType::KeyPress(key))) => held.push(EventType::KeyRelease(*key)),
+            (Ok(()), Some(EventType::ButtonPress(button))) => held.push(EventType::ButtonRelease(*button)),
+            (Ok(()), Some(release @ (EventType::KeyRelease(_) | EventType::ButtonRelease(_)))) => {
+                held.retain(|e| e != release)
+            }
+            (Ok(()), _) => {}
         }
     }
     log::info!("--- Finished playing macro: {} ---", macro_data.name);
-    Ok(())
+    Ok(report)
 }
 
 /// Lists all available macro files in the macros directory.
@@ -257,6 +655,224 @@ pub fn list_macros(app_handle: &tauri::AppHandle) -> Result<Vec<String>, MacroEr
             }
         }
     }
+    macro_names.sort();
     Ok(macro_names)
 }
 
+/// Exports a saved macro to `destination`, or to `nyx-agent/exports/{name}.{ext}` by default.
+/// Returns the path of the written file.
+pub fn export_macro(
+    name: &str,
+    format: MacroFormat,
+    destination: Option<PathBuf>,
+    app_handle: &tauri::AppHandle,
+) -> Result<PathBuf, MacroError> {
+    let macro_data = load_macro(name, app_handle)?;
+    let contents = match format {
+        MacroFormat::Bundle => {
+            macro_formats::to_bundle(&macro_data, Some(&macro_assets_dir(name, app_handle)?))?
+        }
+        MacroFormat::Xdotool => macro_formats::to_xdotool(&macro_data),
+        MacroFormat::Dsl => macro_formats::to_dsl(&macro_data),
+    };
+
+    let file_path = match destination {
+        Some(path) => path,
+        None => {
+            let exports_dir = get_macros_dir(app_handle)?
+                .parent()
+                .map(|dir| dir.join("exports"))
+                .ok_or_else(|| MacroError::FileSystem("Macros directory has no parent".to_string()))?;
+            fs::create_dir_all(&exports_dir)
+                .map_err(|e| MacroError::FileSystem(format!("Failed to create exports dir: {}", e)))?;
+            exports_dir.join(format!("{}.{}", name, format.extension()))
+        }
+    };
+
+    persistence::write_atomic(&file_path, contents.as_bytes())
+        .map_err(|e| MacroError::FileSystem(format!("Failed to write export file: {}", e)))?;
+
+    #[cfg(unix)]
+    if format == MacroFormat::Xdotool {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&file_path, fs::Permissions::from_mode(0o755))
+            .map_err(|e| MacroError::FileSystem(format!("Failed to make script executable: {}", e)))?;
+    }
+
+    log::info!("Exported macro '{}' as {:?} to {:?}", name, format, file_path);
+    Ok(file_path)
+}
+
+/// Imports a macro file and saves it into the macros directory. The format is guessed
+/// from the file extension when not given. Refuses to overwrite an existing macro.
+pub fn import_macro(
+    path: &Path,
+    format: Option<MacroFormat>,
+    app_handle: &tauri::AppHandle,
+) -> Result<Macro, MacroError> {
+    let format = format
+        .or_else(|| MacroFormat::from_path(path))
+        .ok_or_else(|| MacroError::UnsupportedFormat(format!("Cannot tell the format of {:?}", path)))?;
+
+    let contents = fs::read_to_string(path)
+        .map_err(|e| MacroError::FileSystem(format!("Failed to read import file: {}", e)))?;
+
+    let (macro_data, assets) = match format {
+        MacroFormat::Bundle => macro_formats::from_bundle(&contents)?,
+        MacroFormat::Dsl => {
+            let name = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .ok_or_else(|| MacroError::FileSystem(format!("Invalid file name: {:?}", path)))?;
+            (macro_formats::from_dsl(name, &contents)?, Vec::new())
+        }
+        MacroFormat::Xdotool => {
+            return Err(MacroError::UnsupportedFormat(
+                "xdotool scripts can be exported but not imported".to_string(),
+            ))
+        }
+    };
+
+    if macro_data.name.is_empty() || macro_data.name.contains(['/', '\\']) || macro_data.name.starts_with('.') {
+        return Err(MacroError::FileSystem(format!("Invalid macro name: '{}'", macro_data.name)));
+    }
+    if macro_exists(&macro_data.name, app_handle)? {
+        return Err(MacroError::AlreadyExists(macro_data.name));
+    }
+
+    let assets_dir = macro_assets_dir(&macro_data.name, app_handle)?;
+    for (relative_path, bytes) in assets {
+        let asset_path = assets_dir.join(relative_path);
+        if let Some(parent) = asset_path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| MacroError::FileSystem(format!("Failed to create asset dir: {}", e)))?;
+        }
+        persistence::write_atomic(&asset_path, &bytes)
+            .map_err(|e| MacroError::FileSystem(format!("Failed to write asset {:?}: {}", asset_path, e)))?;
+    }
+
+    save_macro(&macro_data, app_handle)?;
+    log::info!("Imported macro '{}' from {:?}", macro_data.name, path);
+    Ok(macro_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::dry_run::DryRunInput;
+
+    fn key(event_type: EventType, millis: u64, text: Option<&str>) -> TimedEvent {
+        TimedEvent {
+            text: text.map(str::to_string),
+            ..TimedEvent::input(event_type, Duration::from_millis(millis))
+        }
+    }
+
+    #[test]
+    fn test_collapse_typed_text() {
+        let editor = WindowContext { title: "notes.txt - gedit".to_string(), class: "Gedit".to_string(), pid: None };
+        let events = vec![
+            key(EventType::MouseMove { x: 1.0, y: 1.0 }, 0, None),
+            TimedEvent { window: Some(editor.clone()), ..key(EventType::KeyPress(Key::ShiftLeft), 500, None) },
+            key(EventType::KeyPress(Key::KeyH), 80, Some("H")),
+            key(EventType::KeyRelease(Key::ShiftLeft), 40, None),
+            key(EventType::KeyPress(Key::KeyI), 90, Some("i")),
+            key(EventType::KeyRelease(Key::KeyH), 10, None),
+            key(EventType::KeyRelease(Key::KeyI), 60, None),
+            key(EventType::KeyPress(Key::Return), 200, Some("\r")),
+            key(EventType::KeyRelease(Key::Return), 60, None),
+        ];
+
+        let collapsed = collapse_typed_text(events);
+        assert_eq!(collapsed.len(), 4);
+        assert_eq!(collapsed[1].event, MacroEvent::TypeText("Hi".to_string()));
+        assert_eq!(collapsed[1].time_since_previous, Duration::from_millis(500));
+        assert_eq!(collapsed[1].window, Some(editor));
+        assert_eq!(collapsed[2].event, MacroEvent::Input(EventType::KeyPress(Key::Return)));
+    }
+
+    #[test]
+    fn test_shortcuts_are_not_collapsed() {
+        let events = vec![
+            key(EventType::KeyPress(Key::ControlLeft), 0, None),
+            key(EventType::KeyPress(Key::KeyC), 50, Some("c")),
+            key(EventType::KeyRelease(Key::KeyC), 50, None),
+            key(EventType::KeyRelease(Key::ControlLeft), 50, None),
+            key(EventType::KeyPress(Key::KeyA), 50, Some("a")),
+            key(EventType::KeyRelease(Key::KeyA), 50, None),
+        ];
+
+        let collapsed = collapse_typed_text(events);
+        assert_eq!(collapsed.len(), 5);
+        assert_eq!(collapsed[4].event, MacroEvent::TypeText("a".to_string()));
+    }
+
+    #[test]
+    fn test_old_macro_json_still_loads() {
+        let json = r#"{"name":"old","events":[{"event_type":{"type":"key_press","key":"KeyA"},"time_since_previous":0.25}]}"#;
+        let macro_data: Macro = serde_json::from_str(json).unwrap();
+        assert_eq!(macro_data.events[0].event, MacroEvent::Input(EventType::KeyPress(Key::KeyA)));
+        assert!(macro_data.events[0].text.is_none());
+    }
+
+    #[test]
+    fn test_click_target_is_stored_as_click_step() {
+        let event = TimedEvent {
+            target: Some(ClickTarget {
+                target_description: "Element with text 'Submit'".to_string(),
+                target_crop_path: "clicks/20250301-120000-1.png".to_string(),
+                original_xy: (812.0, 440.0),
+                crop_rect: Rect { x: 692, y: 380, width: 240, height: 120 },
+                window_title: None,
+                target_text: Some("Submit".to_string()),
+            }),
+            ..TimedEvent::input(EventType::ButtonPress(Button::Left), Duration::ZERO)
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["target"]["action"], "click");
+        assert_eq!(json["target"]["original_xy"], serde_json::json!([812.0, 440.0]));
+
+        let parsed: TimedEvent = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, event);
+    }
+
+    #[test]
+    fn test_failed_playback_releases_held_keys() {
+        let editor = WindowContext { title: "notes.txt - gedit".to_string(), class: "Gedit".to_string(), pid: None };
+        let terminal = WindowContext { title: "bash".to_string(), class: "Gnome-terminal".to_string(), pid: None };
+        let macro_data = Macro {
+            name: "copy".to_string(),
+            events: vec![
+                key(EventType::KeyPress(Key::ControlLeft), 0, None),
+                key(EventType::ButtonPress(Button::Left), 0, None),
+                key(EventType::ButtonRelease(Button::Left), 0, None),
+                TimedEvent { window: Some(editor), ..key(EventType::KeyPress(Key::KeyC), 50, None) },
+                key(EventType::KeyRelease(Key::KeyC), 50, None),
+                key(EventType::KeyRelease(Key::ControlLeft), 50, None),
+            ],
+        };
+
+        let mut backend = DryRunInput::new().with_active_window(terminal);
+        let err = play_macro_with(&macro_data, &mut backend).unwrap_err();
+        assert!(matches!(err, MacroError::WrongWindow { step: 4, .. }), "{}", err);
+        let transcript = backend.take_transcript();
+        assert_eq!(transcript.last().map(String::as_str), Some("t=2.05s release key ControlLeft"), "{:?}", transcript);
+    }
+
+    #[test]
+    fn test_steps_recorded_in_nyx_are_not_checked() {
+        let nyx = WindowContext { title: "Nyx".to_string(), class: "nyx".to_string(), pid: Some(std::process::id()) };
+        let terminal = WindowContext { title: "bash".to_string(), class: "Gnome-terminal".to_string(), pid: None };
+        let macro_data = Macro {
+            name: "record".to_string(),
+            events: vec![
+                TimedEvent { window: Some(nyx), ..key(EventType::ButtonPress(Button::Left), 0, None) },
+                key(EventType::ButtonRelease(Button::Left), 50, None),
+            ],
+        };
+
+        let mut backend = DryRunInput::new().with_active_window(terminal);
+        play_macro_with(&macro_data, &mut backend).unwrap();
+        assert_eq!(backend.take_transcript(), vec!["t=0.00s click left at the current position"]);
+    }
+}
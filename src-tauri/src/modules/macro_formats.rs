@@ -0,0 +1,688 @@
+// Import/export of macros to portable formats
+// Supports a self-contained `.nyxmacro` bundle, generated xdotool scripts and a
+// line-based text DSL that can be edited by hand and parsed back into `TimedEvent`s.
+
+use crate::modules::macro_engine::{key_name, try_parse_key, Macro, MacroError, MacroEvent, TimedEvent};
+use crate::modules::macro_secrets::SecretRef;
+use crate::modules::perception::{Rect, ScreenWait};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use rdev::{Button, EventType, Key};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+/// Consecutive keystrokes separated by more than this are not merged into one `type` line.
+const TYPE_RUN_MAX_GAP: Duration = Duration::from_millis(250);
+
+const BUNDLE_VERSION: u32 = 1;
+
+/// An asset file from a bundle: path relative to the macro's asset folder, and contents.
+pub type BundleAsset = (PathBuf, Vec<u8>);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MacroFormat {
+    /// JSON macro plus base64-encoded assets in a single `.nyxmacro` file.
+    Bundle,
+    /// A `#!/bin/sh` script driving `xdotool`. Export only.
+    Xdotool,
+    /// The line-based `move 100 200` / `click left` text format.
+    Dsl,
+}
+
+impl MacroFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            MacroFormat::Bundle => "nyxmacro",
+            MacroFormat::Xdotool => "sh",
+            MacroFormat::Dsl => "nyx",
+        }
+    }
+
+    /// Guesses the format from a file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "nyxmacro" => Some(MacroFormat::Bundle),
+            "sh" => Some(MacroFormat::Xdotool),
+            "nyx" | "txt" => Some(MacroFormat::Dsl),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Bundle {
+    version: u32,
+    #[serde(rename = "macro")]
+    macro_data: Macro,
+    /// Asset path relative to the macro's asset folder -> base64 file contents.
+    #[serde(default)]
+    assets: BTreeMap<String, String>,
+}
+
+// --- Bundle ---
+
+/// Serializes a macro and every file under `assets_dir` into a `.nyxmacro` bundle.
+pub fn to_bundle(macro_data: &Macro, assets_dir: Option<&Path>) -> Result<String, MacroError> {
+    let mut assets = BTreeMap::new();
+    if let Some(dir) = assets_dir.filter(|dir| dir.is_dir()) {
+        collect_assets(dir, dir, &mut assets)?;
+    }
+    let bundle = Bundle {
+        version: BUNDLE_VERSION,
+        macro_data: macro_data.clone(),
+        assets,
+    };
+    Ok(serde_json::to_string_pretty(&bundle)?)
+}
+
+fn collect_assets(root: &Path, dir: &Path, assets: &mut BTreeMap<String, String>) -> Result<(), MacroError> {
+    let entries = fs::read_dir(dir)
+        .map_err(|e| MacroError::FileSystem(format!("Could not read assets directory: {}", e)))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| MacroError::FileSystem(format!("Could not read directory entry: {}", e)))?
+            .path();
+        if path.is_dir() {
+            collect_assets(root, &path, assets)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            let bytes = fs::read(&path)
+                .map_err(|e| MacroError::FileSystem(format!("Failed to read asset {:?}: {}", path, e)))?;
+            let key = relative.to_string_lossy().replace('\\', "/");
+            assets.insert(key, STANDARD.encode(bytes));
+        }
+    }
+    Ok(())
+}
+
+/// Parses a `.nyxmacro` bundle into the macro and its decoded assets.
+pub fn from_bundle(contents: &str) -> Result<(Macro, Vec<BundleAsset>), MacroError> {
+    let bundle: Bundle = serde_json::from_str(contents)?;
+    if bundle.version > BUNDLE_VERSION {
+        return Err(MacroError::UnsupportedFormat(format!(
+            "bundle version {} is newer than this version of Nyx supports",
+            bundle.version
+        )));
+    }
+
+    let mut assets = Vec::with_capacity(bundle.assets.len());
+    for (name, data) in bundle.assets {
+        let path = PathBuf::from(&name);
+        // Assets must stay inside the macro's own folder.
+        if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(MacroError::FileSystem(format!("Refusing to import asset outside macro folder: {}", name)));
+        }
+        let bytes = STANDARD
+            .decode(data)
+            .map_err(|e| MacroError::FileSystem(format!("Asset {} is not valid base64: {}", name, e)))?;
+        assets.push((path, bytes));
+    }
+    Ok((bundle.macro_data, assets))
+}
+
+// --- Text DSL ---
+
+/// Writes a macro as DSL lines. Press/release pairs are collapsed into `click`, `key`
+/// and `type` lines; delays become `wait` lines rounded to the millisecond.
+pub fn to_dsl(macro_data: &Macro) -> String {
+    let mut out = format!("# Nyx macro: {}\n", macro_data.name);
+    let events = &macro_data.events;
+    let mut i = 0;
+
+    while i < events.len() {
+        write_wait(&mut out, events[i].time_since_previous);
+
+        match &events[i].event {
+            MacroEvent::TypeText(text) => {
+                out.push_str(&format!("type {}\n", quote(text)));
+                i += 1;
+                continue;
+            }
+            MacroEvent::Secret(secret) => {
+                out.push_str(&format!("secret {}\n", secret.id));
+                i += 1;
+                continue;
+            }
+            MacroEvent::WaitFor(wait) => {
+                out.push_str(&wait_until_line(wait));
+                i += 1;
+                continue;
+            }
+            MacroEvent::Input(_) => {}
+        }
+
+        // Collapse a run of typed characters into one `type` line.
+        let mut text = String::new();
+        let mut j = i;
+        while let Some((c, consumed)) = typed_char_at(events, j) {
+            if j > i && events[j].time_since_previous > TYPE_RUN_MAX_GAP {
+                break;
+            }
+            text.push(c);
+            j += consumed;
+        }
+        if !text.is_empty() {
+            out.push_str(&format!("type {}\n", quote(&text)));
+            i = j;
+            continue;
+        }
+
+        let next = events.get(i + 1).and_then(|e| e.event.as_input());
+        match (events[i].event.as_input(), next) {
+            (Some(EventType::ButtonPress(a)), Some(EventType::ButtonRelease(b))) if a == b && quick(events, i + 1) => {
+                out.push_str(&format!("click {}\n", button_name(a)));
+                i += 2;
+                continue;
+            }
+            (Some(EventType::KeyPress(a)), Some(EventType::KeyRelease(b))) if a == b && quick(events, i + 1) => {
+                out.push_str(&format!("key {}\n", key_name(*a)));
+                i += 2;
+                continue;
+            }
+            (Some(event_type), _) => out.push_str(&dsl_line(event_type)),
+            (None, _) => {}
+        }
+        i += 1;
+    }
+    out
+}
+
+/// Parses DSL text back into a macro. Errors carry the 1-based line number.
+pub fn from_dsl(name: &str, text: &str) -> Result<Macro, MacroError> {
+    let mut events = Vec::new();
+    let mut pending_wait = Duration::ZERO;
+
+    for (index, raw_line) in text.lines().enumerate() {
+        let line_no = index + 1;
+        let err = |message: String| MacroError::Parse { line: line_no, message };
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (command, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let args = args.trim();
+        let mut push_event = |event: MacroEvent| {
+            events.push(TimedEvent {
+                event,
+                time_since_previous: std::mem::take(&mut pending_wait),
+                text: None,
+                target: None,
+                window: None,
+            });
+        };
+        let mut push = |event_type: EventType| push_event(MacroEvent::Input(event_type));
+
+        match command {
+            "wait" => {
+                let delay = parse_wait(args).ok_or_else(|| err(format!("invalid duration '{}', expected e.g. 300ms or 1.5s", args)))?;
+                pending_wait += delay;
+            }
+            "move" => {
+                let coords: Vec<f64> = args.split_whitespace().filter_map(|v| v.parse().ok()).collect();
+                if coords.len() != 2 || args.split_whitespace().count() != 2 {
+                    return Err(err("expected 'move X Y'".to_string()));
+                }
+                push(EventType::MouseMove { x: coords[0], y: coords[1] });
+            }
+            "click" | "press" | "release" => {
+                let button = parse_button_name(args).ok_or_else(|| err(format!("unknown mouse button '{}'", args)))?;
+                match command {
+                    "click" => {
+                        push(EventType::ButtonPress(button));
+                        push(EventType::ButtonRelease(button));
+                    }
+                    "press" => push(EventType::ButtonPress(button)),
+                    _ => push(EventType::ButtonRelease(button)),
+                }
+            }
+            "key" | "keydown" | "keyup" => {
+                let key = try_parse_key(args).ok_or_else(|| err(format!("unknown key '{}'", args)))?;
+                match command {
+                    "key" => {
+                        push(EventType::KeyPress(key));
+                        push(EventType::KeyRelease(key));
+                    }
+                    "keydown" => push(EventType::KeyPress(key)),
+                    _ => push(EventType::KeyRelease(key)),
+                }
+            }
+            "type" => {
+                let text = unquote(args).ok_or_else(|| err("expected a quoted string, e.g. type \"hello\"".to_string()))?;
+                push_event(MacroEvent::TypeText(text));
+            }
+            "secret" => {
+                if args.is_empty() || args.contains(char::is_whitespace) {
+                    return Err(err("expected 'secret ID'".to_string()));
+                }
+                push_event(MacroEvent::Secret(SecretRef { id: args.to_string() }));
+            }
+            "wait_until" => {
+                let wait = parse_wait_until(args).ok_or_else(|| {
+                    err("expected 'wait_until stable DURATION TIMEOUT' or 'wait_until change X Y W H TIMEOUT'".to_string())
+                })?;
+                push_event(MacroEvent::WaitFor(wait));
+            }
+            "scroll" => {
+                let deltas: Vec<i64> = args.split_whitespace().filter_map(|v| v.parse().ok()).collect();
+                if deltas.len() != 2 || args.split_whitespace().count() != 2 {
+                    return Err(err("expected 'scroll DX DY'".to_string()));
+                }
+                push(EventType::Wheel { delta_x: deltas[0], delta_y: deltas[1] });
+            }
+            other => return Err(err(format!("unknown command '{}'", other))),
+        }
+    }
+
+    Ok(Macro {
+        name: name.to_string(),
+        events,
+    })
+}
+
+fn dsl_line(event_type: &EventType) -> String {
+    match event_type {
+        EventType::MouseMove { x, y } => format!("move {} {}\n", format_coord(*x), format_coord(*y)),
+        EventType::ButtonPress(button) => format!("press {}\n", button_name(button)),
+        EventType::ButtonRelease(button) => format!("release {}\n", button_name(button)),
+        EventType::KeyPress(key) => format!("keydown {}\n", key_name(*key)),
+        EventType::KeyRelease(key) => format!("keyup {}\n", key_name(*key)),
+        EventType::Wheel { delta_x, delta_y } => format!("scroll {} {}\n", delta_x, delta_y),
+    }
+}
+
+fn write_wait(out: &mut String, delay: Duration) {
+    let millis = delay.as_secs_f64() * 1000.0;
+    if millis.round() >= 1.0 {
+        out.push_str(&format!("wait {}ms\n", millis.round() as u64));
+    }
+}
+
+/// True if the event at `index` follows its predecessor closely enough to be part of
+/// a click or keystroke.
+fn quick(events: &[TimedEvent], index: usize) -> bool {
+    events[index].time_since_previous <= TYPE_RUN_MAX_GAP
+}
+
+/// Recognises a typed character starting at `index`: either `press k, release k` or
+/// `press shift, press k, release k, release shift`. Returns the char and events consumed.
+fn typed_char_at(events: &[TimedEvent], index: usize) -> Option<(char, usize)> {
+    let kinds: Vec<Option<&EventType>> = events[index..].iter().take(4).map(|e| e.event.as_input()).collect();
+    match kinds.as_slice() {
+        [Some(EventType::KeyPress(s1)), Some(EventType::KeyPress(k1)), Some(EventType::KeyRelease(k2)), Some(EventType::KeyRelease(s2)), ..]
+            if is_shift(s1) && s1 == s2 && k1 == k2 && (1..4).all(|o| quick(events, index + o)) =>
+        {
+            keystroke_to_char(*k1, true).map(|c| (c, 4))
+        }
+        [Some(EventType::KeyPress(k1)), Some(EventType::KeyRelease(k2)), ..] if k1 == k2 && quick(events, index + 1) => {
+            keystroke_to_char(*k1, false).map(|c| (c, 2))
+        }
+        _ => None,
+    }
+}
+
+fn is_shift(key: &Key) -> bool {
+    matches!(key, Key::ShiftLeft | Key::ShiftRight)
+}
+
+fn parse_wait(s: &str) -> Option<Duration> {
+    let (value, scale) = if let Some(v) = s.strip_suffix("ms") {
+        (v, 0.001)
+    } else if let Some(v) = s.strip_suffix('s') {
+        (v, 1.0)
+    } else {
+        return None;
+    };
+    let value: f64 = value.trim().parse().ok()?;
+    // Refuses negative, NaN and overflowing durations such as 1e30s.
+    Duration::try_from_secs_f64(value * scale).ok()
+}
+
+fn wait_until_line(wait: &ScreenWait) -> String {
+    match wait {
+        ScreenWait::Stable { stable_ms, timeout_ms } => format!("wait_until stable {}ms {}ms\n", stable_ms, timeout_ms),
+        ScreenWait::RegionChanges { rect, timeout_ms } => format!(
+            "wait_until change {} {} {} {} {}ms\n",
+            rect.x, rect.y, rect.width, rect.height, timeout_ms
+        ),
+    }
+}
+
+fn parse_wait_until(args: &str) -> Option<ScreenWait> {
+    let words: Vec<&str> = args.split_whitespace().collect();
+    let millis = |s: &str| parse_wait(s).map(|d| d.as_millis() as u64);
+    match words.as_slice() {
+        ["stable", stable, timeout] => Some(ScreenWait::Stable { stable_ms: millis(stable)?, timeout_ms: millis(timeout)? }),
+        ["change", x, y, width, height, timeout] => Some(ScreenWait::RegionChanges {
+            rect: Rect { x: x.parse().ok()?, y: y.parse().ok()?, width: width.parse().ok()?, height: height.parse().ok()? },
+            timeout_ms: millis(timeout)?,
+        }),
+        _ => None,
+    }
+}
+
+fn format_coord(value: f64) -> String {
+    if value.fract() == 0.0 {
+        format!("{:.0}", value)
+    } else {
+        format!("{}", value)
+    }
+}
+
+fn button_name(button: &Button) -> String {
+    match button {
+        Button::Left => "left".to_string(),
+        Button::Right => "right".to_string(),
+        Button::Middle => "middle".to_string(),
+        other => format!("{:?}", other).to_lowercase(),
+    }
+}
+
+fn parse_button_name(s: &str) -> Option<Button> {
+    match s.to_lowercase().as_str() {
+        "left" => Some(Button::Left),
+        "right" => Some(Button::Right),
+        "middle" => Some(Button::Middle),
+        _ => None,
+    }
+}
+
+fn quote(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            _ => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn unquote(s: &str) -> Option<String> {
+    let inner = s.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next()? {
+                'n' => out.push('\n'),
+                't' => out.push('\t'),
+                other => out.push(other),
+            },
+            '"' => return None,
+            _ => out.push(c),
+        }
+    }
+    Some(out)
+}
+
+/// US-layout mapping from a physical key (plus shift) to the character it produces.
+fn keystroke_to_char(key: Key, shift: bool) -> Option<char> {
+    let (plain, shifted) = match key {
+        Key::KeyA => ('a', 'A'), Key::KeyB => ('b', 'B'), Key::KeyC => ('c', 'C'), Key::KeyD => ('d', 'D'),
+        Key::KeyE => ('e', 'E'), Key::KeyF => ('f', 'F'), Key::KeyG => ('g', 'G'), Key::KeyH => ('h', 'H'),
+        Key::KeyI => ('i', 'I'), Key::KeyJ => ('j', 'J'), Key::KeyK => ('k', 'K'), Key::KeyL => ('l', 'L'),
+        Key::KeyM => ('m', 'M'), Key::KeyN => ('n', 'N'), Key::KeyO => ('o', 'O'), Key::KeyP => ('p', 'P'),
+        Key::KeyQ => ('q', 'Q'), Key::KeyR => ('r', 'R'), Key::KeyS => ('s', 'S'), Key::KeyT => ('t', 'T'),
+        Key::KeyU => ('u', 'U'), Key::KeyV => ('v', 'V'), Key::KeyW => ('w', 'W'), Key::KeyX => ('x', 'X'),
+        Key::KeyY => ('y', 'Y'), Key::KeyZ => ('z', 'Z'),
+        Key::Num1 => ('1', '!'), Key::Num2 => ('2', '@'), Key::Num3 => ('3', '#'), Key::Num4 => ('4', '$'),
+        Key::Num5 => ('5', '%'), Key::Num6 => ('6', '^'), Key::Num7 => ('7', '&'), Key::Num8 => ('8', '*'),
+        Key::Num9 => ('9', '('), Key::Num0 => ('0', ')'),
+        Key::Minus => ('-', '_'), Key::Equal => ('=', '+'),
+        Key::LeftBracket => ('[', '{'), Key::RightBracket => (']', '}'),
+        Key::SemiColon => (';', ':'), Key::Quote => ('\'', '"'), Key::BackSlash => ('\\', '|'),
+        Key::Comma => (',', '<'), Key::Dot => ('.', '>'), Key::Slash => ('/', '?'),
+        Key::BackQuote => ('`', '~'),
+        Key::Space if !shift => (' ', ' '),
+        _ => return None,
+    };
+    Some(if shift { shifted } else { plain })
+}
+
+// --- xdotool ---
+
+/// Generates a POSIX shell script that replays the macro with xdotool.
+pub fn to_xdotool(macro_data: &Macro) -> String {
+    let mut out = String::from("#!/bin/sh\n");
+    out.push_str(&format!("# Generated by Nyx from macro '{}'\nset -e\n\n", macro_data.name.replace('\n', " ")));
+
+    for timed_event in &macro_data.events {
+        let delay = timed_event.time_since_previous.as_secs_f64();
+        if delay >= 0.001 {
+            out.push_str(&format!("sleep {:.3}\n", delay));
+        }
+        let event_type = match &timed_event.event {
+            MacroEvent::Input(event_type) => event_type,
+            MacroEvent::TypeText(text) => {
+                out.push_str(&format!("xdotool type -- {}\n", shell_quote(text)));
+                continue;
+            }
+            MacroEvent::Secret(secret) => {
+                out.push_str(&format!("# secret '{}' is kept in the Nyx keyring and was not exported\n", secret.id));
+                continue;
+            }
+            MacroEvent::WaitFor(wait) => {
+                // xdotool cannot watch the screen, so sleep for the settle time, or for the
+                // whole timeout when waiting for a change.
+                let millis = match wait {
+                    ScreenWait::Stable { stable_ms, .. } => stable_ms,
+                    ScreenWait::RegionChanges { timeout_ms, .. } => timeout_ms,
+                };
+                out.push_str(&format!("# {}", wait_until_line(wait)));
+                out.push_str(&format!("sleep {:.3}\n", *millis as f64 / 1000.0));
+                continue;
+            }
+        };
+        match event_type {
+            EventType::MouseMove { x, y } => {
+                out.push_str(&format!("xdotool mousemove {} {}\n", x.round() as i64, y.round() as i64))
+            }
+            EventType::ButtonPress(button) => out.push_str(&format!("xdotool mousedown {}\n", xdotool_button(button))),
+            EventType::ButtonRelease(button) => out.push_str(&format!("xdotool mouseup {}\n", xdotool_button(button))),
+            EventType::KeyPress(key) => match xdotool_keysym(*key) {
+                Some(sym) => out.push_str(&format!("xdotool keydown {}\n", sym)),
+                None => out.push_str(&format!("# unsupported key press: {:?}\n", key)),
+            },
+            EventType::KeyRelease(key) => match xdotool_keysym(*key) {
+                Some(sym) => out.push_str(&format!("xdotool keyup {}\n", sym)),
+                None => out.push_str(&format!("# unsupported key release: {:?}\n", key)),
+            },
+            EventType::Wheel { delta_x, delta_y } => {
+                // X11 maps scrolling to buttons 4 (up), 5 (down), 6 (left) and 7 (right).
+                let vertical = if *delta_y > 0 { 4 } else { 5 };
+                let horizontal = if *delta_x > 0 { 7 } else { 6 };
+                if *delta_y != 0 {
+                    out.push_str(&format!("xdotool click --repeat {} {}\n", delta_y.unsigned_abs(), vertical));
+                }
+                if *delta_x != 0 {
+                    out.push_str(&format!("xdotool click --repeat {} {}\n", delta_x.unsigned_abs(), horizontal));
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Single-quotes a string for POSIX shells.
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+fn xdotool_button(button: &Button) -> u8 {
+    match button {
+        Button::Left => 1,
+        Button::Middle => 2,
+        Button::Right => 3,
+        Button::Unknown(n) => *n,
+    }
+}
+
+fn xdotool_keysym(key: Key) -> Option<String> {
+    let sym = match key {
+        Key::Return => "Return",
+        Key::Escape => "Escape",
+        Key::Backspace => "BackSpace",
+        Key::Tab => "Tab",
+        Key::Space => "space",
+        Key::Delete => "Delete",
+        Key::ShiftLeft => "Shift_L",
+        Key::ShiftRight => "Shift_R",
+        Key::ControlLeft => "Control_L",
+        Key::ControlRight => "Control_R",
+        Key::Alt => "Alt_L",
+        Key::AltGr => "ISO_Level3_Shift",
+        Key::MetaLeft => "Super_L",
+        Key::MetaRight => "Super_R",
+        Key::UpArrow => "Up",
+        Key::DownArrow => "Down",
+        Key::LeftArrow => "Left",
+        Key::RightArrow => "Right",
+        Key::Home => "Home",
+        Key::End => "End",
+        Key::PageUp => "Prior",
+        Key::PageDown => "Next",
+        Key::F1 => "F1", Key::F2 => "F2", Key::F3 => "F3", Key::F4 => "F4",
+        Key::F5 => "F5", Key::F6 => "F6", Key::F7 => "F7", Key::F8 => "F8",
+        Key::F9 => "F9", Key::F10 => "F10", Key::F11 => "F11", Key::F12 => "F12",
+        // Punctuation goes by its keysym name; the characters mean something to the shell.
+        Key::Minus => "minus", Key::Equal => "equal",
+        Key::LeftBracket => "bracketleft", Key::RightBracket => "bracketright",
+        Key::SemiColon => "semicolon", Key::Quote => "apostrophe", Key::BackSlash => "backslash",
+        Key::Comma => "comma", Key::Dot => "period", Key::Slash => "slash",
+        Key::BackQuote => "grave",
+        other => {
+            return keystroke_to_char(other, false).filter(char::is_ascii_alphanumeric).map(|c| c.to_string())
+        }
+    };
+    Some(sym.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(event_type: EventType, millis: u64) -> TimedEvent {
+        TimedEvent::input(event_type, Duration::from_millis(millis))
+    }
+
+    #[test]
+    fn test_dsl_round_trip() {
+        let text = "# sample\nmove 100 200\nwait 300ms\nclick left\ntype \"Hi there!\"\nwait 1.5s\nkey Return\nscroll 0 -3\nwait_until stable 500ms 10s\nwait_until change 0 0 100 50 3s\n";
+        let parsed = from_dsl("sample", text).unwrap();
+        assert_eq!(parsed.events[0].event, MacroEvent::Input(EventType::MouseMove { x: 100.0, y: 200.0 }));
+        assert_eq!(parsed.events[3].event, MacroEvent::TypeText("Hi there!".to_string()));
+        assert_eq!(parsed.events[1].time_since_previous, Duration::from_millis(300));
+        assert_eq!(parsed.events[7].event, MacroEvent::WaitFor(ScreenWait::Stable { stable_ms: 500, timeout_ms: 10_000 }));
+
+        let exported = to_dsl(&parsed);
+        assert!(exported.contains("click left\n"));
+        assert!(exported.contains("type \"Hi there!\"\n"));
+        assert!(exported.contains("wait 1500ms\nkey Return\n"));
+        assert!(exported.contains("wait_until change 0 0 100 50 3000ms\n"));
+
+        let reparsed = from_dsl("sample", &exported).unwrap();
+        let original: Vec<_> = parsed.events.iter().map(|e| (e.event.clone(), e.time_since_previous)).collect();
+        let again: Vec<_> = reparsed.events.iter().map(|e| (e.event.clone(), e.time_since_previous)).collect();
+        assert_eq!(original, again);
+    }
+
+    #[test]
+    fn test_dsl_parse_errors_have_line_numbers() {
+        let err = from_dsl("bad", "move 1 2\n\nclick sideways\n").unwrap_err();
+        assert!(matches!(err, MacroError::Parse { line: 3, .. }), "{}", err);
+
+        let err = from_dsl("bad", "wait soon\n").unwrap_err();
+        assert!(matches!(err, MacroError::Parse { line: 1, .. }));
+
+        let err = from_dsl("bad", "type hello\n").unwrap_err();
+        assert!(err.to_string().starts_with("Line 1:"));
+    }
+
+    #[test]
+    fn test_every_key_round_trips() {
+        use crate::modules::macro_engine::KEY_NAMES;
+        let keys: Vec<Key> = KEY_NAMES.iter().map(|(key, _)| *key).chain([Key::Unknown(42)]).collect();
+        // All keys down, then all up, so no press is followed by its own release and
+        // collapsed into typed text.
+        let events = keys
+            .iter()
+            .map(|key| event(EventType::KeyPress(*key), 0))
+            .chain(keys.iter().map(|key| event(EventType::KeyRelease(*key), 0)))
+            .collect();
+        let macro_data = Macro { name: "keys".to_string(), events };
+
+        let reparsed = from_dsl("keys", &to_dsl(&macro_data)).unwrap();
+        let again: Vec<MacroEvent> = reparsed.events.into_iter().map(|e| e.event).collect();
+        let original: Vec<MacroEvent> = macro_data.events.iter().map(|e| e.event.clone()).collect();
+        assert_eq!(again, original);
+
+        let json = serde_json::to_string(&macro_data).unwrap();
+        assert_eq!(serde_json::from_str::<Macro>(&json).unwrap().events, macro_data.events);
+    }
+
+    #[test]
+    fn test_huge_wait_is_a_line_error() {
+        let err = from_dsl("bad", "move 1 2\nwait 1e30s\n").unwrap_err();
+        assert!(matches!(err, MacroError::Parse { line: 2, .. }), "{}", err);
+        let err = from_dsl("bad", "wait_until stable 1e30s 1s\n").unwrap_err();
+        assert!(matches!(err, MacroError::Parse { line: 1, .. }), "{}", err);
+    }
+
+    #[test]
+    fn test_slow_keystrokes_split_type_runs() {
+        let macro_data = Macro {
+            name: "slow".to_string(),
+            events: vec![
+                event(EventType::KeyPress(Key::KeyA), 0),
+                event(EventType::KeyRelease(Key::KeyA), 40),
+                event(EventType::KeyPress(Key::KeyB), 900),
+                event(EventType::KeyRelease(Key::KeyB), 40),
+            ],
+        };
+        assert_eq!(to_dsl(&macro_data), "# Nyx macro: slow\ntype \"a\"\nwait 900ms\ntype \"b\"\n");
+    }
+
+    #[test]
+    fn test_xdotool_script() {
+        let macro_data = from_dsl("x", "move 10 20\nwait 250ms\nclick right\nkey PageDown\nscroll 0 -2\ntype \"it's\"\n").unwrap();
+        let script = to_xdotool(&macro_data);
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains("xdotool mousemove 10 20\nsleep 0.250\nxdotool mousedown 3\nxdotool mouseup 3\n"));
+        assert!(script.contains("xdotool keydown Next\nxdotool keyup Next\n"));
+        assert!(script.contains("xdotool click --repeat 2 5\n"));
+        assert!(script.contains("xdotool type -- 'it'\\''s'\n"));
+
+        let punctuation = Macro {
+            name: "p".to_string(),
+            events: vec![
+                event(EventType::KeyPress(Key::SemiColon), 0),
+                event(EventType::KeyRelease(Key::SemiColon), 40),
+                event(EventType::KeyPress(Key::LeftBracket), 40),
+            ],
+        };
+        let script = to_xdotool(&punctuation);
+        assert!(script.contains("xdotool keydown semicolon\n"), "{}", script);
+        assert!(script.contains("xdotool keyup semicolon\n"), "{}", script);
+        assert!(script.contains("xdotool keydown bracketleft\n"), "{}", script);
+    }
+
+    #[test]
+    fn test_bundle_rejects_escaping_assets() {
+        let bundle = r#"{"version":1,"macro":{"name":"m","events":[]},"assets":{"../evil.sh":""}}"#;
+        assert!(from_bundle(bundle).is_err());
+
+        let bundle = r#"{"version":1,"macro":{"name":"m","events":[]},"assets":{"crops/1.png":"aGk="}}"#;
+        let (macro_data, assets) = from_bundle(bundle).unwrap();
+        assert_eq!(macro_data.name, "m");
+        assert_eq!(assets, vec![(PathBuf::from("crops/1.png"), b"hi".to_vec())]);
+    }
+}
@@ -0,0 +1,416 @@
+// Macro version history
+// Every save of a macro is kept as a numbered revision in
+// `nyx-agent/macro_history/{name}/{revision}.json`, so a bad recording never destroys a
+// working macro. Revisions can be listed, restored and compared. The secrets a revision
+// types are snapshotted in the keyring with it, so a restore brings back their values too.
+
+use crate::modules::macro_engine::{self, Macro, MacroError, MacroEvent, TimedEvent};
+use crate::modules::macro_secrets::{self, secret_ids};
+use crate::modules::persistence;
+use chrono::{Local, NaiveDateTime};
+use rdev::EventType;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tauri::Manager;
+
+/// Revisions kept per macro; older ones are pruned on save.
+const MAX_REVISIONS: usize = 50;
+
+/// Delay changes smaller than this are not reported as retiming.
+const RETIME_TOLERANCE: Duration = Duration::from_millis(5);
+
+/// Above this many comparison cells the differing middle sections are reported as
+/// removed and added wholesale instead of being aligned event by event.
+const MAX_ALIGNMENT_CELLS: usize = 16_000_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RevisionInfo {
+    pub revision: u32,
+    pub saved_at: NaiveDateTime,
+    pub event_count: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct StoredRevision {
+    #[serde(flatten)]
+    info: RevisionInfo,
+    #[serde(rename = "macro")]
+    macro_data: Macro,
+}
+
+/// A single semantic difference between two revisions. Indices refer to the event lists
+/// of the older (`old_index`) and newer (`new_index`) revision.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum EventChange {
+    Added { new_index: usize, event: TimedEvent },
+    Removed { old_index: usize, event: TimedEvent },
+    Retimed { old_index: usize, new_index: usize, old_delay_secs: f64, new_delay_secs: f64 },
+    Moved { old_index: usize, new_index: usize, from: (f64, f64), to: (f64, f64) },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MacroDiff {
+    pub name: String,
+    pub from_revision: u32,
+    pub to_revision: u32,
+    pub changes: Vec<EventChange>,
+}
+
+/// Returns the history folder of a macro, creating it if it doesn't exist.
+fn history_dir(name: &str, app_handle: &tauri::AppHandle) -> Result<PathBuf, MacroError> {
+    let config_dir = app_handle
+        .path()
+        .app_config_dir()
+        .map_err(|e| MacroError::FileSystem(format!("Failed to get config dir: {}", e)))?;
+    let dir = config_dir.join("nyx-agent/macro_history").join(name);
+
+    fs::create_dir_all(&dir)
+        .map_err(|e| MacroError::FileSystem(format!("Failed to create history dir: {}", e)))?;
+    Ok(dir)
+}
+
+fn revision_path(dir: &Path, revision: u32) -> PathBuf {
+    dir.join(format!("{}.json", revision))
+}
+
+fn revision_numbers(dir: &Path) -> Result<Vec<u32>, MacroError> {
+    let entries = fs::read_dir(dir)
+        .map_err(|e| MacroError::FileSystem(format!("Could not read history directory: {}", e)))?;
+    let mut revisions: Vec<u32> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|s| s.to_str()) == Some("json"))
+        .filter_map(|path| path.file_stem()?.to_str()?.parse().ok())
+        .collect();
+    revisions.sort_unstable();
+    Ok(revisions)
+}
+
+fn read_revision(dir: &Path, name: &str, revision: u32) -> Result<StoredRevision, MacroError> {
+    let path = revision_path(dir, revision);
+    if !path.is_file() {
+        return Err(MacroError::RevisionNotFound { name: name.to_string(), revision });
+    }
+    let json_string = fs::read_to_string(&path)
+        .map_err(|e| MacroError::FileSystem(format!("Failed to read revision file: {}", e)))?;
+    Ok(serde_json::from_str(&json_string)?)
+}
+
+/// Appends `macro_data` as the next revision in `dir` and returns its info.
+fn append_revision(dir: &Path, macro_data: &Macro, saved_at: NaiveDateTime) -> Result<RevisionInfo, MacroError> {
+    let revision = revision_numbers(dir)?.last().map_or(1, |last| last + 1);
+    let stored = StoredRevision {
+        info: RevisionInfo { revision, saved_at, event_count: macro_data.events.len() },
+        macro_data: macro_data.clone(),
+    };
+    persistence::write_json(&revision_path(dir, revision), &stored)
+        .map_err(|e| MacroError::FileSystem(format!("Failed to write revision file: {}", e)))?;
+    Ok(stored.info)
+}
+
+/// Deletes all but the newest `keep` revisions in `dir`. Returns each deleted revision
+/// with the secret ids it referenced.
+fn prune_revisions(dir: &Path, name: &str, keep: usize) -> Result<Vec<(u32, Vec<String>)>, MacroError> {
+    let revisions = revision_numbers(dir)?;
+    let excess = revisions.len().saturating_sub(keep);
+    let mut pruned = Vec::new();
+    for &revision in &revisions[..excess] {
+        let ids = read_revision(dir, name, revision)
+            .map(|stored| secret_ids(&stored.macro_data.events))
+            .unwrap_or_default();
+        fs::remove_file(revision_path(dir, revision))
+            .map_err(|e| MacroError::FileSystem(format!("Failed to remove old revision: {}", e)))?;
+        pruned.push((revision, ids));
+    }
+    Ok(pruned)
+}
+
+/// Keyring owner of the secrets snapshotted with a revision.
+fn revision_secret_owner(name: &str, revision: u32) -> String {
+    format!("{}/revision-{}", name, revision)
+}
+
+/// Copies the current values of the secrets a revision types next to that revision.
+fn snapshot_secrets(macro_data: &Macro, revision: u32) {
+    let owner = revision_secret_owner(&macro_data.name, revision);
+    for id in secret_ids(&macro_data.events) {
+        if let Err(e) = macro_secrets::copy_secret(&macro_data.name, &id, &owner, &id) {
+            log::warn!("Could not snapshot secret '{}' of '{}' revision {}: {}", id, macro_data.name, revision, e);
+        }
+    }
+}
+
+/// Records a save of `macro_data`. Must be called before the macro file is overwritten:
+/// a macro saved before history existed gets its current file kept as revision 1.
+pub fn record_save(macro_data: &Macro, app_handle: &tauri::AppHandle) -> Result<RevisionInfo, MacroError> {
+    let dir = history_dir(&macro_data.name, app_handle)?;
+    let now = Local::now().naive_local();
+
+    if revision_numbers(&dir)?.is_empty() && macro_engine::macro_exists(&macro_data.name, app_handle)? {
+        match macro_engine::load_macro(&macro_data.name, app_handle) {
+            Ok(previous) => {
+                let info = append_revision(&dir, &previous, now)?;
+                snapshot_secrets(&previous, info.revision);
+            }
+            Err(e) => log::warn!("Could not keep the previous version of '{}': {}", macro_data.name, e),
+        }
+    }
+
+    let info = append_revision(&dir, macro_data, now)?;
+    snapshot_secrets(macro_data, info.revision);
+    log::info!("Stored revision {} of macro '{}'", info.revision, macro_data.name);
+
+    for (revision, ids) in prune_revisions(&dir, &macro_data.name, MAX_REVISIONS)? {
+        let owner = revision_secret_owner(&macro_data.name, revision);
+        for id in ids {
+            if let Err(e) = macro_secrets::delete_secret(&owner, &id) {
+                log::warn!("Could not remove secret '{}' of pruned revision {}: {}", id, revision, e);
+            }
+        }
+    }
+    Ok(info)
+}
+
+/// Lists the saved revisions of a macro, oldest first.
+pub fn macro_history(name: &str, app_handle: &tauri::AppHandle) -> Result<Vec<RevisionInfo>, MacroError> {
+    let dir = history_dir(name, app_handle)?;
+    revision_numbers(&dir)?
+        .into_iter()
+        .map(|revision| read_revision(&dir, name, revision).map(|stored| stored.info))
+        .collect()
+}
+
+/// Loads a specific revision of a macro.
+pub fn load_revision(name: &str, revision: u32, app_handle: &tauri::AppHandle) -> Result<Macro, MacroError> {
+    let dir = history_dir(name, app_handle)?;
+    Ok(read_revision(&dir, name, revision)?.macro_data)
+}
+
+/// Makes an old revision the current macro, including the secret values it was saved
+/// with. The restore is itself saved as a new revision, so it can be undone the same way.
+pub fn restore_macro_revision(name: &str, revision: u32, app_handle: &tauri::AppHandle) -> Result<Macro, MacroError> {
+    let macro_data = load_revision(name, revision, app_handle)?;
+    let owner = revision_secret_owner(name, revision);
+    for id in secret_ids(&macro_data.events) {
+        // Revisions saved before snapshots existed keep the current value.
+        if let Err(e) = macro_secrets::copy_secret(&owner, &id, name, &id) {
+            log::warn!("Secret '{}' of '{}' revision {} was not restored: {}", id, name, revision, e);
+        }
+    }
+    macro_engine::save_macro(&macro_data, app_handle)?;
+    log::info!("Restored macro '{}' to revision {}", name, revision);
+    Ok(macro_data)
+}
+
+/// Compares two revisions of a macro.
+pub fn diff_revisions(name: &str, from: u32, to: u32, app_handle: &tauri::AppHandle) -> Result<MacroDiff, MacroError> {
+    let old = load_revision(name, from, app_handle)?;
+    let new = load_revision(name, to, app_handle)?;
+    Ok(MacroDiff {
+        name: name.to_string(),
+        from_revision: from,
+        to_revision: to,
+        changes: diff_events(&old.events, &new.events),
+    })
+}
+
+/// Two events are the same step if they only differ in timing or, for mouse moves,
+/// in coordinates.
+fn same_step(a: &TimedEvent, b: &TimedEvent) -> bool {
+    match (&a.event, &b.event) {
+        (MacroEvent::Input(EventType::MouseMove { .. }), MacroEvent::Input(EventType::MouseMove { .. })) => true,
+        (a, b) => a == b,
+    }
+}
+
+/// Aligns the two event lists and reports what was added, removed, retimed and moved.
+pub fn diff_events(old: &[TimedEvent], new: &[TimedEvent]) -> Vec<EventChange> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| same_step(a, b)).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| same_step(a, b))
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    let mut pairs: Vec<(usize, usize)> = (0..prefix).map(|i| (i, i)).collect();
+    pairs.extend(
+        align(old_middle, new_middle)
+            .into_iter()
+            .map(|(i, j)| (i + prefix, j + prefix)),
+    );
+    pairs.extend((0..suffix).map(|k| (old.len() - suffix + k, new.len() - suffix + k)));
+
+    let mut changes = Vec::new();
+    let (mut next_old, mut next_new) = (0, 0);
+    for (old_index, new_index) in pairs {
+        changes.extend((next_old..old_index).map(|i| EventChange::Removed { old_index: i, event: old[i].clone() }));
+        changes.extend((next_new..new_index).map(|j| EventChange::Added { new_index: j, event: new[j].clone() }));
+        changes.extend(compare_matched(old_index, &old[old_index], new_index, &new[new_index]));
+        next_old = old_index + 1;
+        next_new = new_index + 1;
+    }
+    changes.extend((next_old..old.len()).map(|i| EventChange::Removed { old_index: i, event: old[i].clone() }));
+    changes.extend((next_new..new.len()).map(|j| EventChange::Added { new_index: j, event: new[j].clone() }));
+    changes
+}
+
+fn compare_matched(old_index: usize, old: &TimedEvent, new_index: usize, new: &TimedEvent) -> Vec<EventChange> {
+    let mut changes = Vec::new();
+    let (old_delay, new_delay) = (old.time_since_previous, new.time_since_previous);
+    let delay_change = if old_delay > new_delay { old_delay - new_delay } else { new_delay - old_delay };
+    if delay_change > RETIME_TOLERANCE {
+        changes.push(EventChange::Retimed {
+            old_index,
+            new_index,
+            old_delay_secs: old_delay.as_secs_f64(),
+            new_delay_secs: new_delay.as_secs_f64(),
+        });
+    }
+    if let (
+        MacroEvent::Input(EventType::MouseMove { x: old_x, y: old_y }),
+        MacroEvent::Input(EventType::MouseMove { x: new_x, y: new_y }),
+    ) = (&old.event, &new.event)
+    {
+        if old_x != new_x || old_y != new_y {
+            changes.push(EventChange::Moved { old_index, new_index, from: (*old_x, *old_y), to: (*new_x, *new_y) });
+        }
+    }
+    changes
+}
+
+/// Longest common subsequence of steps, as index pairs into `old` and `new`.
+fn align(old: &[TimedEvent], new: &[TimedEvent]) -> Vec<(usize, usize)> {
+    if old.is_empty() || new.is_empty() || (old.len() + 1) * (new.len() + 1) > MAX_ALIGNMENT_CELLS {
+        return Vec::new();
+    }
+
+    // lengths[i][j] = LCS length of old[i..] and new[j..]
+    let width = new.len() + 1;
+    let mut lengths = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i * width + j] = if same_step(&old[i], &new[j]) {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if same_step(&old[i], &new[j]) {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rdev::{Button, Key};
+
+    fn ev(event_type: EventType, millis: u64) -> TimedEvent {
+        TimedEvent::input(event_type, Duration::from_millis(millis))
+    }
+
+    fn sample(name: &str) -> Macro {
+        Macro {
+            name: name.to_string(),
+            events: vec![
+                ev(EventType::MouseMove { x: 10.0, y: 20.0 }, 0),
+                ev(EventType::ButtonPress(Button::Left), 100),
+                ev(EventType::ButtonRelease(Button::Left), 80),
+                ev(EventType::KeyPress(Key::Return), 500),
+                ev(EventType::KeyRelease(Key::Return), 60),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_revisions_are_numbered_and_kept() {
+        let dir = std::env::temp_dir().join(format!("nyx-history-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let now = Local::now().naive_local();
+
+        let first = append_revision(&dir, &sample("m"), now).unwrap();
+        let mut changed = sample("m");
+        changed.events.truncate(3);
+        let second = append_revision(&dir, &changed, now).unwrap();
+
+        assert_eq!((first.revision, second.revision), (1, 2));
+        assert_eq!(read_revision(&dir, "m", 1).unwrap().macro_data.events.len(), 5);
+        assert_eq!(read_revision(&dir, "m", 2).unwrap().info.event_count, 3);
+        assert!(matches!(read_revision(&dir, "m", 3), Err(MacroError::RevisionNotFound { revision: 3, .. })));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_old_revisions_are_pruned() {
+        let dir = std::env::temp_dir().join(format!("nyx-history-prune-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let now = Local::now().naive_local();
+        for _ in 0..5 {
+            append_revision(&dir, &sample("m"), now).unwrap();
+        }
+
+        let pruned: Vec<u32> = prune_revisions(&dir, "m", 3).unwrap().into_iter().map(|(revision, _)| revision).collect();
+        assert_eq!(pruned, vec![1, 2]);
+        assert_eq!(revision_numbers(&dir).unwrap(), vec![3, 4, 5]);
+        assert_eq!(append_revision(&dir, &sample("m"), now).unwrap().revision, 6);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_diff_reports_semantic_changes() {
+        let old = sample("m").events;
+        let mut new = old.clone();
+        new[0] = ev(EventType::MouseMove { x: 15.0, y: 20.0 }, 0);
+        new.insert(3, ev(EventType::KeyPress(Key::Tab), 100));
+        new.insert(4, ev(EventType::KeyRelease(Key::Tab), 50));
+        new.remove(6);
+        new.remove(5);
+
+        let changes = diff_events(&old, &new);
+        assert_eq!(
+            changes,
+            vec![
+                EventChange::Moved { old_index: 0, new_index: 0, from: (10.0, 20.0), to: (15.0, 20.0) },
+                EventChange::Removed { old_index: 3, event: old[3].clone() },
+                EventChange::Removed { old_index: 4, event: old[4].clone() },
+                EventChange::Added { new_index: 3, event: new[3].clone() },
+                EventChange::Added { new_index: 4, event: new[4].clone() },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_reports_retiming() {
+        let old = sample("m").events;
+        let mut new = old.clone();
+        new[3].time_since_previous = Duration::from_millis(900);
+        new[4].time_since_previous = Duration::from_millis(62);
+
+        let changes = diff_events(&old, &new);
+        assert_eq!(
+            changes,
+            vec![EventChange::Retimed { old_index: 3, new_index: 3, old_delay_secs: 0.5, new_delay_secs: 0.9 }]
+        );
+    }
+}
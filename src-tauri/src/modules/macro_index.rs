@@ -0,0 +1,349 @@
+// Macro metadata index
+// Tags, descriptions and usage statistics for saved macros, kept in
+// `nyx-agent/macro_index.json`. The index is refreshed against the macros directory
+// whenever it is used, so files added, changed or removed outside the app are picked up.
+
+use crate::modules::macro_engine::{self, Macro, MacroError};
+use crate::modules::persistence::{self, PersistenceError};
+use chrono::{DateTime, Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+/// Serializes `with_index`, whose read-modify-write would otherwise lose updates such as
+/// play counts from macros finishing at the same time.
+static INDEX_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MacroMetadata {
+    pub name: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub description: String,
+    pub created: NaiveDateTime,
+    pub modified: NaiveDateTime,
+    #[serde(default)]
+    pub last_played: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub play_count: u64,
+    #[serde(default)]
+    pub event_count: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MacroSort {
+    /// Best fuzzy match first; by name when there is no query.
+    #[default]
+    Relevance,
+    Name,
+    /// Most recently modified first.
+    Modified,
+    /// Most recently played first, never-played macros last.
+    LastPlayed,
+    /// Most played first.
+    PlayCount,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct MacroIndex {
+    entries: BTreeMap<String, MacroMetadata>,
+}
+
+fn to_naive(time: SystemTime) -> NaiveDateTime {
+    DateTime::<Local>::from(time).naive_local()
+}
+
+fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut tags: Vec<String> = tags
+        .into_iter()
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+impl MacroIndex {
+    pub fn get(&self, name: &str) -> Option<&MacroMetadata> {
+        self.entries.get(name)
+    }
+
+    /// Brings the index in line with the `.json` files in `macros_dir`. Returns true if
+    /// anything changed.
+    pub fn refresh(&mut self, macros_dir: &Path, now: NaiveDateTime) -> Result<bool, MacroError> {
+        let entries = fs::read_dir(macros_dir)
+            .map_err(|e| MacroError::FileSystem(format!("Could not read macros directory: {}", e)))?;
+
+        let mut on_disk: BTreeMap<String, (PathBuf, fs::Metadata)> = BTreeMap::new();
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            if path.extension().and_then(|s| s.to_str()) != Some("json") {
+                continue;
+            }
+            let (Some(stem), Ok(metadata)) = (path.file_stem().and_then(|s| s.to_str()), entry.metadata()) else {
+                continue;
+            };
+            if metadata.is_file() {
+                on_disk.insert(stem.to_string(), (path.clone(), metadata));
+            }
+        }
+
+        let mut changed = false;
+        let before = self.entries.len();
+        self.entries.retain(|name, _| on_disk.contains_key(name));
+        changed |= self.entries.len() != before;
+
+        for (name, (path, metadata)) in on_disk {
+            let modified = metadata.modified().map(to_naive).unwrap_or(now);
+            if self.entries.get(&name).is_some_and(|entry| entry.modified == modified) {
+                continue;
+            }
+            // Unreadable files are still listed; playing them reports the error.
+            let event_count = persistence::read_json::<Macro>(&path)
+                .map(|loaded| loaded.value.events.len())
+                .unwrap_or(0);
+            let entry = self.entries.entry(name.clone()).or_insert_with(|| MacroMetadata {
+                name,
+                tags: Vec::new(),
+                description: String::new(),
+                created: metadata.created().map(to_naive).unwrap_or(modified),
+                modified,
+                last_played: None,
+                play_count: 0,
+                event_count,
+            });
+            entry.modified = modified;
+            entry.event_count = event_count;
+            changed = true;
+        }
+        Ok(changed)
+    }
+
+    pub fn set_metadata(&mut self, name: &str, tags: Vec<String>, description: String) -> Result<&MacroMetadata, MacroError> {
+        let entry = self
+            .entries
+            .get_mut(name)
+            .ok_or_else(|| MacroError::FileSystem(format!("No macro named '{}'", name)))?;
+        entry.tags = normalize_tags(tags);
+        entry.description = description.trim().to_string();
+        Ok(entry)
+    }
+
+    pub fn record_play(&mut self, name: &str, now: NaiveDateTime) {
+        if let Some(entry) = self.entries.get_mut(name) {
+            entry.last_played = Some(now);
+            entry.play_count += 1;
+        }
+    }
+
+    /// Returns the macros matching `query` (fuzzy, on the name, or a substring of the
+    /// description) that carry all of `tags`.
+    pub fn search(&self, query: &str, tags: &[String], sort: MacroSort) -> Vec<MacroMetadata> {
+        let tags = normalize_tags(tags.to_vec());
+        let query = query.trim().to_lowercase();
+
+        let mut matches: Vec<(u32, &MacroMetadata)> = self
+            .entries
+            .values()
+            .filter(|entry| tags.iter().all(|tag| entry.tags.contains(tag)))
+            .filter_map(|entry| {
+                if query.is_empty() {
+                    return Some((0, entry));
+                }
+                fuzzy_score(&query, &entry.name.to_lowercase())
+                    .or_else(|| entry.description.to_lowercase().contains(&query).then_some(1))
+                    .map(|score| (score, entry))
+            })
+            .collect();
+
+        match sort {
+            MacroSort::Relevance => matches.sort_by_key(|(score, entry)| (Reverse(*score), entry.name.to_lowercase())),
+            MacroSort::Name => matches.sort_by_key(|(_, entry)| entry.name.to_lowercase()),
+            MacroSort::Modified => matches.sort_by_key(|(_, entry)| Reverse(entry.modified)),
+            MacroSort::LastPlayed => matches.sort_by_key(|(_, entry)| Reverse(entry.last_played)),
+            MacroSort::PlayCount => matches.sort_by_key(|(_, entry)| Reverse(entry.play_count)),
+        }
+        matches.into_iter().map(|(_, entry)| entry.clone()).collect()
+    }
+}
+
+/// Scores `candidate` against `query` (both lowercase). Substring matches rank highest,
+/// prefixes above all; otherwise every query character must appear in order, with
+/// bonuses for runs and word starts. Returns None if the query does not match.
+fn fuzzy_score(query: &str, candidate: &str) -> Option<u32> {
+    if let Some(position) = candidate.find(query) {
+        let bonus = if position == 0 { 2000 } else { 1000 };
+        return Some(bonus + query.len() as u32 * 10 - (position as u32).min(100));
+    }
+
+    let candidate: Vec<char> = candidate.chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous_match: Option<usize> = None;
+    for query_char in query.chars() {
+        let offset = candidate[next..].iter().position(|c| *c == query_char)?;
+        let index = next + offset;
+        score += 10;
+        if previous_match == Some(index.wrapping_sub(1)) {
+            score += 15;
+        }
+        if index == 0 || matches!(candidate[index - 1], ' ' | '_' | '-' | '.') {
+            score += 20;
+        }
+        previous_match = Some(index);
+        next = index + 1;
+    }
+    Some(score)
+}
+
+fn index_path(app_handle: &tauri::AppHandle) -> Result<PathBuf, MacroError> {
+    macro_engine::get_macros_dir(app_handle)?
+        .parent()
+        .map(|dir| dir.join("macro_index.json"))
+        .ok_or_else(|| MacroError::FileSystem("Macros directory has no parent".to_string()))
+}
+
+/// Loads the index, refreshes it against the macros directory, applies `update` and
+/// saves the result if anything changed.
+fn with_index<T>(
+    app_handle: &tauri::AppHandle,
+    update: impl FnOnce(&mut MacroIndex, NaiveDateTime) -> Result<(T, bool), MacroError>,
+) -> Result<T, MacroError> {
+    // A panic while holding the lock leaves nothing half-written in memory; keep going.
+    let _guard = INDEX_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let path = index_path(app_handle)?;
+    let mut index = match persistence::read_json::<MacroIndex>(&path) {
+        Ok(loaded) => {
+            if let Some(warning) = &loaded.recovery {
+                persistence::report_recovery(app_handle, warning);
+            }
+            loaded.value
+        }
+        Err(PersistenceError::NotFound(_)) => MacroIndex::default(),
+        Err(e) => {
+            // The index only holds metadata; start over rather than hiding every macro.
+            log::error!("Macro index is unreadable, rebuilding it: {}", e);
+            MacroIndex::default()
+        }
+    };
+
+    let now = Local::now().naive_local();
+    let refreshed = index.refresh(&macro_engine::get_macros_dir(app_handle)?, now)?;
+    let (result, updated) = update(&mut index, now)?;
+    if refreshed || updated {
+        persistence::write_json(&path, &index)
+            .map_err(|e| MacroError::FileSystem(format!("Failed to write macro index: {}", e)))?;
+    }
+    Ok(result)
+}
+
+/// Searches saved macros by fuzzy name, description and tags.
+pub fn search_macros(
+    query: &str,
+    tags: &[String],
+    sort: MacroSort,
+    app_handle: &tauri::AppHandle,
+) -> Result<Vec<MacroMetadata>, MacroError> {
+    with_index(app_handle, |index, _| Ok((index.search(query, tags, sort), false)))
+}
+
+/// Sets the tags and description of a saved macro.
+pub fn set_macro_metadata(
+    name: &str,
+    tags: Vec<String>,
+    description: String,
+    app_handle: &tauri::AppHandle,
+) -> Result<MacroMetadata, MacroError> {
+    with_index(app_handle, |index, _| Ok((index.set_metadata(name, tags, description)?.clone(), true)))
+}
+
+/// Counts a playback of a macro. Failures are only logged so they never stop playback.
+pub fn record_play(name: &str, app_handle: &tauri::AppHandle) {
+    if let Err(e) = with_index(app_handle, |index, now| {
+        index.record_play(name, now);
+        Ok(((), true))
+    }) {
+        log::warn!("Failed to record playback of '{}': {}", name, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn now() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2025, 3, 1).unwrap().and_hms_opt(12, 0, 0).unwrap()
+    }
+
+    fn write_macro(dir: &Path, name: &str) {
+        let macro_data = Macro { name: name.to_string(), events: Vec::new() };
+        fs::write(dir.join(format!("{}.json", name)), serde_json::to_string(&macro_data).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_refresh_tracks_files_changed_outside_the_app() {
+        let dir = std::env::temp_dir().join(format!("nyx-index-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        write_macro(&dir, "open_invoice");
+        write_macro(&dir, "daily_report");
+
+        let mut index = MacroIndex::default();
+        assert!(index.refresh(&dir, now()).unwrap());
+        assert!(!index.refresh(&dir, now()).unwrap());
+        index.set_metadata("daily_report", vec![" Reports ".into(), "reports".into()], "Send report".into()).unwrap();
+
+        fs::remove_file(dir.join("open_invoice.json")).unwrap();
+        write_macro(&dir, "close_invoice");
+        assert!(index.refresh(&dir, now()).unwrap());
+
+        let names: Vec<String> = index.search("", &[], MacroSort::Name).into_iter().map(|m| m.name).collect();
+        assert_eq!(names, vec!["close_invoice", "daily_report"]);
+        assert_eq!(index.get("daily_report").unwrap().tags, vec!["reports"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fuzzy_search_tags_and_sorting() {
+        let mut index = MacroIndex::default();
+        for (name, tags, plays) in [
+            ("open_invoice", vec!["billing"], 3),
+            ("invoice_export", vec!["billing", "export"], 1),
+            ("login_portal", vec![], 7),
+        ] {
+            index.entries.insert(
+                name.to_string(),
+                MacroMetadata {
+                    name: name.to_string(),
+                    tags: tags.into_iter().map(String::from).collect(),
+                    description: String::new(),
+                    created: now(),
+                    modified: now(),
+                    last_played: None,
+                    play_count: plays,
+                    event_count: 0,
+                },
+            );
+        }
+
+        let names = |results: Vec<MacroMetadata>| results.into_iter().map(|m| m.name).collect::<Vec<_>>();
+        assert_eq!(names(index.search("invoice", &[], MacroSort::Relevance)), vec!["invoice_export", "open_invoice"]);
+        assert_eq!(names(index.search("opinv", &[], MacroSort::Relevance)), vec!["open_invoice"]);
+        assert_eq!(names(index.search("", &["Export".into()], MacroSort::Name)), vec!["invoice_export"]);
+        assert_eq!(names(index.search("", &[], MacroSort::PlayCount))[0], "login_portal");
+
+        index.record_play("open_invoice", now());
+        assert_eq!(names(index.search("", &[], MacroSort::LastPlayed))[0], "open_invoice");
+        assert!(index.search("xyz", &[], MacroSort::Relevance).is_empty());
+    }
+}
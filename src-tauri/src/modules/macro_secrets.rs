@@ -0,0 +1,210 @@
+// Sensitive input handling for macros
+// Secrets typed during recording are kept in the OS keyring and referenced from the
+// macro by id, so passwords never end up in the macro JSON.
+
+use crate::modules::macro_engine::{MacroEvent, TimedEvent};
+use rdev::{EventType, Key};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use thiserror::Error;
+
+const KEYRING_SERVICE: &str = "nyx";
+
+/// A typing burst this soon after a click is treated as typing into the clicked field.
+const CLICK_TO_TYPING_WINDOW: Duration = Duration::from_secs(3);
+
+#[derive(Error, Debug)]
+pub enum SecretError {
+    #[error("Secret '{0}' was not found in the keyring")]
+    NotFound(String),
+
+    #[error("Keyring error: {0}")]
+    Keyring(#[from] keyring::Error),
+}
+
+/// Placeholder stored in a macro in place of typed secret text.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SecretRef {
+    pub id: String,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SensitiveInputWarning {
+    pub event_index: usize,
+    pub reason: String,
+}
+
+fn keyring_username(macro_name: &str, secret_id: &str) -> String {
+    format!("macro_secret/{}/{}", macro_name, secret_id)
+}
+
+/// Stores a secret for the given macro in the system keyring.
+pub fn store_secret(macro_name: &str, secret_id: &str, value: &str) -> Result<(), SecretError> {
+    let entry = keyring::Entry::new(KEYRING_SERVICE, &keyring_username(macro_name, secret_id))?;
+    entry.set_password(value)?;
+    Ok(())
+}
+
+/// Retrieves a macro secret from the system keyring.
+pub fn load_secret(macro_name: &str, secret_id: &str) -> Result<String, SecretError> {
+    let entry = keyring::Entry::new(KEYRING_SERVICE, &keyring_username(macro_name, secret_id))?;
+    entry.get_password().map_err(|e| {
+        if matches!(e, keyring::Error::NoEntry) {
+            SecretError::NotFound(secret_id.to_string())
+        } else {
+            SecretError::Keyring(e)
+        }
+    })
+}
+
+/// Removes a macro secret from the system keyring. A missing entry is not an error.
+pub fn delete_secret(macro_name: &str, secret_id: &str) -> Result<(), SecretError> {
+    let entry = keyring::Entry::new(KEYRING_SERVICE, &keyring_username(macro_name, secret_id))?;
+    match entry.delete_password() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Ids of the secrets referenced by `events`, in order.
+pub fn secret_ids(events: &[TimedEvent]) -> Vec<String> {
+    events
+        .iter()
+        .filter_map(|timed_event| match &timed_event.event {
+            MacroEvent::Secret(secret) => Some(secret.id.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Copies a secret into another macro's keyring entries, e.g. when macros are split or joined.
+pub fn copy_secret(from_macro: &str, from_id: &str, to_macro: &str, to_id: &str) -> Result<(), SecretError> {
+    let value = load_secret(from_macro, from_id)?;
+    store_secret(to_macro, to_id, &value)
+}
+
+/// Looks for typing that was probably a password but was recorded in plaintext:
+/// a burst typed right after a click, or right after tabbing out of a username field,
+/// that looks like a password.
+pub fn detect_sensitive_input(events: &[TimedEvent]) -> Vec<SensitiveInputWarning> {
+    let mut warnings = Vec::new();
+    // Time since the last click, or None if there has not been one.
+    let mut since_click: Option<Duration> = None;
+    let mut previous_text: Option<&str> = None;
+    let mut tabbed_from_text = false;
+
+    for (index, timed_event) in events.iter().enumerate() {
+        if let Some(elapsed) = since_click.as_mut() {
+            *elapsed += timed_event.time_since_previous;
+        }
+
+        match &timed_event.event {
+            MacroEvent::TypeText(text) => {
+                let after_click = since_click.is_some_and(|elapsed| elapsed <= CLICK_TO_TYPING_WINDOW);
+                if after_click && looks_like_password(text) {
+                    warnings.push(SensitiveInputWarning {
+                        event_index: index,
+                        reason: "Text typed right after a click looks like a password".to_string(),
+                    });
+                } else if tabbed_from_text && looks_like_password(text) {
+                    warnings.push(SensitiveInputWarning {
+                        event_index: index,
+                        reason: "Text typed after tabbing from a username field looks like a password".to_string(),
+                    });
+                }
+                previous_text = Some(text);
+                tabbed_from_text = false;
+                since_click = None;
+            }
+            MacroEvent::Input(EventType::ButtonRelease(_)) => {
+                since_click = Some(Duration::ZERO);
+                tabbed_from_text = false;
+            }
+            MacroEvent::Input(EventType::KeyPress(Key::Tab)) => {
+                tabbed_from_text = previous_text.is_some_and(looks_like_username);
+            }
+            MacroEvent::Input(EventType::MouseMove { .. }) | MacroEvent::Input(EventType::KeyRelease(_)) => {}
+            _ => {
+                previous_text = None;
+                tabbed_from_text = false;
+            }
+        }
+    }
+    warnings
+}
+
+/// No whitespace, a reasonable length and at least three character classes.
+fn looks_like_password(text: &str) -> bool {
+    let length = text.chars().count();
+    if !(6..=128).contains(&length) || text.chars().any(char::is_whitespace) {
+        return false;
+    }
+    let classes = [
+        text.chars().any(|c| c.is_lowercase()),
+        text.chars().any(|c| c.is_uppercase()),
+        text.chars().any(|c| c.is_ascii_digit()),
+        text.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    classes.iter().filter(|present| **present).count() >= 3
+}
+
+fn looks_like_username(text: &str) -> bool {
+    !text.is_empty() && text.len() <= 254 && !text.chars().any(char::is_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(event: MacroEvent, millis: u64) -> TimedEvent {
+        TimedEvent {
+            event,
+            time_since_previous: Duration::from_millis(millis),
+            text: None,
+            target: None,
+            window: None,
+        }
+    }
+
+    fn text(value: &str, millis: u64) -> TimedEvent {
+        step(MacroEvent::TypeText(value.to_string()), millis)
+    }
+
+    fn input(event_type: EventType, millis: u64) -> TimedEvent {
+        step(MacroEvent::Input(event_type), millis)
+    }
+
+    #[test]
+    fn test_password_after_click_is_flagged() {
+        let events = vec![
+            input(EventType::ButtonPress(rdev::Button::Left), 0),
+            input(EventType::ButtonRelease(rdev::Button::Left), 80),
+            text("hunter2!Secret", 400),
+        ];
+        let warnings = detect_sensitive_input(&events);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].event_index, 2);
+    }
+
+    #[test]
+    fn test_password_after_username_and_tab_is_flagged() {
+        let events = vec![
+            text("alice@example.com", 0),
+            input(EventType::KeyPress(Key::Tab), 100),
+            input(EventType::KeyRelease(Key::Tab), 50),
+            text("Tr0ub4dor&3", 300),
+        ];
+        assert_eq!(detect_sensitive_input(&events)[0].event_index, 3);
+    }
+
+    #[test]
+    fn test_ordinary_text_is_not_flagged() {
+        let events = vec![
+            input(EventType::ButtonRelease(rdev::Button::Left), 0),
+            text("quarterly report", 200),
+            input(EventType::ButtonRelease(rdev::Button::Left), 500),
+            text("Password1!", 5000),
+        ];
+        assert!(detect_sensitive_input(&events).is_empty());
+    }
+}
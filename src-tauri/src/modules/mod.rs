@@ -1,7 +1,22 @@
 pub mod io_controller;
+pub mod accessibility;
 pub mod perception;
 pub mod cognition;
 pub mod tooling;
+pub mod dry_run;
 pub mod knowledge;
 pub mod macro_engine;
-
+pub mod macro_editor;
+pub mod macro_formats;
+pub mod macro_history;
+pub mod macro_index;
+pub mod macro_secrets;
+pub mod scheduler;
+pub mod persistence;
+pub mod self_correction;
+pub mod window;
+pub mod voice;
+pub mod speech;
+pub mod wake_word;
+pub mod screen_recording;
+pub mod redaction;
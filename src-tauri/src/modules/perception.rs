@@ -1,11 +1,2040 @@
-// Placeholder module for Perception
-// This module will handle input perception (mouse, keyboard, screen capture, etc.)
+// Perception module
+// Screen capture (whole screen, monitor, region or window), text recognition, window
+// information and UI maps of the screen for the planner. Recording uses it to store what
+// was under the cursor for each click in AI-assisted mode.
 
-pub struct Perception;
+use crate::modules::accessibility::{Accessibility, AccessibleAction, AccessibleId, AccessibleNode};
+use crate::modules::persistence;
+use crate::modules::window::{WindowContext, WindowManager};
+use image::{imageops, ImageOutputFormat, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::{Cursor, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+use thiserror::Error;
+use x11rb::{
+    connection::Connection,
+    protocol::{
+        randr::ConnectionExt as _,
+        xproto::{AtomEnum, ConnectionExt, ImageFormat, ImageOrder, Window},
+    },
+    rust_connection::RustConnection,
+};
+
+/// Converts 32 bits per pixel ZPixmap data to RGBA, using the server's byte order and
+/// the root visual's channel masks.
+fn decode_zpixmap(data: &[u8], msb_first: bool, masks: [u32; 3]) -> Result<Vec<u8>, PerceptionError> {
+    if masks.contains(&0) {
+        return Err(PerceptionError::Capture(format!("Unsupported color masks {:x?}", masks)));
+    }
+    let channel = |pixel: u32, mask: u32| -> u8 {
+        let shift = mask.trailing_zeros();
+        (u64::from((pixel & mask) >> shift) * 255 / u64::from(mask >> shift)) as u8
+    };
+    Ok(data
+        .chunks_exact(4)
+        .flat_map(|bytes| {
+            let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+            let pixel = if msb_first { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) };
+            [channel(pixel, masks[0]), channel(pixel, masks[1]), channel(pixel, masks[2]), 255]
+        })
+        .collect())
+}
+
+/// Size of the screenshot crop stored for each click in AI-assisted recordings.
+pub const CLICK_CROP_WIDTH: u32 = 240;
+pub const CLICK_CROP_HEIGHT: u32 = 120;
+
+#[derive(Error, Debug)]
+pub enum PerceptionError {
+    #[error("Screen capture failed: {0}")]
+    Capture(String),
+    #[error("Text recognition failed: {0}")]
+    Ocr(String),
+    #[error("Window query failed: {0}")]
+    Window(String),
+    #[error("No monitor with id {0}")]
+    MonitorNotFound(usize),
+    #[error("I/O error: {0}")]
+    Io(String),
+    #[error("Timed out after {0:?} waiting for the screen")]
+    Timeout(Duration),
+    #[error("Accessibility query failed: {0}")]
+    Accessibility(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    /// A rectangle of the given size centered on `(x, y)`.
+    pub fn centered_on(x: f64, y: f64, width: u32, height: u32) -> Self {
+        Self {
+            x: x.round() as i32 - (width / 2) as i32,
+            y: y.round() as i32 - (height / 2) as i32,
+            width,
+            height,
+        }
+    }
+
+    pub fn center(&self) -> (f64, f64) {
+        (self.x as f64 + self.width as f64 / 2.0, self.y as f64 + self.height as f64 / 2.0)
+    }
+
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.x as f64
+            && y >= self.y as f64
+            && x < self.x as f64 + self.width as f64
+            && y < self.y as f64 + self.height as f64
+    }
+
+    /// The part of this rectangle that lies within a `width` x `height` screen.
+    pub fn clamp_to(&self, width: u32, height: u32) -> Option<Rect> {
+        let left = self.x.max(0);
+        let top = self.y.max(0);
+        let right = (self.x + self.width as i32).min(width as i32);
+        let bottom = (self.y + self.height as i32).min(height as i32);
+        (right > left && bottom > top).then(|| Rect {
+            x: left,
+            y: top,
+            width: (right - left) as u32,
+            height: (bottom - top) as u32,
+        })
+    }
+}
+
+/// A monitor and its area on the virtual screen.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Monitor {
+    /// Position in the backend's monitor list.
+    pub id: usize,
+    pub name: String,
+    pub rect: Rect,
+    pub primary: bool,
+}
+
+/// Source of screen pixels and window information: the X11 desktop, or fixture images
+/// in tests.
+pub trait ScreenBackend: Send + Sync {
+    fn screen_size(&self) -> Result<(u32, u32), PerceptionError>;
+    /// Captures the part of `rect` that is on screen.
+    fn capture_region(&self, rect: Rect) -> Result<RgbaImage, PerceptionError>;
+    fn active_window_title(&self) -> Result<Option<String>, PerceptionError>;
+
+    /// The focused window. Defaults to its title alone.
+    fn active_window(&self) -> Result<Option<WindowContext>, PerceptionError> {
+        Ok(self.active_window_title()?.map(|title| WindowContext { title, ..WindowContext::default() }))
+    }
+
+    /// Lists monitors. Defaults to a single monitor covering the whole screen.
+    fn monitors(&self) -> Result<Vec<Monitor>, PerceptionError> {
+        let (width, height) = self.screen_size()?;
+        Ok(vec![Monitor {
+            id: 0,
+            name: "screen".to_string(),
+            rect: Rect { x: 0, y: 0, width, height },
+            primary: true,
+        }])
+    }
+
+    /// Area of a window on the screen, including parts covered by other windows.
+    fn window_rect(&self, window: u32) -> Result<Rect, PerceptionError>;
+}
+
+/// Captures from the X11 root window.
+pub struct X11Screen;
+
+impl X11Screen {
+    fn connect() -> Result<(RustConnection, usize), PerceptionError> {
+        x11rb::connect(None).map_err(|e| PerceptionError::Capture(format!("Cannot connect to X server: {}", e)))
+    }
+}
+
+impl ScreenBackend for X11Screen {
+    fn screen_size(&self) -> Result<(u32, u32), PerceptionError> {
+        let (conn, screen_num) = Self::connect()?;
+        let screen = &conn.setup().roots[screen_num];
+        Ok((screen.width_in_pixels.into(), screen.height_in_pixels.into()))
+    }
+
+    fn capture_region(&self, rect: Rect) -> Result<RgbaImage, PerceptionError> {
+        let (conn, screen_num) = Self::connect()?;
+        let screen = &conn.setup().roots[screen_num];
+        let rect = rect
+            .clamp_to(screen.width_in_pixels.into(), screen.height_in_pixels.into())
+            .ok_or_else(|| PerceptionError::Capture("Region is off screen".to_string()))?;
+
+        let bits_per_pixel = conn
+            .setup()
+            .pixmap_formats
+            .iter()
+            .find(|format| format.depth == screen.root_depth)
+            .map(|format| format.bits_per_pixel);
+        let visual = screen
+            .allowed_depths
+            .iter()
+            .flat_map(|depth| &depth.visuals)
+            .find(|visual| visual.visual_id == screen.root_visual);
+        let (Some(32), Some(visual)) = (bits_per_pixel, visual) else {
+            return Err(PerceptionError::Capture(format!(
+                "Unsupported screen format: depth {} with {:?} bits per pixel",
+                screen.root_depth, bits_per_pixel
+            )));
+        };
+        let masks = [visual.red_mask, visual.green_mask, visual.blue_mask];
+        let msb_first = conn.setup().image_byte_order == ImageOrder::MSB_FIRST;
+
+        let reply = conn
+            .get_image(
+                ImageFormat::Z_PIXMAP,
+                screen.root,
+                rect.x as i16,
+                rect.y as i16,
+                rect.width as u16,
+                rect.height as u16,
+                !0,
+            )
+            .map_err(|e| PerceptionError::Capture(e.to_string()))?
+            .reply()
+            .map_err(|e| PerceptionError::Capture(e.to_string()))?;
+
+        let rgba = decode_zpixmap(&reply.data, msb_first, masks)?;
+        RgbaImage::from_raw(rect.width, rect.height, rgba)
+            .ok_or_else(|| PerceptionError::Capture("Image data has an unexpected size".to_string()))
+    }
+
+    fn active_window_title(&self) -> Result<Option<String>, PerceptionError> {
+        let (conn, screen_num) = Self::connect().map_err(window_error)?;
+        let root = conn.setup().roots[screen_num].root;
+
+        let atom = |name: &[u8]| -> Result<u32, PerceptionError> {
+            Ok(conn
+                .intern_atom(false, name)
+                .map_err(window_error)?
+                .reply()
+                .map_err(window_error)?
+                .atom)
+        };
+        let active_atom = atom(b"_NET_ACTIVE_WINDOW")?;
+        let name_atom = atom(b"_NET_WM_NAME")?;
+        let utf8_atom = atom(b"UTF8_STRING")?;
+
+        let active: Option<Window> = conn
+            .get_property(false, root, active_atom, AtomEnum::WINDOW, 0, 1)
+            .map_err(window_error)?
+            .reply()
+            .map_err(window_error)?
+            .value32()
+            .and_then(|mut values| values.next())
+            .filter(|window| *window != 0);
+        let Some(window) = active else {
+            return Ok(None);
+        };
+
+        for (property, kind) in [(name_atom, utf8_atom), (AtomEnum::WM_NAME.into(), AtomEnum::STRING.into())] {
+            let reply = conn
+                .get_property(false, window, property, kind, 0, 1024)
+                .map_err(window_error)?
+                .reply()
+                .map_err(window_error)?;
+            if !reply.value.is_empty() {
+                return Ok(Some(String::from_utf8_lossy(&reply.value).into_owned()));
+            }
+        }
+        Ok(None)
+    }
+
+    fn active_window(&self) -> Result<Option<WindowContext>, PerceptionError> {
+        let manager = WindowManager::connect().map_err(window_error)?;
+        Ok(manager.active().map_err(window_error)?.map(WindowContext::from))
+    }
+
+    fn monitors(&self) -> Result<Vec<Monitor>, PerceptionError> {
+        let (conn, screen_num) = Self::connect()?;
+        let root = conn.setup().roots[screen_num].root;
+        let reply = conn
+            .randr_get_monitors(root, true)
+            .map_err(|e| PerceptionError::Capture(e.to_string()))?
+            .reply()
+            .map_err(|e| PerceptionError::Capture(e.to_string()))?;
+
+        let monitors: Vec<Monitor> = reply
+            .monitors
+            .iter()
+            .enumerate()
+            .map(|(id, info)| {
+                let name = conn
+                    .get_atom_name(info.name)
+                    .ok()
+                    .and_then(|cookie| cookie.reply().ok())
+                    .map(|reply| String::from_utf8_lossy(&reply.name).into_owned())
+                    .unwrap_or_else(|| format!("monitor-{}", id));
+                Monitor {
+                    id,
+                    name,
+                    rect: Rect { x: info.x.into(), y: info.y.into(), width: info.width.into(), height: info.height.into() },
+                    primary: info.primary,
+                }
+            })
+            .collect();
+        if monitors.is_empty() {
+            // Servers without RandR monitors (e.g. plain Xvfb) have one screen.
+            let screen = &conn.setup().roots[screen_num];
+            return Ok(vec![Monitor {
+                id: 0,
+                name: "screen".to_string(),
+                rect: Rect { x: 0, y: 0, width: screen.width_in_pixels.into(), height: screen.height_in_pixels.into() },
+                primary: true,
+            }]);
+        }
+        Ok(monitors)
+    }
+
+    fn window_rect(&self, window: u32) -> Result<Rect, PerceptionError> {
+        let (conn, screen_num) = Self::connect().map_err(window_error)?;
+        let root = conn.setup().roots[screen_num].root;
+        let geometry = conn
+            .get_geometry(window)
+            .map_err(window_error)?
+            .reply()
+            .map_err(|e| PerceptionError::Window(format!("No window {:#x}: {}", window, e)))?;
+        let origin = conn
+            .translate_coordinates(window, root, 0, 0)
+            .map_err(window_error)?
+            .reply()
+            .map_err(window_error)?;
+        Ok(Rect {
+            x: origin.dst_x.into(),
+            y: origin.dst_y.into(),
+            width: geometry.width.into(),
+            height: geometry.height.into(),
+        })
+    }
+}
+
+fn window_error(e: impl ToString) -> PerceptionError {
+    PerceptionError::Window(e.to_string())
+}
+
+/// Serves captures from a fixed screenshot, for tests and offline runs. Monitors and
+/// windows are areas of that screenshot.
+#[derive(Default)]
+pub struct FixtureScreen {
+    pub image: RgbaImage,
+    pub window_title: Option<String>,
+    /// Empty for a single monitor covering the image.
+    pub monitors: Vec<Monitor>,
+    pub windows: Vec<(u32, Rect)>,
+}
+
+impl FixtureScreen {
+    pub fn from_file(path: &Path, window_title: Option<String>) -> Result<Self, PerceptionError> {
+        Ok(Self { image: load_image(path)?, window_title, ..Self::default() })
+    }
+}
+
+impl ScreenBackend for FixtureScreen {
+    fn screen_size(&self) -> Result<(u32, u32), PerceptionError> {
+        Ok(self.image.dimensions())
+    }
+
+    fn capture_region(&self, rect: Rect) -> Result<RgbaImage, PerceptionError> {
+        let rect = rect
+            .clamp_to(self.image.width(), self.image.height())
+            .ok_or_else(|| PerceptionError::Capture("Region is off screen".to_string()))?;
+        Ok(imageops::crop_imm(&self.image, rect.x as u32, rect.y as u32, rect.width, rect.height).to_image())
+    }
+
+    fn active_window_title(&self) -> Result<Option<String>, PerceptionError> {
+        Ok(self.window_title.clone())
+    }
+
+    fn monitors(&self) -> Result<Vec<Monitor>, PerceptionError> {
+        if self.monitors.is_empty() {
+            let (width, height) = self.image.dimensions();
+            return Ok(vec![Monitor {
+                id: 0,
+                name: "screen".to_string(),
+                rect: Rect { x: 0, y: 0, width, height },
+                primary: true,
+            }]);
+        }
+        Ok(self.monitors.clone())
+    }
+
+    fn window_rect(&self, window: u32) -> Result<Rect, PerceptionError> {
+        self.windows
+            .iter()
+            .find(|(id, _)| *id == window)
+            .map(|(_, rect)| *rect)
+            .ok_or_else(|| PerceptionError::Window(format!("No window {:#x}", window)))
+    }
+}
+
+/// Loads an image file (a fixture screenshot or a template) as RGBA.
+pub fn load_image(path: &Path) -> Result<RgbaImage, PerceptionError> {
+    Ok(image::open(path)
+        .map_err(|e| PerceptionError::Io(format!("Cannot open image {:?}: {}", path, e)))?
+        .to_rgba8())
+}
+
+/// Encodes an image as PNG.
+pub fn encode_png(image: &RgbaImage) -> Result<Vec<u8>, PerceptionError> {
+    let mut bytes = Cursor::new(Vec::new());
+    image
+        .write_to(&mut bytes, ImageOutputFormat::Png)
+        .map_err(|e| PerceptionError::Capture(format!("PNG encoding failed: {}", e)))?;
+    Ok(bytes.into_inner())
+}
+
+/// A recognized word with its position in the image.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OcrWord {
+    pub text: String,
+    pub bbox: Rect,
+    /// 0.0 to 1.0.
+    pub confidence: f32,
+    /// Words with the same line id are on the same text line.
+    pub line: (u32, u32, u32),
+}
+
+/// Text recognition over screenshots.
+pub trait OcrEngine: Send + Sync {
+    /// Recognizes the words in `image`, or only those inside `region` of it. Word boxes
+    /// are in image coordinates either way.
+    fn recognize(&self, image: &RgbaImage, region: Option<Rect>) -> Result<Vec<OcrWord>, PerceptionError>;
+}
+
+/// Runs the local `tesseract` binary.
+#[derive(Default)]
+pub struct TesseractOcr {
+    /// Tesseract language code, e.g. "eng" or "deu+eng". Tesseract's default if unset.
+    pub language: Option<String>,
+}
+
+impl TesseractOcr {
+    /// Runs `tesseract` on an image and returns its TSV output.
+    fn run(&self, image: &RgbaImage) -> Result<String, PerceptionError> {
+        let png = encode_png(image)?;
+        let mut command = Command::new("tesseract");
+        command.args(["stdin", "stdout"]);
+        if let Some(language) = &self.language {
+            command.args(["-l", language]);
+        }
+        let mut child = command
+            .arg("tsv")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| PerceptionError::Ocr(format!("Cannot run tesseract: {}", e)))?;
+        child
+            .stdin
+            .take()
+            .ok_or_else(|| PerceptionError::Ocr("tesseract stdin unavailable".to_string()))?
+            .write_all(&png)
+            .map_err(|e| PerceptionError::Ocr(e.to_string()))?;
+        let output = child.wait_with_output().map_err(|e| PerceptionError::Ocr(e.to_string()))?;
+        if !output.status.success() {
+            return Err(PerceptionError::Ocr(format!("tesseract exited with {}", output.status)));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+impl OcrEngine for TesseractOcr {
+    fn recognize(&self, image: &RgbaImage, region: Option<Rect>) -> Result<Vec<OcrWord>, PerceptionError> {
+        let Some(region) = region else {
+            return Ok(parse_tesseract_tsv(&self.run(image)?));
+        };
+        let region = region
+            .clamp_to(image.width(), image.height())
+            .ok_or_else(|| PerceptionError::Ocr("Region is outside the image".to_string()))?;
+        let crop = imageops::crop_imm(image, region.x as u32, region.y as u32, region.width, region.height).to_image();
+        let mut words = parse_tesseract_tsv(&self.run(&crop)?);
+        for word in &mut words {
+            word.bbox.x += region.x;
+            word.bbox.y += region.y;
+        }
+        Ok(words)
+    }
+}
+
+/// Returns fixed words, for tests against fixture screenshots. The words are written by
+/// hand in tesseract's TSV format rather than recorded from tesseract, so they stand in for
+/// OCR output without depending on a tesseract version.
+pub struct FixtureOcr {
+    pub words: Vec<OcrWord>,
+}
+
+impl FixtureOcr {
+    /// Loads words in tesseract's TSV format saved next to a fixture screenshot.
+    pub fn from_tsv(path: &Path) -> Result<Self, PerceptionError> {
+        let tsv = std::fs::read_to_string(path)
+            .map_err(|e| PerceptionError::Ocr(format!("Cannot open fixture {:?}: {}", path, e)))?;
+        Ok(Self { words: parse_tesseract_tsv(&tsv) })
+    }
+}
+
+impl OcrEngine for FixtureOcr {
+    fn recognize(&self, _image: &RgbaImage, region: Option<Rect>) -> Result<Vec<OcrWord>, PerceptionError> {
+        let inside = |word: &OcrWord| match region {
+            Some(region) => {
+                let (x, y) = word.bbox.center();
+                region.contains(x, y)
+            }
+            None => true,
+        };
+        Ok(self.words.iter().filter(|word| inside(word)).cloned().collect())
+    }
+}
+
+/// Recognizes the words in an image with the `tesseract` command.
+pub fn read_words(image: &RgbaImage) -> Result<Vec<OcrWord>, PerceptionError> {
+    TesseractOcr::default().recognize(image, None)
+}
+
+/// Recognizes the text in an image with the `tesseract` command, one line per text line.
+pub fn read_text(image: &RgbaImage) -> Result<String, PerceptionError> {
+    Ok(words_to_text(&read_words(image)?))
+}
+
+/// Joins words into text, with a newline between text lines.
+pub fn words_to_text(words: &[OcrWord]) -> String {
+    let mut text = String::new();
+    for (index, word) in words.iter().enumerate() {
+        if index > 0 {
+            text.push(if words[index - 1].line == word.line { ' ' } else { '\n' });
+        }
+        text.push_str(&word.text);
+    }
+    text
+}
+
+/// Parses tesseract's TSV output, keeping word-level rows with text.
+fn parse_tesseract_tsv(tsv: &str) -> Vec<OcrWord> {
+    tsv.lines()
+        .skip(1)
+        .filter_map(|row| {
+            let columns: Vec<&str> = row.split('\t').collect();
+            if columns.len() < 12 || columns[0] != "5" {
+                return None;
+            }
+            let number = |i: usize| columns[i].trim().parse::<i64>().ok();
+            let text = columns[11].trim();
+            let confidence = columns[10].trim().parse::<f32>().ok()?;
+            if text.is_empty() || confidence < 0.0 {
+                return None;
+            }
+            Some(OcrWord {
+                text: text.to_string(),
+                bbox: Rect {
+                    x: number(6)? as i32,
+                    y: number(7)? as i32,
+                    width: number(8)? as u32,
+                    height: number(9)? as u32,
+                },
+                confidence: confidence / 100.0,
+                line: (number(2)? as u32, number(3)? as u32, number(4)? as u32),
+            })
+        })
+        .collect()
+}
+
+fn distance_to_rect(rect: &Rect, (x, y): (f64, f64)) -> f64 {
+    let dx = (rect.x as f64 - x).max(x - (rect.x + rect.width as i32) as f64).max(0.0);
+    let dy = (rect.y as f64 - y).max(y - (rect.y + rect.height as i32) as f64).max(0.0);
+    (dx * dx + dy * dy).sqrt()
+}
+
+/// Words on one line belong to the same label when the gap between them is under a
+/// word height.
+fn same_label(left: &OcrWord, right: &OcrWord) -> bool {
+    right.bbox.x - (left.bbox.x + left.bbox.width as i32) < left.bbox.height.max(right.bbox.height) as i32
+}
+
+/// The text closest to `point`: the nearest word, joined with the words next to it on
+/// the same line (e.g. "Save as").
+pub fn text_near(words: &[OcrWord], point: (f64, f64)) -> Option<String> {
+    let nearest = words
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| distance_to_rect(&a.bbox, point).total_cmp(&distance_to_rect(&b.bbox, point)))?
+        .0;
+
+    let mut line: Vec<&OcrWord> = words.iter().filter(|w| w.line == words[nearest].line).collect();
+    line.sort_by_key(|w| w.bbox.x);
+    let position = line.iter().position(|w| std::ptr::eq(*w, &words[nearest]))?;
+
+    let mut start = position;
+    while start > 0 && same_label(line[start - 1], line[start]) {
+        start -= 1;
+    }
+    let mut end = position;
+    while end + 1 < line.len() && same_label(line[end], line[end + 1]) {
+        end += 1;
+    }
+    Some(line[start..=end].iter().map(|w| w.text.as_str()).collect::<Vec<_>>().join(" "))
+}
+
+/// Lowercases a word and strips surrounding punctuation, so "Customer:" matches "customer".
+fn normalize_word(word: &str) -> String {
+    word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase()
+}
+
+/// Finds `text` (one or more words) among recognized words, ignoring case and
+/// surrounding punctuation. Returns the bounding box of each occurrence with the lowest
+/// word confidence in it.
+pub fn find_text(words: &[OcrWord], text: &str) -> Vec<(Rect, f32)> {
+    let wanted: Vec<String> = text.split_whitespace().map(normalize_word).collect();
+    if wanted.is_empty() {
+        return Vec::new();
+    }
+    let mut found = Vec::new();
+    for window in words.windows(wanted.len()) {
+        let same_line = window.iter().all(|w| w.line == window[0].line);
+        let matches = window.iter().zip(&wanted).all(|(w, want)| normalize_word(&w.text) == *want);
+        if same_line && matches {
+            let confidence = window.iter().map(|w| w.confidence).fold(1.0, f32::min);
+            found.push((words_bbox(window), confidence));
+        }
+    }
+    found
+}
+
+/// The bounding box around `words`.
+fn words_bbox(words: &[OcrWord]) -> Rect {
+    let left = words.iter().map(|w| w.bbox.x).min().unwrap_or(0);
+    let top = words.iter().map(|w| w.bbox.y).min().unwrap_or(0);
+    let right = words.iter().map(|w| w.bbox.x + w.bbox.width as i32).max().unwrap_or(0);
+    let bottom = words.iter().map(|w| w.bbox.y + w.bbox.height as i32).max().unwrap_or(0);
+    Rect { x: left, y: top, width: (right - left) as u32, height: (bottom - top) as u32 }
+}
+
+/// Best position of a template in an image.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct TemplateMatch {
+    /// Top-left corner of the match.
+    pub x: u32,
+    pub y: u32,
+    /// Normalized cross-correlation, -1.0 to 1.0.
+    pub score: f32,
+}
+
+/// Dot product in eight independent lanes, which the compiler can vectorize.
+fn dot(a: &[f32], b: &[f32]) -> f64 {
+    let mut lanes = [0.0f32; 8];
+    let (a_chunks, b_chunks) = (a.chunks_exact(8), b.chunks_exact(8));
+    let tail: f32 = a_chunks.remainder().iter().zip(b_chunks.remainder()).map(|(x, y)| x * y).sum();
+    for (a, b) in a_chunks.zip(b_chunks) {
+        for lane in 0..8 {
+            lanes[lane] += a[lane] * b[lane];
+        }
+    }
+    lanes.iter().map(|lane| *lane as f64).sum::<f64>() + tail as f64
+}
+
+/// Grayscale pixels as f32 with summed-area tables for fast window statistics.
+struct GrayImage {
+    width: usize,
+    height: usize,
+    pixels: Vec<f32>,
+    /// (width + 1) x (height + 1) tables of sums and squared sums.
+    sums: Vec<f64>,
+    squares: Vec<f64>,
+}
+
+impl GrayImage {
+    fn new(image: &RgbaImage, scale: u32) -> Self {
+        let width = (image.width() / scale) as usize;
+        let height = (image.height() / scale) as usize;
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                // Average each scale x scale block.
+                let mut total = 0.0;
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let p = image.get_pixel(x as u32 * scale + dx, y as u32 * scale + dy);
+                        total += 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32;
+                    }
+                }
+                pixels.push(total / (scale * scale) as f32);
+            }
+        }
+
+        let stride = width + 1;
+        let mut sums = vec![0.0; stride * (height + 1)];
+        let mut squares = vec![0.0; stride * (height + 1)];
+        for y in 0..height {
+            for x in 0..width {
+                let value = pixels[y * width + x] as f64;
+                let i = (y + 1) * stride + x + 1;
+                sums[i] = value + sums[i - 1] + sums[i - stride] - sums[i - stride - 1];
+                squares[i] = value * value + squares[i - 1] + squares[i - stride] - squares[i - stride - 1];
+            }
+        }
+        Self { width, height, pixels, sums, squares }
+    }
+
+    fn window_sum(table: &[f64], stride: usize, x: usize, y: usize, w: usize, h: usize) -> f64 {
+        table[(y + h) * stride + x + w] - table[y * stride + x + w] - table[(y + h) * stride + x] + table[y * stride + x]
+    }
+
+    /// Normalized cross-correlation of `template` placed at `(x, y)`.
+    fn ncc_at(&self, template: &GrayImage, template_norm: f64, x: usize, y: usize) -> f32 {
+        let (w, h) = (template.width, template.height);
+        let n = (w * h) as f64;
+        let stride = self.width + 1;
+        let sum = Self::window_sum(&self.sums, stride, x, y, w, h);
+        let variance = Self::window_sum(&self.squares, stride, x, y, w, h) - sum * sum / n;
+        if variance <= 1e-6 || template_norm <= 1e-6 {
+            return 0.0;
+        }
+        // The template is zero-mean, so correlating with raw pixels equals correlating
+        // with mean-subtracted ones.
+        let mut cross = 0.0f64;
+        for ty in 0..h {
+            let row = &self.pixels[(y + ty) * self.width + x..][..w];
+            let template_row = &template.pixels[ty * w..][..w];
+            cross += dot(row, template_row);
+        }
+        (cross / (variance.sqrt() * template_norm)) as f32
+    }
+
+    /// Makes the pixels zero-mean and returns their norm.
+    fn center(&mut self) -> f64 {
+        let mean = self.pixels.iter().map(|p| *p as f64).sum::<f64>() / self.pixels.len().max(1) as f64;
+        for p in self.pixels.iter_mut() {
+            *p -= mean as f32;
+        }
+        self.pixels.iter().map(|p| (*p as f64).powi(2)).sum::<f64>().sqrt()
+    }
+}
+
+/// An image converted to grayscale once per downscale factor, shared by searches for
+/// several templates or template scales.
+struct Haystack<'a> {
+    image: &'a RgbaImage,
+    levels: HashMap<u32, GrayImage>,
+}
+
+impl<'a> Haystack<'a> {
+    fn new(image: &'a RgbaImage) -> Self {
+        Self { image, levels: HashMap::new() }
+    }
+
+    fn prepare(&mut self, factor: u32) {
+        let image = self.image;
+        self.levels.entry(factor).or_insert_with(|| GrayImage::new(image, factor));
+    }
+}
+
+/// How far coarse scores may fall below the threshold and still be refined, since
+/// downscaling blurs away detail the full-resolution match has.
+const COARSE_SCORE_SLACK: f32 = 0.15;
+
+/// Coarse matches refined when only the best match is wanted. Downscaling can rank a
+/// few near misses above the real match, so several are checked.
+const BEST_MATCH_CANDIDATES: usize = 16;
+
+/// Scores `template` at every position of a downscaled copy of the haystack, then
+/// refines up to `limit` of the best local maxima at full resolution. Returns the
+/// refined matches scoring at least `min_score`.
+fn search_template(haystack: &mut Haystack, template: &RgbaImage, min_score: f32, limit: usize) -> Vec<TemplateMatch> {
+    if template.width() == 0
+        || template.height() == 0
+        || template.width() > haystack.image.width()
+        || template.height() > haystack.image.height()
+    {
+        return Vec::new();
+    }
+    // Keep at least 16 pixels of template on each side at the coarse level.
+    let factor = (template.width().min(template.height()) / 16).clamp(1, 4);
+    haystack.prepare(factor);
+    haystack.prepare(1);
+    let coarse = &haystack.levels[&factor];
+    let full = &haystack.levels[&1];
+
+    let mut needle = GrayImage::new(template, factor);
+    let norm = needle.center();
+    if needle.width > coarse.width || needle.height > coarse.height {
+        return Vec::new();
+    }
+    let columns = coarse.width - needle.width + 1;
+    let rows = coarse.height - needle.height + 1;
+    let scores: Vec<f32> = (0..columns * rows)
+        .map(|i| coarse.ncc_at(&needle, norm, i % columns, i / columns))
+        .collect();
+
+    let coarse_min = if factor == 1 { min_score } else { min_score - COARSE_SCORE_SLACK };
+    let is_peak = |x: usize, y: usize, score: f32| {
+        (y.saturating_sub(1)..=(y + 1).min(rows - 1))
+            .all(|ny| (x.saturating_sub(1)..=(x + 1).min(columns - 1)).all(|nx| scores[ny * columns + nx] <= score))
+    };
+    let mut peaks: Vec<(usize, usize, f32)> = scores
+        .iter()
+        .enumerate()
+        .map(|(i, score)| (i % columns, i / columns, *score))
+        .filter(|(x, y, score)| *score >= coarse_min && is_peak(*x, *y, *score))
+        .collect();
+    peaks.sort_by(|a, b| b.2.total_cmp(&a.2));
+    peaks.truncate(limit);
+
+    if factor == 1 {
+        return peaks.into_iter().map(|(x, y, score)| TemplateMatch { x: x as u32, y: y as u32, score }).collect();
+    }
+
+    let mut needle = GrayImage::new(template, 1);
+    let norm = needle.center();
+    let radius = factor as usize;
+    peaks
+        .into_iter()
+        .filter_map(|(cx, cy, _)| {
+            let (fx, fy) = (cx * factor as usize, cy * factor as usize);
+            (fy.saturating_sub(radius)..=fy + radius)
+                .flat_map(|y| (fx.saturating_sub(radius)..=fx + radius).map(move |x| (x, y)))
+                .filter(|(x, y)| x + needle.width <= full.width && y + needle.height <= full.height)
+                .map(|(x, y)| TemplateMatch { x: x as u32, y: y as u32, score: full.ncc_at(&needle, norm, x, y) })
+                .max_by(|a, b| a.score.total_cmp(&b.score))
+        })
+        .filter(|found| found.score >= min_score)
+        .collect()
+}
+
+/// Finds the best match of `template` in `image` by normalized cross-correlation, however
+/// low it scores. The search runs on a downscaled copy first and is refined at full
+/// resolution.
+pub fn match_template(image: &RgbaImage, template: &RgbaImage) -> Option<TemplateMatch> {
+    search_template(&mut Haystack::new(image), template, -1.0, BEST_MATCH_CANDIDATES)
+        .into_iter()
+        .max_by(|a, b| a.score.total_cmp(&b.score))
+}
+
+/// Whether `find_image` returns only the best match or every match.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMode {
+    #[default]
+    Best,
+    All,
+}
+
+/// Template scales tried by default, for 100%, 125%, 150% and 200% display scaling.
+pub const HIDPI_SCALES: [f32; 4] = [1.0, 1.25, 1.5, 2.0];
+
+/// Coarse matches refined per template scale when looking for every match.
+const MAX_MATCHES_PER_SCALE: usize = 64;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct FindImageOptions {
+    /// Only search this part of the image.
+    pub region: Option<Rect>,
+    /// Minimum normalized cross-correlation, up to 1.0 for an exact match.
+    pub threshold: f32,
+    /// Sizes to try the template at, relative to its stored size.
+    pub scales: Vec<f32>,
+    pub mode: MatchMode,
+}
+
+impl Default for FindImageOptions {
+    fn default() -> Self {
+        Self { region: None, threshold: 0.9, scales: HIDPI_SCALES.to_vec(), mode: MatchMode::Best }
+    }
+}
+
+/// Where a template was found, in the coordinates of the searched image.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct ImageMatch {
+    pub rect: Rect,
+    pub center: (f64, f64),
+    pub score: f32,
+    /// Template scale that matched.
+    pub scale: f32,
+}
+
+/// Finds `template` in `image` by normalized cross-correlation at each of the option's
+/// scales. Matches are ordered by score; overlapping matches keep only the best one.
+pub fn find_image(image: &RgbaImage, template: &RgbaImage, options: &FindImageOptions) -> Vec<ImageMatch> {
+    let cropped;
+    let (search_image, origin) = match options.region {
+        Some(region) => {
+            let Some(region) = region.clamp_to(image.width(), image.height()) else {
+                return Vec::new();
+            };
+            cropped = imageops::crop_imm(image, region.x as u32, region.y as u32, region.width, region.height).to_image();
+            (&cropped, (region.x, region.y))
+        }
+        None => (image, (0, 0)),
+    };
+
+    let limit = match options.mode {
+        MatchMode::Best => BEST_MATCH_CANDIDATES,
+        MatchMode::All => MAX_MATCHES_PER_SCALE,
+    };
+    let mut haystack = Haystack::new(search_image);
+    let mut matches = Vec::new();
+    for &scale in &options.scales {
+        let width = (template.width() as f32 * scale).round() as u32;
+        let height = (template.height() as f32 * scale).round() as u32;
+        let scaled;
+        let sized = if (width, height) == template.dimensions() {
+            template
+        } else {
+            scaled = imageops::resize(template, width, height, imageops::FilterType::Triangle);
+            &scaled
+        };
+        for found in search_template(&mut haystack, sized, options.threshold, limit) {
+            let rect = Rect { x: found.x as i32 + origin.0, y: found.y as i32 + origin.1, width, height };
+            matches.push(ImageMatch { rect, center: rect.center(), score: found.score, scale });
+        }
+    }
+
+    matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+    // Drop matches centered inside a better one, including the same spot at other scales.
+    let mut kept: Vec<ImageMatch> = Vec::new();
+    for candidate in matches {
+        if !kept.iter().any(|better| better.rect.contains(candidate.center.0, candidate.center.1)) {
+            kept.push(candidate);
+        }
+    }
+    if options.mode == MatchMode::Best {
+        kept.truncate(1);
+    }
+    kept
+}
+
+/// Describes a click target for the macro file and for the planner.
+pub fn describe_click_target(nearby_text: &str, window_title: Option<&str>, (x, y): (f64, f64)) -> String {
+    let target = if nearby_text.is_empty() {
+        format!("Element at ({}, {})", x.round(), y.round())
+    } else {
+        format!("Element with text '{}'", nearby_text)
+    };
+    match window_title {
+        Some(title) if !title.is_empty() => format!("{} in window '{}'", target, title),
+        _ => target,
+    }
+}
+
+/// Side length of the tiles `FrameDiffer` hashes.
+pub const DIFF_TILE_SIZE: u32 = 16;
+/// How often screen waits capture a new frame.
+pub const SCREEN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A condition to wait for on screen instead of a fixed delay.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "until", rename_all = "snake_case")]
+pub enum ScreenWait {
+    /// Until nothing on screen has changed for `stable_ms`.
+    Stable { stable_ms: u64, timeout_ms: u64 },
+    /// Until something inside `rect` changes.
+    RegionChanges { rect: Rect, timeout_ms: u64 },
+}
+
+/// Compares consecutive frames tile by tile and reports the areas that changed.
+pub struct FrameDiffer {
+    tile_size: u32,
+    /// Dimensions and tile hashes of the previous frame.
+    previous: Option<(u32, u32, Vec<u64>)>,
+}
+
+impl Default for FrameDiffer {
+    fn default() -> Self {
+        Self::new(DIFF_TILE_SIZE)
+    }
+}
+
+impl FrameDiffer {
+    pub fn new(tile_size: u32) -> Self {
+        Self { tile_size: tile_size.max(1), previous: None }
+    }
+
+    fn tile_hashes(&self, frame: &RgbaImage) -> Vec<u64> {
+        let (width, height) = frame.dimensions();
+        let columns = width.div_ceil(self.tile_size);
+        let rows = height.div_ceil(self.tile_size);
+        let mut hashes = Vec::with_capacity((columns * rows) as usize);
+        for row in 0..rows {
+            for column in 0..columns {
+                // FNV-1a over the tile's rows.
+                let mut hash: u64 = 0xcbf29ce484222325;
+                let left = column * self.tile_size;
+                let right = (left + self.tile_size).min(width);
+                for y in row * self.tile_size..((row + 1) * self.tile_size).min(height) {
+                    let start = ((y * width + left) * 4) as usize;
+                    let end = ((y * width + right) * 4) as usize;
+                    for byte in &frame.as_raw()[start..end] {
+                        hash = (hash ^ *byte as u64).wrapping_mul(0x100000001b3);
+                    }
+                }
+                hashes.push(hash);
+            }
+        }
+        hashes
+    }
+
+    /// Compares `frame` with the previous one and returns the bounding rectangles of the
+    /// changed areas, one per group of touching tiles. The first frame, or one of a
+    /// different size, counts as changed everywhere.
+    pub fn diff(&mut self, frame: &RgbaImage) -> Vec<Rect> {
+        let (width, height) = frame.dimensions();
+        let hashes = self.tile_hashes(frame);
+        let previous = self.previous.replace((width, height, hashes));
+        let (_, _, hashes) = self.previous.as_ref().expect("just stored");
+
+        let previous_hashes = match previous {
+            Some((w, h, previous_hashes)) if (w, h) == (width, height) => previous_hashes,
+            _ => return vec![Rect { x: 0, y: 0, width, height }],
+        };
+
+        let columns = width.div_ceil(self.tile_size) as usize;
+        let mut changed: Vec<bool> = hashes.iter().zip(&previous_hashes).map(|(a, b)| a != b).collect();
+        let mut regions = Vec::new();
+        for start in 0..changed.len() {
+            if !changed[start] {
+                continue;
+            }
+            // Flood-fill the group of changed tiles touching this one.
+            changed[start] = false;
+            let mut stack = vec![start];
+            let (mut min_column, mut max_column) = (start % columns, start % columns);
+            let (mut min_row, mut max_row) = (start / columns, start / columns);
+            while let Some(tile) = stack.pop() {
+                let (column, row) = (tile % columns, tile / columns);
+                min_column = min_column.min(column);
+                max_column = max_column.max(column);
+                min_row = min_row.min(row);
+                max_row = max_row.max(row);
+                let neighbours = [
+                    (column > 0).then(|| tile - 1),
+                    (column + 1 < columns).then_some(tile + 1),
+                    (row > 0).then(|| tile - columns),
+                    Some(tile + columns),
+                ];
+                for neighbour in neighbours.into_iter().flatten() {
+                    if neighbour < changed.len() && changed[neighbour] {
+                        changed[neighbour] = false;
+                        stack.push(neighbour);
+                    }
+                }
+            }
+            let size = self.tile_size as usize;
+            let (left, top) = (min_column * size, min_row * size);
+            let right = ((max_column + 1) * size).min(width as usize);
+            let bottom = ((max_row + 1) * size).min(height as usize);
+            regions.push(Rect {
+                x: left as i32,
+                y: top as i32,
+                width: (right - left) as u32,
+                height: (bottom - top) as u32,
+            });
+        }
+        regions
+    }
+
+    /// Captures frames every `interval` until none has changed for `stable_for`. Returns
+    /// how long it waited. Time is counted in polling intervals, so the real wait is at
+    /// least that long.
+    pub fn wait_until_stable(
+        &mut self,
+        capture: &mut dyn FnMut() -> Result<RgbaImage, PerceptionError>,
+        sleep: &mut dyn FnMut(Duration),
+        stable_for: Duration,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<Duration, PerceptionError> {
+        self.previous = None;
+        self.diff(&capture()?);
+        let mut waited = Duration::ZERO;
+        let mut stable = Duration::ZERO;
+        while stable < stable_for {
+            if waited >= timeout {
+                return Err(PerceptionError::Timeout(timeout));
+            }
+            sleep(interval);
+            waited += interval;
+            if self.diff(&capture()?).is_empty() {
+                stable += interval;
+            } else {
+                stable = Duration::ZERO;
+            }
+        }
+        Ok(waited)
+    }
+
+    /// Captures frames every `interval` until one differs from the first, returning the
+    /// changed rectangles.
+    pub fn wait_until_changed(
+        &mut self,
+        capture: &mut dyn FnMut() -> Result<RgbaImage, PerceptionError>,
+        sleep: &mut dyn FnMut(Duration),
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<Vec<Rect>, PerceptionError> {
+        self.previous = None;
+        self.diff(&capture()?);
+        let mut waited = Duration::ZERO;
+        while waited < timeout {
+            sleep(interval);
+            waited += interval;
+            let changed = self.diff(&capture()?);
+            if !changed.is_empty() {
+                return Ok(changed);
+            }
+        }
+        Err(PerceptionError::Timeout(timeout))
+    }
+}
+
+/// Colors within this distance (per channel) of a region's first pixel belong to it.
+const BOX_COLOR_TOLERANCE: i16 = 10;
+/// Smallest width and height of a detected box.
+const MIN_BOX_SIDE: u32 = 10;
+
+/// What a UI element probably is, guessed from its shape and text.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UiRole {
+    Button,
+    TextField,
+    Checkbox,
+    Label,
+}
+
+impl UiRole {
+    fn name(&self) -> &'static str {
+        match self {
+            UiRole::Button => "button",
+            UiRole::TextField => "text_field",
+            UiRole::Checkbox => "checkbox",
+            UiRole::Label => "label",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UiElement {
+    /// Position in reading order, starting at 1, so the same screen always gets the same ids.
+    pub id: u32,
+    pub role: UiRole,
+    /// Text inside the element; empty for e.g. an empty text field.
+    pub text: String,
+    pub rect: Rect,
+    /// The accessibility object behind the element, when the map came from the
+    /// accessibility tree.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accessible: Option<AccessibleId>,
+}
+
+/// The elements on screen, for the planner to refer to by id.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct UiMap {
+    pub width: u32,
+    pub height: u32,
+    pub elements: Vec<UiElement>,
+}
+
+/// A solid rectangle found in a screenshot, e.g. a button or the inside of a text field.
+struct DetectedBox {
+    rect: Rect,
+    /// Brightness of its fill, 0 to 255.
+    luma: u8,
+}
+
+impl UiMap {
+    /// Combines OCR words with boxes detected in `image`: words inside a box give it its
+    /// text, and the remaining words are grouped into labels.
+    pub fn build(image: &RgbaImage, words: &[OcrWord]) -> Self {
+        let boxes = detect_boxes(image);
+        // The smallest box around each word's center, if any.
+        let owners: Vec<Option<usize>> = words
+            .iter()
+            .map(|word| {
+                let (x, y) = word.bbox.center();
+                boxes
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, b)| b.rect.contains(x, y))
+                    .min_by_key(|(_, b)| b.rect.width * b.rect.height)
+                    .map(|(index, _)| index)
+            })
+            .collect();
+
+        let mut elements = Vec::new();
+        for (index, detected) in boxes.iter().enumerate() {
+            let inside: Vec<OcrWord> =
+                words.iter().zip(&owners).filter(|(_, o)| **o == Some(index)).map(|(w, _)| w.clone()).collect();
+            if let Some(role) = guess_role(detected, &inside) {
+                elements.push((role, (words_to_text(&inside), None), detected.rect));
+            }
+        }
+
+        let mut label: Vec<OcrWord> = Vec::new();
+        for (word, _) in words.iter().zip(&owners).filter(|(_, o)| o.is_none()) {
+            if let Some(last) = label.last() {
+                if last.line != word.line || !same_label(last, word) {
+                    elements.push((UiRole::Label, (words_to_text(&label), None), words_bbox(&label)));
+                    label.clear();
+                }
+            }
+            label.push(word.clone());
+        }
+        if !label.is_empty() {
+            elements.push((UiRole::Label, (words_to_text(&label), None), words_bbox(&label)));
+        }
+
+        Self::numbered(image.width(), image.height(), elements)
+    }
+
+    /// Maps the showing buttons, fields, checkboxes and labels of an accessibility tree.
+    /// Their elements keep the accessible id, so they can be acted on without the mouse.
+    pub fn from_accessible(tree: &AccessibleNode, width: u32, height: u32) -> Self {
+        let elements = tree
+            .descendants()
+            .into_iter()
+            .filter(|node| node.has_state("showing"))
+            .filter_map(|node| {
+                let rect = node.extents?.clamp_to(width, height)?;
+                let role = accessible_role(node)?;
+                if role == UiRole::Label && node.name.trim().is_empty() {
+                    return None;
+                }
+                Some((role, (node.name.clone(), Some(node.id.clone())), rect))
+            })
+            .collect();
+        Self::numbered(width, height, elements)
+    }
+
+    fn numbered(width: u32, height: u32, elements: Vec<(UiRole, (String, Option<AccessibleId>), Rect)>) -> Self {
+        Self {
+            width,
+            height,
+            elements: reading_order(elements)
+                .into_iter()
+                .enumerate()
+                .map(|(index, (role, (text, accessible), rect))| UiElement {
+                    id: index as u32 + 1,
+                    role,
+                    text,
+                    rect,
+                    accessible,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn element(&self, id: u32) -> Option<&UiElement> {
+        self.elements.iter().find(|element| element.id == id)
+    }
+
+    /// Where to click to hit element `id`.
+    pub fn center_of(&self, id: u32) -> Option<(f64, f64)> {
+        self.element(id).map(|element| element.rect.center())
+    }
+
+    /// One line per element for the planner prompt, e.g. `#4 button "Save as" [250,165 100x34]`.
+    pub fn to_prompt(&self) -> String {
+        let mut prompt = format!(
+            "Screen {}x{}. Elements as #id role \"text\" [x,y widthxheight]:",
+            self.width, self.height
+        );
+        for element in &self.elements {
+            let rect = element.rect;
+            prompt.push_str(&format!("\n#{} {}", element.id, element.role.name()));
+            if !element.text.is_empty() {
+                prompt.push_str(&format!(" {:?}", element.text));
+            }
+            prompt.push_str(&format!(" [{},{} {}x{}]", rect.x, rect.y, rect.width, rect.height));
+        }
+        prompt
+    }
+}
+
+/// Maps AT-SPI role names to UI map roles; structural roles (panels, fillers) are left out.
+fn accessible_role(node: &AccessibleNode) -> Option<UiRole> {
+    match node.role.as_str() {
+        "push button" | "toggle button" | "push button menu" | "menu item" | "page tab" | "link" | "combo box" => {
+            Some(UiRole::Button)
+        }
+        "check box" | "radio button" | "check menu item" | "radio menu item" => Some(UiRole::Checkbox),
+        "entry" | "password text" | "spin button" => Some(UiRole::TextField),
+        "text" if node.editable => Some(UiRole::TextField),
+        "label" | "heading" | "static" | "text" => Some(UiRole::Label),
+        _ => None,
+    }
+}
+
+/// Light, wide boxes are text fields, small empty squares are checkboxes and other boxes
+/// with text are buttons. Boxes that are none of these are not listed.
+fn guess_role(detected: &DetectedBox, words: &[OcrWord]) -> Option<UiRole> {
+    let Rect { width, height, .. } = detected.rect;
+    let light = detected.luma >= 230;
+    let starts_left = words.iter().map(|w| w.bbox.x - detected.rect.x).min().map_or(true, |inset| inset < height as i32);
+    if words.is_empty() && width <= 24 && height <= 24 && width.abs_diff(height) <= 3 {
+        Some(UiRole::Checkbox)
+    } else if light && width >= 3 * height && height <= 48 && starts_left {
+        Some(UiRole::TextField)
+    } else if !words.is_empty() && height <= 64 {
+        Some(UiRole::Button)
+    } else {
+        None
+    }
+}
+
+/// Sorts elements into rows by their vertical centers, then each row left to right.
+fn reading_order<T>(mut elements: Vec<(UiRole, T, Rect)>) -> Vec<(UiRole, T, Rect)> {
+    elements.sort_by(|a, b| a.2.center().1.total_cmp(&b.2.center().1));
+    let mut rows: Vec<Vec<(UiRole, T, Rect)>> = Vec::new();
+    for element in elements {
+        match rows.last_mut() {
+            // Same row when its center is within half the height of the row's first element.
+            Some(row) if element.2.center().1 - row[0].2.center().1 < row[0].2.height as f64 / 2.0 => row.push(element),
+            _ => rows.push(vec![element]),
+        }
+    }
+    rows.into_iter()
+        .flat_map(|mut row| {
+            row.sort_by_key(|element| element.2.x);
+            row
+        })
+        .collect()
+}
+
+/// Finds solid single-color rectangles: regions whose pixels fill their whole outline.
+/// Text and icons inside a region leave holes but do not break the outline. Regions
+/// spanning most of the image (backgrounds, title bars) or containing other boxes
+/// (panels) are left out.
+fn detect_boxes(image: &RgbaImage) -> Vec<DetectedBox> {
+    let (width, height) = image.dimensions();
+    let pixels = image.as_raw();
+    let color = |index: usize| [pixels[index * 4] as i16, pixels[index * 4 + 1] as i16, pixels[index * 4 + 2] as i16];
+    let mut labels = vec![0u32; (width * height) as usize];
+    let mut label = 0;
+    let mut stack = Vec::new();
+    let mut boxes = Vec::new();
+
+    for start in 0..labels.len() {
+        if labels[start] != 0 {
+            continue;
+        }
+        label += 1;
+        let seed = color(start);
+        let (mut left, mut top) = (start as u32 % width, start as u32 / width);
+        let (mut right, mut bottom) = (left, top);
+        let mut count = 0usize;
+        labels[start] = label;
+        stack.push(start);
+        while let Some(index) = stack.pop() {
+            count += 1;
+            let (x, y) = (index as u32 % width, index as u32 / width);
+            left = left.min(x);
+            right = right.max(x);
+            top = top.min(y);
+            bottom = bottom.max(y);
+            let neighbours = [
+                (x > 0).then(|| index - 1),
+                (x + 1 < width).then(|| index + 1),
+                (y > 0).then(|| index - width as usize),
+                (y + 1 < height).then(|| index + width as usize),
+            ];
+            for neighbour in neighbours.into_iter().flatten() {
+                let similar = color(neighbour).iter().zip(&seed).all(|(a, b)| (a - b).abs() <= BOX_COLOR_TOLERANCE);
+                if labels[neighbour] == 0 && similar {
+                    labels[neighbour] = label;
+                    stack.push(neighbour);
+                }
+            }
+        }
+
+        let (box_width, box_height) = (right - left + 1, bottom - top + 1);
+        if box_width < MIN_BOX_SIDE
+            || box_height < MIN_BOX_SIDE
+            || box_width > width * 4 / 5
+            || box_height > height * 4 / 5
+            || count < (box_width * box_height / 2) as usize
+        {
+            continue;
+        }
+        // At least 95% of the outline must belong to the region.
+        let on_region = |&(x, y): &(u32, u32)| labels[(y * width + x) as usize] == label;
+        let outline: Vec<(u32, u32)> = (left..=right)
+            .flat_map(|x| [(x, top), (x, bottom)])
+            .chain((top..=bottom).flat_map(|y| [(left, y), (right, y)]))
+            .collect();
+        if outline.iter().filter(|point| on_region(point)).count() * 20 < outline.len() * 19 {
+            continue;
+        }
+        let [r, g, b] = seed;
+        boxes.push(DetectedBox {
+            rect: Rect { x: left as i32, y: top as i32, width: box_width, height: box_height },
+            luma: ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8,
+        });
+    }
+
+    let encloses = |outer: &Rect, inner: &Rect| {
+        outer != inner
+            && inner.x >= outer.x
+            && inner.y >= outer.y
+            && inner.x + inner.width as i32 <= outer.x + outer.width as i32
+            && inner.y + inner.height as i32 <= outer.y + outer.height as i32
+    };
+    let panels: Vec<bool> = boxes.iter().map(|outer| boxes.iter().any(|inner| encloses(&outer.rect, &inner.rect))).collect();
+    boxes.into_iter().zip(panels).filter(|(_, panel)| !panel).map(|(detected, _)| detected).collect()
+}
+
+/// What `take_screenshot` captures.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CaptureTarget {
+    #[default]
+    Screen,
+    Monitor { id: usize },
+    Region { rect: Rect },
+    Window { id: u32 },
+}
+
+/// A screenshot as returned to the frontend: saved to `path`, or inline as PNG bytes.
+#[derive(Serialize, Debug, Clone)]
+pub struct Screenshot {
+    pub width: u32,
+    pub height: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub png: Option<Vec<u8>>,
+}
+
+/// Text read from the screen, as lines and as positioned words.
+#[derive(Serialize, Debug, Clone)]
+pub struct ScreenText {
+    pub text: String,
+    pub words: Vec<OcrWord>,
+}
+
+/// An occurrence of searched text on screen.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TextMatch {
+    pub rect: Rect,
+    /// Where to click to hit the text.
+    pub center: (f64, f64),
+    pub confidence: f32,
+}
+
+pub struct Perception {
+    screen: Arc<dyn ScreenBackend>,
+    ocr: Arc<dyn OcrEngine>,
+    /// Whether UI maps come from the accessibility tree when one is available.
+    use_accessibility: bool,
+}
+
+impl Default for Perception {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Perception {
+    /// Captures from the X11 desktop, reads text with tesseract and maps UIs from the
+    /// accessibility tree where possible.
     pub fn new() -> Self {
-        Self
+        Self::with_backend(Arc::new(X11Screen)).with_accessibility(true)
+    }
+
+    pub fn with_backend(screen: Arc<dyn ScreenBackend>) -> Self {
+        Self { screen, ocr: Arc::new(TesseractOcr::default()), use_accessibility: false }
+    }
+
+    pub fn with_accessibility(mut self, enabled: bool) -> Self {
+        self.use_accessibility = enabled;
+        self
+    }
+
+    pub fn with_ocr(mut self, ocr: Arc<dyn OcrEngine>) -> Self {
+        self.ocr = ocr;
+        self
+    }
+
+    pub fn backend(&self) -> Arc<dyn ScreenBackend> {
+        self.screen.clone()
+    }
+
+    /// Captures the whole virtual screen, across all monitors.
+    pub fn capture_screen(&self) -> Result<RgbaImage, PerceptionError> {
+        let (width, height) = self.screen.screen_size()?;
+        self.screen.capture_region(Rect { x: 0, y: 0, width, height })
+    }
+
+    pub fn monitors(&self) -> Result<Vec<Monitor>, PerceptionError> {
+        self.screen.monitors()
+    }
+
+    pub fn active_window(&self) -> Result<Option<WindowContext>, PerceptionError> {
+        self.screen.active_window()
+    }
+
+    pub fn capture_monitor(&self, id: usize) -> Result<RgbaImage, PerceptionError> {
+        let monitor = self
+            .screen
+            .monitors()?
+            .into_iter()
+            .find(|monitor| monitor.id == id)
+            .ok_or(PerceptionError::MonitorNotFound(id))?;
+        self.screen.capture_region(monitor.rect)
+    }
+
+    pub fn capture_region(&self, rect: Rect) -> Result<RgbaImage, PerceptionError> {
+        self.screen.capture_region(rect)
+    }
+
+    /// Captures what is on screen where the window is, so overlapping windows show too.
+    pub fn capture_window(&self, id: u32) -> Result<RgbaImage, PerceptionError> {
+        self.screen.capture_region(self.screen.window_rect(id)?)
+    }
+
+    pub fn capture(&self, target: CaptureTarget) -> Result<RgbaImage, PerceptionError> {
+        match target {
+            CaptureTarget::Screen => self.capture_screen(),
+            CaptureTarget::Monitor { id } => self.capture_monitor(id),
+            CaptureTarget::Region { rect } => self.capture_region(rect),
+            CaptureTarget::Window { id } => self.capture_window(id),
+        }
+    }
+
+    /// Finds `template` on screen.
+    pub fn find_image(&self, template: &RgbaImage, options: &FindImageOptions) -> Result<Vec<ImageMatch>, PerceptionError> {
+        Ok(find_image(&self.capture_screen()?, template, options))
+    }
+
+    /// Reads the text on screen, or only inside `region`.
+    pub fn read_screen(&self, region: Option<Rect>) -> Result<ScreenText, PerceptionError> {
+        let words = self.ocr.recognize(&self.capture_screen()?, region)?;
+        Ok(ScreenText { text: words_to_text(&words), words })
+    }
+
+    /// Finds every occurrence of `query` on screen, in reading order.
+    pub fn find_text_on_screen(&self, query: &str) -> Result<Vec<TextMatch>, PerceptionError> {
+        let words = self.ocr.recognize(&self.capture_screen()?, None)?;
+        Ok(find_text(&words, query)
+            .into_iter()
+            .map(|(rect, confidence)| TextMatch { rect, center: rect.center(), confidence })
+            .collect())
+    }
+
+    /// Maps the elements on screen for the planner: from the focused application's
+    /// accessibility tree if it exposes one, otherwise from a screenshot and OCR.
+    pub fn ui_map(&self) -> Result<UiMap, PerceptionError> {
+        if let Some(map) = self.accessible_ui_map(|| self.accessibility_tree())? {
+            return Ok(map);
+        }
+        self.ocr_ui_map(&self.capture_screen()?)
+    }
+
+    /// Maps the elements of the application running as `pid`, which need not be focused,
+    /// from its accessibility tree. Without a pid or a tree, maps `screenshot` with OCR.
+    pub fn application_ui_map(
+        &self,
+        pid: Option<u32>,
+        screenshot: impl FnOnce() -> Result<RgbaImage, PerceptionError>,
+    ) -> Result<UiMap, PerceptionError> {
+        if let Some(pid) = pid {
+            if let Some(map) = self.accessible_ui_map(|| self.application_tree(pid))? {
+                return Ok(map);
+            }
+        }
+        self.ocr_ui_map(&screenshot()?)
+    }
+
+    /// Maps the tree from `tree`, or `None` when accessibility is off or gives no elements.
+    fn accessible_ui_map(
+        &self,
+        tree: impl FnOnce() -> Result<AccessibleNode, PerceptionError>,
+    ) -> Result<Option<UiMap>, PerceptionError> {
+        if !self.use_accessibility {
+            return Ok(None);
+        }
+        let (width, height) = self.screen.screen_size()?;
+        match tree() {
+            Ok(tree) => {
+                let map = UiMap::from_accessible(&tree, width, height);
+                Ok((!map.elements.is_empty()).then_some(map))
+            }
+            Err(e) => {
+                log::debug!("No accessibility tree, mapping the screenshot instead: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    fn ocr_ui_map(&self, image: &RgbaImage) -> Result<UiMap, PerceptionError> {
+        let words = self.ocr.recognize(image, None)?;
+        Ok(UiMap::build(image, &words))
+    }
+
+    /// The accessibility tree of the application with the active window.
+    pub fn accessibility_tree(&self) -> Result<AccessibleNode, PerceptionError> {
+        Accessibility::connect()
+            .and_then(|accessibility| accessibility.focused_application())
+            .map_err(|e| PerceptionError::Accessibility(e.to_string()))
+    }
+
+    /// The accessibility tree of the application running as `pid`.
+    pub fn application_tree(&self, pid: u32) -> Result<AccessibleNode, PerceptionError> {
+        Accessibility::connect()
+            .and_then(|accessibility| accessibility.application_for_pid(pid))
+            .map_err(|e| PerceptionError::Accessibility(e.to_string()))
+    }
+
+    /// Clicks, sets the text of or focuses an accessible object without moving the mouse.
+    pub fn perform_accessible_action(&self, target: &AccessibleId, action: &AccessibleAction) -> Result<(), PerceptionError> {
+        Accessibility::connect()
+            .and_then(|accessibility| accessibility.perform(target, action))
+            .map_err(|e| PerceptionError::Accessibility(e.to_string()))
+    }
+
+    /// Blocks until nothing on screen has changed for `stable_for`.
+    pub fn wait_until_stable(&self, stable_for: Duration, timeout: Duration) -> Result<Duration, PerceptionError> {
+        FrameDiffer::default().wait_until_stable(
+            &mut || self.capture_screen(),
+            &mut thread::sleep,
+            stable_for,
+            timeout,
+            SCREEN_POLL_INTERVAL,
+        )
+    }
+
+    /// Blocks until something inside `rect` changes, returning the changed areas in
+    /// screen coordinates.
+    pub fn wait_until_region_changes(&self, rect: Rect, timeout: Duration) -> Result<Vec<Rect>, PerceptionError> {
+        let changed = FrameDiffer::default().wait_until_changed(
+            &mut || self.capture_region(rect),
+            &mut thread::sleep,
+            timeout,
+            SCREEN_POLL_INTERVAL,
+        )?;
+        // Captures are clamped to the screen, so offsets start at the clamped origin.
+        let origin = (rect.x.max(0), rect.y.max(0));
+        Ok(changed.into_iter().map(|r| Rect { x: r.x + origin.0, y: r.y + origin.1, ..r }).collect())
+    }
+
+    pub fn wait_for(&self, wait: &ScreenWait) -> Result<(), PerceptionError> {
+        match *wait {
+            ScreenWait::Stable { stable_ms, timeout_ms } => self
+                .wait_until_stable(Duration::from_millis(stable_ms), Duration::from_millis(timeout_ms))
+                .map(|_| ()),
+            ScreenWait::RegionChanges { rect, timeout_ms } => self
+                .wait_until_region_changes(rect, Duration::from_millis(timeout_ms))
+                .map(|_| ()),
+        }
+    }
+
+    /// Captures `target`, writing it to `save_to` as PNG if given and returning the PNG
+    /// bytes otherwise.
+    pub fn take_screenshot(&self, target: CaptureTarget, save_to: Option<&Path>) -> Result<Screenshot, PerceptionError> {
+        match save_to {
+            Some(path) => {
+                let image = self.save_screenshot(target, path)?;
+                Ok(Screenshot { width: image.width(), height: image.height(), path: Some(path.to_path_buf()), png: None })
+            }
+            None => {
+                let image = self.capture(target)?;
+                Ok(Screenshot { width: image.width(), height: image.height(), path: None, png: Some(encode_png(&image)?) })
+            }
+        }
+    }
+
+    /// Captures `target` and writes it to `path` as PNG.
+    pub fn save_screenshot(&self, target: CaptureTarget, path: &Path) -> Result<RgbaImage, PerceptionError> {
+        let image = self.capture(target)?;
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(|e| PerceptionError::Io(e.to_string()))?;
+        }
+        persistence::write_atomic(path, &encode_png(&image)?).map_err(|e| PerceptionError::Io(e.to_string()))?;
+        Ok(image)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    /// A 400x300 fixture screen: grey, with a red "button" at (300..360, 250..280).
+    fn fixture_screen() -> FixtureScreen {
+        let image = RgbaImage::from_fn(400, 300, |x, y| {
+            if (300..360).contains(&x) && (250..280).contains(&y) {
+                Rgba([200, 0, 0, 255])
+            } else {
+                Rgba([128, 128, 128, 255])
+            }
+        });
+        FixtureScreen {
+            image,
+            window_title: Some("Invoices".to_string()),
+            monitors: vec![
+                Monitor { id: 0, name: "left".to_string(), rect: Rect { x: 0, y: 0, width: 200, height: 300 }, primary: true },
+                Monitor { id: 1, name: "right".to_string(), rect: Rect { x: 200, y: 0, width: 200, height: 300 }, primary: false },
+            ],
+            windows: vec![(0x2a00004, Rect { x: 290, y: 240, width: 80, height: 50 })],
+        }
+    }
+
+    #[test]
+    fn test_click_crop_is_clamped_to_the_screen() {
+        let screen = fixture_screen();
+        let rect = Rect::centered_on(330.0, 265.0, CLICK_CROP_WIDTH, CLICK_CROP_HEIGHT);
+        assert_eq!(rect, Rect { x: 210, y: 205, width: 240, height: 120 });
+
+        let crop = screen.capture_region(rect).unwrap();
+        assert_eq!(crop.dimensions(), (190, 95));
+        // The button sits at the same offset inside the crop.
+        assert_eq!(crop.get_pixel(330 - 210, 265 - 205), &Rgba([200, 0, 0, 255]));
+        assert!(screen.capture_region(Rect { x: 500, y: 0, width: 10, height: 10 }).is_err());
+    }
+
+    #[test]
+    fn test_zpixmap_byte_order_and_masks() {
+        let rgb = [0xff0000, 0x00ff00, 0x0000ff];
+        // Orange on a little-endian BGRX server and on a big-endian XRGB one.
+        assert_eq!(decode_zpixmap(&[0x00, 0x80, 0xff, 0x00], false, rgb).unwrap(), vec![255, 128, 0, 255]);
+        assert_eq!(decode_zpixmap(&[0x00, 0xff, 0x80, 0x00], true, rgb).unwrap(), vec![255, 128, 0, 255]);
+        // A 30-bit visual with 10 bits per channel, blue in the low bits.
+        let deep = [0x3ff0_0000, 0x000f_fc00, 0x0000_03ff];
+        let pixel = (0x3ffu32 << 20) | 0x1ff;
+        assert_eq!(decode_zpixmap(&pixel.to_le_bytes(), false, deep).unwrap(), vec![255, 0, 127, 255]);
+    }
+
+    #[test]
+    fn test_capture_targets() {
+        let perception = Perception::with_backend(Arc::new(fixture_screen()));
+        assert_eq!(perception.capture_screen().unwrap().dimensions(), (400, 300));
+
+        let right = perception.capture_monitor(1).unwrap();
+        assert_eq!(right.dimensions(), (200, 300));
+        assert_eq!(right.get_pixel(310 - 200, 260), &Rgba([200, 0, 0, 255]));
+        assert!(matches!(perception.capture_monitor(2), Err(PerceptionError::MonitorNotFound(2))));
+
+        let window = perception.capture_window(0x2a00004).unwrap();
+        assert_eq!(window.dimensions(), (80, 50));
+        assert_eq!(window.get_pixel(0, 0), &Rgba([128, 128, 128, 255]));
+        assert_eq!(window.get_pixel(10, 10), &Rgba([200, 0, 0, 255]));
+        assert!(perception.capture_window(1).is_err());
+    }
+
+    #[test]
+    fn test_saved_screenshot_loads_as_fixture() {
+        let path = std::env::temp_dir().join(format!("nyx-screenshot-{}.png", std::process::id()));
+        let perception = Perception::with_backend(Arc::new(fixture_screen()));
+        let target = CaptureTarget::Region { rect: Rect { x: 300, y: 250, width: 60, height: 30 } };
+        let saved = perception.save_screenshot(target, &path).unwrap();
+
+        let fixture = FixtureScreen::from_file(&path, None).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(fixture.image, saved);
+        assert_eq!(fixture.image.get_pixel(0, 0), &Rgba([200, 0, 0, 255]));
+    }
+
+    /// A 100x60 grey frame with a white square at `(x, y)`.
+    fn frame_with_square(x: u32, y: u32) -> RgbaImage {
+        RgbaImage::from_fn(100, 60, |px, py| {
+            if (x..x + 10).contains(&px) && (y..y + 10).contains(&py) {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([40, 40, 40, 255])
+            }
+        })
+    }
+
+    #[test]
+    fn test_frame_differ_reports_changed_tiles() {
+        let mut differ = FrameDiffer::new(16);
+        assert_eq!(differ.diff(&frame_with_square(0, 0)), vec![Rect { x: 0, y: 0, width: 100, height: 60 }]);
+        assert!(differ.diff(&frame_with_square(0, 0)).is_empty());
+
+        // The square leaves tile (0, 0) and lands across the edge tiles at the bottom right.
+        let changed = differ.diff(&frame_with_square(90, 44));
+        assert_eq!(
+            changed,
+            vec![
+                Rect { x: 0, y: 0, width: 16, height: 16 },
+                Rect { x: 80, y: 32, width: 20, height: 28 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wait_until_stable_and_changed() {
+        let mut frames = [(0, 0), (5, 0), (10, 0), (10, 0), (10, 0), (10, 0), (10, 0), (10, 0), (40, 20)]
+            .into_iter()
+            .map(|(x, y)| frame_with_square(x, y));
+        let mut capture = || frames.next().ok_or_else(|| PerceptionError::Capture("no more frames".to_string()));
+        let mut slept = Duration::ZERO;
+        let mut sleep = |d: Duration| slept += d;
+        let interval = Duration::from_millis(100);
+
+        let mut differ = FrameDiffer::default();
+        let waited = differ
+            .wait_until_stable(&mut capture, &mut sleep, Duration::from_millis(300), Duration::from_secs(5), interval)
+            .unwrap();
+        assert_eq!(waited, Duration::from_millis(500));
+
+        let changed = differ.wait_until_changed(&mut capture, &mut sleep, Duration::from_secs(5), interval).unwrap();
+        assert_eq!(changed.len(), 2);
+        assert_eq!(slept, Duration::from_millis(700));
+
+        let mut still = || Ok(frame_with_square(0, 0));
+        assert!(matches!(
+            differ.wait_until_changed(&mut still, &mut |_| {}, Duration::from_millis(300), interval),
+            Err(PerceptionError::Timeout(_))
+        ));
+    }
+
+    fn fixture_path(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+    }
+
+    /// The invoice dialog screenshot with the hand-written words on it.
+    fn invoice_dialog() -> Perception {
+        let screen = FixtureScreen::from_file(&fixture_path("invoice_dialog.png"), None).unwrap();
+        let ocr = FixtureOcr::from_tsv(&fixture_path("invoice_dialog.tsv")).unwrap();
+        Perception::with_backend(Arc::new(screen)).with_ocr(Arc::new(ocr))
+    }
+
+    #[test]
+    fn test_read_screen_fixture() {
+        let perception = invoice_dialog();
+        let screen_text = perception.read_screen(None).unwrap();
+        assert_eq!(
+            screen_text.text,
+            "Invoices - Nyx\nCustomer: Acme Corp\nTotal due: 1,250.00\nSave a copy before sending\nSave as\nCancel"
+        );
+        assert_eq!(screen_text.words[0].bbox, Rect { x: 13, y: 9, width: 64, height: 14 });
+        assert!((screen_text.words[0].confidence - 0.955).abs() < 1e-6);
+
+        let buttons = perception.read_screen(Some(Rect { x: 240, y: 160, width: 240, height: 50 })).unwrap();
+        assert_eq!(buttons.text, "Save as\nCancel");
+    }
+
+    #[test]
+    fn test_find_text_on_screen_fixture() {
+        let perception = invoice_dialog();
+        let matches = perception.find_text_on_screen("save AS").unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].rect, Rect { x: 272, y: 176, width: 61, height: 13 });
+        assert_eq!(matches[0].center, (302.5, 182.5));
+
+        assert_eq!(perception.find_text_on_screen("customer").unwrap().len(), 1);
+        assert_eq!(perception.find_text_on_screen("save").unwrap().len(), 2);
+        assert!(perception.find_text_on_screen("Delete").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_ui_map_fixture() {
+        let map = invoice_dialog().ui_map().unwrap();
+        assert_eq!(
+            map.to_prompt(),
+            "Screen 480x220. Elements as #id role \"text\" [x,y widthxheight]:\n\
+             #1 label \"Invoices - Nyx\" [13,9 111x17]\n\
+             #2 label \"Customer:\" [20,58 82x13]\n\
+             #3 text_field \"Acme Corp\" [105,51 158x28]\n\
+             #4 label \"Total due: 1,250.00\" [19,87 158x15]\n\
+             #5 label \"Save a copy before sending\" [21,117 221x17]\n\
+             #6 button \"Save as\" [250,165 100x34]\n\
+             #7 button \"Cancel\" [364,165 100x34]"
+        );
+        assert_eq!(map.center_of(6), Some((300.0, 182.0)));
+        assert_eq!(map.center_of(8), None);
+    }
+
+    #[test]
+    fn test_ui_map_detects_empty_fields_and_checkboxes() {
+        let mut image = RgbaImage::from_pixel(300, 120, Rgba([240, 240, 240, 255]));
+        let mut fill = |rect: Rect, color: [u8; 4]| {
+            for y in rect.y..rect.y + rect.height as i32 {
+                for x in rect.x..rect.x + rect.width as i32 {
+                    image.put_pixel(x as u32, y as u32, Rgba(color));
+                }
+            }
+        };
+        fill(Rect { x: 80, y: 20, width: 200, height: 26 }, [120, 120, 120, 255]);
+        fill(Rect { x: 81, y: 21, width: 198, height: 24 }, [255, 255, 255, 255]);
+        fill(Rect { x: 20, y: 70, width: 16, height: 16 }, [120, 120, 120, 255]);
+        fill(Rect { x: 21, y: 71, width: 14, height: 14 }, [255, 255, 255, 255]);
+
+        let map = UiMap::build(&image, &[]);
+        let roles: Vec<(UiRole, Rect)> = map.elements.iter().map(|e| (e.role, e.rect)).collect();
+        assert_eq!(
+            roles,
+            vec![
+                (UiRole::TextField, Rect { x: 81, y: 21, width: 198, height: 24 }),
+                (UiRole::Checkbox, Rect { x: 21, y: 71, width: 14, height: 14 }),
+            ]
+        );
+        assert_eq!(map.elements[1].id, 2);
+    }
+
+    #[test]
+    fn test_ui_map_from_accessibility_tree() {
+        let node = |path: &str, role: &str, name: &str, extents: Option<Rect>, children: Vec<AccessibleNode>| AccessibleNode {
+            id: AccessibleId { bus: ":1.42".to_string(), path: path.to_string() },
+            role: role.to_string(),
+            name: name.to_string(),
+            states: vec!["showing".to_string()],
+            extents,
+            actions: Vec::new(),
+            editable: role == "text",
+            children,
+        };
+        let rect = |x, y, width, height| Some(Rect { x, y, width, height });
+        let tree = node(
+            "/app",
+            "application",
+            "invoices",
+            None,
+            vec![node(
+                "/frame",
+                "frame",
+                "Invoices - Nyx",
+                rect(0, 0, 480, 220),
+                vec![
+                    node("/save", "push button", "Save as", rect(250, 165, 100, 34), vec![]),
+                    node("/customer", "label", "Customer:", rect(20, 58, 82, 13), vec![]),
+                    node("/field", "text", "", rect(105, 51, 158, 28), vec![]),
+                    node("/filler", "filler", "", rect(0, 40, 480, 100), vec![]),
+                    node("/offscreen", "push button", "Delete", rect(900, 10, 60, 20), vec![]),
+                ],
+            )],
+        );
+
+        let map = UiMap::from_accessible(&tree, 480, 220);
+        assert_eq!(
+            map.to_prompt(),
+            "Screen 480x220. Elements as #id role \"text\" [x,y widthxheight]:\n\
+             #1 label \"Customer:\" [20,58 82x13]\n\
+             #2 text_field [105,51 158x28]\n\
+             #3 button \"Save as\" [250,165 100x34]"
+        );
+        assert_eq!(map.element(3).unwrap().accessible.as_ref().unwrap().path, "/save");
+    }
+
+    #[test]
+    #[ignore = "needs the tesseract binary"]
+    fn test_tesseract_reads_fixture() {
+        let image = image::open(fixture_path("invoice_dialog.png")).unwrap().to_rgba8();
+        let words = TesseractOcr::default().recognize(&image, Some(Rect { x: 240, y: 160, width: 240, height: 50 })).unwrap();
+        let found = find_text(&words, "Save as");
+        assert_eq!(found.len(), 1);
+        assert!(found[0].0.contains(302.0, 182.0));
+    }
+
+    /// A busy 16-bit-ish background, so templates only match where they were pasted.
+    fn textured_screen(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            let value = (x * 7 + y * 13 + (x * y) % 29 + (x / 9) * (y / 11) * 5) % 256;
+            Rgba([value as u8, (value * 5 % 256) as u8, ((x + y) % 256) as u8, 255])
+        })
+    }
+
+    /// A 24x24 green "export" icon with a white arrow.
+    fn export_icon() -> RgbaImage {
+        RgbaImage::from_fn(24, 24, |x, y| {
+            let arrow = (x == 12 && y > 4 && y < 19) || (y + x == 12 + 6 && (6..=12).contains(&x)) || (x == y + 6 && (12..=18).contains(&x));
+            if arrow {
+                Rgba([255, 255, 255, 255])
+            } else if x < 2 || y < 2 || x > 21 || y > 21 {
+                Rgba([20, 90, 40, 255])
+            } else {
+                Rgba([40, 170 + (y as u8), 70, 255])
+            }
+        })
+    }
+
+    #[test]
+    fn test_find_image_at_hidpi_scale() {
+        let mut screen = textured_screen(400, 300);
+        let icon = export_icon();
+        let doubled = imageops::resize(&icon, 48, 48, imageops::FilterType::Triangle);
+        imageops::replace(&mut screen, &doubled, 250, 120);
+
+        let found = find_image(&screen, &icon, &FindImageOptions::default());
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].rect, Rect { x: 250, y: 120, width: 48, height: 48 });
+        assert_eq!(found[0].scale, 2.0);
+        assert!(found[0].score > 0.99);
+
+        let unscaled = FindImageOptions { scales: vec![1.0], ..FindImageOptions::default() };
+        assert!(find_image(&screen, &icon, &unscaled).is_empty());
+    }
+
+    #[test]
+    fn test_find_all_images_in_region() {
+        let mut screen = textured_screen(400, 300);
+        let icon = export_icon();
+        for (x, y) in [(30, 40), (200, 40), (330, 250)] {
+            imageops::replace(&mut screen, &icon, x, y);
+        }
+        let all = FindImageOptions { mode: MatchMode::All, ..FindImageOptions::default() };
+        let found = find_image(&screen, &icon, &all);
+        let mut corners: Vec<(i32, i32)> = found.iter().map(|m| (m.rect.x, m.rect.y)).collect();
+        corners.sort();
+        assert_eq!(corners, vec![(30, 40), (200, 40), (330, 250)]);
+
+        let top_right = FindImageOptions { region: Some(Rect { x: 150, y: 0, width: 250, height: 150 }), ..all };
+        let found = find_image(&screen, &icon, &top_right);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].center, (212.0, 52.0));
+    }
+
+    /// Upper bound per search in release builds, so the benchmark catches regressions.
+    const BENCH_BUDGET: Duration = Duration::from_millis(500);
+
+    /// Run with `cargo test --release bench_find_image -- --ignored`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_find_image_1080p() {
+        let mut screen = textured_screen(1920, 1080);
+        let icon = imageops::resize(&export_icon(), 32, 32, imageops::FilterType::Triangle);
+        imageops::replace(&mut screen, &icon, 1500, 900);
+        for (name, options) in [
+            ("best, scale 1.0", FindImageOptions { scales: vec![1.0], ..FindImageOptions::default() }),
+            ("best, HiDPI scales", FindImageOptions::default()),
+            ("all, HiDPI scales", FindImageOptions { mode: MatchMode::All, ..FindImageOptions::default() }),
+        ] {
+            let started = std::time::Instant::now();
+            let runs = 5;
+            for _ in 0..runs {
+                assert_eq!(find_image(&screen, &icon, &options)[0].rect.x, 1500);
+            }
+            let per_run = started.elapsed() / runs;
+            assert!(per_run < BENCH_BUDGET, "find_image 1920x1080, {}: {:?}/run", name, per_run);
+        }
+    }
+
+    #[test]
+    fn test_describe_click_target() {
+        assert_eq!(
+            describe_click_target("Submit", Some("Invoices"), (812.0, 440.0)),
+            "Element with text 'Submit' in window 'Invoices'"
+        );
+        assert_eq!(describe_click_target("", None, (812.4, 439.6)), "Element at (812, 440)");
+    }
+
+    #[test]
+    #[ignore = "needs an X server, e.g. run under xvfb-run"]
+    fn test_x11_capture_region() {
+        let crop = X11Screen.capture_region(Rect { x: 0, y: 0, width: 64, height: 32 }).unwrap();
+        assert_eq!(crop.dimensions(), (64, 32));
+    }
+}
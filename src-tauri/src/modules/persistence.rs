@@ -0,0 +1,213 @@
+// Crash-safe file persistence
+// Files are written to a temporary sibling, fsynced and atomically renamed over the
+// target, keeping the previous version as `{file}.bak`. Reads fall back to that backup
+// when the main file is missing or corrupt.
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use tauri::Emitter;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum PersistenceError {
+    #[error("File system error for {path:?}: {message}")]
+    FileSystem { path: PathBuf, message: String },
+    #[error("{path:?} is corrupt and no usable backup exists: {message}")]
+    Corrupt { path: PathBuf, message: String },
+    #[error("{0:?} does not exist")]
+    NotFound(PathBuf),
+}
+
+impl PersistenceError {
+    fn file_system(path: &Path, error: io::Error) -> Self {
+        PersistenceError::FileSystem {
+            path: path.to_path_buf(),
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Emitted as `storage_recovered` when a file had to be restored from its backup.
+#[derive(Serialize, Debug, Clone)]
+pub struct RecoveryWarning {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// A value read from disk, with a warning if it came from the backup.
+pub struct Loaded<T> {
+    pub value: T,
+    pub recovery: Option<RecoveryWarning>,
+}
+
+/// Returns the path of the backup kept next to `path`.
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".bak");
+    path.with_file_name(file_name)
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".tmp");
+    path.with_file_name(file_name)
+}
+
+/// Atomically replaces `path` with `contents`. Readers see either the old or the new
+/// file, never a truncated one, and the old version is kept as the backup.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), PersistenceError> {
+    let temp = temp_path(path);
+    let write_temp = || -> io::Result<()> {
+        let mut file = File::create(&temp)?;
+        file.write_all(contents)?;
+        file.sync_all()
+    };
+    if let Err(e) = write_temp() {
+        let _ = fs::remove_file(&temp);
+        return Err(PersistenceError::file_system(&temp, e));
+    }
+
+    if path.is_file() {
+        refresh_backup(path)?;
+    }
+
+    fs::rename(&temp, path).map_err(|e| PersistenceError::file_system(path, e))?;
+    sync_parent_dir(path);
+    Ok(())
+}
+
+/// Serializes `value` as pretty JSON and writes it with `write_atomic`.
+pub fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), PersistenceError> {
+    let json = serde_json::to_string_pretty(value).map_err(|e| PersistenceError::FileSystem {
+        path: path.to_path_buf(),
+        message: format!("Serialization failed: {}", e),
+    })?;
+    write_atomic(path, json.as_bytes())
+}
+
+/// Points the backup at the current file. A hard link avoids copying large files;
+/// file systems without links get a copy instead.
+fn refresh_backup(path: &Path) -> Result<(), PersistenceError> {
+    let backup = backup_path(path);
+    match fs::remove_file(&backup) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(PersistenceError::file_system(&backup, e)),
+    }
+    if fs::hard_link(path, &backup).is_err() {
+        fs::copy(path, &backup).map_err(|e| PersistenceError::file_system(&backup, e))?;
+    }
+    Ok(())
+}
+
+/// Makes the rename itself durable. Not supported everywhere, so failures are ignored.
+fn sync_parent_dir(path: &Path) {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        if let Ok(dir) = File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+/// Reads JSON from `path`, falling back to the backup if the file is missing or does
+/// not parse.
+pub fn read_json<T: DeserializeOwned>(path: &Path) -> Result<Loaded<T>, PersistenceError> {
+    let reason = match fs::read_to_string(path) {
+        Ok(json) => match serde_json::from_str(&json) {
+            Ok(value) => return Ok(Loaded { value, recovery: None }),
+            Err(e) => format!("{:?} is corrupt: {}", path, e),
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => format!("{:?} is missing", path),
+        Err(e) => return Err(PersistenceError::file_system(path, e)),
+    };
+
+    let backup = backup_path(path);
+    let json = match fs::read_to_string(&backup) {
+        Ok(json) => json,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(if path.exists() {
+                PersistenceError::Corrupt { path: path.to_path_buf(), message: reason }
+            } else {
+                PersistenceError::NotFound(path.to_path_buf())
+            });
+        }
+        Err(e) => return Err(PersistenceError::file_system(&backup, e)),
+    };
+    let value = serde_json::from_str(&json).map_err(|e| PersistenceError::Corrupt {
+        path: path.to_path_buf(),
+        message: format!("{}; backup is corrupt too: {}", reason, e),
+    })?;
+
+    log::warn!("{}, recovered from backup {:?}", reason, backup);
+    Ok(Loaded {
+        value,
+        recovery: Some(RecoveryWarning { path: path.to_path_buf(), reason }),
+    })
+}
+
+/// Tells the frontend that a file was restored from its backup.
+pub fn report_recovery(app_handle: &tauri::AppHandle, warning: &RecoveryWarning) {
+    if let Err(e) = app_handle.emit("storage_recovered", warning.clone()) {
+        log::error!("Failed to emit storage_recovered event: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nyx-persistence-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_write_keeps_previous_version_as_backup() {
+        let dir = test_dir("backup");
+        let path = dir.join("data.json");
+        write_json(&path, &vec![1]).unwrap();
+        write_json(&path, &vec![1, 2]).unwrap();
+
+        assert!(!temp_path(&path).exists());
+        let loaded: Loaded<Vec<u32>> = read_json(&path).unwrap();
+        assert_eq!(loaded.value, vec![1, 2]);
+        assert!(loaded.recovery.is_none());
+        assert_eq!(fs::read_to_string(backup_path(&path)).unwrap().replace(char::is_whitespace, ""), "[1]");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_file_falls_back_to_backup() {
+        let dir = test_dir("corrupt");
+        let path = dir.join("data.json");
+        write_json(&path, &vec![1]).unwrap();
+        write_json(&path, &vec![1, 2]).unwrap();
+        // Simulate a write that was cut off by a crash.
+        fs::write(&path, "[1, ").unwrap();
+
+        let loaded: Loaded<Vec<u32>> = read_json(&path).unwrap();
+        assert_eq!(loaded.value, vec![1]);
+        assert!(loaded.recovery.unwrap().reason.contains("corrupt"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_missing_and_unrecoverable_files() {
+        let dir = test_dir("missing");
+        let path = dir.join("data.json");
+        assert!(matches!(read_json::<Vec<u32>>(&path), Err(PersistenceError::NotFound(_))));
+
+        fs::write(&path, "{").unwrap();
+        assert!(matches!(read_json::<Vec<u32>>(&path), Err(PersistenceError::Corrupt { .. })));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,648 @@
+// Redaction module
+// Removes secrets from what Perception hands to Cognition: windows of configured
+// applications (password managers, banking) are blurred or dropped, and emails, card
+// numbers, API keys and user patterns are masked in screen text. Every pass produces an
+// audit record of what was redacted, without the redacted text itself.
+
+use crate::modules::perception::{OcrWord, Rect, UiMap};
+use crate::modules::persistence::{self, PersistenceError};
+use crate::modules::window::{WindowContext, WindowInfo, WindowManager};
+use chrono::{Local, NaiveDateTime};
+use image::{imageops, Rgba, RgbaImage};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{BufRead, BufReader, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+use tauri::Manager;
+use thiserror::Error;
+
+/// Blurred windows are scaled down by this factor and back up.
+const BLUR_FACTOR: u32 = 16;
+
+const EMAIL_PATTERN: &str = r"(?i)\b[a-z0-9._%+-]+@[a-z0-9.-]+\.[a-z]{2,}\b";
+/// 13 to 19 digits, optionally grouped with spaces or dashes; checked with Luhn.
+const CARD_NUMBER_PATTERN: &str = r"\b(?:\d[ -]?){12,18}\d\b";
+/// Keys with well-known prefixes, and values assigned to key-like names.
+const API_KEY_PATTERN: &str = concat!(
+    r"\b(?:sk-[A-Za-z0-9_-]{20,}|AKIA[0-9A-Z]{16}|AIza[0-9A-Za-z_-]{35}|gh[pousr]_[A-Za-z0-9]{36,}",
+    r"|xox[abprs]-[A-Za-z0-9-]{10,}|glpat-[A-Za-z0-9_-]{20,})",
+    r"|(?i)\b(?:api[_-]?key|secret|token)\s*[:=]\s*\S{8,}"
+);
+
+#[derive(Error, Debug)]
+pub enum RedactionError {
+    #[error("Invalid redaction pattern '{0}': {1}")]
+    Pattern(String, String),
+    #[error("Redaction settings error: {0}")]
+    Settings(String),
+}
+
+/// What to redact besides the built-in emails, card numbers and API keys.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct RedactionSettings {
+    /// Windows whose class or title contains one of these, ignoring case, are blurred.
+    pub window_classes: Vec<String>,
+    /// Regular expressions for further text to mask, e.g. customer numbers.
+    pub patterns: Vec<String>,
+}
+
+impl Default for RedactionSettings {
+    fn default() -> Self {
+        Self {
+            window_classes: ["keepassxc", "1password", "bitwarden", "enpass", "seahorse", "banking"]
+                .map(String::from)
+                .to_vec(),
+            patterns: Vec::new(),
+        }
+    }
+}
+
+impl RedactionSettings {
+    pub fn validate(&self) -> Result<(), RedactionError> {
+        self.patterns.iter().try_for_each(|pattern| compile(pattern).map(|_| ()))
+    }
+
+    /// Loads the saved settings, or the defaults if there are none or they are unreadable.
+    pub fn load(app_handle: &tauri::AppHandle) -> Self {
+        let path = match config_path(app_handle, "redaction.json") {
+            Ok(path) => path,
+            Err(e) => {
+                log::error!("Failed to load redaction settings: {}", e);
+                return Self::default();
+            }
+        };
+        match persistence::read_json::<Self>(&path) {
+            Ok(loaded) => {
+                if let Some(warning) = &loaded.recovery {
+                    persistence::report_recovery(app_handle, warning);
+                }
+                loaded.value
+            }
+            Err(PersistenceError::NotFound(_)) => Self::default(),
+            Err(e) => {
+                log::error!("Failed to load redaction settings: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, app_handle: &tauri::AppHandle) -> Result<(), RedactionError> {
+        self.validate()?;
+        persistence::write_json(&config_path(app_handle, "redaction.json")?, self)
+            .map_err(|e| RedactionError::Settings(e.to_string()))
+    }
+}
+
+/// Why something was redacted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RedactionKind {
+    Window { class: String },
+    Email,
+    CardNumber,
+    ApiKey,
+    Pattern { pattern: String },
+}
+
+impl RedactionKind {
+    /// What the redacted text is replaced with, so the model still knows something was there.
+    fn placeholder(&self) -> &'static str {
+        match self {
+            RedactionKind::Window { .. } => "[redacted window]",
+            RedactionKind::Email => "[redacted email]",
+            RedactionKind::CardNumber => "[redacted card number]",
+            RedactionKind::ApiKey => "[redacted API key]",
+            RedactionKind::Pattern { .. } => "[redacted]",
+        }
+    }
+}
+
+/// One redacted item.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Redaction {
+    #[serde(flatten)]
+    pub kind: RedactionKind,
+    /// Where it was on screen, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rect: Option<Rect>,
+    /// Length of the redacted text in characters; 0 for images.
+    pub chars: usize,
+}
+
+/// What was redacted from one request to Cognition.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RedactionAudit {
+    pub at: NaiveDateTime,
+    /// The request, e.g. "plan" or "recovery".
+    pub purpose: String,
+    pub redactions: Vec<Redaction>,
+}
+
+impl RedactionAudit {
+    pub fn new(purpose: &str) -> Self {
+        Self { at: Local::now().naive_local(), purpose: purpose.to_string(), redactions: Vec::new() }
+    }
+
+    /// Records a redaction. An item redacted from both the text and the screenshot sent
+    /// with one request is recorded once.
+    fn add(&mut self, kind: RedactionKind, rect: Option<Rect>, chars: usize) {
+        let same = |redaction: &&mut Redaction| rect.is_some() && redaction.rect == rect && redaction.kind == kind;
+        match self.redactions.iter_mut().find(same) {
+            Some(redaction) => redaction.chars = redaction.chars.max(chars),
+            None => self.redactions.push(Redaction { kind, rect, chars }),
+        }
+    }
+}
+
+/// A match of one of the detectors in some text.
+struct Found {
+    range: Range<usize>,
+    kind: RedactionKind,
+}
+
+pub struct Redactor {
+    detectors: Vec<(RedactionKind, Regex)>,
+    window_classes: Vec<String>,
+    /// Visible windows matching `window_classes`.
+    windows: Vec<WindowInfo>,
+    audit_log: Option<PathBuf>,
+}
+
+impl Redactor {
+    pub fn new(settings: &RedactionSettings) -> Result<Self, RedactionError> {
+        let mut detectors = vec![
+            (RedactionKind::Email, compile(EMAIL_PATTERN)?),
+            (RedactionKind::CardNumber, compile(CARD_NUMBER_PATTERN)?),
+            (RedactionKind::ApiKey, compile(API_KEY_PATTERN)?),
+        ];
+        for pattern in &settings.patterns {
+            detectors.push((RedactionKind::Pattern { pattern: pattern.clone() }, compile(pattern)?));
+        }
+        Ok(Self { detectors, window_classes: settings.window_classes.clone(), windows: Vec::new(), audit_log: None })
+    }
+
+    /// A redactor with the saved settings and the windows currently on the desktop, which
+    /// appends its audit records to `nyx-agent/redaction_audit.jsonl`.
+    pub fn load(app_handle: &tauri::AppHandle) -> Self {
+        let settings = RedactionSettings::load(app_handle);
+        let mut redactor = Self::new(&settings).unwrap_or_else(|e| {
+            log::error!("Ignoring redaction patterns: {}", e);
+            Self::new(&RedactionSettings { patterns: Vec::new(), ..settings })
+                .expect("built-in redaction patterns compile")
+        });
+        redactor.refresh_windows();
+        match audit_log_path(app_handle) {
+            Ok(path) => redactor.with_audit_log(path),
+            Err(e) => {
+                log::error!("Redactions will not be audited: {}", e);
+                redactor
+            }
+        }
+    }
+
+    /// Sets the windows to redact from among `windows`.
+    pub fn with_windows(mut self, windows: Vec<WindowInfo>) -> Self {
+        self.set_windows(windows);
+        self
+    }
+
+    pub fn with_audit_log(mut self, path: PathBuf) -> Self {
+        self.audit_log = Some(path);
+        self
+    }
+
+    fn set_windows(&mut self, windows: Vec<WindowInfo>) {
+        self.windows = windows
+            .into_iter()
+            .filter(|window| !window.minimized && self.window_classes.iter().any(|class| window.matches(class)))
+            .collect();
+    }
+
+    /// Looks up the windows on the desktop again, as they may have moved or opened.
+    pub fn refresh_windows(&mut self) {
+        match WindowManager::connect().and_then(|windows| windows.list_windows()) {
+            Ok(windows) => self.set_windows(windows),
+            Err(e) => log::warn!("Cannot list windows, sensitive windows will not be redacted: {}", e),
+        }
+    }
+
+    /// The sensitive window at a point of the screen.
+    fn window_at(&self, (x, y): (f64, f64)) -> Option<&WindowInfo> {
+        self.windows.iter().find(|window| window.rect.contains(x, y))
+    }
+
+    fn find(&self, text: &str) -> Vec<Found> {
+        let mut found: Vec<Found> = self
+            .detectors
+            .iter()
+            .flat_map(|(kind, regex)| {
+                regex
+                    .find_iter(text)
+                    .filter(move |m| *kind != RedactionKind::CardNumber || luhn_valid(m.as_str()))
+                    .map(move |m| Found { range: m.range(), kind: kind.clone() })
+            })
+            .collect();
+        // Where matches overlap, the earliest and then longest wins.
+        found.sort_by_key(|found| (found.range.start, std::cmp::Reverse(found.range.end)));
+        let mut end = 0;
+        found.retain(|found| {
+            let keep = found.range.start >= end;
+            if keep {
+                end = found.range.end;
+            }
+            keep
+        });
+        found
+    }
+
+    /// Replaces every secret in `text` with a placeholder.
+    pub fn redact_text(&self, text: &str, audit: &mut RedactionAudit) -> String {
+        let mut redacted = String::with_capacity(text.len());
+        let mut copied = 0;
+        for found in self.find(text) {
+            redacted.push_str(&text[copied..found.range.start]);
+            redacted.push_str(found.kind.placeholder());
+            audit.add(found.kind, None, text[found.range.clone()].chars().count());
+            copied = found.range.end;
+        }
+        redacted.push_str(&text[copied..]);
+        redacted
+    }
+
+    /// The words' secrets, as the indexes of the words each covers. Matches may span
+    /// several words of a line, e.g. a card number read in groups of four digits.
+    fn find_in_words(&self, words: &[OcrWord]) -> Vec<(RedactionKind, Vec<usize>)> {
+        let mut lines: Vec<((u32, u32, u32), Vec<usize>)> = Vec::new();
+        for (index, word) in words.iter().enumerate() {
+            match lines.iter_mut().find(|(line, _)| *line == word.line) {
+                Some((_, indexes)) => indexes.push(index),
+                None => lines.push((word.line, vec![index])),
+            }
+        }
+
+        let mut found = Vec::new();
+        for (_, indexes) in lines {
+            let mut text = String::new();
+            let mut spans = Vec::new();
+            for &index in &indexes {
+                if !text.is_empty() {
+                    text.push(' ');
+                }
+                spans.push((index, text.len()..text.len() + words[index].text.len()));
+                text.push_str(&words[index].text);
+            }
+            for secret in self.find(&text) {
+                let covered = spans
+                    .iter()
+                    .filter(|(_, span)| span.start < secret.range.end && secret.range.start < span.end)
+                    .map(|(index, _)| *index)
+                    .collect();
+                found.push((secret.kind, covered));
+            }
+        }
+        found
+    }
+
+    /// The words without those inside sensitive windows, and with secrets replaced by
+    /// placeholders.
+    pub fn redact_words(&self, words: &[OcrWord], audit: &mut RedactionAudit) -> Vec<OcrWord> {
+        let mut hidden = vec![false; words.len()];
+        for window in &self.windows {
+            let inside: Vec<usize> = (0..words.len())
+                .filter(|&index| self.window_at(words[index].bbox.center()).is_some_and(|at| at.id == window.id))
+                .collect();
+            if !inside.is_empty() {
+                let chars = inside.iter().map(|&index| words[index].text.chars().count()).sum();
+                audit.add(RedactionKind::Window { class: window.class.clone() }, Some(window.rect), chars);
+                inside.into_iter().for_each(|index| hidden[index] = true);
+            }
+        }
+
+        let mut redacted = words.to_vec();
+        for (kind, covered) in self.find_in_words(words) {
+            let Some((&first, rest)) = covered.split_first() else { continue };
+            if covered.iter().any(|&index| hidden[index]) {
+                continue;
+            }
+            let rect = bounding_rect(covered.iter().map(|&index| words[index].bbox));
+            let chars = covered.iter().map(|&index| words[index].text.chars().count()).sum();
+            redacted[first].text = kind.placeholder().to_string();
+            redacted[first].bbox = rect;
+            rest.iter().for_each(|&index| hidden[index] = true);
+            audit.add(kind, Some(rect), chars);
+        }
+        redacted.into_iter().zip(hidden).filter(|(_, hidden)| !hidden).map(|(word, _)| word).collect()
+    }
+
+    /// The map with the text of elements inside sensitive windows, and secrets in any
+    /// other text, replaced by placeholders. Ids and positions stay, so a plan for the
+    /// redacted map works on the real one.
+    pub fn redact_ui_map(&self, map: &UiMap, audit: &mut RedactionAudit) -> UiMap {
+        let mut redacted = map.clone();
+        for element in &mut redacted.elements {
+            if element.text.is_empty() {
+                continue;
+            }
+            element.text = match self.window_at(element.rect.center()) {
+                Some(window) => {
+                    let kind = RedactionKind::Window { class: window.class.clone() };
+                    audit.add(kind.clone(), Some(element.rect), element.text.chars().count());
+                    kind.placeholder().to_string()
+                }
+                None => self.redact_text(&element.text, audit),
+            };
+        }
+        redacted
+    }
+
+    /// The window with its title redacted, entirely if it is a sensitive window.
+    pub fn redact_window(&self, window: &WindowContext, audit: &mut RedactionAudit) -> WindowContext {
+        let sensitive = self
+            .window_classes
+            .iter()
+            .map(|class| class.to_lowercase())
+            .any(|class| window.title.to_lowercase().contains(&class) || window.class.to_lowercase().contains(&class));
+        let title = if sensitive {
+            let kind = RedactionKind::Window { class: window.class.clone() };
+            audit.add(kind.clone(), None, window.title.chars().count());
+            kind.placeholder().to_string()
+        } else {
+            self.redact_text(&window.title, audit)
+        };
+        WindowContext { title, ..window.clone() }
+    }
+
+    /// A copy of a whole-screen capture with sensitive windows blurred and the boxes of
+    /// secret `words`, as recognized on it, blacked out.
+    pub fn redact_image(&self, image: &RgbaImage, words: &[OcrWord], audit: &mut RedactionAudit) -> RgbaImage {
+        let mut redacted = image.clone();
+        for window in &self.windows {
+            let Some(rect) = window.rect.clamp_to(image.width(), image.height()) else { continue };
+            let area = imageops::crop_imm(image, rect.x as u32, rect.y as u32, rect.width, rect.height).to_image();
+            let small = imageops::resize(
+                &area,
+                (rect.width / BLUR_FACTOR).max(1),
+                (rect.height / BLUR_FACTOR).max(1),
+                imageops::FilterType::Triangle,
+            );
+            let blurred = imageops::resize(&small, rect.width, rect.height, imageops::FilterType::Triangle);
+            imageops::replace(&mut redacted, &blurred, rect.x as i64, rect.y as i64);
+            // Recorded with the window's own rect, as `redact_words` does.
+            audit.add(RedactionKind::Window { class: window.class.clone() }, Some(window.rect), 0);
+        }
+        for (kind, covered) in self.find_in_words(words) {
+            let rect = bounding_rect(covered.iter().map(|&index| words[index].bbox));
+            let Some(visible) = rect.clamp_to(image.width(), image.height()) else { continue };
+            for y in visible.y as u32..visible.y as u32 + visible.height {
+                for x in visible.x as u32..visible.x as u32 + visible.width {
+                    redacted.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+                }
+            }
+            let chars = covered.iter().map(|&index| words[index].text.chars().count()).sum();
+            // Recorded with the words' own box, as `redact_words` does.
+            audit.add(kind, Some(rect), chars);
+        }
+        redacted
+    }
+
+    /// Appends `audit` to the audit log, if there is one.
+    pub fn record(&self, audit: &RedactionAudit) {
+        if !audit.redactions.is_empty() {
+            log::info!("Redacted {} item(s) before {}", audit.redactions.len(), audit.purpose);
+        }
+        let Some(path) = &self.audit_log else { return };
+        if let Err(e) = append_audit(path, audit) {
+            log::error!("Failed to write the redaction audit log: {}", e);
+        }
+    }
+}
+
+fn compile(pattern: &str) -> Result<Regex, RedactionError> {
+    Regex::new(pattern).map_err(|e| RedactionError::Pattern(pattern.to_string(), e.to_string()))
+}
+
+/// Whether the digits in `number` pass the Luhn checksum used by payment cards.
+fn luhn_valid(number: &str) -> bool {
+    let digits: Vec<u32> = number.chars().filter_map(|c| c.to_digit(10)).collect();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &digit)| match (i % 2 == 1, digit * 2) {
+            (true, doubled) if doubled > 9 => doubled - 9,
+            (true, doubled) => doubled,
+            (false, _) => digit,
+        })
+        .sum();
+    (13..=19).contains(&digits.len()) && sum % 10 == 0
+}
+
+fn bounding_rect(rects: impl Iterator<Item = Rect>) -> Rect {
+    let (mut left, mut top, mut right, mut bottom) = (i32::MAX, i32::MAX, i32::MIN, i32::MIN);
+    for rect in rects {
+        left = left.min(rect.x);
+        top = top.min(rect.y);
+        right = right.max(rect.x + rect.width as i32);
+        bottom = bottom.max(rect.y + rect.height as i32);
+    }
+    if right < left {
+        return Rect { x: 0, y: 0, width: 0, height: 0 };
+    }
+    Rect { x: left, y: top, width: (right - left) as u32, height: (bottom - top) as u32 }
+}
+
+fn config_path(app_handle: &tauri::AppHandle, name: &str) -> Result<PathBuf, RedactionError> {
+    let config_dir = app_handle
+        .path()
+        .app_config_dir()
+        .map_err(|e| RedactionError::Settings(format!("No config directory: {}", e)))?;
+    Ok(config_dir.join("nyx-agent").join(name))
+}
+
+/// The audit log, one JSON record per line in `nyx-agent/redaction_audit.jsonl`.
+pub fn audit_log_path(app_handle: &tauri::AppHandle) -> Result<PathBuf, RedactionError> {
+    config_path(app_handle, "redaction_audit.jsonl")
+}
+
+fn append_audit(path: &Path, audit: &RedactionAudit) -> Result<(), RedactionError> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| RedactionError::Settings(e.to_string()))?;
+    }
+    let line = serde_json::to_string(audit).map_err(|e| RedactionError::Settings(e.to_string()))?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| RedactionError::Settings(e.to_string()))?;
+    writeln!(file, "{}", line).map_err(|e| RedactionError::Settings(e.to_string()))
+}
+
+/// The audit records in the log, oldest first. Unreadable lines are skipped.
+pub fn read_audit_log(path: &Path) -> Result<Vec<RedactionAudit>, RedactionError> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(RedactionError::Settings(e.to_string())),
+    };
+    Ok(BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(&line) {
+            Ok(audit) => Some(audit),
+            Err(e) => {
+                log::warn!("Skipping unreadable redaction audit record: {}", e);
+                None
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::perception::{UiElement, UiRole};
+
+    fn word(text: &str, x: i32, y: i32) -> OcrWord {
+        let width = text.len() as u32 * 8;
+        OcrWord { text: text.to_string(), bbox: Rect { x, y, width, height: 14 }, confidence: 0.95, line: (1, 1, y as u32) }
+    }
+
+    fn keepass() -> WindowInfo {
+        WindowInfo {
+            id: 7,
+            title: "Passwords.kdbx - KeePassXC".to_string(),
+            class: "KeePassXC".to_string(),
+            pid: None,
+            rect: Rect { x: 200, y: 0, width: 200, height: 100 },
+            desktop: Some(0),
+            active: false,
+            minimized: false,
+        }
+    }
+
+    #[test]
+    fn test_masks_text_patterns() {
+        let settings = RedactionSettings { patterns: vec![r"CUST-\d{6}".to_string()], ..Default::default() };
+        let redactor = Redactor::new(&settings).unwrap();
+        let mut audit = RedactionAudit::new("test");
+
+        let text = "Mail jane.doe@example.com, card 4111 1111 1111 1111, order 1234 5678 9012 3456, \
+                    key sk-abcdefghijklmnopqrstuvwx, customer CUST-004211.";
+        assert_eq!(
+            redactor.redact_text(text, &mut audit),
+            "Mail [redacted email], card [redacted card number], order 1234 5678 9012 3456, \
+             key [redacted API key], customer [redacted]."
+        );
+        let kinds: Vec<&RedactionKind> = audit.redactions.iter().map(|redaction| &redaction.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                &RedactionKind::Email,
+                &RedactionKind::CardNumber,
+                &RedactionKind::ApiKey,
+                &RedactionKind::Pattern { pattern: r"CUST-\d{6}".to_string() }
+            ]
+        );
+        assert_eq!(audit.redactions[1].chars, 19);
+
+        // The audit says what was redacted, never the secret itself.
+        let path = std::env::temp_dir().join(format!("nyx-redaction-audit-{}.jsonl", std::process::id()));
+        redactor.with_audit_log(path.clone()).record(&audit);
+        let logged = std::fs::read_to_string(&path).unwrap();
+        assert!(!logged.contains("jane.doe") && !logged.contains("4111"), "{}", logged);
+        assert_eq!(read_audit_log(&path).unwrap(), [audit]);
+        std::fs::remove_file(&path).unwrap();
+
+        let invalid = RedactionSettings { patterns: vec!["(unclosed".to_string()], ..Default::default() };
+        assert!(matches!(invalid.validate(), Err(RedactionError::Pattern(..))));
+    }
+
+    #[test]
+    fn test_redacts_sensitive_windows() {
+        let redactor = Redactor::new(&RedactionSettings::default()).unwrap().with_windows(vec![keepass()]);
+        let mut audit = RedactionAudit::new("test");
+
+        let words = vec![
+            word("Card", 10, 40),
+            word("4111", 50, 40),
+            word("1111", 90, 40),
+            word("1111", 130, 40),
+            word("1111", 170, 40),
+            word("hunter2", 220, 40),
+        ];
+        let redacted = redactor.redact_words(&words, &mut audit);
+        let texts: Vec<&str> = redacted.iter().map(|word| word.text.as_str()).collect();
+        assert_eq!(texts, ["Card", "[redacted card number]"]);
+        assert_eq!(redacted[1].bbox, Rect { x: 50, y: 40, width: 152, height: 14 });
+
+        let map = UiMap {
+            width: 400,
+            height: 100,
+            elements: vec![
+                UiElement {
+                    id: 1,
+                    role: UiRole::Label,
+                    text: "Send to jane@example.com".to_string(),
+                    rect: Rect { x: 10, y: 10, width: 150, height: 20 },
+                    accessible: None,
+                },
+                UiElement {
+                    id: 2,
+                    role: UiRole::TextField,
+                    text: "hunter2".to_string(),
+                    rect: Rect { x: 220, y: 40, width: 100, height: 20 },
+                    accessible: None,
+                },
+            ],
+        };
+        let redacted = redactor.redact_ui_map(&map, &mut audit);
+        assert_eq!(redacted.elements[0].text, "Send to [redacted email]");
+        assert_eq!(redacted.elements[1].text, "[redacted window]");
+        assert_eq!(redacted.elements[1].rect, map.elements[1].rect);
+
+        let window = WindowContext { title: keepass().title, class: keepass().class, pid: Some(42) };
+        assert_eq!(redactor.redact_window(&window, &mut audit).title, "[redacted window]");
+
+        // Fine stripes inside the window are blurred into a flat grey; the card number
+        // outside it is blacked out and the rest of the screen is untouched.
+        let image = RgbaImage::from_fn(400, 100, |x, _| {
+            if x % 2 == 0 {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        });
+        let blurred = redactor.redact_image(&image, &words, &mut audit);
+        let pixel = blurred.get_pixel(300, 50).0;
+        assert!((100..=155).contains(&pixel[0]), "{:?}", pixel);
+        assert_eq!(blurred.get_pixel(60, 45).0, [0, 0, 0, 255]);
+        assert_eq!(blurred.get_pixel(62, 45).0, [0, 0, 0, 255]);
+        assert_eq!(blurred.get_pixel(10, 10), image.get_pixel(10, 10));
+        assert_eq!(blurred.get_pixel(11, 10), image.get_pixel(11, 10));
+
+        // The window and card number were redacted from both the words and the image, and
+        // are audited once each.
+        let count = |matches: &dyn Fn(&Redaction) -> bool| audit.redactions.iter().filter(|r| matches(r)).count();
+        assert_eq!(count(&|r| r.rect == Some(keepass().rect)), 1);
+        assert_eq!(count(&|r| r.kind == RedactionKind::CardNumber), 1);
+    }
+
+    #[test]
+    fn test_secret_at_screen_edge_is_audited_once() {
+        let redactor = Redactor::new(&RedactionSettings::default()).unwrap();
+        let mut audit = RedactionAudit::new("test");
+        // The address runs past the right edge of the capture.
+        let words = vec![word("Mail", 300, 40), word("jane@example.com", 340, 40)];
+        let image = RgbaImage::from_pixel(400, 100, Rgba([255, 255, 255, 255]));
+
+        redactor.redact_words(&words, &mut audit);
+        let redacted = redactor.redact_image(&image, &words, &mut audit);
+        assert_eq!(redacted.get_pixel(399, 45).0, [0, 0, 0, 255]);
+        assert_eq!(audit.redactions.len(), 1, "{:?}", audit.redactions);
+    }
+}
@@ -0,0 +1,908 @@
+// Scheduler module for time-based and triggered macro/task execution
+// Jobs are owned by the Orchestrator, which polls the scheduler and runs whatever is due.
+
+use crate::modules::persistence::{self, PersistenceError, RecoveryWarning};
+use crate::orchestrator::AppState;
+use chrono::{Datelike, Duration as ChronoDuration, Local, NaiveDateTime, Timelike};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
+
+/// Maximum number of run records kept in the history file.
+const MAX_HISTORY: usize = 500;
+
+#[derive(Error, Debug)]
+pub enum SchedulerError {
+    #[error("Invalid schedule '{0}': {1}")]
+    InvalidSchedule(String, String),
+    #[error("No scheduled job with id {0}")]
+    JobNotFound(u64),
+    #[error("File system error: {0}")]
+    FileSystem(String),
+    #[error("Serialization error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Source of the current local time. Swapped for a `ManualClock` in tests.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> NaiveDateTime;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+}
+
+/// A clock that only moves when told to.
+pub struct ManualClock {
+    now: Mutex<NaiveDateTime>,
+}
+
+impl ManualClock {
+    pub fn new(start: NaiveDateTime) -> Self {
+        Self { now: Mutex::new(start) }
+    }
+
+    pub fn set(&self, time: NaiveDateTime) {
+        *self.now.lock().unwrap() = time;
+    }
+
+    pub fn advance(&self, by: ChronoDuration) {
+        let mut now = self.now.lock().unwrap();
+        *now += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> NaiveDateTime {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobTrigger {
+    /// A cron expression (`0 9 * * 1-5`) or a phrase (`every weekday at 9:00`).
+    Schedule { spec: String },
+    /// A new file matching `pattern` shows up in `directory`.
+    FileAppeared { directory: PathBuf, pattern: String },
+    /// The agent has been IDLE for at least `idle_secs` seconds.
+    AgentIdle { idle_secs: u64 },
+    /// The application has just started.
+    AppStart,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobAction {
+    Macro { name: String },
+    Task { description: String },
+}
+
+/// What to do with a job that fires while the agent is not IDLE.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BusyPolicy {
+    #[default]
+    Skip,
+    Queue,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScheduledJob {
+    pub id: u64,
+    pub name: String,
+    pub trigger: JobTrigger,
+    pub action: JobAction,
+    #[serde(default)]
+    pub busy_policy: BusyPolicy,
+    pub enabled: bool,
+    pub next_run: Option<NaiveDateTime>,
+    pub last_run: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum RunOutcome {
+    Success,
+    Failed { message: String },
+    Skipped { reason: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunRecord {
+    pub job_id: u64,
+    pub job_name: String,
+    pub started_at: NaiveDateTime,
+    pub finished_at: NaiveDateTime,
+    pub outcome: RunOutcome,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct JobsFile {
+    next_id: u64,
+    jobs: Vec<ScheduledJob>,
+}
+
+// --- Cron Schedules ---
+
+/// A parsed five-field cron expression: minute, hour, day of month, month, day of week.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u32,
+    days_of_month: u32,
+    months: u16,
+    days_of_week: u8,
+    dom_restricted: bool,
+    dow_restricted: bool,
+}
+
+impl CronSchedule {
+    /// Parses either a cron expression or an "every ..." phrase.
+    pub fn parse(spec: &str) -> Result<Self, SchedulerError> {
+        let trimmed = spec.trim();
+        let expression = if trimmed.to_lowercase().starts_with("every ") {
+            phrase_to_cron(trimmed)?
+        } else {
+            trimmed.to_string()
+        };
+        Self::parse_cron(&expression)
+            .map_err(|reason| SchedulerError::InvalidSchedule(spec.to_string(), reason))
+    }
+
+    fn parse_cron(expression: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("expected 5 fields, found {}", fields.len()));
+        }
+
+        let minutes = parse_field(fields[0], 0, 59, &[])?;
+        let hours = parse_field(fields[1], 0, 23, &[])?;
+        let days_of_month = parse_field(fields[2], 1, 31, &[])?;
+        let months = parse_field(fields[3], 1, 12, &MONTH_NAMES)?;
+        let mut days_of_week = parse_field(fields[4], 0, 7, &WEEKDAY_NAMES)?;
+        // Both 0 and 7 mean Sunday.
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week |= 1;
+        }
+
+        Ok(Self {
+            minutes,
+            hours: hours as u32,
+            days_of_month: days_of_month as u32,
+            months: months as u16,
+            days_of_week: (days_of_week & 0x7f) as u8,
+            dom_restricted: fields[2] != "*",
+            dow_restricted: fields[4] != "*",
+        })
+    }
+
+    fn day_matches(&self, time: &NaiveDateTime) -> bool {
+        if self.months & (1 << time.month()) == 0 {
+            return false;
+        }
+        let dom = self.days_of_month & (1 << time.day()) != 0;
+        let dow = self.days_of_week & (1 << time.weekday().num_days_from_sunday()) != 0;
+        // Standard cron semantics: when both day fields are restricted, either may match.
+        match (self.dom_restricted, self.dow_restricted) {
+            (true, true) => dom || dow,
+            (true, false) => dom,
+            (false, true) => dow,
+            (false, false) => true,
+        }
+    }
+
+    pub fn matches(&self, time: &NaiveDateTime) -> bool {
+        self.day_matches(time)
+            && self.hours & (1 << time.hour()) != 0
+            && self.minutes & (1 << time.minute()) != 0
+    }
+
+    /// Returns the first matching minute strictly after `after`, searching up to ~4 years ahead.
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut candidate = after.with_second(0)?.with_nanosecond(0)? + ChronoDuration::minutes(1);
+        let limit = after + ChronoDuration::days(366 * 4);
+
+        while candidate <= limit {
+            if !self.day_matches(&candidate) {
+                candidate = candidate.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if self.hours & (1 << candidate.hour()) == 0 {
+                candidate = candidate.with_minute(0)? + ChronoDuration::hours(1);
+                continue;
+            }
+            if self.minutes & (1 << candidate.minute()) == 0 {
+                candidate += ChronoDuration::minutes(1);
+                continue;
+            }
+            return Some(candidate);
+        }
+        None
+    }
+}
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+/// Full day names accepted in phrases, in the order of `WEEKDAY_NAMES`.
+const WEEKDAY_FULL_NAMES: [&str; 7] = ["sunday", "monday", "tuesday", "wednesday", "thursday", "friday", "saturday"];
+
+/// Parses one cron field into a bitmask. `names[i]` is an alias for the value `min + i`.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+    let parse_value = |s: &str| -> Result<u32, String> {
+        let lower = s.to_lowercase();
+        if let Some(pos) = names.iter().position(|n| *n == lower) {
+            return Ok(pos as u32 + min);
+        }
+        let value: u32 = s.parse().map_err(|_| format!("'{}' is not a number", s))?;
+        if value < min || value > max {
+            return Err(format!("{} is out of range {}-{}", value, min, max));
+        }
+        Ok(value)
+    };
+
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step.parse().map_err(|_| format!("invalid step '{}'", step))?;
+                if step == 0 {
+                    return Err("step cannot be zero".to_string());
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            (parse_value(a)?, parse_value(b)?)
+        } else {
+            let value = parse_value(range)?;
+            // "5/15" means "from 5 to the end, every 15".
+            if step > 1 { (value, max) } else { (value, value) }
+        };
+
+        if start > end {
+            return Err(format!("range {}-{} is backwards", start, end));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+/// Translates a small set of English phrases into cron expressions, e.g.
+/// "every weekday at 9:00", "every monday at 18:30", "every 15 minutes", "every day at 7am".
+fn phrase_to_cron(phrase: &str) -> Result<String, SchedulerError> {
+    let invalid = |reason: &str| SchedulerError::InvalidSchedule(phrase.to_string(), reason.to_string());
+    let lower = phrase.to_lowercase();
+    let rest = lower.trim_start_matches("every ").trim();
+
+    let (when, at) = match rest.split_once(" at ") {
+        Some((when, at)) => (when.trim(), Some(at.trim())),
+        None => (rest, None),
+    };
+
+    let (hour, minute) = match at {
+        Some(time) => parse_time_of_day(time).ok_or_else(|| invalid("could not understand the time"))?,
+        None => (0, 0),
+    };
+
+    let days = match when {
+        "minute" => return Ok("* * * * *".to_string()),
+        "hour" => return Ok("0 * * * *".to_string()),
+        "day" => "*",
+        "weekday" => "1-5",
+        "weekend" => "0,6",
+        _ => {
+            let mut words = when.split_whitespace();
+            if let (Some(n), Some(unit), None) = (words.next(), words.next(), words.next()) {
+                let n: u32 = n.parse().map_err(|_| invalid("expected a number"))?;
+                if n == 0 {
+                    return Err(invalid("interval cannot be zero"));
+                }
+                // Cron steps restart every hour or day, so longer ones would not repeat evenly.
+                return match unit {
+                    "minutes" | "mins" if n < 60 => Ok(format!("*/{} * * * *", n)),
+                    "hours" if n < 24 => Ok(format!("0 */{} * * *", n)),
+                    "minutes" | "mins" => Err(invalid("use hours for intervals of 60 minutes or more")),
+                    "hours" => Err(invalid("use days for intervals of 24 hours or more")),
+                    _ => Err(invalid("unknown interval unit")),
+                };
+            }
+            // "monday", "mondays", "mon" or "mons".
+            let day = when.strip_suffix('s').unwrap_or(when);
+            (0..WEEKDAY_NAMES.len())
+                .find(|&pos| day == WEEKDAY_NAMES[pos] || day == WEEKDAY_FULL_NAMES[pos])
+                .map(|pos| WEEKDAY_NAMES[pos])
+                .ok_or_else(|| invalid("unknown day"))?
+        }
+    };
+
+    Ok(format!("{} {} * * {}", minute, hour, days))
+}
+
+/// Parses "9:00", "18:30", "7am", "7:15pm".
+fn parse_time_of_day(time: &str) -> Option<(u32, u32)> {
+    let (clock, pm) = if let Some(t) = time.strip_suffix("pm") {
+        (t.trim(), Some(true))
+    } else if let Some(t) = time.strip_suffix("am") {
+        (t.trim(), Some(false))
+    } else {
+        (time, None)
+    };
+
+    let (hour, minute) = match clock.split_once(':') {
+        Some((h, m)) => (h.parse::<u32>().ok()?, m.parse::<u32>().ok()?),
+        None => (clock.parse::<u32>().ok()?, 0),
+    };
+
+    let hour = match pm {
+        Some(true) if hour < 12 => hour + 12,
+        Some(false) if hour == 12 => 0,
+        _ => hour,
+    };
+    (hour < 24 && minute < 60).then_some((hour, minute))
+}
+
+/// Minimal glob matching supporting `*` and `?`, which is all the file trigger needs.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+    let (mut pi, mut ni) = (0, 0);
+    let (mut star, mut mark) = (None, 0);
+
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some(pi);
+            mark = ni;
+            pi += 1;
+        } else if let Some(s) = star {
+            pi = s + 1;
+            mark += 1;
+            ni = mark;
+        } else {
+            return false;
+        }
+    }
+    while pi < p.len() && p[pi] == '*' {
+        pi += 1;
+    }
+    pi == p.len()
+}
+
+fn list_matching_files(directory: &Path, pattern: &str) -> HashSet<PathBuf> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return HashSet::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| glob_matches(pattern, n))
+        })
+        .collect()
+}
+
+// --- Scheduler ---
+
+pub struct Scheduler {
+    clock: Arc<dyn Clock>,
+    storage_dir: Option<PathBuf>,
+    jobs: Vec<ScheduledJob>,
+    history: Vec<RunRecord>,
+    next_id: u64,
+    /// Job ids waiting for the agent to become IDLE.
+    queue: VecDeque<u64>,
+    app_started: bool,
+    idle_since: Option<NaiveDateTime>,
+    /// AgentIdle jobs that already fired during the current idle period.
+    idle_fired: HashSet<u64>,
+    /// Files already seen by each FileAppeared job.
+    seen_files: HashMap<u64, HashSet<PathBuf>>,
+    /// Storage files restored from backup while loading.
+    recovery_warnings: Vec<RecoveryWarning>,
+}
+
+impl Scheduler {
+    /// Creates a scheduler that keeps its jobs in memory only.
+    pub fn in_memory(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            storage_dir: None,
+            jobs: Vec::new(),
+            history: Vec::new(),
+            next_id: 1,
+            queue: VecDeque::new(),
+            app_started: false,
+            idle_since: None,
+            idle_fired: HashSet::new(),
+            seen_files: HashMap::new(),
+            recovery_warnings: Vec::new(),
+        }
+    }
+
+    /// Loads jobs and run history from `storage_dir`, starting empty if nothing was saved yet.
+    pub fn load(storage_dir: PathBuf, clock: Arc<dyn Clock>) -> Result<Self, SchedulerError> {
+        let mut scheduler = Self::in_memory(clock);
+
+        if let Some(file) = scheduler.read_file::<JobsFile>(&storage_dir.join("jobs.json"))? {
+            scheduler.next_id = file.next_id.max(1);
+            scheduler.jobs = file.jobs;
+        }
+        if let Some(history) = scheduler.read_file(&storage_dir.join("history.json"))? {
+            scheduler.history = history;
+        }
+
+        scheduler.storage_dir = Some(storage_dir);
+
+        // Recompute schedules that were missed while the app was closed, and take a
+        // snapshot of watched directories so pre-existing files do not fire.
+        let now = scheduler.clock.now();
+        for job in scheduler.jobs.iter_mut() {
+            match &job.trigger {
+                JobTrigger::Schedule { spec } if job.next_run.map_or(true, |next| next < now) => {
+                    job.next_run = CronSchedule::parse(spec).ok().and_then(|s| s.next_after(now));
+                }
+                JobTrigger::FileAppeared { directory, pattern } => {
+                    scheduler
+                        .seen_files
+                        .insert(job.id, list_matching_files(directory, pattern));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(scheduler)
+    }
+
+    /// Reads a storage file, or `None` if it was never written.
+    fn read_file<T: DeserializeOwned>(&mut self, path: &Path) -> Result<Option<T>, SchedulerError> {
+        match persistence::read_json(path) {
+            Ok(loaded) => {
+                self.recovery_warnings.extend(loaded.recovery);
+                Ok(Some(loaded.value))
+            }
+            Err(PersistenceError::NotFound(_)) => Ok(None),
+            Err(e) => Err(SchedulerError::FileSystem(e.to_string())),
+        }
+    }
+
+    /// Returns the files that had to be restored from backup while loading, once.
+    pub fn take_recovery_warnings(&mut self) -> Vec<RecoveryWarning> {
+        std::mem::take(&mut self.recovery_warnings)
+    }
+
+    pub fn jobs(&self) -> &[ScheduledJob] {
+        &self.jobs
+    }
+
+    /// Returns the run history, newest first, optionally filtered to one job.
+    pub fn history(&self, job_id: Option<u64>) -> Vec<RunRecord> {
+        self.history
+            .iter()
+            .rev()
+            .filter(|record| job_id.map_or(true, |id| record.job_id == id))
+            .cloned()
+            .collect()
+    }
+
+    pub fn add_job(
+        &mut self,
+        name: String,
+        trigger: JobTrigger,
+        action: JobAction,
+        busy_policy: BusyPolicy,
+    ) -> Result<ScheduledJob, SchedulerError> {
+        let now = self.clock.now();
+        let next_run = match &trigger {
+            JobTrigger::Schedule { spec } => CronSchedule::parse(spec)?.next_after(now),
+            _ => None,
+        };
+
+        let job = ScheduledJob {
+            id: self.next_id,
+            name,
+            trigger,
+            action,
+            busy_policy,
+            enabled: true,
+            next_run,
+            last_run: None,
+        };
+        self.next_id += 1;
+
+        if let JobTrigger::FileAppeared { directory, pattern } = &job.trigger {
+            self.seen_files
+                .insert(job.id, list_matching_files(directory, pattern));
+        }
+
+        log::info!("Scheduled job {} '{}' ({:?})", job.id, job.name, job.trigger);
+        self.jobs.push(job.clone());
+        self.save_jobs()?;
+        Ok(job)
+    }
+
+    pub fn remove_job(&mut self, id: u64) -> Result<(), SchedulerError> {
+        let index = self
+            .jobs
+            .iter()
+            .position(|job| job.id == id)
+            .ok_or(SchedulerError::JobNotFound(id))?;
+        self.jobs.remove(index);
+        self.queue.retain(|queued| *queued != id);
+        self.seen_files.remove(&id);
+        self.idle_fired.remove(&id);
+        self.save_jobs()
+    }
+
+    pub fn set_enabled(&mut self, id: u64, enabled: bool) -> Result<(), SchedulerError> {
+        let now = self.clock.now();
+        let job = self
+            .jobs
+            .iter_mut()
+            .find(|job| job.id == id)
+            .ok_or(SchedulerError::JobNotFound(id))?;
+        job.enabled = enabled;
+        if enabled {
+            if let JobTrigger::Schedule { spec } = &job.trigger {
+                job.next_run = CronSchedule::parse(spec)?.next_after(now);
+            }
+        } else {
+            self.queue.retain(|queued| *queued != id);
+        }
+        self.save_jobs()
+    }
+
+    /// Marks the application as started so AppStart jobs fire on the next poll.
+    pub fn notify_app_started(&mut self) {
+        self.app_started = true;
+    }
+
+    /// Checks every trigger against the current time and agent state. Returns the next job
+    /// to run if the agent is IDLE; jobs that fire while busy are skipped or queued
+    /// according to their `BusyPolicy`.
+    pub fn poll(&mut self, state: &AppState) -> Option<ScheduledJob> {
+        let now = self.clock.now();
+        let is_idle = *state == AppState::IDLE;
+
+        if is_idle {
+            if self.idle_since.is_none() {
+                self.idle_since = Some(now);
+            }
+        } else {
+            self.idle_since = None;
+            self.idle_fired.clear();
+        }
+
+        let app_started = std::mem::take(&mut self.app_started);
+        let mut fired = Vec::new();
+        let mut next_runs_changed = false;
+
+        for job in self.jobs.iter_mut().filter(|job| job.enabled) {
+            let is_due = match &job.trigger {
+                JobTrigger::Schedule { spec } => match job.next_run {
+                    Some(next) if next <= now => {
+                        job.next_run = CronSchedule::parse(spec).ok().and_then(|s| s.next_after(now));
+                        next_runs_changed = true;
+                        true
+                    }
+                    _ => false,
+                },
+                JobTrigger::FileAppeared { directory, pattern } => {
+                    let current = list_matching_files(directory, pattern);
+                    let seen = self.seen_files.entry(job.id).or_default();
+                    let has_new = current.iter().any(|path| !seen.contains(path));
+                    *seen = current;
+                    has_new
+                }
+                JobTrigger::AgentIdle { idle_secs } => match self.idle_since {
+                    Some(since)
+                        if now - since >= ChronoDuration::seconds(*idle_secs as i64)
+                            && !self.idle_fired.contains(&job.id) =>
+                    {
+                        self.idle_fired.insert(job.id);
+                        true
+                    }
+                    _ => false,
+                },
+                JobTrigger::AppStart => app_started,
+            };
+
+            if is_due {
+                fired.push((job.id, job.name.clone(), job.busy_policy));
+            }
+        }
+
+        for (id, name, policy) in fired {
+            if is_idle || policy == BusyPolicy::Queue {
+                if !self.queue.contains(&id) {
+                    self.queue.push_back(id);
+                }
+            } else {
+                log::info!("Skipping scheduled job '{}': agent is {:?}", name, state);
+                self.push_history(RunRecord {
+                    job_id: id,
+                    job_name: name,
+                    started_at: now,
+                    finished_at: now,
+                    outcome: RunOutcome::Skipped {
+                        reason: format!("Agent was {:?}", state),
+                    },
+                });
+            }
+        }
+
+        // Polls run every second; only a changed next run is worth a write. The queue is
+        // not persisted and history saves itself.
+        if next_runs_changed {
+            if let Err(e) = self.save_jobs() {
+                log::error!("Failed to persist scheduled jobs: {}", e);
+            }
+        }
+
+        if !is_idle {
+            return None;
+        }
+        while let Some(id) = self.queue.pop_front() {
+            if let Some(job) = self.jobs.iter().find(|job| job.id == id && job.enabled) {
+                return Some(job.clone());
+            }
+        }
+        None
+    }
+
+    /// Records the outcome of a job returned by `poll`.
+    pub fn record_run(&mut self, job: &ScheduledJob, started_at: NaiveDateTime, outcome: RunOutcome) -> RunRecord {
+        let record = RunRecord {
+            job_id: job.id,
+            job_name: job.name.clone(),
+            started_at,
+            finished_at: self.clock.now(),
+            outcome,
+        };
+        if let Some(stored) = self.jobs.iter_mut().find(|j| j.id == job.id) {
+            stored.last_run = Some(started_at);
+        }
+        self.push_history(record.clone());
+        if let Err(e) = self.save_jobs() {
+            log::error!("Failed to persist scheduled jobs: {}", e);
+        }
+        record
+    }
+
+    pub fn now(&self) -> NaiveDateTime {
+        self.clock.now()
+    }
+
+    fn push_history(&mut self, record: RunRecord) {
+        self.history.push(record);
+        if self.history.len() > MAX_HISTORY {
+            let overflow = self.history.len() - MAX_HISTORY;
+            self.history.drain(..overflow);
+        }
+        if let Err(e) = self.save_history() {
+            log::error!("Failed to persist scheduler history: {}", e);
+        }
+    }
+
+    fn save_jobs(&self) -> Result<(), SchedulerError> {
+        let Some(dir) = &self.storage_dir else {
+            return Ok(());
+        };
+        let file = JobsFile {
+            next_id: self.next_id,
+            jobs: self.jobs.clone(),
+        };
+        write_json(dir, "jobs.json", &file)
+    }
+
+    fn save_history(&self) -> Result<(), SchedulerError> {
+        let Some(dir) = &self.storage_dir else {
+            return Ok(());
+        };
+        write_json(dir, "history.json", &self.history)
+    }
+}
+
+fn write_json<T: Serialize>(dir: &Path, file_name: &str, value: &T) -> Result<(), SchedulerError> {
+    fs::create_dir_all(dir)
+        .map_err(|e| SchedulerError::FileSystem(format!("Failed to create scheduler dir: {}", e)))?;
+    persistence::write_json(&dir.join(file_name), value)
+        .map_err(|e| SchedulerError::FileSystem(format!("Failed to write {}: {}", file_name, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, mo, d).unwrap().and_hms_opt(h, mi, 0).unwrap()
+    }
+
+    fn scheduler_at(start: NaiveDateTime) -> (Scheduler, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(start));
+        (Scheduler::in_memory(clock.clone()), clock)
+    }
+
+    fn macro_action(name: &str) -> JobAction {
+        JobAction::Macro { name: name.to_string() }
+    }
+
+    #[test]
+    fn test_cron_next_after() {
+        let schedule = CronSchedule::parse("30 9 * * 1-5").unwrap();
+        // 2025-01-03 is a Friday; the next weekday 9:30 after Friday 10:00 is Monday.
+        assert_eq!(schedule.next_after(at(2025, 1, 3, 10, 0)), Some(at(2025, 1, 6, 9, 30)));
+        assert_eq!(schedule.next_after(at(2025, 1, 6, 9, 0)), Some(at(2025, 1, 6, 9, 30)));
+    }
+
+    #[test]
+    fn test_cron_steps_and_names() {
+        let schedule = CronSchedule::parse("*/15 * * jan sun").unwrap();
+        assert!(schedule.matches(&at(2025, 1, 5, 12, 45)));
+        assert!(!schedule.matches(&at(2025, 1, 5, 12, 50)));
+        assert!(!schedule.matches(&at(2025, 2, 2, 12, 45)));
+        assert!(CronSchedule::parse("61 * * * *").is_err());
+        assert!(CronSchedule::parse("* * *").is_err());
+    }
+
+    #[test]
+    fn test_phrases() {
+        assert_eq!(phrase_to_cron("every weekday at 9:00").unwrap(), "0 9 * * 1-5");
+        assert_eq!(phrase_to_cron("every Monday at 6:30pm").unwrap(), "30 18 * * mon");
+        assert_eq!(phrase_to_cron("every 15 minutes").unwrap(), "*/15 * * * *");
+        assert_eq!(phrase_to_cron("every day at 7am").unwrap(), "0 7 * * *");
+        assert_eq!(phrase_to_cron("every fridays at 17:00").unwrap(), "0 17 * * fri");
+        assert!(phrase_to_cron("every blue moon").is_err());
+        assert!(phrase_to_cron("every month").is_err());
+        assert!(phrase_to_cron("every 90 minutes").is_err());
+        assert!(phrase_to_cron("every 48 hours").is_err());
+        assert_eq!(phrase_to_cron("every 23 hours").unwrap(), "0 */23 * * *");
+    }
+
+    #[test]
+    fn test_schedule_fires_when_due() {
+        let (mut scheduler, clock) = scheduler_at(at(2025, 1, 6, 8, 59));
+        let job = scheduler
+            .add_job(
+                "standup".into(),
+                JobTrigger::Schedule { spec: "every weekday at 9:00".into() },
+                macro_action("open_meet"),
+                BusyPolicy::Skip,
+            )
+            .unwrap();
+        assert_eq!(job.next_run, Some(at(2025, 1, 6, 9, 0)));
+
+        assert!(scheduler.poll(&AppState::IDLE).is_none());
+        clock.advance(ChronoDuration::minutes(1));
+        let due = scheduler.poll(&AppState::IDLE).expect("job should be due");
+        assert_eq!(due.id, job.id);
+        assert_eq!(scheduler.jobs()[0].next_run, Some(at(2025, 1, 7, 9, 0)));
+        assert!(scheduler.poll(&AppState::IDLE).is_none());
+    }
+
+    #[test]
+    fn test_poll_saves_only_changes() {
+        let dir = std::env::temp_dir().join(format!("nyx-scheduler-poll-{}", std::process::id()));
+        let clock = Arc::new(ManualClock::new(at(2025, 1, 6, 8, 59)));
+        let mut scheduler = Scheduler::load(dir.clone(), clock.clone()).unwrap();
+        scheduler
+            .add_job(
+                "standup".into(),
+                JobTrigger::Schedule { spec: "every weekday at 9:00".into() },
+                macro_action("open_meet"),
+                BusyPolicy::Skip,
+            )
+            .unwrap();
+        let jobs_file = dir.join("jobs.json");
+        fs::remove_file(&jobs_file).unwrap();
+
+        assert!(scheduler.poll(&AppState::IDLE).is_none());
+        assert!(!jobs_file.exists());
+
+        clock.advance(ChronoDuration::minutes(1));
+        assert!(scheduler.poll(&AppState::IDLE).is_some());
+        let saved = Scheduler::load(dir.clone(), clock).unwrap();
+        assert_eq!(saved.jobs()[0].next_run, Some(at(2025, 1, 7, 9, 0)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_busy_skip_records_history() {
+        let (mut scheduler, clock) = scheduler_at(at(2025, 1, 6, 8, 59));
+        scheduler
+            .add_job(
+                "skip me".into(),
+                JobTrigger::Schedule { spec: "0 9 * * *".into() },
+                macro_action("m"),
+                BusyPolicy::Skip,
+            )
+            .unwrap();
+
+        clock.advance(ChronoDuration::minutes(1));
+        assert!(scheduler.poll(&AppState::EXECUTING).is_none());
+        assert!(scheduler.poll(&AppState::IDLE).is_none());
+
+        let history = scheduler.history(None);
+        assert_eq!(history.len(), 1);
+        assert!(matches!(history[0].outcome, RunOutcome::Skipped { .. }));
+    }
+
+    #[test]
+    fn test_busy_queue_runs_when_idle() {
+        let (mut scheduler, clock) = scheduler_at(at(2025, 1, 6, 8, 59));
+        let job = scheduler
+            .add_job(
+                "queue me".into(),
+                JobTrigger::Schedule { spec: "0 9 * * *".into() },
+                JobAction::Task { description: "tidy downloads".into() },
+                BusyPolicy::Queue,
+            )
+            .unwrap();
+
+        clock.advance(ChronoDuration::minutes(1));
+        assert!(scheduler.poll(&AppState::RECORDING).is_none());
+        let due = scheduler.poll(&AppState::IDLE).expect("queued job should run");
+        assert_eq!(due.id, job.id);
+
+        scheduler.record_run(&due, clock.now(), RunOutcome::Success);
+        assert_eq!(scheduler.history(Some(job.id))[0].outcome, RunOutcome::Success);
+        assert_eq!(scheduler.jobs()[0].last_run, Some(at(2025, 1, 6, 9, 0)));
+    }
+
+    #[test]
+    fn test_idle_and_app_start_triggers() {
+        let (mut scheduler, clock) = scheduler_at(at(2025, 1, 6, 12, 0));
+        let idle = scheduler
+            .add_job("idle".into(), JobTrigger::AgentIdle { idle_secs: 60 }, macro_action("a"), BusyPolicy::Skip)
+            .unwrap();
+        let start = scheduler
+            .add_job("start".into(), JobTrigger::AppStart, macro_action("b"), BusyPolicy::Skip)
+            .unwrap();
+
+        scheduler.notify_app_started();
+        assert_eq!(scheduler.poll(&AppState::IDLE).map(|j| j.id), Some(start.id));
+        assert!(scheduler.poll(&AppState::IDLE).is_none());
+
+        clock.advance(ChronoDuration::seconds(61));
+        assert_eq!(scheduler.poll(&AppState::IDLE).map(|j| j.id), Some(idle.id));
+        clock.advance(ChronoDuration::seconds(120));
+        assert!(scheduler.poll(&AppState::IDLE).is_none(), "fires once per idle period");
+
+        assert!(scheduler.poll(&AppState::EXECUTING).is_none());
+        clock.advance(ChronoDuration::seconds(61));
+        assert!(scheduler.poll(&AppState::IDLE).is_none());
+        clock.advance(ChronoDuration::seconds(61));
+        assert_eq!(scheduler.poll(&AppState::IDLE).map(|j| j.id), Some(idle.id));
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("*.pdf", "report.pdf"));
+        assert!(!glob_matches("*.pdf", "report.pdf.part"));
+        assert!(glob_matches("scan_??.png", "scan_01.png"));
+        assert!(glob_matches("*", "anything"));
+    }
+}
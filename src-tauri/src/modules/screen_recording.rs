@@ -0,0 +1,419 @@
+// Screen recording module
+// Records the screen at a low frame rate while a task executes, for audit and debugging.
+// Frames come from the Perception capture backend with every synthetic click and
+// keystroke drawn on them, and are encoded as a GIF, a WebM video (with ffmpeg) or kept
+// as numbered PNG files.
+
+use crate::modules::io_controller::{self, SyntheticInput};
+use crate::modules::perception::{self, Rect, ScreenBackend};
+use crate::modules::persistence;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{imageops, Delay, Frame, Rgba, RgbaImage};
+use rdev::{EventType, Key};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+/// How long a marker stays on the frames after its input.
+const MARKER_SHOWN: Duration = Duration::from_millis(1000);
+const CLICK_COLOR: Rgba<u8> = Rgba([230, 40, 40, 255]);
+const KEY_COLOR: Rgba<u8> = Rgba([250, 200, 30, 255]);
+const TEXT_COLOR: Rgba<u8> = Rgba([40, 190, 230, 255]);
+/// Size of the badges drawn along the bottom edge for keystrokes and typing.
+const BADGE_SIZE: u32 = 14;
+
+#[derive(Error, Debug)]
+pub enum RecordingError {
+    #[error("File system error: {0}")]
+    FileSystem(String),
+    #[error("Screen capture failed: {0}")]
+    Capture(String),
+    #[error("Encoding failed: {0}")]
+    Encoding(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingFormat {
+    #[default]
+    Gif,
+    /// VP9 video; needs the `ffmpeg` binary.
+    Webm,
+    /// Numbered PNG files, `frame-00001.png` onwards.
+    Frames,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct RecordingOptions {
+    pub format: RecordingFormat,
+    /// Frames per second.
+    pub fps: f32,
+    /// Wider screens are scaled down to this width.
+    pub max_width: u32,
+}
+
+impl Default for RecordingOptions {
+    fn default() -> Self {
+        Self { format: RecordingFormat::default(), fps: 2.0, max_width: 1280 }
+    }
+}
+
+/// Synthetic input seen while recording, at milliseconds since the recording started.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum InputMarker {
+    /// `at` is unknown when no mouse movement was sent before the click.
+    Click { at_ms: u64, button: String, at: Option<(f64, f64)> },
+    Key { at_ms: u64, key: String },
+    /// Only the length of typed text is kept.
+    Text { at_ms: u64, chars: usize },
+}
+
+impl InputMarker {
+    fn at_ms(&self) -> u64 {
+        match self {
+            InputMarker::Click { at_ms, .. } | InputMarker::Key { at_ms, .. } | InputMarker::Text { at_ms, .. } => *at_ms,
+        }
+    }
+}
+
+/// A finished recording, as linked from the trajectory log.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScreenRecording {
+    /// The GIF or WebM file, or the folder of frames.
+    pub path: PathBuf,
+    pub format: RecordingFormat,
+    pub frames: usize,
+    pub fps: f32,
+    pub markers: Vec<InputMarker>,
+}
+
+/// Collects markers from the input observer.
+#[derive(Clone)]
+pub struct MarkerLog {
+    started: Instant,
+    inner: Arc<Mutex<MarkerState>>,
+}
+
+#[derive(Default)]
+struct MarkerState {
+    cursor: Option<(f64, f64)>,
+    markers: Vec<InputMarker>,
+}
+
+impl MarkerLog {
+    pub fn new() -> Self {
+        Self { started: Instant::now(), inner: Arc::new(Mutex::new(MarkerState::default())) }
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+
+    pub fn observe(&self, input: &SyntheticInput) {
+        let at_ms = self.elapsed_ms();
+        let Ok(mut state) = self.inner.lock() else {
+            return;
+        };
+        let marker = match input {
+            SyntheticInput::Event(EventType::MouseMove { x, y }) => {
+                state.cursor = Some((*x, *y));
+                return;
+            }
+            SyntheticInput::Event(EventType::ButtonPress(button)) => {
+                InputMarker::Click { at_ms, button: format!("{:?}", button), at: state.cursor }
+            }
+            SyntheticInput::Event(EventType::KeyPress(key)) => InputMarker::Key { at_ms, key: key_name(key) },
+            SyntheticInput::Text { chars } => InputMarker::Text { at_ms, chars: *chars },
+            SyntheticInput::Event(_) => return,
+        };
+        state.markers.push(marker);
+    }
+
+    pub fn markers(&self) -> Vec<InputMarker> {
+        self.inner.lock().map(|state| state.markers.clone()).unwrap_or_default()
+    }
+}
+
+impl Default for MarkerLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn key_name(key: &Key) -> String {
+    format!("{:?}", key)
+}
+
+/// Draws the markers shown at `now_ms` onto a frame scaled by `scale` from the screen.
+fn draw_markers(frame: &mut RgbaImage, markers: &[InputMarker], now_ms: u64, scale: f64) {
+    let shown = markers
+        .iter()
+        .filter(|marker| marker.at_ms() <= now_ms && now_ms - marker.at_ms() <= MARKER_SHOWN.as_millis() as u64);
+    let mut badges = 0;
+    for marker in shown {
+        let color = match marker {
+            InputMarker::Click { at: Some((x, y)), .. } => {
+                let radius = (16.0 * scale).max(6.0);
+                draw_ring(frame, (x * scale, y * scale), radius, 3.0);
+                continue;
+            }
+            InputMarker::Click { at: None, .. } => CLICK_COLOR,
+            InputMarker::Key { .. } => KEY_COLOR,
+            InputMarker::Text { .. } => TEXT_COLOR,
+        };
+        // Input without a position is shown as a row of badges in the bottom left corner.
+        let left = 4 + badges * (BADGE_SIZE + 4);
+        let top = frame.height().saturating_sub(BADGE_SIZE + 4);
+        for y in top..(top + BADGE_SIZE).min(frame.height()) {
+            for x in left..(left + BADGE_SIZE).min(frame.width()) {
+                frame.put_pixel(x, y, color);
+            }
+        }
+        badges += 1;
+    }
+}
+
+fn draw_ring(frame: &mut RgbaImage, center: (f64, f64), radius: f64, thickness: f64) {
+    let reach = (radius + thickness).ceil();
+    let (left, top) = ((center.0 - reach).max(0.0) as u32, (center.1 - reach).max(0.0) as u32);
+    let (right, bottom) = ((center.0 + reach).max(0.0) as u32, (center.1 + reach).max(0.0) as u32);
+    for y in top..=bottom.min(frame.height().saturating_sub(1)) {
+        for x in left..=right.min(frame.width().saturating_sub(1)) {
+            let distance = (x as f64 - center.0).hypot(y as f64 - center.1);
+            if (distance - radius).abs() <= thickness / 2.0 {
+                frame.put_pixel(x, y, CLICK_COLOR);
+            }
+        }
+    }
+}
+
+fn frame_path(frames_dir: &Path, index: usize) -> PathBuf {
+    frames_dir.join(format!("frame-{:05}.png", index))
+}
+
+/// Captures one frame, scaled down to `max_width` with the current markers drawn on it.
+fn capture_frame(
+    screen: &dyn ScreenBackend,
+    max_width: u32,
+    markers: &MarkerLog,
+) -> Result<RgbaImage, perception::PerceptionError> {
+    let (width, height) = screen.screen_size()?;
+    let mut frame = screen.capture_region(Rect { x: 0, y: 0, width, height })?;
+    let mut scale = 1.0;
+    if frame.width() > max_width && max_width > 0 {
+        scale = max_width as f64 / frame.width() as f64;
+        let scaled_height = ((frame.height() as f64 * scale).round() as u32).max(1);
+        frame = imageops::resize(&frame, max_width, scaled_height, imageops::FilterType::Triangle);
+    }
+    draw_markers(&mut frame, &markers.markers(), markers.elapsed_ms(), scale);
+    Ok(frame)
+}
+
+/// Records the screen into `dir` on its own thread until finished. Frames are written as
+/// they are captured, so long tasks do not pile up in memory.
+pub struct ScreenRecorder {
+    dir: PathBuf,
+    options: RecordingOptions,
+    markers: MarkerLog,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<usize, RecordingError>>>,
+    /// How many frames were captured, once stopped.
+    captured: Option<Result<usize, RecordingError>>,
+}
+
+impl ScreenRecorder {
+    /// Starts recording and marking synthetic input.
+    pub fn start(screen: Arc<dyn ScreenBackend>, dir: PathBuf, options: RecordingOptions) -> Result<Self, RecordingError> {
+        let frames_dir = dir.join("frames");
+        std::fs::create_dir_all(&frames_dir).map_err(|e| RecordingError::FileSystem(e.to_string()))?;
+        let markers = MarkerLog::new();
+        let observed = markers.clone();
+        io_controller::set_input_observer(Some(Box::new(move |input| observed.observe(input))));
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let interval = Duration::from_secs_f32(1.0 / options.fps.clamp(0.1, 30.0));
+        let (thread_stop, thread_markers, max_width) = (stop.clone(), markers.clone(), options.max_width);
+        let thread = std::thread::spawn(move || {
+            let mut frames = 0;
+            let mut next = Instant::now();
+            while !thread_stop.load(Ordering::SeqCst) {
+                match capture_frame(screen.as_ref(), max_width, &thread_markers) {
+                    Ok(frame) => {
+                        frames += 1;
+                        let png = perception::encode_png(&frame).map_err(|e| RecordingError::Encoding(e.to_string()))?;
+                        persistence::write_atomic(&frame_path(&frames_dir, frames), &png)
+                            .map_err(|e| RecordingError::FileSystem(e.to_string()))?;
+                    }
+                    // A missed frame is not worth ending the recording for.
+                    Err(e) => log::warn!("Could not capture a recording frame: {}", e),
+                }
+                next += interval;
+                // Woken early when the recording stops.
+                std::thread::park_timeout(next.saturating_duration_since(Instant::now()));
+            }
+            Ok(frames)
+        });
+        Ok(Self { dir, options, markers, stop, thread: Some(thread), captured: None })
+    }
+
+    /// Tells the capture thread to stop and stops marking input, without waiting for the
+    /// frame being captured.
+    pub fn request_stop(&mut self) {
+        if self.stop.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        if let Some(thread) = &self.thread {
+            thread.thread().unpark();
+        }
+        io_controller::set_input_observer(None);
+    }
+
+    /// Stops capturing frames and marking input, and waits for the capture thread.
+    /// `finish` encodes what was captured.
+    pub fn stop(&mut self) {
+        self.request_stop();
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+        self.captured = Some(
+            thread
+                .join()
+                .unwrap_or_else(|_| Err(RecordingError::Capture("The recording thread panicked".to_string()))),
+        );
+    }
+
+    /// Stops recording and encodes the frames. Slow for long recordings.
+    pub fn finish(mut self) -> Result<ScreenRecording, RecordingError> {
+        self.stop();
+        let frames = self.captured.take().unwrap_or(Ok(0))?;
+        if frames == 0 {
+            return Err(RecordingError::Capture("No frames were captured".to_string()));
+        }
+
+        let frames_dir = self.dir.join("frames");
+        let path = match self.options.format {
+            RecordingFormat::Frames => frames_dir.clone(),
+            RecordingFormat::Gif => {
+                let path = self.dir.join("recording.gif");
+                encode_gif(&frames_dir, frames, self.options.fps, &path)?;
+                path
+            }
+            RecordingFormat::Webm => {
+                let path = self.dir.join("recording.webm");
+                encode_webm(&frames_dir, self.options.fps, &path)?;
+                path
+            }
+        };
+        if self.options.format != RecordingFormat::Frames {
+            if let Err(e) = std::fs::remove_dir_all(&frames_dir) {
+                log::warn!("Could not remove recording frames: {}", e);
+            }
+        }
+        Ok(ScreenRecording {
+            path,
+            format: self.options.format,
+            frames,
+            fps: self.options.fps,
+            markers: self.markers.markers(),
+        })
+    }
+}
+
+impl Drop for ScreenRecorder {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn encode_gif(frames_dir: &Path, frames: usize, fps: f32, output: &Path) -> Result<(), RecordingError> {
+    let file = File::create(output).map_err(|e| RecordingError::FileSystem(e.to_string()))?;
+    let error = |e: image::ImageError| RecordingError::Encoding(e.to_string());
+    let mut encoder = GifEncoder::new_with_speed(BufWriter::new(file), 10);
+    encoder.set_repeat(Repeat::Infinite).map_err(error)?;
+    let delay = Delay::from_numer_denom_ms(1000, (fps.max(0.1) * 1000.0).round() as u32);
+    for index in 1..=frames {
+        let image = perception::load_image(&frame_path(frames_dir, index))
+            .map_err(|e| RecordingError::Encoding(e.to_string()))?;
+        encoder.encode_frame(Frame::from_parts(image, 0, 0, delay)).map_err(error)?;
+    }
+    Ok(())
+}
+
+fn encode_webm(frames_dir: &Path, fps: f32, output: &Path) -> Result<(), RecordingError> {
+    let status = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-framerate", &fps.to_string(), "-i"])
+        .arg(frames_dir.join("frame-%05d.png"))
+        // VP9 needs even dimensions.
+        .args(["-vf", "scale=trunc(iw/2)*2:trunc(ih/2)*2", "-c:v", "libvpx-vp9", "-b:v", "0", "-crf", "40"])
+        .args(["-pix_fmt", "yuv420p"])
+        .arg(output)
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|e| RecordingError::Encoding(format!("Cannot run ffmpeg: {}", e)))?;
+    if !status.success() {
+        return Err(RecordingError::Encoding(format!("ffmpeg exited with {}", status)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::perception::FixtureScreen;
+    use image::AnimationDecoder;
+    use rdev::Button;
+
+    fn record(format: RecordingFormat, name: &str) -> ScreenRecording {
+        let screen = FixtureScreen { image: RgbaImage::from_pixel(400, 300, Rgba([255, 255, 255, 255])), ..Default::default() };
+        let dir = std::env::temp_dir().join(format!("nyx-recording-{}-{}", name, std::process::id()));
+        let options = RecordingOptions { format, fps: 20.0, max_width: 200 };
+        let recorder = ScreenRecorder::start(Arc::new(screen), dir, options).unwrap();
+        recorder.markers.observe(&SyntheticInput::Event(EventType::MouseMove { x: 100.0, y: 80.0 }));
+        recorder.markers.observe(&SyntheticInput::Event(EventType::ButtonPress(Button::Left)));
+        recorder.markers.observe(&SyntheticInput::Event(EventType::ButtonRelease(Button::Left)));
+        recorder.markers.observe(&SyntheticInput::Text { chars: 7 });
+        std::thread::sleep(Duration::from_millis(300));
+        recorder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_frames_show_markers() {
+        let recording = record(RecordingFormat::Frames, "frames");
+        assert!(recording.frames >= 2, "{} frames", recording.frames);
+        assert!(matches!(recording.markers.as_slice(), [
+            InputMarker::Click { at: Some((x, y)), .. },
+            InputMarker::Text { chars: 7, .. },
+        ] if (*x, *y) == (100.0, 80.0)));
+
+        // The screen is scaled to half, so the click is drawn as a ring around (50, 40).
+        let frame = perception::load_image(&frame_path(&recording.path, recording.frames)).unwrap();
+        assert_eq!(frame.dimensions(), (200, 150));
+        assert_eq!(*frame.get_pixel(50, 40), Rgba([255, 255, 255, 255]));
+        assert_eq!(*frame.get_pixel(50 + 8, 40), CLICK_COLOR);
+        // Typing has no position and is shown in the corner.
+        assert_eq!(*frame.get_pixel(6, 150 - 6), TEXT_COLOR);
+        std::fs::remove_dir_all(recording.path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_encodes_gif() {
+        let recording = record(RecordingFormat::Gif, "gif");
+        let decoder = image::codecs::gif::GifDecoder::new(File::open(&recording.path).unwrap()).unwrap();
+        assert_eq!(decoder.into_frames().count(), recording.frames);
+        assert!(!recording.path.with_file_name("frames").exists());
+        std::fs::remove_dir_all(recording.path.parent().unwrap()).unwrap();
+    }
+}
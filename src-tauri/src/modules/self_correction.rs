@@ -0,0 +1,444 @@
+// Self-correcting playback
+// Re-finds recorded click targets on the current screen: first by matching the stored
+// crop, then by searching for the target's text near where it was recorded. If the target
+// is missing, a recovery advisor may suggest an action such as scrolling or closing a
+// dialog before trying again. A target that stays missing stops playback, unless the
+// recorded coordinates are allowed as a fallback.
+
+use crate::modules::cognition::{self, CognitionSettings, Gemini};
+use crate::modules::io_controller::{InputBackend, LiveInput};
+use crate::modules::macro_engine::{
+    self, ClickResolution, ClickTarget, LocateMethod, Macro, MacroError, PlaybackReport, TargetLocator,
+};
+use crate::modules::perception::{self, OcrEngine, OcrWord, PerceptionError, Rect, ScreenBackend, TesseractOcr, X11Screen};
+use crate::modules::redaction::Redactor;
+use crate::modules::tooling::{ToolCall, Tooling};
+use image::RgbaImage;
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+/// Matches scoring below this are rejected.
+pub const DEFAULT_CONFIDENCE_THRESHOLD: f32 = 0.8;
+/// Area around the recorded click searched for the target's text.
+const TEXT_SEARCH_WIDTH: u32 = 800;
+const TEXT_SEARCH_HEIGHT: u32 = 600;
+/// Recovery actions tried per click before giving up.
+const MAX_RECOVERIES: usize = 2;
+/// Time for the screen to settle after a recovery action.
+const RECOVERY_SETTLE: Duration = Duration::from_millis(500);
+
+/// Suggests an action that may bring a missing target into view.
+pub trait RecoveryAdvisor: Send {
+    /// `screen` is what the target was looked for on, if it could be read; `tried` lists
+    /// the actions already taken for this target.
+    fn suggest(&mut self, target: &ClickTarget, screen: Option<&Screen>, tried: &[String]) -> Option<ToolCall>;
+}
+
+/// A screenshot and the words recognized on it.
+pub struct Screen {
+    pub image: RgbaImage,
+    pub words: Vec<OcrWord>,
+}
+
+/// Asks Cognition for recovery actions. Must run on a blocking thread of the runtime.
+pub struct CognitionAdvisor {
+    runtime: tokio::runtime::Handle,
+    redactor: Redactor,
+    gemini: Gemini,
+}
+
+impl CognitionAdvisor {
+    pub fn new(runtime: tokio::runtime::Handle, redactor: Redactor, gemini: Gemini) -> Self {
+        Self { runtime, redactor, gemini }
+    }
+}
+
+impl RecoveryAdvisor for CognitionAdvisor {
+    fn suggest(&mut self, target: &ClickTarget, screen: Option<&Screen>, tried: &[String]) -> Option<ToolCall> {
+        // Windows may have moved or opened since the last suggestion.
+        self.redactor.refresh_windows();
+        let suggestion = self.runtime.block_on(cognition::suggest_recovery(
+            &target.target_description,
+            screen.map_or(&[], |screen| &screen.words),
+            screen.map(|screen| &screen.image),
+            tried,
+            &self.redactor,
+            &self.gemini,
+        ));
+        suggestion.unwrap_or_else(|e| {
+            log::warn!("Could not get a recovery action: {}", e);
+            None
+        })
+    }
+}
+
+/// The best guess for a target on one screenshot.
+struct Candidate {
+    xy: (f64, f64),
+    method: LocateMethod,
+    confidence: f32,
+}
+
+pub struct SelfCorrectingLocator {
+    screen: Arc<dyn ScreenBackend>,
+    /// The macro's assets folder, which holds the stored crops.
+    assets_dir: PathBuf,
+    threshold: f32,
+    advisor: Option<Box<dyn RecoveryAdvisor>>,
+    ocr: Arc<dyn OcrEngine>,
+    /// Click the recorded position when the target is not found, instead of stopping.
+    coordinate_fallback: bool,
+}
+
+impl SelfCorrectingLocator {
+    pub fn new(screen: Arc<dyn ScreenBackend>, assets_dir: PathBuf) -> Self {
+        Self {
+            screen,
+            assets_dir,
+            threshold: DEFAULT_CONFIDENCE_THRESHOLD,
+            advisor: None,
+            ocr: Arc::new(TesseractOcr::default()),
+            coordinate_fallback: false,
+        }
+    }
+
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn with_advisor(mut self, advisor: Box<dyn RecoveryAdvisor>) -> Self {
+        self.advisor = Some(advisor);
+        self
+    }
+
+    pub fn with_ocr(mut self, ocr: Arc<dyn OcrEngine>) -> Self {
+        self.ocr = ocr;
+        self
+    }
+
+    pub fn with_coordinate_fallback(mut self, enabled: bool) -> Self {
+        self.coordinate_fallback = enabled;
+        self
+    }
+
+    fn capture_screen(&self) -> Result<RgbaImage, PerceptionError> {
+        let (width, height) = self.screen.screen_size()?;
+        self.screen.capture_region(Rect { x: 0, y: 0, width, height })
+    }
+
+    /// Finds the stored crop on screen and maps the click's offset inside the crop onto it.
+    fn match_crop(&self, target: &ClickTarget, screen: &RgbaImage) -> Option<Candidate> {
+        let crop_path = self.assets_dir.join(&target.target_crop_path);
+        let crop = match image::open(&crop_path) {
+            Ok(crop) => crop.to_rgba8(),
+            Err(e) => {
+                log::warn!("Could not load click crop {}: {}", crop_path.display(), e);
+                return None;
+            }
+        };
+        let found = perception::match_template(screen, &crop)?;
+        let (x, y) = target.original_xy;
+        Some(Candidate {
+            xy: (
+                found.x as f64 + x - target.crop_rect.x as f64,
+                found.y as f64 + y - target.crop_rect.y as f64,
+            ),
+            method: LocateMethod::Template,
+            confidence: found.score,
+        })
+    }
+
+    /// Finds the target's text on screen, preferring the occurrence nearest the recorded click.
+    fn match_text(&self, target: &ClickTarget, words: &[perception::OcrWord]) -> Option<Candidate> {
+        let text = target.target_text.as_deref()?;
+        let (x, y) = target.original_xy;
+        perception::find_text(words, text)
+            .into_iter()
+            .map(|(rect, confidence)| Candidate { xy: rect.center(), method: LocateMethod::Text, confidence })
+            .min_by(|a, b| (a.xy.0 - x).hypot(a.xy.1 - y).total_cmp(&(b.xy.0 - x).hypot(b.xy.1 - y)))
+    }
+
+    /// Tries the crop, then the text near the recorded click. Returns the accepted
+    /// candidate, or the best rejected confidence and the screen for the recovery advisor.
+    /// Without its text the screen cannot be redacted, so none is returned then.
+    fn find(&self, target: &ClickTarget) -> Result<Candidate, (f32, Option<Screen>)> {
+        let screen = match self.capture_screen() {
+            Ok(screen) => screen,
+            Err(e) => {
+                log::warn!("Could not capture the screen to locate '{}': {}", target.target_description, e);
+                return Err((0.0, None));
+            }
+        };
+
+        let mut best = 0.0f32;
+        if let Some(candidate) = self.match_crop(target, &screen) {
+            if candidate.confidence >= self.threshold {
+                return Ok(candidate);
+            }
+            best = best.max(candidate.confidence);
+        }
+
+        if target.target_text.is_some() {
+            let (x, y) = target.original_xy;
+            let region = Rect::centered_on(x, y, TEXT_SEARCH_WIDTH, TEXT_SEARCH_HEIGHT);
+            match self.ocr.recognize(&screen, Some(region)) {
+                Ok(words) => {
+                    if let Some(candidate) = self.match_text(target, &words) {
+                        if candidate.confidence >= self.threshold {
+                            return Ok(candidate);
+                        }
+                        best = best.max(candidate.confidence);
+                    }
+                }
+                Err(e) => log::warn!("Could not read screen text near '{}': {}", target.target_description, e),
+            }
+        }
+
+        // Only the advisor needs the whole screen read, to redact it.
+        if self.advisor.is_none() {
+            return Err((best, None));
+        }
+        match self.ocr.recognize(&screen, None) {
+            Ok(words) => Err((best, Some(Screen { image: screen, words }))),
+            Err(e) => {
+                log::warn!("Could not read screen text: {}", e);
+                Err((best, None))
+            }
+        }
+    }
+}
+
+/// Refuses `play_macro` inside recovery actions, which must not start other macros.
+fn no_macros(name: &str) -> Result<Macro, MacroError> {
+    Err(MacroError::Io(format!("Recovery actions cannot play macro '{}'", name)))
+}
+
+impl TargetLocator for SelfCorrectingLocator {
+    fn locate(
+        &mut self,
+        event_index: usize,
+        target: &ClickTarget,
+        backend: &mut dyn InputBackend,
+    ) -> Result<ClickResolution, MacroError> {
+        let mut recovery_actions = Vec::new();
+        let mut best_confidence = 0.0;
+
+        loop {
+            let screen = match self.find(target) {
+                Ok(candidate) => {
+                    return Ok(ClickResolution {
+                        event_index,
+                        original_xy: target.original_xy,
+                        resolved_xy: candidate.xy,
+                        method: candidate.method,
+                        confidence: candidate.confidence,
+                        recovery_actions,
+                    });
+                }
+                Err((confidence, screen)) => {
+                    best_confidence = f32::max(best_confidence, confidence);
+                    screen
+                }
+            };
+
+            if recovery_actions.len() >= MAX_RECOVERIES {
+                break;
+            }
+            let Some(advisor) = self.advisor.as_mut() else { break };
+            let Some(call) = advisor.suggest(target, screen.as_ref(), &recovery_actions) else { break };
+            let tool = match call.resolve() {
+                Ok(tool) => tool,
+                Err(e) => {
+                    log::warn!("Ignoring recovery action: {}", e);
+                    break;
+                }
+            };
+
+            log::info!("Target '{}' not found, trying recovery {:?}", target.target_description, tool);
+            recovery_actions.push(format!("{} {}", call.tool, call.params));
+            if let Err(e) = Tooling::new().execute(&tool, backend, &no_macros) {
+                log::warn!("Recovery action failed: {}", e);
+                break;
+            }
+            backend.wait(RECOVERY_SETTLE);
+        }
+
+        if !self.coordinate_fallback {
+            log::warn!(
+                "Target '{}' not found (best confidence {:.2}), stopping playback",
+                target.target_description,
+                best_confidence
+            );
+            return Err(MacroError::TargetNotFound {
+                step: event_index + 1,
+                target: target.target_description.clone(),
+            });
+        }
+        log::warn!(
+            "Target '{}' not found (best confidence {:.2}), using recorded position",
+            target.target_description,
+            best_confidence
+        );
+        Ok(ClickResolution {
+            event_index,
+            original_xy: target.original_xy,
+            resolved_xy: target.original_xy,
+            method: LocateMethod::Coordinates,
+            confidence: best_confidence,
+            recovery_actions,
+        })
+    }
+}
+
+/// Plays a macro on the desktop, relocating its recorded click targets if it has any.
+/// Cognition is asked for recovery actions when called from a blocking runtime thread.
+/// A target that cannot be found stops playback unless `coordinate_fallback` is set.
+pub fn play_macro_self_correcting(
+    macro_data: &Macro,
+    app_handle: &tauri::AppHandle,
+    threshold: f32,
+    coordinate_fallback: bool,
+) -> Result<PlaybackReport, MacroError> {
+    if macro_data.events.iter().all(|event| event.target.is_none()) {
+        macro_engine::play_macro(macro_data)?;
+        return Ok(PlaybackReport { macro_name: macro_data.name.clone(), clicks: Vec::new() });
+    }
+
+    let assets_dir = macro_engine::macro_assets_dir(&macro_data.name, app_handle)?;
+    let mut locator = SelfCorrectingLocator::new(Arc::new(X11Screen), assets_dir)
+        .with_threshold(threshold)
+        .with_coordinate_fallback(coordinate_fallback);
+    if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+        let gemini = Gemini::new(CognitionSettings::load(app_handle));
+        locator = locator.with_advisor(Box::new(CognitionAdvisor::new(runtime, Redactor::load(app_handle), gemini)));
+    }
+    let report = macro_engine::play_macro_corrected(macro_data, &mut LiveInput, &mut locator)?;
+    for click in report.relocated() {
+        log::info!(
+            "Click {} of '{}' relocated from {:?} to {:?} by {:?} after {} recovery action(s)",
+            click.event_index,
+            report.macro_name,
+            click.original_xy,
+            click.resolved_xy,
+            click.method,
+            click.recovery_actions.len()
+        );
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::dry_run::DryRunInput;
+    use crate::modules::macro_engine::{self, Macro, TimedEvent};
+    use crate::modules::perception::{FixtureOcr, FixtureScreen};
+    use image::Rgba;
+    use rdev::{Button, EventType};
+
+    /// A screen of distinct blocks so every crop matches in exactly one place.
+    fn patterned_screen(offset: u32) -> RgbaImage {
+        RgbaImage::from_fn(400, 300, |x, y| {
+            let (x, y) = (x as i64 - offset as i64 + 1000, y as i64);
+            let value = ((x / 7) * 31 + (y / 5) * 17 + (x * y) % 13) % 256;
+            Rgba([value as u8, (value * 3 % 256) as u8, (255 - value) as u8, 255])
+        })
+    }
+
+    fn target() -> ClickTarget {
+        ClickTarget {
+            target_description: "Element with text 'Submit'".to_string(),
+            target_crop_path: "clicks/1.png".to_string(),
+            original_xy: (150.0, 100.0),
+            crop_rect: Rect { x: 120, y: 80, width: 60, height: 40 },
+            window_title: None,
+            target_text: None,
+        }
+    }
+
+    struct ScriptedAdvisor(Vec<ToolCall>);
+
+    impl RecoveryAdvisor for ScriptedAdvisor {
+        fn suggest(&mut self, _: &ClickTarget, _: Option<&Screen>, _: &[String]) -> Option<ToolCall> {
+            (!self.0.is_empty()).then(|| self.0.remove(0))
+        }
+    }
+
+    fn locator_with_crop(test: &str, screen: RgbaImage, crop: &RgbaImage) -> (SelfCorrectingLocator, PathBuf) {
+        let assets_dir = std::env::temp_dir().join(format!("nyx-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(assets_dir.join("clicks")).unwrap();
+        crop.save(assets_dir.join("clicks/1.png")).unwrap();
+        let screen = Arc::new(FixtureScreen { image: screen, ..FixtureScreen::default() });
+        (SelfCorrectingLocator::new(screen, assets_dir.clone()), assets_dir)
+    }
+
+    #[test]
+    fn test_moved_target_is_relocated() {
+        let recorded = patterned_screen(0);
+        let crop = image::imageops::crop_imm(&recorded, 120, 80, 60, 40).to_image();
+        // The whole UI shifted 30px to the right since recording.
+        let (mut locator, assets_dir) = locator_with_crop("relocate", patterned_screen(30), &crop);
+
+        let macro_data = Macro {
+            name: "submit".to_string(),
+            events: vec![
+                TimedEvent::input(EventType::MouseMove { x: 150.0, y: 100.0 }, Duration::ZERO),
+                TimedEvent {
+                    target: Some(target()),
+                    ..TimedEvent::input(EventType::ButtonPress(Button::Left), Duration::ZERO)
+                },
+                TimedEvent::input(EventType::ButtonRelease(Button::Left), Duration::ZERO),
+            ],
+        };
+        let mut backend = DryRunInput::new();
+        let report = macro_engine::play_macro_corrected(&macro_data, &mut backend, &mut locator).unwrap();
+        std::fs::remove_dir_all(assets_dir).unwrap();
+
+        assert_eq!(report.clicks.len(), 1);
+        assert_eq!(report.clicks[0].method, LocateMethod::Template);
+        assert_eq!(report.clicks[0].resolved_xy, (180.0, 100.0));
+        assert_eq!(backend.take_transcript(), vec!["t=0.00s click left at (180, 100)"]);
+    }
+
+    #[test]
+    fn test_missing_target_tries_recovery_then_falls_back() {
+        let crop = RgbaImage::from_pixel(60, 40, Rgba([255, 0, 0, 255]));
+        let (locator, assets_dir) = locator_with_crop("recovery", patterned_screen(0), &crop);
+        let scroll = ToolCall { tool: "scroll".to_string(), params: serde_json::json!({ "dy": -3 }) };
+        let mut locator = locator
+            .with_advisor(Box::new(ScriptedAdvisor(vec![scroll])))
+            .with_ocr(Arc::new(FixtureOcr { words: Vec::new() }))
+            .with_coordinate_fallback(true);
+
+        let mut backend = DryRunInput::new();
+        let resolution = locator.locate(4, &target(), &mut backend).unwrap();
+        std::fs::remove_dir_all(assets_dir).unwrap();
+
+        assert_eq!(resolution.method, LocateMethod::Coordinates);
+        assert_eq!(resolution.resolved_xy, (150.0, 100.0));
+        assert_eq!(resolution.recovery_actions, vec![r#"scroll {"dy":-3}"#]);
+        assert_eq!(backend.take_transcript(), vec!["t=0.00s scroll (0, -3) at the current position"]);
+    }
+
+    #[test]
+    fn test_missing_target_stops_playback() {
+        let crop = RgbaImage::from_pixel(60, 40, Rgba([255, 0, 0, 255]));
+        let (locator, assets_dir) = locator_with_crop("abort", patterned_screen(0), &crop);
+        // "Submit" is on screen, but too far from the recorded click to be searched.
+        let far_away = OcrWord {
+            text: "Submit".to_string(),
+            bbox: Rect { x: 1200, y: 100, width: 50, height: 12 },
+            confidence: 0.95,
+            line: (1, 1, 1),
+        };
+        let mut locator = locator.with_ocr(Arc::new(FixtureOcr { words: vec![far_away] }));
+        let target = ClickTarget { target_text: Some("Submit".to_string()), ..target() };
+
+        let mut backend = DryRunInput::new();
+        let result = locator.locate(4, &target, &mut backend);
+        std::fs::remove_dir_all(assets_dir).unwrap();
+
+        assert!(matches!(result, Err(MacroError::TargetNotFound { step: 5, .. })));
+        assert!(backend.take_transcript().is_empty());
+    }
+}
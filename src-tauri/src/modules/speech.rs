@@ -0,0 +1,282 @@
+// Speech module
+// Speaks the results of spoken commands aloud: a local text-to-speech engine (espeak-ng
+// or piper) run as a subprocess renders a WAV file, which is played on the default output
+// device with cpal. Nyx does not ask clarifying questions of its own yet; the UI can say
+// any text, such as a question it shows, through `speak_command`.
+
+use crate::modules::voice::{self, SAMPLE_RATE};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SizedSample};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+/// espeak-ng's default speed, in words per minute.
+pub const DEFAULT_RATE: u32 = 175;
+/// Slowest and fastest rates used; espeak-ng accepts 80 to 450 words per minute.
+pub const MIN_RATE: u32 = 80;
+pub const MAX_RATE: u32 = 450;
+/// Longest the engine may take to render one response.
+const RENDER_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often a running engine is checked on.
+const RENDER_POLL: Duration = Duration::from_millis(50);
+/// Extra time playback may take before it is given up on.
+const PLAYBACK_GRACE: Duration = Duration::from_secs(2);
+
+/// Numbers temporary WAV files, so concurrent calls do not share one.
+static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Error, Debug)]
+pub enum SpeechError {
+    #[error("Text-to-speech engine failed: {0}")]
+    Engine(String),
+    #[error("Cannot play speech: {0}")]
+    Playback(String),
+}
+
+/// The local text-to-speech program.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(tag = "engine", rename_all = "snake_case")]
+pub enum SpeechEngine {
+    #[default]
+    EspeakNg,
+    /// A piper voice model, e.g. `en_US-lessac-medium.onnx`.
+    Piper { model: PathBuf },
+}
+
+/// How spoken responses sound, or whether there are any.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct SpeechSettings {
+    pub engine: SpeechEngine,
+    /// An espeak-ng voice such as "en-us", or the speaker id of a multi-speaker piper
+    /// model. The engine's default if unset.
+    pub voice: Option<String>,
+    /// Words per minute, kept between `MIN_RATE` and `MAX_RATE`.
+    pub rate: u32,
+    pub muted: bool,
+}
+
+impl Default for SpeechSettings {
+    fn default() -> Self {
+        Self { engine: SpeechEngine::default(), voice: None, rate: DEFAULT_RATE, muted: false }
+    }
+}
+
+impl SpeechSettings {
+    fn rate(&self) -> u32 {
+        self.rate.clamp(MIN_RATE, MAX_RATE)
+    }
+
+    /// The engine command writing speech to `output`; the text goes to its stdin.
+    fn command(&self, output: &Path) -> Command {
+        match &self.engine {
+            SpeechEngine::EspeakNg => {
+                let mut command = Command::new("espeak-ng");
+                command.arg("--stdin").arg("-w").arg(output).args(["-s", &self.rate().to_string()]);
+                if let Some(voice) = &self.voice {
+                    command.args(["-v", voice]);
+                }
+                command
+            }
+            SpeechEngine::Piper { model } => {
+                // Piper sets the speed as the length of each phoneme.
+                let length_scale = DEFAULT_RATE as f32 / self.rate() as f32;
+                let mut command = Command::new("piper");
+                command
+                    .arg("--model")
+                    .arg(model)
+                    .arg("--output_file")
+                    .arg(output)
+                    .args(["--length_scale", &format!("{:.2}", length_scale)]);
+                if let Some(voice) = &self.voice {
+                    command.args(["--speaker", voice]);
+                }
+                command
+            }
+        }
+    }
+
+    /// Renders `text` to a WAV file, even when muted. The engine is killed if it takes
+    /// longer than `RENDER_TIMEOUT`.
+    pub fn render(&self, text: &str, output: &Path) -> Result<(), SpeechError> {
+        let mut command = self.command(output);
+        let program = command.get_program().to_string_lossy().into_owned();
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| SpeechError::Engine(format!("Cannot run {}: {}", program, e)))?;
+        // Piper reads a line per sentence it writes; the whole text goes into one file.
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        child
+            .stdin
+            .take()
+            .ok_or_else(|| SpeechError::Engine(format!("{} stdin unavailable", program)))?
+            .write_all(format!("{}\n", text).as_bytes())
+            .map_err(|e| SpeechError::Engine(e.to_string()))?;
+        wait_for_engine(child, &program, RENDER_TIMEOUT)
+    }
+
+    /// Says `text` and waits until it has been said. Does nothing when muted.
+    pub fn speak(&self, text: &str) -> Result<(), SpeechError> {
+        if self.muted || text.trim().is_empty() {
+            return Ok(());
+        }
+        let path = std::env::temp_dir().join(format!(
+            "nyx-speech-{}-{}.wav",
+            std::process::id(),
+            NEXT_FILE.fetch_add(1, Ordering::SeqCst)
+        ));
+        let result = self.render(text, &path).and_then(|()| play_wav(&path));
+        if let Err(e) = std::fs::remove_file(&path) {
+            log::warn!("Could not remove {}: {}", path.display(), e);
+        }
+        result
+    }
+}
+
+/// Waits for the engine to exit successfully, killing it after `timeout`.
+fn wait_for_engine(mut child: Child, program: &str, timeout: Duration) -> Result<(), SpeechError> {
+    let started = Instant::now();
+    loop {
+        match child.try_wait().map_err(|e| SpeechError::Engine(e.to_string()))? {
+            Some(status) if status.success() => return Ok(()),
+            Some(status) => return Err(SpeechError::Engine(format!("{} exited with {}", program, status))),
+            None if started.elapsed() >= timeout => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(SpeechError::Engine(format!("{} did not finish within {:?}", program, timeout)));
+            }
+            None => thread::sleep(RENDER_POLL),
+        }
+    }
+}
+
+fn build_output_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    samples: Arc<Vec<f32>>,
+    done: mpsc::Sender<()>,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels.max(1) as usize;
+    let mut position = 0;
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            for frame in data.chunks_mut(channels) {
+                let value = samples.get(position).copied().unwrap_or(0.0);
+                position += 1;
+                frame.fill(T::from_sample(value));
+            }
+            if position >= samples.len() {
+                // Nobody waits any more once playback gave up.
+                let _ = done.send(());
+            }
+        },
+        |e| log::error!("Speaker stream error: {}", e),
+        None,
+    )
+}
+
+/// Plays a WAV file on the default output device and waits until it has finished.
+pub fn play_wav(path: &Path) -> Result<(), SpeechError> {
+    let audio = voice::read_wav(path).map_err(|e| SpeechError::Playback(e.to_string()))?;
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or_else(|| SpeechError::Playback("No speaker found".to_string()))?;
+    let supported = device.default_output_config().map_err(|e| SpeechError::Playback(e.to_string()))?;
+    let config = supported.config();
+    let samples = Arc::new(voice::resample(&audio, SAMPLE_RATE, config.sample_rate.0));
+    let length = Duration::from_secs_f64(samples.len() as f64 / config.sample_rate.0 as f64);
+
+    let (done, finished) = mpsc::channel();
+    let stream = match supported.sample_format() {
+        cpal::SampleFormat::I8 => build_output_stream::<i8>(&device, &config, samples, done),
+        cpal::SampleFormat::I16 => build_output_stream::<i16>(&device, &config, samples, done),
+        cpal::SampleFormat::I32 => build_output_stream::<i32>(&device, &config, samples, done),
+        cpal::SampleFormat::U8 => build_output_stream::<u8>(&device, &config, samples, done),
+        cpal::SampleFormat::U16 => build_output_stream::<u16>(&device, &config, samples, done),
+        cpal::SampleFormat::F32 => build_output_stream::<f32>(&device, &config, samples, done),
+        format => return Err(SpeechError::Playback(format!("Unsupported sample format {}", format))),
+    }
+    .map_err(|e| SpeechError::Playback(e.to_string()))?;
+    stream.play().map_err(|e| SpeechError::Playback(e.to_string()))?;
+    finished
+        .recv_timeout(length + PLAYBACK_GRACE)
+        .map_err(|_| SpeechError::Playback("Playback did not finish".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arguments(settings: &SpeechSettings) -> Vec<String> {
+        let command = settings.command(Path::new("/tmp/out.wav"));
+        std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_engine_commands() {
+        let espeak = SpeechSettings { voice: Some("en-gb".to_string()), rate: 200, ..Default::default() };
+        assert_eq!(arguments(&espeak), ["espeak-ng", "--stdin", "-w", "/tmp/out.wav", "-s", "200", "-v", "en-gb"]);
+
+        let piper = SpeechSettings {
+            engine: SpeechEngine::Piper { model: PathBuf::from("/models/lessac.onnx") },
+            rate: 350,
+            ..Default::default()
+        };
+        assert_eq!(
+            arguments(&piper),
+            ["piper", "--model", "/models/lessac.onnx", "--output_file", "/tmp/out.wav", "--length_scale", "0.50"]
+        );
+
+        // Rates out of range are clamped rather than passed on.
+        let stalled = SpeechSettings { rate: 0, ..Default::default() };
+        assert_eq!(arguments(&stalled)[5], "80");
+        let hurried = SpeechSettings { engine: piper.engine.clone(), rate: 10_000, ..Default::default() };
+        assert_eq!(arguments(&hurried)[6], "0.39");
+
+        // Muted speech is not even rendered, so no engine is needed.
+        let muted = SpeechSettings { engine: SpeechEngine::Piper { model: PathBuf::from("/missing") }, muted: true, ..espeak };
+        assert!(muted.speak("Task completed successfully!").is_ok());
+    }
+
+    #[test]
+    fn test_stuck_engine_is_killed() {
+        let child = Command::new("sleep").arg("10").spawn().unwrap();
+        let started = Instant::now();
+        let result = wait_for_engine(child, "sleep", Duration::from_millis(200));
+        assert!(matches!(result, Err(SpeechError::Engine(message)) if message.contains("did not finish")));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    #[ignore = "needs the espeak-ng binary"]
+    fn test_espeak_renders_wav() {
+        let path = std::env::temp_dir().join(format!("nyx-speech-test-{}.wav", std::process::id()));
+        SpeechSettings::default().render("Task completed successfully!", &path).unwrap();
+        let audio = voice::read_wav(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // About a second and a half of speech at the default rate.
+        assert!(audio.len() > SAMPLE_RATE as usize, "{} samples", audio.len());
+        assert!(audio.iter().any(|sample| sample.abs() > 0.05));
+    }
+}
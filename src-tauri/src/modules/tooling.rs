@@ -1,11 +1,541 @@
-// Placeholder module for Tooling
-// This module will handle execution of actions (mouse movements, clicks, typing, etc.)
+// Tooling module
+// Executes plan steps (tool calls) through an input backend, so plans can run against
+// the desktop or be simulated as a dry run.
 
-pub struct Tooling;
+use crate::modules::accessibility::AccessibleAction;
+use crate::modules::dry_run::DryRunInput;
+use crate::modules::io_controller::InputBackend;
+use crate::modules::macro_engine::{self, Macro, MacroError};
+use crate::modules::perception::{FindImageOptions, Rect, ScreenWait, UiElement, UiMap};
+use crate::modules::window::WindowAction;
+use rdev::{Button, EventType, Key};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{path::Path, time::Duration};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ToolError {
+    #[error("Invalid call to tool '{tool}': {message}")]
+    InvalidCall { tool: String, message: String },
+    #[error("Tool '{tool}' failed: {message}")]
+    Execution { tool: String, message: String },
+}
+
+/// A single plan step as produced by the planner: a tool name and its parameters.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ToolCall {
+    pub tool: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MouseButton {
+    #[default]
+    Left,
+    Right,
+    Middle,
+}
+
+impl From<MouseButton> for Button {
+    fn from(button: MouseButton) -> Self {
+        match button {
+            MouseButton::Left => Button::Left,
+            MouseButton::Right => Button::Right,
+            MouseButton::Middle => Button::Middle,
+        }
+    }
+}
+
+/// Built-in tools with their parameters resolved and defaults filled in.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "tool", content = "params", rename_all = "snake_case", deny_unknown_fields)]
+pub enum BuiltinTool {
+    MoveMouse { x: f64, y: f64 },
+    /// Clicks at `(x, y)`, or where the cursor is if no position is given.
+    Click {
+        #[serde(default)]
+        x: Option<f64>,
+        #[serde(default)]
+        y: Option<f64>,
+        #[serde(default)]
+        button: MouseButton,
+    },
+    /// Clicks the center of a template image found on screen.
+    ClickImage {
+        /// Path of the template, e.g. a PNG of the icon.
+        template: String,
+        #[serde(default = "default_image_threshold")]
+        threshold: f32,
+        #[serde(default)]
+        button: MouseButton,
+    },
+    /// Clicks an element from the UI map the plan was made with: through accessibility
+    /// when the element came from the accessibility tree, otherwise at its center.
+    ClickElement {
+        id: u32,
+        #[serde(default)]
+        button: MouseButton,
+    },
+    /// Replaces the text of a UI map field, or clicks it and types when it has no
+    /// accessible text.
+    SetElementText { id: u32, text: String },
+    /// Presses a key (rdev name, e.g. "Return" or "KeyS") while holding `modifiers`.
+    PressKey {
+        key: String,
+        #[serde(default)]
+        modifiers: Vec<String>,
+    },
+    TypeText { text: String },
+    /// Scrolls by wheel notches; negative `dy` scrolls down.
+    Scroll {
+        #[serde(default)]
+        dx: i64,
+        #[serde(default)]
+        dy: i64,
+    },
+    Wait { ms: u64 },
+    /// Waits until nothing on screen has changed for `ms`.
+    WaitForStable {
+        ms: u64,
+        #[serde(default = "default_screen_timeout")]
+        timeout_ms: u64,
+    },
+    /// Waits until something changes in the given screen area.
+    WaitForChange {
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        #[serde(default = "default_screen_timeout")]
+        timeout_ms: u64,
+    },
+    /// Raises and focuses the first window whose title or class contains `window`.
+    FocusWindow { window: String },
+    /// Moves and resizes a window, e.g. to put two windows side by side.
+    MoveWindow {
+        window: String,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+    },
+    MinimizeWindow { window: String },
+    CloseWindow { window: String },
+    /// Waits until a window whose title or class contains `window` appears.
+    WaitForWindow {
+        window: String,
+        #[serde(default = "default_screen_timeout")]
+        timeout_ms: u64,
+    },
+    PlayMacro { name: String },
+}
+
+fn default_screen_timeout() -> u64 {
+    10_000
+}
+
+fn default_image_threshold() -> f32 {
+    FindImageOptions::default().threshold
+}
+
+/// A plan step after resolution, with the input it would send.
+#[derive(Serialize, Debug, Clone)]
+pub struct PlannedCall {
+    pub step: usize,
+    pub tool: String,
+    pub params: Value,
+    pub transcript: Vec<String>,
+}
+
+fn parse_key(tool: &str, name: &str) -> Result<Key, ToolError> {
+    macro_engine::try_parse_key(name).ok_or_else(|| ToolError::InvalidCall {
+        tool: tool.to_string(),
+        message: format!("Unknown key '{}'", name),
+    })
+}
+
+impl ToolCall {
+    /// Resolves the call into a built-in tool, rejecting unknown tools and bad params.
+    pub fn resolve(&self) -> Result<BuiltinTool, ToolError> {
+        let params = if self.params.is_null() { json!({}) } else { self.params.clone() };
+        let tool: BuiltinTool = serde_json::from_value(json!({ "tool": self.tool, "params": params }))
+            .map_err(|e| ToolError::InvalidCall { tool: self.tool.clone(), message: e.to_string() })?;
+
+        if let BuiltinTool::Click { x, y, .. } = &tool {
+            if x.is_some() != y.is_some() {
+                return Err(ToolError::InvalidCall {
+                    tool: self.tool.clone(),
+                    message: "x and y must be given together".to_string(),
+                });
+            }
+        }
+        if let BuiltinTool::PressKey { key, modifiers } = &tool {
+            for name in modifiers.iter().chain(std::iter::once(key)) {
+                parse_key(&self.tool, name)?;
+            }
+        }
+        Ok(tool)
+    }
+}
+
+/// Loads saved macros by name for the `play_macro` tool.
+pub type MacroLoader = dyn Fn(&str) -> Result<Macro, MacroError> + Send + Sync;
+
+#[derive(Default)]
+pub struct Tooling {
+    /// Resolves `click_element` ids to screen positions.
+    ui_map: Option<UiMap>,
+}
 
 impl Tooling {
     pub fn new() -> Self {
-        Self
+        Self::default()
+    }
+
+    pub fn with_ui_map(mut self, ui_map: UiMap) -> Self {
+        self.ui_map = Some(ui_map);
+        self
+    }
+
+    fn element(&self, id: u32) -> Option<&UiElement> {
+        self.ui_map.as_ref().and_then(|map| map.element(id))
     }
+
+    /// Runs one resolved tool through `backend`.
+    pub fn execute(
+        &self,
+        tool: &BuiltinTool,
+        backend: &mut dyn InputBackend,
+        load_macro: &MacroLoader,
+    ) -> Result<(), ToolError> {
+        let name = tool_name(tool);
+        let failed = |message: String| ToolError::Execution { tool: name.clone(), message };
+
+        match tool {
+            BuiltinTool::MoveMouse { x, y } => backend.send_event(&EventType::MouseMove { x: *x, y: *y }).map_err(failed),
+            BuiltinTool::Click { x, y, button } => {
+                if let (Some(x), Some(y)) = (x, y) {
+                    backend.send_event(&EventType::MouseMove { x: *x, y: *y }).map_err(failed)?;
+                }
+                let button = Button::from(*button);
+                backend.send_event(&EventType::ButtonPress(button)).map_err(failed)?;
+                backend.send_event(&EventType::ButtonRelease(button)).map_err(failed)
+            }
+            BuiltinTool::ClickImage { template, threshold, button } => {
+                // Clicking wherever the cursor happens to be would hit the wrong thing.
+                let (x, y) = backend
+                    .find_image(Path::new(template), *threshold)
+                    .map_err(failed)?
+                    .ok_or_else(|| failed(format!("Cannot locate '{}' without seeing the screen", template)))?;
+                backend.send_event(&EventType::MouseMove { x, y }).map_err(failed)?;
+                let button = Button::from(*button);
+                backend.send_event(&EventType::ButtonPress(button)).map_err(failed)?;
+                backend.send_event(&EventType::ButtonRelease(button)).map_err(failed)
+            }
+            BuiltinTool::ClickElement { id, button } => {
+                let element = self.element(*id).ok_or_else(|| failed(format!("No element #{} in the UI map", id)))?;
+                if let (Some(target), MouseButton::Left) = (&element.accessible, button) {
+                    match backend.accessible_action(target, &AccessibleAction::Click) {
+                        Ok(()) => return Ok(()),
+                        Err(e) => log::warn!("Accessible click on #{} failed, clicking with the mouse: {}", id, e),
+                    }
+                }
+                let (x, y) = element.rect.center();
+                backend.send_event(&EventType::MouseMove { x, y }).map_err(failed)?;
+                let button = Button::from(*button);
+                backend.send_event(&EventType::ButtonPress(button)).map_err(failed)?;
+                backend.send_event(&EventType::ButtonRelease(button)).map_err(failed)
+            }
+            BuiltinTool::SetElementText { id, text } => {
+                let element = self.element(*id).ok_or_else(|| failed(format!("No element #{} in the UI map", id)))?;
+                if let Some(target) = &element.accessible {
+                    let action = AccessibleAction::SetText { text: text.clone() };
+                    match backend.accessible_action(target, &action) {
+                        Ok(()) => return Ok(()),
+                        Err(e) => log::warn!("Setting the text of #{} failed, typing instead: {}", id, e),
+                    }
+                }
+                let (x, y) = element.rect.center();
+                backend.send_event(&EventType::MouseMove { x, y }).map_err(failed)?;
+                backend.send_event(&EventType::ButtonPress(Button::Left)).map_err(failed)?;
+                backend.send_event(&EventType::ButtonRelease(Button::Left)).map_err(failed)?;
+                backend.type_text(text).map_err(failed)
+            }
+            BuiltinTool::PressKey { key, modifiers } => {
+                let modifiers = modifiers.iter().map(|m| parse_key(&name, m)).collect::<Result<Vec<_>, _>>()?;
+                let key = parse_key(&name, key)?;
+                let mut held = Vec::new();
+                let mut result = modifiers.iter().try_for_each(|modifier| {
+                    backend.send_event(&EventType::KeyPress(*modifier))?;
+                    held.push(*modifier);
+                    Ok(())
+                });
+                if result.is_ok() {
+                    result = backend
+                        .send_event(&EventType::KeyPress(key))
+                        .and_then(|()| backend.send_event(&EventType::KeyRelease(key)));
+                }
+                // Release the modifiers even after a failure, so none stay held.
+                for modifier in held.iter().rev() {
+                    let released = backend.send_event(&EventType::KeyRelease(*modifier));
+                    result = result.and(released);
+                }
+                result.map_err(failed)
+            }
+            BuiltinTool::TypeText { text } => backend.type_text(text).map_err(failed),
+            BuiltinTool::Scroll { dx, dy } => {
+                backend.send_event(&EventType::Wheel { delta_x: *dx, delta_y: *dy }).map_err(failed)
+            }
+            BuiltinTool::Wait { ms } => {
+                backend.wait(Duration::from_millis(*ms));
+                Ok(())
+            }
+            BuiltinTool::WaitForStable { ms, timeout_ms } => backend
+                .wait_for_screen(&ScreenWait::Stable { stable_ms: *ms, timeout_ms: *timeout_ms })
+                .map_err(failed),
+            BuiltinTool::WaitForChange { x, y, width, height, timeout_ms } => {
+                let rect = Rect { x: *x, y: *y, width: *width, height: *height };
+                backend.wait_for_screen(&ScreenWait::RegionChanges { rect, timeout_ms: *timeout_ms }).map_err(failed)
+            }
+            BuiltinTool::FocusWindow { window } => {
+                backend.window_action(&WindowAction::Focus { window: window.clone() }).map_err(failed)
+            }
+            BuiltinTool::MoveWindow { window, x, y, width, height } => {
+                let rect = Rect { x: *x, y: *y, width: *width, height: *height };
+                backend.window_action(&WindowAction::MoveResize { window: window.clone(), rect }).map_err(failed)
+            }
+            BuiltinTool::MinimizeWindow { window } => {
+                backend.window_action(&WindowAction::Minimize { window: window.clone() }).map_err(failed)
+            }
+            BuiltinTool::CloseWindow { window } => {
+                backend.window_action(&WindowAction::Close { window: window.clone() }).map_err(failed)
+            }
+            BuiltinTool::WaitForWindow { window, timeout_ms } => backend
+                .window_action(&WindowAction::WaitFor { window: window.clone(), timeout_ms: *timeout_ms })
+                .map_err(failed),
+            BuiltinTool::PlayMacro { name: macro_name } => {
+                let macro_data = load_macro(macro_name).map_err(|e| failed(e.to_string()))?;
+                macro_engine::play_macro_with(&macro_data, backend).map_err(|e| failed(e.to_string()))
+            }
+        }
+    }
+
+    /// Resolves every step up front, so a bad step fails the plan before any input is sent.
+    fn resolve_plan(&self, steps: &[ToolCall]) -> Result<Vec<BuiltinTool>, ToolError> {
+        let tools = steps.iter().map(ToolCall::resolve).collect::<Result<Vec<_>, _>>()?;
+        for tool in &tools {
+            if let BuiltinTool::ClickElement { id, .. } | BuiltinTool::SetElementText { id, .. } = tool {
+                if self.element(*id).is_none() {
+                    return Err(ToolError::InvalidCall {
+                        tool: tool_name(tool),
+                        message: format!("No element #{} in the UI map", id),
+                    });
+                }
+            }
+        }
+        Ok(tools)
+    }
+
+    /// Executes a plan through `backend`.
+    pub fn execute_plan(
+        &self,
+        steps: &[ToolCall],
+        backend: &mut dyn InputBackend,
+        load_macro: &MacroLoader,
+    ) -> Result<(), ToolError> {
+        for (index, tool) in self.resolve_plan(steps)?.iter().enumerate() {
+            log::info!("[Plan step {}/{}] {:?}", index + 1, steps.len(), tool);
+            self.execute(tool, backend, load_macro)?;
+        }
+        Ok(())
+    }
+
+    /// Lists the tool calls a plan would make, with resolved params and the input each
+    /// would send, without side effects.
+    pub fn dry_run_plan(&self, steps: &[ToolCall], load_macro: &MacroLoader) -> Result<Vec<PlannedCall>, ToolError> {
+        let mut backend = DryRunInput::new();
+        let mut planned = Vec::new();
+        for (index, tool) in self.resolve_plan(steps)?.iter().enumerate() {
+            let execution = self.execute(tool, &mut backend, load_macro);
+            let mut transcript = backend.take_transcript();
+            if let Err(e) = execution {
+                transcript.push(format!("error: {}", e));
+            }
+            let resolved = serde_json::to_value(tool).unwrap_or(Value::Null);
+            planned.push(PlannedCall {
+                step: index + 1,
+                tool: tool_name(tool),
+                params: resolved.get("params").cloned().unwrap_or(Value::Null),
+                transcript,
+            });
+        }
+        Ok(planned)
+    }
+}
+
+fn tool_name(tool: &BuiltinTool) -> String {
+    serde_json::to_value(tool)
+        .ok()
+        .and_then(|value| value.get("tool").and_then(Value::as_str).map(str::to_string))
+        .unwrap_or_default()
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::accessibility::AccessibleId;
+    use crate::modules::perception::UiRole;
+    use crate::modules::window::WindowContext;
+
+    fn call(tool: &str, params: Value) -> ToolCall {
+        ToolCall { tool: tool.to_string(), params }
+    }
+
+    #[test]
+    fn test_resolve_fills_defaults_and_rejects_bad_calls() {
+        assert_eq!(
+            call("click", json!({ "x": 812, "y": 440 })).resolve().unwrap(),
+            BuiltinTool::Click { x: Some(812.0), y: Some(440.0), button: MouseButton::Left }
+        );
+        assert!(call("click", json!({ "x": 1 })).resolve().is_err());
+        assert!(call("launch_rockets", json!({})).resolve().is_err());
+        assert!(call("press_key", json!({ "key": "Hyper" })).resolve().is_err());
+        assert!(call("type_text", json!({ "text": "hi", "speed": 3 })).resolve().is_err());
+    }
+
+    #[test]
+    fn test_dry_run_plan_lists_resolved_calls() {
+        let steps = vec![
+            call("click", json!({ "x": 812, "y": 440 })),
+            call("type_text", json!({ "text": "invoice" })),
+            call("wait", json!({ "ms": 500 })),
+            call("press_key", json!({ "key": "KeyS", "modifiers": ["ControlLeft"] })),
+            call("wait_for_stable", json!({ "ms": 300 })),
+            call("click_image", json!({ "template": "icons/export.png" })),
+            call("move_window", json!({ "window": "notes", "x": 0, "y": 0, "width": 960, "height": 1080 })),
+        ];
+        let no_macros = |name: &str| Err(MacroError::FileSystem(format!("No macro named '{}'", name)));
+        let planned = Tooling::new().dry_run_plan(&steps, &no_macros).unwrap();
+
+        assert_eq!(planned.len(), 7);
+        assert_eq!(planned[0].params, json!({ "x": 812.0, "y": 440.0, "button": "left" }));
+        assert_eq!(planned[0].transcript, vec!["t=0.00s click left at (812, 440)"]);
+        assert_eq!(planned[1].transcript, vec!["t=0.00s type 'invoice'"]);
+        assert!(planned[2].transcript.is_empty());
+        assert_eq!(
+            planned[3].transcript,
+            vec!["t=0.50s press key ControlLeft", "t=0.50s key KeyS", "t=0.50s release key ControlLeft"]
+        );
+        assert_eq!(
+            planned[4].transcript,
+            vec!["t=0.50s wait until the screen is stable for 300ms (timeout 10000ms)"]
+        );
+        assert_eq!(
+            planned[5].transcript,
+            vec![
+                "t=0.50s find image 'icons/export.png' (threshold 0.90)",
+                "error: Tool 'click_image' failed: Cannot locate 'icons/export.png' without seeing the screen",
+            ]
+        );
+        assert_eq!(planned[6].transcript, vec!["t=0.50s move window 'notes' to (0, 0) 960x1080"]);
+    }
+
+    #[test]
+    fn test_click_element_resolves_through_the_ui_map() {
+        let ui_map = UiMap {
+            width: 480,
+            height: 220,
+            elements: vec![
+                UiElement {
+                    id: 6,
+                    role: UiRole::Button,
+                    text: "Save as".to_string(),
+                    rect: Rect { x: 250, y: 165, width: 100, height: 34 },
+                    accessible: None,
+                },
+                UiElement {
+                    id: 3,
+                    role: UiRole::TextField,
+                    text: String::new(),
+                    rect: Rect { x: 105, y: 51, width: 158, height: 28 },
+                    accessible: Some(AccessibleId { bus: ":1.42".to_string(), path: "/field".to_string() }),
+                },
+            ],
+        };
+        let no_macros = |name: &str| Err(MacroError::FileSystem(format!("No macro named '{}'", name)));
+        let steps = vec![call("click_element", json!({ "id": 6 }))];
+
+        let planned = Tooling::new().with_ui_map(ui_map.clone()).dry_run_plan(&steps, &no_macros).unwrap();
+        assert_eq!(planned[0].transcript, vec!["t=0.00s click left at (300, 182)"]);
+
+        let accessible = vec![
+            call("set_element_text", json!({ "id": 3, "text": "Acme Corp" })),
+            call("click_element", json!({ "id": 3 })),
+        ];
+        let planned = Tooling::new().with_ui_map(ui_map.clone()).dry_run_plan(&accessible, &no_macros).unwrap();
+        assert_eq!(planned[0].transcript, vec!["t=0.00s set text 'Acme Corp' of :1.42/field via accessibility"]);
+        assert_eq!(planned[1].transcript, vec!["t=0.00s click :1.42/field via accessibility"]);
+        assert!(Tooling::new().with_ui_map(ui_map).dry_run_plan(&[call("click_element", json!({ "id": 7 }))], &no_macros).is_err());
+        assert!(Tooling::new().dry_run_plan(&steps, &no_macros).is_err());
+    }
+
+    /// Records sent events and fails to press `failing_key`.
+    struct FailingKeyboard {
+        failing_key: Key,
+        sent: Vec<EventType>,
+    }
+
+    impl InputBackend for FailingKeyboard {
+        fn send_event(&mut self, event_type: &EventType) -> Result<(), String> {
+            if *event_type == EventType::KeyPress(self.failing_key) {
+                return Err("keyboard unplugged".to_string());
+            }
+            self.sent.push(*event_type);
+            Ok(())
+        }
+        fn type_text(&mut self, _text: &str) -> Result<(), String> {
+            Ok(())
+        }
+        fn type_secret(&mut self, _macro_name: &str, _secret_id: &str) -> Result<(), String> {
+            Ok(())
+        }
+        fn wait(&mut self, _duration: Duration) {}
+        fn wait_for_screen(&mut self, _wait: &ScreenWait) -> Result<(), String> {
+            Ok(())
+        }
+        fn find_image(&mut self, _template: &Path, _threshold: f32) -> Result<Option<(f64, f64)>, String> {
+            Ok(None)
+        }
+        fn accessible_action(&mut self, _target: &AccessibleId, _action: &AccessibleAction) -> Result<(), String> {
+            Ok(())
+        }
+        fn window_action(&mut self, _action: &WindowAction) -> Result<(), String> {
+            Ok(())
+        }
+        fn active_window(&mut self) -> Result<Option<WindowContext>, String> {
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn test_press_key_releases_modifiers_when_a_press_fails() {
+        let no_macros = |name: &str| Err(MacroError::FileSystem(format!("No macro named '{}'", name)));
+        let tool = call("press_key", json!({ "key": "KeyS", "modifiers": ["ControlLeft", "ShiftLeft", "Alt"] }))
+            .resolve()
+            .unwrap();
+
+        for failing_key in [Key::KeyS, Key::Alt] {
+            let mut backend = FailingKeyboard { failing_key, sent: Vec::new() };
+            assert!(Tooling::new().execute(&tool, &mut backend, &no_macros).is_err());
+            let released: Vec<EventType> = backend.sent.iter().filter(|e| matches!(e, EventType::KeyRelease(_))).copied().collect();
+            let mut expected = vec![EventType::KeyRelease(Key::ShiftLeft), EventType::KeyRelease(Key::ControlLeft)];
+            if failing_key == Key::KeyS {
+                expected.insert(0, EventType::KeyRelease(Key::Alt));
+            }
+            assert_eq!(released, expected);
+        }
+    }
+}
@@ -0,0 +1,813 @@
+// Voice module
+// Turns speech into task text: audio from the microphone (cpal) or a WAV file is cut into
+// utterances by an energy-based voice activity detector and transcribed by a local
+// whisper.cpp model. Listening ends after a pause in speech or a timeout.
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SizedSample};
+use crate::modules::macro_engine;
+use crate::modules::persistence::{self, PersistenceError};
+use crate::modules::speech::SpeechSettings;
+use rdev::Key;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    time::Duration,
+};
+use tauri::Manager;
+use thiserror::Error;
+use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters, WhisperState};
+
+/// Whisper models take 16 kHz mono audio; every source is converted to it.
+pub const SAMPLE_RATE: u32 = 16_000;
+/// Model file looked up in the app config's `nyx-agent/models` folder, and moved there
+/// from the `models` folder it used to be looked up in.
+pub const DEFAULT_MODEL: &str = "ggml-base.en.bin";
+
+/// The detector looks at 30 ms frames.
+const FRAME_LEN: usize = 480;
+/// Frames quieter than this are never speech, however quiet the room is.
+const MIN_SPEECH_RMS: f32 = 0.01;
+/// Speech must be this many times louder than the background noise.
+const SPEECH_TO_NOISE: f32 = 3.0;
+/// Consecutive loud frames that start an utterance (90 ms), so clicks and bumps do not.
+const START_FRAMES: usize = 3;
+/// Quiet frames that end an utterance (810 ms).
+const END_FRAMES: usize = 27;
+/// Audio kept from before an utterance starts, so its first syllable is not cut off.
+const PRE_ROLL: usize = SAMPLE_RATE as usize * 3 / 10;
+/// Utterances are cut at 30 s, the longest audio whisper transcribes in one pass.
+const MAX_UTTERANCE: usize = SAMPLE_RATE as usize * 30;
+/// New speech between partial transcripts.
+const PARTIAL_INTERVAL: usize = SAMPLE_RATE as usize;
+/// Whisper rejects audio shorter than a second, so shorter utterances are padded.
+const MIN_TRANSCRIBE_LEN: usize = SAMPLE_RATE as usize + SAMPLE_RATE as usize / 10;
+/// Samples a file source returns per call (100 ms).
+const FILE_BLOCK_LEN: usize = 1600;
+/// How long the microphone source waits for audio before letting the caller check for stop.
+const MICROPHONE_POLL: Duration = Duration::from_millis(100);
+
+#[derive(Error, Debug)]
+pub enum VoiceError {
+    #[error("No microphone found")]
+    NoInputDevice,
+    #[error("Audio device error: {0}")]
+    Device(String),
+    #[error("Cannot read audio file: {0}")]
+    AudioFile(String),
+    #[error("Cannot load speech model {0}: {1}")]
+    Model(String, String),
+    #[error("Transcription failed: {0}")]
+    Transcription(String),
+    #[error("Voice settings error: {0}")]
+    Settings(String),
+}
+
+/// How listening is started and answered, saved in the app config as `nyx-agent/voice.json`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct VoiceSettings {
+    /// Key held down to talk, named as in macro files, e.g. "F9".
+    pub push_to_talk_key: Option<String>,
+    /// Listen for the trained wake word whenever the agent is idle.
+    pub wake_word: bool,
+    /// Whisper model to use instead of the default one.
+    pub whisper_model: Option<PathBuf>,
+    /// How results of spoken commands are read back.
+    pub speech: SpeechSettings,
+}
+
+impl VoiceSettings {
+    pub fn push_to_talk_key(&self) -> Option<Key> {
+        self.push_to_talk_key.as_deref().and_then(macro_engine::try_parse_key)
+    }
+
+    pub fn validate(&self) -> Result<(), VoiceError> {
+        match &self.push_to_talk_key {
+            Some(name) if self.push_to_talk_key().is_none() => {
+                Err(VoiceError::Settings(format!("Unknown push-to-talk key '{}'", name)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Loads the saved settings, or the defaults if there are none or they are unreadable.
+    pub fn load(app_handle: &tauri::AppHandle) -> Self {
+        let path = match config_path(app_handle, "voice.json") {
+            Ok(path) => path,
+            Err(e) => {
+                log::error!("Failed to load voice settings: {}", e);
+                return Self::default();
+            }
+        };
+        match persistence::read_json::<Self>(&path) {
+            Ok(loaded) => {
+                if let Some(warning) = &loaded.recovery {
+                    persistence::report_recovery(app_handle, warning);
+                }
+                loaded.value
+            }
+            Err(PersistenceError::NotFound(_)) => Self::default(),
+            Err(e) => {
+                log::error!("Failed to load voice settings: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, app_handle: &tauri::AppHandle) -> Result<(), VoiceError> {
+        self.validate()?;
+        persistence::write_json(&config_path(app_handle, "voice.json")?, self)
+            .map_err(|e| VoiceError::Settings(e.to_string()))
+    }
+
+    /// The chosen whisper model, or the default one.
+    pub fn whisper_model_path(&self, app_handle: &tauri::AppHandle) -> Result<PathBuf, VoiceError> {
+        match &self.whisper_model {
+            Some(path) => Ok(path.clone()),
+            None => default_model_path(app_handle),
+        }
+    }
+}
+
+/// Where spoken commands come from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum VoiceInput {
+    #[default]
+    Microphone,
+    /// A WAV file, played through the pipeline as if it were spoken.
+    File { path: PathBuf },
+}
+
+/// Audio converted to 16 kHz mono.
+pub trait AudioSource {
+    /// The next block of samples, which may be empty, or `None` once the source has ended.
+    fn next_samples(&mut self) -> Result<Option<Vec<f32>>, VoiceError>;
+
+    /// Drops audio that arrived while nobody was reading it, e.g. during a task.
+    fn skip_pending(&mut self) {}
+
+    /// Takes the audio that has already arrived, without waiting for more.
+    fn drain_pending(&mut self) -> Vec<f32> {
+        Vec::new()
+    }
+}
+
+/// Ends the wrapped source once `held` is cleared, e.g. when the push-to-talk key is
+/// released, so the utterance so far is transcribed. Audio buffered before the release,
+/// such as while the model was loading, is still read.
+pub struct HoldSource {
+    source: Box<dyn AudioSource>,
+    held: Arc<AtomicBool>,
+    drained: bool,
+}
+
+impl HoldSource {
+    pub fn new(source: Box<dyn AudioSource>, held: Arc<AtomicBool>) -> Self {
+        Self { source, held, drained: false }
+    }
+}
+
+impl AudioSource for HoldSource {
+    fn next_samples(&mut self) -> Result<Option<Vec<f32>>, VoiceError> {
+        if self.held.load(Ordering::SeqCst) {
+            return self.source.next_samples();
+        }
+        if self.drained {
+            return Ok(None);
+        }
+        self.drained = true;
+        Ok(Some(self.source.drain_pending()))
+    }
+
+    fn skip_pending(&mut self) {
+        self.source.skip_pending();
+    }
+}
+
+/// Opens the microphone or the audio file. The microphone stream must stay on the
+/// thread that opened it.
+pub fn open_source(input: &VoiceInput) -> Result<Box<dyn AudioSource>, VoiceError> {
+    match input {
+        VoiceInput::Microphone => Ok(Box::new(MicrophoneSource::open()?)),
+        VoiceInput::File { path } => Ok(Box::new(FileSource::open(path)?)),
+    }
+}
+
+/// Downmixes interleaved audio and converts its rate by linear interpolation, carrying
+/// its position across blocks.
+struct Resampler {
+    channels: usize,
+    /// Input samples per output sample.
+    step: f64,
+    /// Position of the next output sample, relative to `previous`.
+    position: f64,
+    previous: Option<f32>,
+}
+
+impl Resampler {
+    /// Converts to `SAMPLE_RATE`.
+    fn new(channels: u16, sample_rate: u32) -> Self {
+        Self::between(channels, sample_rate, SAMPLE_RATE)
+    }
+
+    fn between(channels: u16, from: u32, to: u32) -> Self {
+        Self { channels: channels.max(1) as usize, step: from as f64 / to as f64, position: 0.0, previous: None }
+    }
+
+    fn process(&mut self, interleaved: &[f32]) -> Vec<f32> {
+        let mut input: Vec<f32> = self.previous.into_iter().collect();
+        input.extend(interleaved.chunks(self.channels).map(|frame| frame.iter().sum::<f32>() / frame.len() as f32));
+        let mut output = Vec::new();
+        while self.position + 1.0 < input.len() as f64 {
+            let index = self.position as usize;
+            let fraction = (self.position - index as f64) as f32;
+            output.push(input[index] + (input[index + 1] - input[index]) * fraction);
+            self.position += self.step;
+        }
+        if let Some(last) = input.last() {
+            self.position -= (input.len() - 1) as f64;
+            self.previous = Some(*last);
+        }
+        output
+    }
+}
+
+/// Records from the default input device.
+pub struct MicrophoneSource {
+    _stream: cpal::Stream,
+    receiver: mpsc::Receiver<Vec<f32>>,
+    resampler: Resampler,
+}
+
+fn build_input_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    sender: mpsc::Sender<Vec<f32>>,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            // The receiver is gone once listening stops; the stream is dropped right after.
+            let _ = sender.send(data.iter().map(|sample| sample.to_sample::<f32>()).collect());
+        },
+        |e| log::error!("Microphone stream error: {}", e),
+        None,
+    )
+}
+
+impl MicrophoneSource {
+    pub fn open() -> Result<Self, VoiceError> {
+        let device = cpal::default_host().default_input_device().ok_or(VoiceError::NoInputDevice)?;
+        let supported = device.default_input_config().map_err(|e| VoiceError::Device(e.to_string()))?;
+        let config = supported.config();
+        log::info!(
+            "Listening on '{}' ({} Hz, {} channel(s), {:?})",
+            device.name().unwrap_or_else(|_| "unknown device".to_string()),
+            config.sample_rate.0,
+            config.channels,
+            supported.sample_format()
+        );
+
+        let (sender, receiver) = mpsc::channel();
+        let stream = match supported.sample_format() {
+            cpal::SampleFormat::I8 => build_input_stream::<i8>(&device, &config, sender),
+            cpal::SampleFormat::I16 => build_input_stream::<i16>(&device, &config, sender),
+            cpal::SampleFormat::I32 => build_input_stream::<i32>(&device, &config, sender),
+            cpal::SampleFormat::U8 => build_input_stream::<u8>(&device, &config, sender),
+            cpal::SampleFormat::U16 => build_input_stream::<u16>(&device, &config, sender),
+            cpal::SampleFormat::F32 => build_input_stream::<f32>(&device, &config, sender),
+            format => return Err(VoiceError::Device(format!("Unsupported sample format {}", format))),
+        }
+        .map_err(|e| VoiceError::Device(e.to_string()))?;
+        stream.play().map_err(|e| VoiceError::Device(e.to_string()))?;
+
+        Ok(Self { _stream: stream, receiver, resampler: Resampler::new(config.channels, config.sample_rate.0) })
+    }
+}
+
+impl AudioSource for MicrophoneSource {
+    fn next_samples(&mut self) -> Result<Option<Vec<f32>>, VoiceError> {
+        match self.receiver.recv_timeout(MICROPHONE_POLL) {
+            Ok(block) => Ok(Some(self.resampler.process(&block))),
+            Err(RecvTimeoutError::Timeout) => Ok(Some(Vec::new())),
+            Err(RecvTimeoutError::Disconnected) => Err(VoiceError::Device("The microphone stream stopped".to_string())),
+        }
+    }
+
+    fn skip_pending(&mut self) {
+        while self.receiver.try_recv().is_ok() {}
+    }
+
+    fn drain_pending(&mut self) -> Vec<f32> {
+        let mut samples = Vec::new();
+        while let Ok(block) = self.receiver.try_recv() {
+            samples.extend(self.resampler.process(&block));
+        }
+        samples
+    }
+}
+
+/// Converts mono audio from one sample rate to another.
+pub(crate) fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    Resampler::between(1, from, to).process(samples)
+}
+
+/// Reads a whole WAV file as 16 kHz mono.
+pub fn read_wav(path: &Path) -> Result<Vec<f32>, VoiceError> {
+    Ok(FileSource::open(path)?.samples)
+}
+
+/// Reads a WAV file, as fast as the pipeline consumes it.
+pub struct FileSource {
+    samples: Vec<f32>,
+    position: usize,
+}
+
+impl FileSource {
+    pub fn open(path: &Path) -> Result<Self, VoiceError> {
+        let error = |e: hound::Error| VoiceError::AudioFile(format!("{}: {}", path.display(), e));
+        let mut reader = hound::WavReader::open(path).map_err(error)?;
+        let spec = reader.spec();
+        let interleaved: Vec<f32> = match spec.sample_format {
+            hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>().map_err(error)?,
+            hound::SampleFormat::Int => {
+                let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|sample| sample.map(|value| value as f32 / scale))
+                    .collect::<Result<_, _>>()
+                    .map_err(error)?
+            }
+        };
+        let samples = Resampler::new(spec.channels, spec.sample_rate).process(&interleaved);
+        Ok(Self { samples, position: 0 })
+    }
+}
+
+impl AudioSource for FileSource {
+    fn next_samples(&mut self) -> Result<Option<Vec<f32>>, VoiceError> {
+        if self.position >= self.samples.len() {
+            return Ok(None);
+        }
+        let end = (self.position + FILE_BLOCK_LEN).min(self.samples.len());
+        let block = self.samples[self.position..end].to_vec();
+        self.position = end;
+        Ok(Some(block))
+    }
+}
+
+/// Something the detector noticed in the audio it was fed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VadEvent {
+    SpeechStarted,
+    /// The whole utterance, including a little audio from before it started.
+    SpeechEnded(Vec<f32>),
+}
+
+/// Finds utterances by loudness against a running estimate of the background noise.
+#[derive(Default)]
+pub struct VoiceActivityDetector {
+    noise_floor: Option<f32>,
+    /// Samples not yet making up a whole frame.
+    pending: Vec<f32>,
+    pre_roll: VecDeque<f32>,
+    loud_frames: usize,
+    quiet_frames: usize,
+    speaking: bool,
+    utterance: Vec<f32>,
+}
+
+fn rms(frame: &[f32]) -> f32 {
+    (frame.iter().map(|sample| sample * sample).sum::<f32>() / frame.len() as f32).sqrt()
+}
+
+impl VoiceActivityDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_speaking(&self) -> bool {
+        self.speaking
+    }
+
+    /// The current utterance so far; empty between utterances.
+    pub fn utterance(&self) -> &[f32] {
+        &self.utterance
+    }
+
+    pub fn push(&mut self, samples: &[f32]) -> Vec<VadEvent> {
+        self.pending.extend_from_slice(samples);
+        let frames: Vec<Vec<f32>> = self.pending.chunks_exact(FRAME_LEN).map(<[f32]>::to_vec).collect();
+        self.pending.drain(..frames.len() * FRAME_LEN);
+        frames.iter().filter_map(|frame| self.push_frame(frame)).collect()
+    }
+
+    fn push_frame(&mut self, frame: &[f32]) -> Option<VadEvent> {
+        let level = rms(frame);
+        let floor = *self.noise_floor.get_or_insert(level);
+        let loud = level > MIN_SPEECH_RMS.max(floor * SPEECH_TO_NOISE);
+        if !loud {
+            // Slowly follow the background noise while nobody speaks.
+            self.noise_floor = Some(floor * 0.95 + level * 0.05);
+        }
+
+        if !self.speaking {
+            self.pre_roll.extend(frame);
+            let excess = self.pre_roll.len().saturating_sub(PRE_ROLL);
+            self.pre_roll.drain(..excess);
+            self.loud_frames = if loud { self.loud_frames + 1 } else { 0 };
+            if self.loud_frames < START_FRAMES {
+                return None;
+            }
+            self.speaking = true;
+            self.quiet_frames = 0;
+            self.utterance = self.pre_roll.drain(..).collect();
+            return Some(VadEvent::SpeechStarted);
+        }
+
+        self.utterance.extend_from_slice(frame);
+        self.quiet_frames = if loud { 0 } else { self.quiet_frames + 1 };
+        (self.quiet_frames >= END_FRAMES || self.utterance.len() >= MAX_UTTERANCE).then(|| self.end_utterance())
+    }
+
+    fn end_utterance(&mut self) -> VadEvent {
+        self.speaking = false;
+        self.loud_frames = 0;
+        VadEvent::SpeechEnded(std::mem::take(&mut self.utterance))
+    }
+
+    /// Ends the current utterance when the audio runs out mid-speech.
+    pub fn finish(&mut self) -> Option<VadEvent> {
+        self.speaking.then(|| self.end_utterance())
+    }
+}
+
+pub trait Transcriber {
+    /// Transcribes 16 kHz mono audio.
+    fn transcribe(&mut self, samples: &[f32]) -> Result<String, VoiceError>;
+}
+
+/// Transcribes with a whisper.cpp model file, e.g. `ggml-base.en.bin`.
+pub struct WhisperTranscriber {
+    state: WhisperState,
+}
+
+impl WhisperTranscriber {
+    pub fn new(model: &Path) -> Result<Self, VoiceError> {
+        let name = model.display().to_string();
+        let path = model.to_str().ok_or_else(|| VoiceError::Model(name.clone(), "path is not UTF-8".to_string()))?;
+        let context = WhisperContext::new_with_params(path, WhisperContextParameters::default())
+            .map_err(|e| VoiceError::Model(name.clone(), e.to_string()))?;
+        let state = context.create_state().map_err(|e| VoiceError::Model(name, e.to_string()))?;
+        Ok(Self { state })
+    }
+}
+
+impl Transcriber for WhisperTranscriber {
+    fn transcribe(&mut self, samples: &[f32]) -> Result<String, VoiceError> {
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        params.set_print_special(false);
+        params.set_print_progress(false);
+        params.set_print_realtime(false);
+        params.set_print_timestamps(false);
+        params.set_no_context(true);
+        params.set_suppress_blank(true);
+
+        let mut audio = samples.to_vec();
+        if audio.len() < MIN_TRANSCRIBE_LEN {
+            audio.resize(MIN_TRANSCRIBE_LEN, 0.0);
+        }
+        let error = |e: whisper_rs::WhisperError| VoiceError::Transcription(e.to_string());
+        self.state.full(params, &audio).map_err(error)?;
+        let mut text = String::new();
+        for segment in 0..self.state.full_n_segments().map_err(error)? {
+            text.push_str(&self.state.full_get_segment_text_lossy(segment).map_err(error)?);
+        }
+        Ok(clean_transcript(&text))
+    }
+}
+
+/// Drops the annotations whisper writes for non-speech, e.g. "[BLANK_AUDIO]" or "(wind blowing)".
+fn clean_transcript(text: &str) -> String {
+    let mut cleaned = String::new();
+    let mut closing: Option<char> = None;
+    for c in text.chars() {
+        match (closing, c) {
+            (None, '[') => closing = Some(']'),
+            (None, '(') => closing = Some(')'),
+            (Some(close), c) if c == close => closing = None,
+            (None, c) => cleaned.push(c),
+            _ => {}
+        }
+    }
+    cleaned.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn config_dir(app_handle: &tauri::AppHandle) -> Result<PathBuf, VoiceError> {
+    app_handle.path().app_config_dir().map_err(|e| VoiceError::Settings(format!("No config directory: {}", e)))
+}
+
+/// Path of `name` in the app config's `nyx-agent` folder.
+fn config_path(app_handle: &tauri::AppHandle, name: &str) -> Result<PathBuf, VoiceError> {
+    Ok(config_dir(app_handle)?.join("nyx-agent").join(name))
+}
+
+/// Path of the default whisper model, in the app config's `nyx-agent/models` folder. A
+/// model still in the app config's `models` folder is moved there first.
+pub fn default_model_path(app_handle: &tauri::AppHandle) -> Result<PathBuf, VoiceError> {
+    let path = config_path(app_handle, &format!("models/{}", DEFAULT_MODEL))?;
+    let legacy = config_dir(app_handle)?.join("models").join(DEFAULT_MODEL);
+    Ok(migrate_model(&legacy, path))
+}
+
+/// Moves a model from `legacy` to `path` unless `path` already exists. When the move
+/// fails, the model is used where it is.
+fn migrate_model(legacy: &Path, path: PathBuf) -> PathBuf {
+    if path.exists() || !legacy.exists() {
+        return path;
+    }
+    let moved = path.parent().map_or(Ok(()), std::fs::create_dir_all).and_then(|()| std::fs::rename(legacy, &path));
+    match moved {
+        Ok(()) => {
+            log::info!("Moved the whisper model from {} to {}", legacy.display(), path.display());
+            path
+        }
+        Err(e) => {
+            log::warn!("Using the whisper model at {}, as it could not be moved: {}", legacy.display(), e);
+            legacy.to_path_buf()
+        }
+    }
+}
+
+/// Path of the trained wake word model, next to the whisper models.
+pub fn wake_word_model_path(app_handle: &tauri::AppHandle) -> Result<PathBuf, VoiceError> {
+    config_path(app_handle, "models/wake_word.json")
+}
+
+/// How long `listen` waits for speech to start, and how long it listens in all.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ListenTimeouts {
+    pub no_speech: Duration,
+    pub total: Duration,
+}
+
+impl Default for ListenTimeouts {
+    fn default() -> Self {
+        Self { no_speech: Duration::from_secs(5), total: Duration::from_secs(30) }
+    }
+}
+
+fn samples_in(duration: Duration) -> usize {
+    (duration.as_secs_f64() * SAMPLE_RATE as f64) as usize
+}
+
+/// Transcripts produced while listening, in order.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", content = "text", rename_all = "snake_case")]
+pub enum TranscriptEvent {
+    /// The utterance so far, while it is still being spoken.
+    Partial(String),
+    /// A whole utterance, once the speaker paused.
+    Final(String),
+}
+
+/// Transcribes an utterance and reports it as final, unless it transcribes to nothing.
+fn finish_utterance(
+    utterance: &[f32],
+    transcriber: &mut dyn Transcriber,
+    on_transcript: &mut dyn FnMut(TranscriptEvent),
+) -> Result<(), VoiceError> {
+    let text = transcriber.transcribe(utterance)?;
+    if !text.is_empty() {
+        on_transcript(TranscriptEvent::Final(text));
+    }
+    Ok(())
+}
+
+/// Feeds `source` through the detector and `transcriber` until it ends, `should_stop`
+/// returns true or a timeout passes, reporting a partial transcript for every second of
+/// speech and a final one per utterance.
+pub fn listen(
+    source: &mut dyn AudioSource,
+    transcriber: &mut dyn Transcriber,
+    should_stop: &dyn Fn() -> bool,
+    timeouts: &ListenTimeouts,
+    on_transcript: &mut dyn FnMut(TranscriptEvent),
+) -> Result<(), VoiceError> {
+    let mut detector = VoiceActivityDetector::new();
+    // Utterance length at which the next partial transcript is due.
+    let mut partial_at = PARTIAL_INTERVAL;
+    let mut heard = 0;
+    let mut spoken = false;
+
+    while !should_stop() {
+        let Some(samples) = source.next_samples()? else {
+            if let Some(VadEvent::SpeechEnded(utterance)) = detector.finish() {
+                finish_utterance(&utterance, transcriber, on_transcript)?;
+            }
+            break;
+        };
+        heard += samples.len();
+        for event in detector.push(&samples) {
+            match event {
+                VadEvent::SpeechStarted => {
+                    spoken = true;
+                    partial_at = PARTIAL_INTERVAL;
+                }
+                VadEvent::SpeechEnded(utterance) => finish_utterance(&utterance, transcriber, on_transcript)?,
+            }
+            // The listener may stop after a final transcript.
+            if should_stop() {
+                return Ok(());
+            }
+        }
+        if detector.is_speaking() && detector.utterance().len() >= partial_at {
+            partial_at = detector.utterance().len() + PARTIAL_INTERVAL;
+            let text = transcriber.transcribe(detector.utterance())?;
+            if !text.is_empty() {
+                on_transcript(TranscriptEvent::Partial(text));
+            }
+        }
+
+        if !spoken && heard >= samples_in(timeouts.no_speech) {
+            log::info!("No speech within {:?}, stopped listening", timeouts.no_speech);
+            break;
+        }
+        if heard >= samples_in(timeouts.total) {
+            log::info!("Stopped listening after {:?}", timeouts.total);
+            if let Some(VadEvent::SpeechEnded(utterance)) = detector.finish() {
+                finish_utterance(&utterance, transcriber, on_transcript)?;
+            }
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reports how long each utterance was, in milliseconds.
+    struct LengthTranscriber;
+
+    impl Transcriber for LengthTranscriber {
+        fn transcribe(&mut self, samples: &[f32]) -> Result<String, VoiceError> {
+            Ok(format!("{}", samples.len() * 1000 / SAMPLE_RATE as usize))
+        }
+    }
+
+    /// Writes 48 kHz stereo 16-bit audio: silence, except for a 220 Hz tone during the
+    /// given (start, end) seconds.
+    fn write_wav(name: &str, seconds: f32, tones: &[(f32, f32)]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("nyx-voice-{}-{}.wav", name, std::process::id()));
+        let spec = hound::WavSpec { channels: 2, sample_rate: 48_000, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for index in 0..(seconds * 48_000.0) as usize {
+            let t = index as f32 / 48_000.0;
+            let loud = tones.iter().any(|(start, end)| (*start..*end).contains(&t));
+            let value = if loud { (t * 220.0 * std::f32::consts::TAU).sin() * 0.3 } else { 0.0 };
+            for _ in 0..2 {
+                writer.write_sample((value * i16::MAX as f32) as i16).unwrap();
+            }
+        }
+        writer.finalize().unwrap();
+        path
+    }
+
+    #[test]
+    fn test_file_pipeline_finds_utterances() {
+        let path = write_wav("utterances", 5.0, &[(0.5, 2.0), (3.2, 3.8)]);
+        let mut source = FileSource::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut transcripts = Vec::new();
+        let timeouts = ListenTimeouts::default();
+        listen(&mut source, &mut LengthTranscriber, &|| false, &timeouts, &mut |event| {
+            transcripts.push(event)
+        })
+        .unwrap();
+
+        let kinds: Vec<&str> = transcripts
+            .iter()
+            .map(|event| match event {
+                TranscriptEvent::Partial(_) => "partial",
+                TranscriptEvent::Final(_) => "final",
+            })
+            .collect();
+        assert_eq!(kinds, ["partial", "partial", "final", "partial", "final"]);
+        let finals: Vec<u32> = transcripts
+            .iter()
+            .filter_map(|event| match event {
+                TranscriptEvent::Final(millis) => millis.parse().ok(),
+                _ => None,
+            })
+            .collect();
+        // Each tone plus the pre-roll before it and the pause that ended it.
+        assert!((2400..2700).contains(&finals[0]), "{:?}", finals);
+        assert!((1500..1800).contains(&finals[1]), "{:?}", finals);
+    }
+
+    #[test]
+    fn test_listening_times_out() {
+        let path = write_wav("timeouts", 8.0, &[(3.0, 7.0)]);
+        let mut source = FileSource::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut finals = Vec::new();
+        let mut listen_with = |source: &mut FileSource, timeouts: ListenTimeouts| {
+            source.position = 0;
+            listen(source, &mut LengthTranscriber, &|| false, &timeouts, &mut |event| {
+                if let TranscriptEvent::Final(millis) = event {
+                    finals.push(millis);
+                }
+            })
+            .unwrap();
+            source.position * 1000 / SAMPLE_RATE as usize
+        };
+
+        // Nobody speaks in the first two seconds.
+        let no_speech = ListenTimeouts { no_speech: Duration::from_secs(2), total: Duration::from_secs(30) };
+        assert_eq!(listen_with(&mut source, no_speech), 2000);
+        // Speech that runs past the total timeout is cut there and still transcribed.
+        let total = ListenTimeouts { no_speech: Duration::from_secs(5), total: Duration::from_secs(5) };
+        assert_eq!(listen_with(&mut source, total), 5000);
+        assert_eq!(finals.len(), 1);
+    }
+
+    /// Has `pending` blocks buffered, then waits forever.
+    struct BufferedSource {
+        pending: Vec<Vec<f32>>,
+    }
+
+    impl AudioSource for BufferedSource {
+        fn next_samples(&mut self) -> Result<Option<Vec<f32>>, VoiceError> {
+            Ok(Some(if self.pending.is_empty() { Vec::new() } else { self.pending.remove(0) }))
+        }
+
+        fn drain_pending(&mut self) -> Vec<f32> {
+            self.pending.drain(..).flatten().collect()
+        }
+    }
+
+    #[test]
+    fn test_hold_source_reads_buffered_audio_after_release() {
+        let held = Arc::new(AtomicBool::new(true));
+        let source = BufferedSource { pending: vec![vec![0.1; 3], vec![0.2; 2], vec![0.3; 4]] };
+        let mut source = HoldSource::new(Box::new(source), held.clone());
+        assert_eq!(source.next_samples().unwrap(), Some(vec![0.1; 3]));
+
+        held.store(false, Ordering::SeqCst);
+        assert_eq!(source.next_samples().unwrap().map(|samples| samples.len()), Some(6));
+        assert_eq!(source.next_samples().unwrap(), None);
+    }
+
+    #[test]
+    fn test_model_is_moved_from_old_folder() {
+        let dir = std::env::temp_dir().join(format!("nyx-voice-models-{}", std::process::id()));
+        let legacy = dir.join("models").join(DEFAULT_MODEL);
+        let path = dir.join("nyx-agent").join("models").join(DEFAULT_MODEL);
+        std::fs::create_dir_all(legacy.parent().unwrap()).unwrap();
+        std::fs::write(&legacy, b"model").unwrap();
+
+        assert_eq!(migrate_model(&legacy, path.clone()), path);
+        assert_eq!(std::fs::read(&path).unwrap(), b"model");
+        assert!(!legacy.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_push_to_talk_key_setting() {
+        let settings = VoiceSettings { push_to_talk_key: Some("F9".to_string()), ..Default::default() };
+        assert_eq!(settings.push_to_talk_key(), Some(Key::F9));
+        let settings = VoiceSettings { push_to_talk_key: Some("Hyper".to_string()), ..Default::default() };
+        assert!(matches!(settings.validate(), Err(VoiceError::Settings(_))));
+    }
+
+    #[test]
+    fn test_clean_transcript() {
+        assert_eq!(clean_transcript(" [BLANK_AUDIO]"), "");
+        assert_eq!(clean_transcript(" Open the (door creaks) invoice\n folder."), "Open the invoice folder.");
+    }
+
+    /// Needs a whisper.cpp model, e.g.
+    /// `WHISPER_MODEL=~/models/ggml-base.en.bin cargo test voice -- --ignored`.
+    #[test]
+    #[ignore = "needs a whisper model"]
+    fn test_whisper_transcribes_silence_to_nothing() {
+        let model = PathBuf::from(std::env::var("WHISPER_MODEL").expect("WHISPER_MODEL is not set"));
+        let mut transcriber = WhisperTranscriber::new(&model).unwrap();
+        assert_eq!(transcriber.transcribe(&vec![0.0; SAMPLE_RATE as usize * 2]).unwrap(), "");
+    }
+}
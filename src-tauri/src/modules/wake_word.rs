@@ -0,0 +1,398 @@
+// Wake word module
+// A small keyword spotter for always-on activation: a model is a set of MFCC templates
+// recorded from a few people saying the wake word, and the detector matches the last
+// second or two of audio against them with dynamic time warping.
+
+use crate::modules::persistence;
+use crate::modules::voice::{self, VoiceError, SAMPLE_RATE};
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, f32::consts::PI, path::{Path, PathBuf}};
+use thiserror::Error;
+
+/// 25 ms analysis frames every 10 ms.
+const FRAME_LEN: usize = 400;
+const FRAME_HOP: usize = 160;
+const FFT_LEN: usize = 512;
+const MEL_FILTERS: usize = 26;
+/// Added to every filter's energy, so differences in bands holding only room noise do
+/// not count.
+const MEL_FLOOR: f32 = 1.0;
+/// Cepstral coefficients kept per frame; the first (overall loudness) is dropped.
+const CEPSTRA: usize = 12;
+/// Frames quieter than this are not speech.
+const MIN_SPEECH_RMS: f32 = 0.01;
+/// The detector matches after every this many new frames (50 ms).
+const DETECT_EVERY: usize = 5;
+/// Frames ignored after a detection, so one wake word is not reported twice (1 s).
+const REFRACTORY_FRAMES: usize = 100;
+/// Threshold for models trained from a single clip, which have nothing to compare to.
+const DEFAULT_THRESHOLD: f32 = 3.5;
+/// How much further than the enrollment clips are from each other a match may be.
+const THRESHOLD_MARGIN: f32 = 1.7;
+
+#[derive(Error, Debug)]
+pub enum WakeWordError {
+    #[error("Cannot train a wake word from no speech")]
+    NoSpeech,
+    #[error("Cannot read or write the wake word model: {0}")]
+    Storage(String),
+    #[error(transparent)]
+    Audio(#[from] VoiceError),
+}
+
+type Features = [f32; CEPSTRA];
+
+/// Keyword templates and the largest distance at which audio matches them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WakeWordModel {
+    pub name: String,
+    pub templates: Vec<Vec<Features>>,
+    pub threshold: f32,
+}
+
+impl WakeWordModel {
+    /// Builds a model from clips of the wake word, 16 kHz mono, each trimmed to its
+    /// speech. The threshold is set from how much the clips differ from each other.
+    pub fn train(name: &str, clips: &[Vec<f32>]) -> Result<Self, WakeWordError> {
+        let templates: Vec<Vec<Features>> = clips
+            .iter()
+            .map(|clip| {
+                let frames = analyze(clip);
+                let speech: Vec<usize> = (0..frames.len()).filter(|i| frames[*i].1 >= MIN_SPEECH_RMS).collect();
+                match (speech.first(), speech.last()) {
+                    (Some(first), Some(last)) => normalize(&frames[*first..=*last]),
+                    _ => Vec::new(),
+                }
+            })
+            .filter(|template| !template.is_empty())
+            .collect();
+        if templates.is_empty() {
+            return Err(WakeWordError::NoSpeech);
+        }
+
+        let mut largest: Option<f32> = None;
+        for (index, template) in templates.iter().enumerate() {
+            for other in &templates[index + 1..] {
+                let distance = dtw_distance(template, other).max(dtw_distance(other, template));
+                largest = Some(largest.map_or(distance, |largest| largest.max(distance)));
+            }
+        }
+        let threshold = largest.map_or(DEFAULT_THRESHOLD, |distance| distance * THRESHOLD_MARGIN);
+        Ok(Self { name: name.to_string(), templates, threshold })
+    }
+
+    /// Trains from WAV recordings of the wake word.
+    pub fn train_from_files(name: &str, paths: &[PathBuf]) -> Result<Self, WakeWordError> {
+        let clips = paths.iter().map(|path| voice::read_wav(path)).collect::<Result<Vec<_>, _>>()?;
+        Self::train(name, &clips)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, WakeWordError> {
+        persistence::read_json(path).map(|loaded| loaded.value).map_err(|e| WakeWordError::Storage(e.to_string()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), WakeWordError> {
+        persistence::write_json(path, self).map_err(|e| WakeWordError::Storage(e.to_string()))
+    }
+
+    fn longest_template(&self) -> usize {
+        self.templates.iter().map(Vec::len).max().unwrap_or(0)
+    }
+}
+
+/// Streams audio through the model and reports when the wake word was just said.
+pub struct WakeWordDetector {
+    model: WakeWordModel,
+    /// Samples not yet analyzed.
+    pending: Vec<f32>,
+    /// Recent frames with their loudness, enough to hold a slowly spoken wake word.
+    frames: VecDeque<(Features, f32)>,
+    capacity: usize,
+    since_check: usize,
+    refractory: usize,
+}
+
+impl WakeWordDetector {
+    pub fn new(model: WakeWordModel) -> Self {
+        let capacity = model.longest_template() * 3 / 2;
+        Self { model, pending: Vec::new(), frames: VecDeque::new(), capacity, since_check: 0, refractory: 0 }
+    }
+
+    pub fn model(&self) -> &WakeWordModel {
+        &self.model
+    }
+
+    /// Feeds 16 kHz mono audio. Returns true when the wake word ends in it.
+    pub fn push(&mut self, samples: &[f32]) -> bool {
+        self.pending.extend_from_slice(samples);
+        let mut detected = false;
+        while self.pending.len() >= FRAME_LEN {
+            let frame = analyze_frame(&self.pending[..FRAME_LEN]);
+            self.pending.drain(..FRAME_HOP);
+            self.frames.push_back(frame);
+            if self.frames.len() > self.capacity {
+                self.frames.pop_front();
+            }
+            if self.refractory > 0 {
+                self.refractory -= 1;
+                continue;
+            }
+            self.since_check += 1;
+            if self.since_check >= DETECT_EVERY && self.frames.len() == self.capacity {
+                self.since_check = 0;
+                if self.matches() {
+                    detected = true;
+                    self.frames.clear();
+                    self.refractory = REFRACTORY_FRAMES;
+                }
+            }
+        }
+        detected
+    }
+
+    /// The distance from the recent audio to the closest template, or `None` when the
+    /// audio ending now is not speech.
+    fn distance(&self) -> Option<f32> {
+        let shortest = self.model.templates.iter().map(Vec::len).min().unwrap_or(0);
+        // Half of the last template-length of audio must be speech.
+        let recent = self.frames.iter().rev().take(shortest);
+        let loud = recent.filter(|(_, rms)| *rms >= MIN_SPEECH_RMS).count();
+        if loud * 2 < shortest {
+            return None;
+        }
+        let window: Vec<(Features, f32)> = self.frames.iter().copied().collect();
+        let audio = normalize(&window);
+        self.model.templates.iter().map(|template| subsequence_distance(template, &audio)).min_by(f32::total_cmp)
+    }
+
+    fn matches(&self) -> bool {
+        match self.distance() {
+            Some(distance) => {
+                log::trace!("Wake word '{}' distance {:.2}", self.model.name, distance);
+                distance <= self.model.threshold
+            }
+            None => false,
+        }
+    }
+}
+
+/// MFCCs and loudness of each frame of a clip.
+fn analyze(samples: &[f32]) -> Vec<(Features, f32)> {
+    if samples.len() < FRAME_LEN {
+        return Vec::new();
+    }
+    (0..=(samples.len() - FRAME_LEN) / FRAME_HOP)
+        .map(|index| analyze_frame(&samples[index * FRAME_HOP..index * FRAME_HOP + FRAME_LEN]))
+        .collect()
+}
+
+fn analyze_frame(frame: &[f32]) -> (Features, f32) {
+    let rms = (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt();
+
+    // Pre-emphasis and a Hamming window, then the power spectrum.
+    let mut real = [0.0f32; FFT_LEN];
+    let mut imag = [0.0f32; FFT_LEN];
+    for (index, value) in real.iter_mut().enumerate().take(frame.len()) {
+        let previous = if index > 0 { frame[index - 1] } else { 0.0 };
+        let window = 0.54 - 0.46 * (2.0 * PI * index as f32 / (frame.len() - 1) as f32).cos();
+        *value = (frame[index] - 0.97 * previous) * window;
+    }
+    fft(&mut real, &mut imag);
+    let power: Vec<f32> = (0..=FFT_LEN / 2).map(|bin| real[bin] * real[bin] + imag[bin] * imag[bin]).collect();
+
+    // Triangular filters evenly spaced on the mel scale, up to the Nyquist frequency.
+    let mel = |hz: f32| 2595.0 * (1.0 + hz / 700.0).log10();
+    let hz = |mel: f32| 700.0 * (10f32.powf(mel / 2595.0) - 1.0);
+    let top = mel(SAMPLE_RATE as f32 / 2.0);
+    let edges: Vec<f32> = (0..MEL_FILTERS + 2)
+        .map(|index| hz(top * index as f32 / (MEL_FILTERS + 1) as f32) * FFT_LEN as f32 / SAMPLE_RATE as f32)
+        .collect();
+    let energies: Vec<f32> = (0..MEL_FILTERS)
+        .map(|filter| {
+            let (left, center, right) = (edges[filter], edges[filter + 1], edges[filter + 2]);
+            let energy: f32 = power
+                .iter()
+                .enumerate()
+                .map(|(bin, power)| {
+                    let bin = bin as f32;
+                    let weight = if bin < left || bin > right {
+                        0.0
+                    } else if bin <= center {
+                        (bin - left) / (center - left)
+                    } else {
+                        (right - bin) / (right - center)
+                    };
+                    weight * power
+                })
+                .sum();
+            (energy + MEL_FLOOR).ln()
+        })
+        .collect();
+
+    let mut cepstra = [0.0f32; CEPSTRA];
+    for (index, coefficient) in cepstra.iter_mut().enumerate() {
+        let k = (index + 1) as f32;
+        *coefficient = energies
+            .iter()
+            .enumerate()
+            .map(|(m, energy)| energy * (PI * k * (m as f32 + 0.5) / MEL_FILTERS as f32).cos())
+            .sum();
+    }
+    (cepstra, rms)
+}
+
+/// In-place radix-2 FFT.
+fn fft(real: &mut [f32], imag: &mut [f32]) {
+    let n = real.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            real.swap(i, j);
+            imag.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let (br, bi) = (real[b] * cos - imag[b] * sin, real[b] * sin + imag[b] * cos);
+                real[b] = real[a] - br;
+                imag[b] = imag[a] - bi;
+                real[a] += br;
+                imag[a] += bi;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Subtracts the mean of each coefficient, which removes the microphone's coloring.
+fn normalize(frames: &[(Features, f32)]) -> Vec<Features> {
+    let mut mean = [0.0f32; CEPSTRA];
+    for (features, _) in frames {
+        for (sum, value) in mean.iter_mut().zip(features) {
+            *sum += value / frames.len() as f32;
+        }
+    }
+    frames
+        .iter()
+        .map(|(features, _)| {
+            let mut normalized = *features;
+            for (value, mean) in normalized.iter_mut().zip(&mean) {
+                *value -= mean;
+            }
+            normalized
+        })
+        .collect()
+}
+
+fn frame_distance(a: &Features, b: &Features) -> f32 {
+    a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum::<f32>().sqrt()
+}
+
+/// Average distance per template frame along the best alignment of `template` with the
+/// end of `audio`, which may start anywhere.
+fn subsequence_distance(template: &[Features], audio: &[Features]) -> f32 {
+    if template.is_empty() || audio.is_empty() {
+        return f32::INFINITY;
+    }
+    let mut previous: Vec<f32> = audio.iter().map(|frame| frame_distance(&template[0], frame)).collect();
+    for features in &template[1..] {
+        let mut current = vec![0.0; audio.len()];
+        for (j, frame) in audio.iter().enumerate() {
+            let best = if j == 0 { previous[0] } else { previous[j].min(previous[j - 1]).min(current[j - 1]) };
+            current[j] = frame_distance(features, frame) + best;
+        }
+        previous = current;
+    }
+    previous[audio.len() - 1] / template.len() as f32
+}
+
+/// Average distance per frame of `a` along the best alignment of both whole sequences.
+fn dtw_distance(a: &[Features], b: &[Features]) -> f32 {
+    if a.is_empty() || b.is_empty() {
+        return f32::INFINITY;
+    }
+    let mut previous = vec![f32::INFINITY; b.len()];
+    for (i, features) in a.iter().enumerate() {
+        let mut current = vec![0.0; b.len()];
+        for (j, frame) in b.iter().enumerate() {
+            let best = match (i, j) {
+                (0, 0) => 0.0,
+                (0, _) => current[j - 1],
+                (_, 0) => previous[0],
+                _ => previous[j].min(previous[j - 1]).min(current[j - 1]),
+            };
+            current[j] = frame_distance(features, frame) + best;
+        }
+        previous = current;
+    }
+    previous[b.len() - 1] / a.len() as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::voice::{AudioSource, FileSource};
+
+    /// Clips made by `tests/fixtures/wake_word/synth.py`.
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/wake_word").join(name)
+    }
+
+    fn trained() -> WakeWordModel {
+        let enrollment: Vec<PathBuf> = (1..=3).map(|index| fixture(&format!("enroll_{}.wav", index))).collect();
+        WakeWordModel::train_from_files("hey nyx", &enrollment).unwrap()
+    }
+
+    /// Seconds into the clip at which the detector fired.
+    fn detections(model: &WakeWordModel, clip: &str) -> Vec<f32> {
+        let mut detector = WakeWordDetector::new(model.clone());
+        let mut source = FileSource::open(&fixture(clip)).unwrap();
+        let (mut position, mut found) = (0, Vec::new());
+        while let Some(samples) = source.next_samples().unwrap() {
+            position += samples.len();
+            if detector.push(&samples) {
+                found.push(position as f32 / SAMPLE_RATE as f32);
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn test_detects_wake_word_once() {
+        let model = trained();
+        assert_eq!(model.templates.len(), 3);
+        // The wake word is spoken from 0.6 s to about 1.25 s.
+        let found = detections(&model, "wake_then_command.wav");
+        assert_eq!(found.len(), 1, "{:?}", found);
+        assert!((1.1..1.6).contains(&found[0]), "{:?}", found);
+    }
+
+    #[test]
+    fn test_ignores_other_speech_and_noise() {
+        let model = trained();
+        assert_eq!(detections(&model, "other_phrases.wav"), Vec::<f32>::new());
+        assert_eq!(detections(&model, "background_noise.wav"), Vec::<f32>::new());
+    }
+
+    #[test]
+    fn test_model_round_trip() {
+        let model = trained();
+        let path = std::env::temp_dir().join(format!("nyx-wake-word-{}.json", std::process::id()));
+        model.save(&path).unwrap();
+        let loaded = WakeWordModel::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, model);
+        assert!(matches!(WakeWordModel::train("silence", &[vec![0.0; 16_000]]), Err(WakeWordError::NoSpeech)));
+    }
+}
@@ -0,0 +1,554 @@
+// Window module
+// Lists and manages top-level windows through the window manager's EWMH hints
+// (_NET_CLIENT_LIST, _NET_ACTIVE_WINDOW), so plans can focus, arrange and close windows
+// instead of dragging them with synthetic input.
+
+use crate::modules::perception::Rect;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use x11rb::{
+    connection::Connection,
+    errors::{ConnectionError, ReplyError},
+    protocol::xproto::{
+        Atom, AtomEnum, ClientMessageEvent, ConfigureWindowAux, ConnectionExt, EventMask, InputFocus, StackMode,
+        Window,
+    },
+    rust_connection::RustConnection,
+    CURRENT_TIME,
+};
+
+/// How often `wait_for_window` lists windows.
+const WINDOW_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Source indication for EWMH requests: 2 means a pager or other direct user action,
+/// which window managers honor without focus-stealing prevention.
+const SOURCE_PAGER: u32 = 2;
+/// ICCCM IconicState, requested through WM_CHANGE_STATE to minimize a window.
+const ICONIC_STATE: u32 = 3;
+/// `_NET_WM_DESKTOP` value for windows shown on every desktop.
+const ALL_DESKTOPS: u32 = 0xFFFF_FFFF;
+/// How often `FocusTracker` reads the focused window.
+const FOCUS_POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// Consecutive failed reads after which `FocusTracker` reconnects.
+const FOCUS_MAX_FAILURES: u32 = 10;
+/// Wait before `FocusTracker` connects again after losing the X server.
+const FOCUS_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        _NET_SUPPORTED,
+        _NET_CLIENT_LIST,
+        _NET_ACTIVE_WINDOW,
+        _NET_CLOSE_WINDOW,
+        _NET_MOVERESIZE_WINDOW,
+        _NET_WM_NAME,
+        _NET_WM_PID,
+        _NET_WM_DESKTOP,
+        _NET_WM_STATE,
+        _NET_WM_STATE_HIDDEN,
+        _NET_WM_STATE_MAXIMIZED_VERT,
+        _NET_WM_STATE_MAXIMIZED_HORZ,
+        WM_CHANGE_STATE,
+        WM_PROTOCOLS,
+        WM_DELETE_WINDOW,
+        UTF8_STRING,
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum WindowError {
+    #[error("Cannot connect to X server: {0}")]
+    Connection(String),
+    #[error("X request failed: {0}")]
+    Request(String),
+    #[error("The window manager does not support {0}")]
+    Unsupported(String),
+    #[error("No window {0:#x}")]
+    NotFound(u32),
+    #[error("No window matches '{0}'")]
+    NoMatch(String),
+    #[error("No window matching '{0}' appeared within {1:?}")]
+    Timeout(String, Duration),
+}
+
+impl From<ConnectionError> for WindowError {
+    fn from(e: ConnectionError) -> Self {
+        WindowError::Request(e.to_string())
+    }
+}
+
+impl From<ReplyError> for WindowError {
+    fn from(e: ReplyError) -> Self {
+        WindowError::Request(e.to_string())
+    }
+}
+
+/// A top-level window as the window manager reports it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WindowInfo {
+    pub id: u32,
+    pub title: String,
+    /// WM_CLASS class name, e.g. "firefox" or "Gedit".
+    pub class: String,
+    pub pid: Option<u32>,
+    /// Client area on the screen, without window manager decorations.
+    pub rect: Rect,
+    /// Virtual desktop number, or `None` for windows on every desktop.
+    pub desktop: Option<u32>,
+    pub active: bool,
+    pub minimized: bool,
+}
+
+impl WindowInfo {
+    /// Whether the title or class contains `pattern`, ignoring case.
+    pub fn matches(&self, pattern: &str) -> bool {
+        let pattern = pattern.to_lowercase();
+        self.title.to_lowercase().contains(&pattern) || self.class.to_lowercase().contains(&pattern)
+    }
+}
+
+/// The focused window, sampled for recorded clicks and key bursts and given to the
+/// planner during execution.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct WindowContext {
+    pub title: String,
+    /// WM_CLASS class name; empty when only the title is known.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub class: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+}
+
+impl WindowContext {
+    /// Whether both contexts belong to the same application. Titles change with the open
+    /// document, so only classes are compared; without both classes this cannot tell and
+    /// returns true.
+    pub fn same_app(&self, other: &WindowContext) -> bool {
+        self.class.is_empty() || other.class.is_empty() || self.class.eq_ignore_ascii_case(&other.class)
+    }
+
+    /// One line for prompts, e.g. `"report.odt - LibreOffice Writer" (libreoffice-writer, pid 4242)`.
+    pub fn describe(&self) -> String {
+        let mut details: Vec<String> = Vec::new();
+        if !self.class.is_empty() {
+            details.push(self.class.clone());
+        }
+        if let Some(pid) = self.pid {
+            details.push(format!("pid {}", pid));
+        }
+        if details.is_empty() {
+            format!("{:?}", self.title)
+        } else {
+            format!("{:?} ({})", self.title, details.join(", "))
+        }
+    }
+}
+
+impl From<WindowInfo> for WindowContext {
+    fn from(info: WindowInfo) -> Self {
+        Self { title: info.title, class: info.class, pid: info.pid }
+    }
+}
+
+#[derive(Default)]
+struct Focus {
+    current: Option<WindowContext>,
+    /// The last focused window that does not belong to this process.
+    outside: Option<WindowContext>,
+}
+
+/// Follows the focused window from a background thread over one X connection, so readers
+/// never wait on the X server. Also remembers the window the user was in before focusing
+/// Nyx itself, which is the one a task typed into Nyx is about.
+#[derive(Default)]
+pub struct FocusTracker {
+    focus: Arc<Mutex<Focus>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl FocusTracker {
+    /// Starts following the focused window until the tracker is dropped.
+    pub fn start() -> Self {
+        let tracker = Self::default();
+        let focus = tracker.focus.clone();
+        let stop = tracker.stop.clone();
+        thread::spawn(move || {
+            let mut warned = false;
+            while !stop.load(Ordering::SeqCst) {
+                let manager = match WindowManager::connect() {
+                    Ok(manager) => manager,
+                    Err(e) => {
+                        if !warned {
+                            log::warn!("Cannot follow the focused window: {}", e);
+                            warned = true;
+                        }
+                        thread::sleep(FOCUS_RECONNECT_DELAY);
+                        continue;
+                    }
+                };
+                let mut failures = 0;
+                while !stop.load(Ordering::SeqCst) && failures < FOCUS_MAX_FAILURES {
+                    match manager.active() {
+                        Ok(active) => {
+                            failures = 0;
+                            observe(&focus, active.map(WindowContext::from));
+                        }
+                        // A window closing between the two requests fails once; only
+                        // repeated failures mean the connection is gone.
+                        Err(e) => {
+                            failures += 1;
+                            log::debug!("Could not read the focused window: {}", e);
+                        }
+                    }
+                    thread::sleep(FOCUS_POLL_INTERVAL);
+                }
+                if failures >= FOCUS_MAX_FAILURES {
+                    log::warn!("Lost the X connection, reconnecting to follow the focused window");
+                    thread::sleep(FOCUS_RECONNECT_DELAY);
+                }
+            }
+        });
+        tracker
+    }
+
+    /// Records `window` as focused.
+    pub fn observe(&self, window: Option<WindowContext>) {
+        observe(&self.focus, window);
+    }
+
+    /// The focused window as of the last poll.
+    pub fn current(&self) -> Option<WindowContext> {
+        lock_focus(&self.focus).current.clone()
+    }
+
+    /// The focused window, or when that is one of Nyx's own, the window focused before it.
+    pub fn outside_nyx(&self) -> Option<WindowContext> {
+        lock_focus(&self.focus).outside.clone()
+    }
+}
+
+impl Drop for FocusTracker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+fn lock_focus(focus: &Mutex<Focus>) -> std::sync::MutexGuard<'_, Focus> {
+    focus.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn observe(focus: &Mutex<Focus>, window: Option<WindowContext>) {
+    let mut focus = lock_focus(focus);
+    if let Some(window) = &window {
+        if window.pid != Some(std::process::id()) {
+            focus.outside = Some(window.clone());
+        }
+    }
+    focus.current = window;
+}
+
+/// A window action for plans, addressing the window by title or class pattern.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum WindowAction {
+    Focus { window: String },
+    MoveResize { window: String, rect: Rect },
+    Minimize { window: String },
+    Close { window: String },
+    WaitFor { window: String, timeout_ms: u64 },
+}
+
+/// A connection to the X server's window manager.
+pub struct WindowManager {
+    conn: RustConnection,
+    root: Window,
+    atoms: Atoms,
+}
+
+impl WindowManager {
+    pub fn connect() -> Result<Self, WindowError> {
+        let (conn, screen_num) = x11rb::connect(None).map_err(|e| WindowError::Connection(e.to_string()))?;
+        let root = conn.setup().roots[screen_num].root;
+        let atoms = Atoms::new(&conn)?.reply()?;
+        Ok(Self { conn, root, atoms })
+    }
+
+    fn property32(&self, window: Window, property: Atom, kind: impl Into<Atom>) -> Result<Vec<u32>, WindowError> {
+        let reply = self.conn.get_property(false, window, property, kind, 0, 1024)?.reply()?;
+        Ok(reply.value32().map(|values| values.collect()).unwrap_or_default())
+    }
+
+    fn property_bytes(&self, window: Window, property: impl Into<Atom>, kind: impl Into<Atom>) -> Result<Vec<u8>, WindowError> {
+        Ok(self.conn.get_property(false, window, property, kind, 0, 4096)?.reply()?.value)
+    }
+
+    fn supports(&self, atom: Atom) -> Result<bool, WindowError> {
+        Ok(self.property32(self.root, self.atoms._NET_SUPPORTED, AtomEnum::ATOM)?.contains(&atom))
+    }
+
+    fn active_window(&self) -> Result<Option<Window>, WindowError> {
+        let active = self.property32(self.root, self.atoms._NET_ACTIVE_WINDOW, AtomEnum::WINDOW)?;
+        Ok(active.first().copied().filter(|window| *window != 0))
+    }
+
+    /// The window that has focus, or `None` when no window is active.
+    pub fn active(&self) -> Result<Option<WindowInfo>, WindowError> {
+        let Some(id) = self.active_window()? else {
+            return Ok(None);
+        };
+        self.describe(id, Some(id)).map(Some)
+    }
+
+    /// Lists managed windows in the order the window manager mapped them.
+    pub fn list_windows(&self) -> Result<Vec<WindowInfo>, WindowError> {
+        if !self.supports(self.atoms._NET_CLIENT_LIST)? {
+            return Err(WindowError::Unsupported("_NET_CLIENT_LIST".to_string()));
+        }
+        let active = self.active_window()?;
+        let mut windows = Vec::new();
+        for id in self.property32(self.root, self.atoms._NET_CLIENT_LIST, AtomEnum::WINDOW)? {
+            // Windows can close between listing and querying them.
+            match self.describe(id, active) {
+                Ok(info) => windows.push(info),
+                Err(e) => log::debug!("Skipping window {:#x}: {}", id, e),
+            }
+        }
+        Ok(windows)
+    }
+
+    pub fn window(&self, id: u32) -> Result<WindowInfo, WindowError> {
+        self.describe(id, self.active_window()?).map_err(|_| WindowError::NotFound(id))
+    }
+
+    fn describe(&self, id: Window, active: Option<Window>) -> Result<WindowInfo, WindowError> {
+        let geometry = self.conn.get_geometry(id)?.reply()?;
+        let origin = self.conn.translate_coordinates(id, self.root, 0, 0)?.reply()?;
+
+        let mut title = self.property_bytes(id, self.atoms._NET_WM_NAME, self.atoms.UTF8_STRING)?;
+        if title.is_empty() {
+            title = self.property_bytes(id, AtomEnum::WM_NAME, AtomEnum::STRING)?;
+        }
+        let class = self.property_bytes(id, AtomEnum::WM_CLASS, AtomEnum::STRING)?;
+        let states = self.property32(id, self.atoms._NET_WM_STATE, AtomEnum::ATOM)?;
+        let desktop = self.property32(id, self.atoms._NET_WM_DESKTOP, AtomEnum::CARDINAL)?;
+
+        Ok(WindowInfo {
+            id,
+            title: String::from_utf8_lossy(&title).into_owned(),
+            class: parse_wm_class(&class),
+            pid: self.property32(id, self.atoms._NET_WM_PID, AtomEnum::CARDINAL)?.first().copied(),
+            rect: Rect {
+                x: origin.dst_x.into(),
+                y: origin.dst_y.into(),
+                width: geometry.width.into(),
+                height: geometry.height.into(),
+            },
+            desktop: desktop.first().copied().filter(|desktop| *desktop != ALL_DESKTOPS),
+            active: active == Some(id),
+            minimized: states.contains(&self.atoms._NET_WM_STATE_HIDDEN),
+        })
+    }
+
+    /// The first window whose title or class contains `pattern`, preferring the active one.
+    pub fn find_window(&self, pattern: &str) -> Result<WindowInfo, WindowError> {
+        let matching: Vec<WindowInfo> = self.list_windows()?.into_iter().filter(|w| w.matches(pattern)).collect();
+        matching
+            .iter()
+            .find(|window| window.active)
+            .or(matching.first())
+            .cloned()
+            .ok_or_else(|| WindowError::NoMatch(pattern.to_string()))
+    }
+
+    /// Sends an EWMH request about `window` to the window manager.
+    fn request(&self, window: Window, message: Atom, data: [u32; 5]) -> Result<(), WindowError> {
+        let event = ClientMessageEvent::new(32, window, message, data);
+        let mask = EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY;
+        self.conn.send_event(false, self.root, mask, event)?;
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    /// Raises and focuses a window, restoring it if it is minimized.
+    pub fn focus_window(&self, id: u32) -> Result<(), WindowError> {
+        self.window(id)?;
+        if self.supports(self.atoms._NET_ACTIVE_WINDOW)? {
+            return self.request(id, self.atoms._NET_ACTIVE_WINDOW, [SOURCE_PAGER, CURRENT_TIME, 0, 0, 0]);
+        }
+        self.conn.map_window(id)?;
+        self.conn.configure_window(id, &ConfigureWindowAux::new().stack_mode(StackMode::ABOVE))?;
+        self.conn.set_input_focus(InputFocus::PARENT, id, CURRENT_TIME)?;
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    /// Moves and resizes a window's frame to `rect`, un-maximizing it first.
+    pub fn move_resize(&self, id: u32, rect: Rect) -> Result<(), WindowError> {
+        self.window(id)?;
+        // Action 0 removes the states; a maximized window ignores geometry requests.
+        let unmaximize = [0, self.atoms._NET_WM_STATE_MAXIMIZED_VERT, self.atoms._NET_WM_STATE_MAXIMIZED_HORZ, SOURCE_PAGER, 0];
+        self.request(id, self.atoms._NET_WM_STATE, unmaximize)?;
+        if self.supports(self.atoms._NET_MOVERESIZE_WINDOW)? {
+            // NorthWest gravity, with x, y, width and height all given.
+            let flags = 1 | (0b1111 << 8) | (SOURCE_PAGER << 12);
+            let data = [flags, rect.x as u32, rect.y as u32, rect.width, rect.height];
+            return self.request(id, self.atoms._NET_MOVERESIZE_WINDOW, data);
+        }
+        let geometry = ConfigureWindowAux::new().x(rect.x).y(rect.y).width(rect.width).height(rect.height);
+        self.conn.configure_window(id, &geometry)?;
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    pub fn minimize(&self, id: u32) -> Result<(), WindowError> {
+        self.window(id)?;
+        self.request(id, self.atoms.WM_CHANGE_STATE, [ICONIC_STATE, 0, 0, 0, 0])
+    }
+
+    /// Asks a window to close, as its close button would; the application may still
+    /// prompt, e.g. to save changes.
+    pub fn close(&self, id: u32) -> Result<(), WindowError> {
+        self.window(id)?;
+        if self.supports(self.atoms._NET_CLOSE_WINDOW)? {
+            return self.request(id, self.atoms._NET_CLOSE_WINDOW, [CURRENT_TIME, SOURCE_PAGER, 0, 0, 0]);
+        }
+        let protocols = self.property32(id, self.atoms.WM_PROTOCOLS, AtomEnum::ATOM)?;
+        if !protocols.contains(&self.atoms.WM_DELETE_WINDOW) {
+            return Err(WindowError::Unsupported("closing this window".to_string()));
+        }
+        let event = ClientMessageEvent::new(32, id, self.atoms.WM_PROTOCOLS, [self.atoms.WM_DELETE_WINDOW, CURRENT_TIME, 0, 0, 0]);
+        self.conn.send_event(false, id, EventMask::NO_EVENT, event)?;
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    /// Blocks until a window whose title or class contains `pattern` exists.
+    pub fn wait_for_window(&self, pattern: &str, timeout: Duration) -> Result<WindowInfo, WindowError> {
+        let started = Instant::now();
+        loop {
+            match self.find_window(pattern) {
+                Ok(window) => return Ok(window),
+                Err(WindowError::NoMatch(_)) if started.elapsed() < timeout => thread::sleep(WINDOW_POLL_INTERVAL),
+                Err(WindowError::NoMatch(_)) => return Err(WindowError::Timeout(pattern.to_string(), timeout)),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Runs a plan's window action on the first matching window.
+    pub fn perform(&self, action: &WindowAction) -> Result<(), WindowError> {
+        match action {
+            WindowAction::Focus { window } => self.focus_window(self.find_window(window)?.id),
+            WindowAction::MoveResize { window, rect } => self.move_resize(self.find_window(window)?.id, *rect),
+            WindowAction::Minimize { window } => self.minimize(self.find_window(window)?.id),
+            WindowAction::Close { window } => self.close(self.find_window(window)?.id),
+            WindowAction::WaitFor { window, timeout_ms } => {
+                self.wait_for_window(window, Duration::from_millis(*timeout_ms)).map(|_| ())
+            }
+        }
+    }
+}
+
+/// WM_CLASS holds the instance and class names, each NUL-terminated; returns the class.
+fn parse_wm_class(value: &[u8]) -> String {
+    let mut parts = value.split(|byte| *byte == 0).filter(|part| !part.is_empty());
+    let instance = parts.next().unwrap_or_default();
+    String::from_utf8_lossy(parts.next().unwrap_or(instance)).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x11rb::protocol::xproto::{CreateWindowAux, PropMode, WindowClass};
+    use x11rb::wrapper::ConnectionExt as _;
+
+    #[test]
+    fn test_parse_wm_class_and_matching() {
+        assert_eq!(parse_wm_class(b"navigator\0firefox\0"), "firefox");
+        assert_eq!(parse_wm_class(b"xterm\0"), "xterm");
+        assert_eq!(parse_wm_class(b""), "");
+
+        let window = WindowInfo {
+            id: 0x2a00004,
+            title: "Invoices - Nyx".to_string(),
+            class: "Gedit".to_string(),
+            pid: Some(4242),
+            rect: Rect { x: 0, y: 0, width: 800, height: 600 },
+            desktop: Some(0),
+            active: false,
+            minimized: false,
+        };
+        assert!(window.matches("invoices"));
+        assert!(window.matches("GEDIT"));
+        assert!(!window.matches("firefox"));
+
+        let context = WindowContext::from(window);
+        assert_eq!(context.describe(), "\"Invoices - Nyx\" (Gedit, pid 4242)");
+        let renamed = WindowContext { title: "Untitled - Nyx".to_string(), class: "gedit".to_string(), pid: None };
+        assert!(context.same_app(&renamed));
+        assert!(!context.same_app(&WindowContext { class: "firefox".to_string(), ..renamed }));
+        assert!(context.same_app(&WindowContext { title: "Mozilla Firefox".to_string(), ..WindowContext::default() }));
+    }
+
+    #[test]
+    fn test_focus_tracker_remembers_window_outside_nyx() {
+        let tracker = FocusTracker::default();
+        let editor = WindowContext { title: "report.odt".to_string(), class: "libreoffice".to_string(), pid: Some(1) };
+        let nyx = WindowContext { title: "Nyx".to_string(), class: "nyx".to_string(), pid: Some(std::process::id()) };
+
+        tracker.observe(Some(editor.clone()));
+        tracker.observe(Some(nyx.clone()));
+        assert_eq!(tracker.current(), Some(nyx));
+        assert_eq!(tracker.outside_nyx(), Some(editor.clone()));
+
+        tracker.observe(None);
+        assert_eq!(tracker.current(), None);
+        assert_eq!(tracker.outside_nyx(), Some(editor));
+    }
+
+    /// Needs an X server with an EWMH window manager, e.g.
+    /// `xvfb-run -a sh -c 'openbox & sleep 1; cargo test window -- --ignored'`.
+    #[test]
+    #[ignore = "needs Xvfb and a window manager"]
+    fn test_manage_window_under_xvfb() {
+        let (conn, screen_num) = x11rb::connect(None).unwrap();
+        let screen = &conn.setup().roots[screen_num];
+        let id = conn.generate_id().unwrap();
+        conn.create_window(0, id, screen.root, 10, 10, 300, 200, 0, WindowClass::INPUT_OUTPUT, 0, &CreateWindowAux::new())
+            .unwrap();
+        let manager = WindowManager::connect().unwrap();
+        conn.change_property8(PropMode::REPLACE, id, AtomEnum::WM_NAME, AtomEnum::STRING, b"Nyx window test")
+            .unwrap();
+        conn.change_property8(PropMode::REPLACE, id, AtomEnum::WM_CLASS, AtomEnum::STRING, b"nyx\0NyxTest\0")
+            .unwrap();
+        conn.change_property32(PropMode::REPLACE, id, manager.atoms.WM_PROTOCOLS, AtomEnum::ATOM, &[manager.atoms.WM_DELETE_WINDOW])
+            .unwrap();
+        conn.map_window(id).unwrap();
+        conn.flush().unwrap();
+
+        let window = manager.wait_for_window("nyx window", Duration::from_secs(5)).unwrap();
+        assert_eq!(window.id, id);
+        assert_eq!(window.class, "NyxTest");
+
+        manager.focus_window(id).unwrap();
+        manager.move_resize(id, Rect { x: 100, y: 120, width: 400, height: 250 }).unwrap();
+        let started = Instant::now();
+        while manager.window(id).unwrap().rect.width != 400 || !manager.window(id).unwrap().active {
+            assert!(started.elapsed() < Duration::from_secs(5), "{:?}", manager.window(id));
+            thread::sleep(WINDOW_POLL_INTERVAL);
+        }
+        assert_eq!(manager.window(id).unwrap().rect.height, 250);
+
+        assert!(matches!(
+            manager.wait_for_window("no such window", Duration::from_millis(300)),
+            Err(WindowError::Timeout(_, _))
+        ));
+    }
+}
@@ -2,11 +2,31 @@
 use serde::Serialize;
 use tauri::{Emitter, Manager};
 use thiserror::Error;
-use std::{sync::{mpsc::Receiver, Arc}, time::{Duration, Instant}, fs};
+use std::{cell::Cell, path::PathBuf, sync::{atomic::{AtomicBool, Ordering}, mpsc::Receiver, Arc}, time::{Duration, Instant}};
 use tokio::sync::Mutex;
 use rdev::Key;
+use image::RgbaImage;
+use chrono::{Local, NaiveDateTime};
 
-use crate::modules::macro_engine::{Macro, TimedEvent};
+use crate::modules::cognition::{self, CognitionSettings, Gemini};
+use crate::modules::io_controller::LiveInput;
+use crate::modules::knowledge::{self, TrajectoryEntry};
+use crate::modules::macro_engine::{self, ClickTarget, Macro, MacroEvent, TimedEvent};
+use crate::modules::macro_index;
+use crate::modules::macro_secrets::{self, SecretRef};
+use crate::modules::perception::{self, Rect, ScreenBackend, UiMap, X11Screen, CLICK_CROP_HEIGHT, CLICK_CROP_WIDTH};
+use crate::modules::persistence;
+use crate::modules::redaction::Redactor;
+use crate::modules::self_correction;
+use crate::modules::screen_recording::{RecordingOptions, ScreenRecorder};
+use crate::modules::tooling::{self, ToolCall};
+use crate::modules::scheduler::{Clock, JobAction, RunOutcome, ScheduledJob, Scheduler, SystemClock};
+use crate::modules::voice::{
+    self, AudioSource, HoldSource, ListenTimeouts, TranscriptEvent, Transcriber, VoiceError, VoiceInput, VoiceSettings,
+    WhisperTranscriber,
+};
+use crate::modules::wake_word::{WakeWordDetector, WakeWordModel};
+use crate::modules::window::{FocusTracker, WindowContext};
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
 #[serde(rename_all = "UPPERCASE")]
@@ -17,6 +37,13 @@ pub enum AppState {
     EXECUTING,
 }
 
+/// What the agent is working on, and the window that was focused when it started.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct SessionContext {
+    pub task: String,
+    pub window: Option<WindowContext>,
+}
+
 #[derive(Debug, Serialize, Clone)]
 pub struct TaskResult {
     pub success: bool,
@@ -36,11 +63,34 @@ pub enum OrchestratorError {
 
     #[error("File system error: {0}")]
     FileSystemError(String),
+
+    #[error("Secret storage failed: {0}")]
+    SecretStorageError(String),
+
+    #[error("Voice input failed: {0}")]
+    VoiceError(String),
     
     #[error("Tauri event emission failed: {0}")]
     EventError(#[from] tauri::Error),
 }
 
+/// Pressing this key while recording toggles secure input mode, in which typing is
+/// stored as a keyring secret instead of keystrokes. The key itself is never recorded.
+const SECURE_INPUT_TOGGLE_KEY: Key = Key::F8;
+/// A key press after a pause longer than this starts a new burst, whose focused window
+/// is sampled again.
+const KEY_BURST_GAP: Duration = Duration::from_millis(1000);
+/// Time for the compositor to remove Nyx's window before the screen is captured.
+const NYX_HIDE_SETTLE: Duration = Duration::from_millis(150);
+
+/// A task being executed, logged to the trajectory log when it ends.
+struct Execution {
+    id: String,
+    task: String,
+    started_at: NaiveDateTime,
+    recorder: Option<ScreenRecorder>,
+}
+
 // Placeholder structs for other modules
 pub struct Perception;
 pub struct Cognition;
@@ -51,7 +101,7 @@ pub struct Knowledge;
 pub struct Orchestrator {
     pub state: AppState,
     app_handle: tauri::AppHandle,
-    session_context: Option<String>,
+    session_context: Option<SessionContext>,
     // Module references will be added here
     perception: Arc<Mutex<Perception>>,
     cognition: Arc<Mutex<Cognition>>,
@@ -60,13 +110,40 @@ pub struct Orchestrator {
     recording_buffer: Vec<TimedEvent>,
     last_event_time: Option<Instant>,
     last_recorded_event_type: Option<rdev::EventType>,
+    secure_input: bool,
+    secret_buffer: String,
+    secret_delay: Option<Duration>,
+    secret_keys_down: Vec<Key>,
+    /// Focused window when the pending secret's first key was typed.
+    secret_window: Option<WindowContext>,
+    pending_secrets: Vec<(String, String)>,
+    /// Capture screen context for each click while recording.
+    ai_assisted: bool,
+    screen: Arc<dyn ScreenBackend>,
+    focus: FocusTracker,
+    planner: Arc<dyn TaskPlanner>,
+    last_cursor: Option<(f64, f64)>,
+    /// Prefix for click crop files, unique per recording so older revisions keep theirs.
+    recording_id: String,
+    /// Click captures waiting to be written into the macro's assets folder.
+    pending_click_crops: Vec<(String, ClickCapture)>,
+    /// Set to stop the voice listener while LISTENING.
+    voice_stop: Option<Arc<AtomicBool>>,
+    /// Cleared when the push-to-talk key is released, which ends the utterance.
+    push_to_talk_held: Option<Arc<AtomicBool>>,
+    /// Set to stop the wake word listener.
+    wake_word_stop: Option<Arc<AtomicBool>>,
+    pub voice_settings: VoiceSettings,
+    /// Record the screen while executing; off when unset.
+    pub execution_recording: Option<RecordingOptions>,
+    execution: Option<Execution>,
+    pub scheduler: Scheduler,
 }
 
 impl Orchestrator {
     pub fn new(app_handle: tauri::AppHandle) -> Self {
-        Self {
+        let mut orchestrator = Self {
             state: AppState::IDLE,
-            app_handle,
             session_context: None,
             perception: Arc::new(Mutex::new(Perception)),
             cognition: Arc::new(Mutex::new(Cognition)),
@@ -75,7 +152,47 @@ impl Orchestrator {
             recording_buffer: Vec::new(),
             last_event_time: None,
             last_recorded_event_type: None,
+            secure_input: false,
+            secret_buffer: String::new(),
+            secret_delay: None,
+            secret_keys_down: Vec::new(),
+            secret_window: None,
+            pending_secrets: Vec::new(),
+            ai_assisted: false,
+            screen: Arc::new(X11Screen),
+            focus: FocusTracker::start(),
+            planner: Arc::new(CognitionPlanner::new(app_handle.clone())),
+            last_cursor: None,
+            recording_id: String::new(),
+            pending_click_crops: Vec::new(),
+            voice_stop: None,
+            push_to_talk_held: None,
+            wake_word_stop: None,
+            voice_settings: VoiceSettings::load(&app_handle),
+            execution_recording: None,
+            execution: None,
+            scheduler: Self::load_scheduler(&app_handle),
+            app_handle,
+        };
+        for warning in orchestrator.scheduler.take_recovery_warnings() {
+            persistence::report_recovery(&orchestrator.app_handle, &warning);
         }
+        orchestrator
+    }
+
+    fn load_scheduler(app_handle: &tauri::AppHandle) -> Scheduler {
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let config_dir = match app_handle.path().app_config_dir() {
+            Ok(dir) => dir,
+            Err(e) => {
+                log::error!("Failed to get config dir, scheduled jobs will not be saved: {}", e);
+                return Scheduler::in_memory(clock);
+            }
+        };
+        Scheduler::load(config_dir.join("nyx-agent/scheduler"), clock.clone()).unwrap_or_else(|e| {
+            log::error!("Failed to load scheduled jobs: {}", e);
+            Scheduler::in_memory(clock)
+        })
     }
 
     fn set_state(&mut self, new_state: AppState) -> Result<(), OrchestratorError> {
@@ -106,10 +223,27 @@ impl Orchestrator {
         self.recording_buffer.clear();
         self.last_event_time = None; // Reset timer for the new recording
         self.last_recorded_event_type = None; // Reset last recorded event type
+        self.secure_input = false;
+        self.secret_buffer.clear();
+        self.secret_delay = None;
+        self.secret_keys_down.clear();
+        self.secret_window = None;
+        self.pending_secrets.clear();
+        self.last_cursor = None;
+        self.recording_id = Local::now().format("%Y%m%d-%H%M%S").to_string();
+        self.pending_click_crops.clear();
         self.set_state(AppState::RECORDING)
     }
 
-    pub fn stop_recording(&mut self, name: String) -> Result<(), OrchestratorError> {
+    /// Enables AI-assisted recording, which stores a screenshot crop, nearby text and the
+    /// window title for every click.
+    pub fn set_ai_assisted(&mut self, enabled: bool) {
+        self.ai_assisted = enabled;
+    }
+
+    /// Ends the recording and returns to IDLE. The returned recording still has to be
+    /// saved, which is slow and is best done without holding the orchestrator.
+    pub fn stop_recording(&mut self, name: String) -> Result<FinishedRecording, OrchestratorError> {
         if self.state != AppState::RECORDING {
             return Err(OrchestratorError::InvalidStateTransition {
                 from: self.state.clone(),
@@ -118,17 +252,19 @@ impl Orchestrator {
         }
     
         log::info!("Stopping recording for macro: {}", name);
+        self.flush_secret();
+        self.secure_input = false;
         log::info!("Recorded {} events", self.recording_buffer.len());
     
         // --- NEW: Heuristic to fix missed initial Win key press ---
         if let Some(first_event) = self.recording_buffer.first() {
             // Check if the macro starts with typing, not a modifier.
-            if let rdev::EventType::KeyPress(key) = first_event.event_type {
+            if let MacroEvent::Input(rdev::EventType::KeyPress(key)) = first_event.event {
                 if !matches!(key, Key::ControlLeft | Key::ControlRight | Key::ShiftLeft | Key::ShiftRight | Key::Alt | Key::AltGr | Key::MetaLeft | Key::MetaRight) {
                     
                     // Now, find the first Win key press/release pair that happened *later*.
-                    let meta_press_pos = self.recording_buffer.iter().position(|ev| ev.event_type == rdev::EventType::KeyPress(Key::MetaLeft));
-                    let meta_release_pos = self.recording_buffer.iter().position(|ev| ev.event_type == rdev::EventType::KeyRelease(Key::MetaLeft));
+                    let meta_press_pos = self.recording_buffer.iter().position(|ev| ev.event == MacroEvent::Input(rdev::EventType::KeyPress(Key::MetaLeft)));
+                    let meta_release_pos = self.recording_buffer.iter().position(|ev| ev.event == MacroEvent::Input(rdev::EventType::KeyRelease(Key::MetaLeft)));
                     
                     if let (Some(press_idx), Some(release_idx)) = (meta_press_pos, meta_release_pos) {
                         if release_idx > press_idx {
@@ -155,30 +291,16 @@ impl Orchestrator {
         }
         // --- END of Heuristic ---
     
-        let macro_data = Macro {
-            name: name.clone(),
-            events: self.recording_buffer.clone(),
+        // Store typing as layout-independent text instead of physical key codes.
+        let events = macro_engine::collapse_typed_text(self.recording_buffer.clone());
+        let finished = FinishedRecording {
+            macro_data: Macro { name, events },
+            secrets: std::mem::take(&mut self.pending_secrets),
+            click_crops: std::mem::take(&mut self.pending_click_crops),
+            app_handle: self.app_handle.clone(),
         };
-    
-        let json_string = serde_json::to_string_pretty(&macro_data)
-            .map_err(|e| OrchestratorError::FileSystemError(format!("Serialization failed: {}", e)))?;
-    
-        let config_dir = self.app_handle.path().app_config_dir()
-            .map_err(|e| OrchestratorError::FileSystemError(format!("Failed to get config dir: {}", e)))?;
-        let macros_dir = config_dir.join("nyx-agent/macros");
-        
-        log::info!("Saving macro to: {:?}", macros_dir);
-        
-        fs::create_dir_all(&macros_dir)
-            .map_err(|e| OrchestratorError::FileSystemError(format!("Failed to create macros dir: {}", e)))?;
-    
-        let file_path = macros_dir.join(format!("{}.json", name));
-        fs::write(&file_path, json_string)
-            .map_err(|e| OrchestratorError::FileSystemError(format!("Failed to write macro file: {}", e)))?;
-    
-        log::info!("Macro saved successfully to: {:?}", file_path);
-    
-        self.stop()
+        self.stop()?;
+        Ok(finished)
     }
     
     pub fn start_executing(&mut self, task: String) -> Result<(), OrchestratorError> {
@@ -188,44 +310,208 @@ impl Orchestrator {
                 to: AppState::EXECUTING,
             });
         }
-        self.session_context = Some(task);
+        // Requests typed or spoken into Nyx are about the window the user was in before.
+        let window = self.focus.outside_nyx();
+        self.begin_trajectory(&task);
+        self.session_context = Some(SessionContext { task, window });
         self.set_state(AppState::EXECUTING)
     }
 
+    /// Returns to IDLE after executing, logging how the task ended.
+    pub fn stop_executing(&mut self, success: bool, message: &str) -> Result<(), OrchestratorError> {
+        self.finish_trajectory(success, message);
+        self.stop()
+    }
+
+    /// Starts the task's trajectory log entry, and its screen recording if enabled.
+    fn begin_trajectory(&mut self, task: &str) {
+        let now = Local::now();
+        let id = now.format("%Y%m%d-%H%M%S-%3f").to_string();
+        let recorder = match (&self.execution_recording, knowledge::trajectory_dir(&self.app_handle)) {
+            (Some(options), Ok(dir)) => ScreenRecorder::start(self.screen.clone(), dir.join(&id), options.clone())
+                .map_err(|e| log::error!("Could not start the screen recording: {}", e))
+                .ok(),
+            (Some(_), Err(e)) => {
+                log::error!("Could not start the screen recording: {}", e);
+                None
+            }
+            (None, _) => None,
+        };
+        self.execution = Some(Execution { id, task: task.to_string(), started_at: now.naive_local(), recorder });
+    }
+
+    /// Saves the task's trajectory log entry and emits it as `trajectory_saved`. The
+    /// recording is encoded on another thread, so the agent does not wait for it.
+    fn finish_trajectory(&mut self, success: bool, message: &str) {
+        let Some(mut execution) = self.execution.take() else {
+            return;
+        };
+        // The finishing thread waits for the capture thread, so the agent does not.
+        if let Some(recorder) = execution.recorder.as_mut() {
+            recorder.request_stop();
+        }
+        let dir = match knowledge::trajectory_dir(&self.app_handle) {
+            Ok(dir) => dir,
+            Err(e) => {
+                log::error!("Could not log the task: {}", e);
+                return;
+            }
+        };
+        let mut entry = TrajectoryEntry {
+            id: execution.id,
+            task: execution.task,
+            started_at: execution.started_at,
+            finished_at: Local::now().naive_local(),
+            success,
+            message: message.to_string(),
+            recording: None,
+        };
+        let app_handle = self.app_handle.clone();
+        std::thread::spawn(move || {
+            if let Some(recorder) = execution.recorder {
+                match recorder.finish() {
+                    Ok(recording) => entry.recording = Some(recording),
+                    Err(e) => log::error!("Screen recording of '{}' failed: {}", entry.task, e),
+                }
+            }
+            if let Err(e) = knowledge::save_trajectory(&dir, &entry) {
+                log::error!("Could not log the task: {}", e);
+                return;
+            }
+            if let Err(e) = knowledge::prune_trajectories(&dir, knowledge::MAX_TRAJECTORIES) {
+                log::warn!("Could not remove old trajectory entries: {}", e);
+            }
+            if let Err(e) = app_handle.emit("trajectory_saved", &entry) {
+                log::error!("Failed to emit trajectory entry: {}", e);
+            }
+        });
+    }
+
+    pub fn session_context(&self) -> Option<&SessionContext> {
+        self.session_context.as_ref()
+    }
+
+    /// The window recorded input goes to. Nyx's own window is skipped: it is focused when
+    /// recording starts, and playback could never find it again.
+    fn sample_window(&self) -> Option<WindowContext> {
+        self.focus.outside_nyx()
+    }
+
+    /// The window a task should act on: the focused one, or the one focused before Nyx.
+    pub fn task_window(&self) -> Option<WindowContext> {
+        self.focus.outside_nyx()
+    }
+
+    /// Enters LISTENING and returns the flag that stops the voice listener; `stop` sets it.
+    pub fn start_voice_input(&mut self) -> Result<Arc<AtomicBool>, OrchestratorError> {
+        self.start_listening()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        self.voice_stop = Some(stop.clone());
+        Ok(stop)
+    }
+
+    /// Whether `stop` belongs to the voice session still running, i.e. one that was neither
+    /// stopped nor replaced by a newer session.
+    fn is_current_voice_session(&self, stop: &Arc<AtomicBool>) -> bool {
+        self.voice_stop.as_ref().is_some_and(|current| Arc::ptr_eq(current, stop)) && !stop.load(Ordering::SeqCst)
+    }
+
+    /// Starts listening when the push-to-talk key is pressed while idle, returning the
+    /// listener's stop and hold flags. Releasing the key clears the hold flag.
+    pub fn handle_push_to_talk(&mut self, event_type: &rdev::EventType) -> Option<(Arc<AtomicBool>, Arc<AtomicBool>)> {
+        let key = self.voice_settings.push_to_talk_key()?;
+        match event_type {
+            rdev::EventType::KeyPress(pressed) if *pressed == key && self.state == AppState::IDLE => {
+                let stop = self.start_voice_input().ok()?;
+                let held = Arc::new(AtomicBool::new(true));
+                self.push_to_talk_held = Some(held.clone());
+                Some((stop, held))
+            }
+            rdev::EventType::KeyRelease(released) if *released == key => {
+                if let Some(held) = self.push_to_talk_held.take() {
+                    held.store(false, Ordering::SeqCst);
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Stops the wake word listener, and starts a new one if the settings enable it.
+    pub fn restart_wake_word_listener(
+        &mut self,
+        orchestrator_state: Arc<Mutex<Orchestrator>>,
+    ) -> Result<(), OrchestratorError> {
+        if let Some(wake_word_stop) = self.wake_word_stop.take() {
+            wake_word_stop.store(true, Ordering::SeqCst);
+        }
+        if !self.voice_settings.wake_word {
+            return Ok(());
+        }
+        let load = || -> Result<(WakeWordModel, PathBuf), String> {
+            let model_path = voice::wake_word_model_path(&self.app_handle).map_err(|e| e.to_string())?;
+            let model = WakeWordModel::load(&model_path).map_err(|e| e.to_string())?;
+            let whisper_model = self.voice_settings.whisper_model_path(&self.app_handle).map_err(|e| e.to_string())?;
+            Ok((model, whisper_model))
+        };
+        let (model, whisper_model) = load().map_err(OrchestratorError::VoiceError)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        self.wake_word_stop = Some(stop.clone());
+        let app_handle = self.app_handle.clone();
+        std::thread::spawn(move || wake_word_listener(orchestrator_state, app_handle, model, whisper_model, stop));
+        Ok(())
+    }
+
     pub fn stop(&mut self) -> Result<(), OrchestratorError> {
+        self.finish_trajectory(false, "Stopped before finishing");
+        if let Some(voice_stop) = self.voice_stop.take() {
+            voice_stop.store(true, Ordering::SeqCst);
+        }
+        if let Some(held) = self.push_to_talk_held.take() {
+            held.store(false, Ordering::SeqCst);
+        }
         self.session_context = None;
         self.set_state(AppState::IDLE)
     }
 
     pub async fn execute_task(&mut self, task_description: String) -> Result<TaskResult, OrchestratorError> {
         self.start_executing(task_description.clone())?;
+        let window = self.session_context.as_ref().and_then(|context| context.window.clone());
 
-        // 1. Call Cognition to get a plan
+        // 1. Call Cognition to get a plan for the window the task is about
         self.app_handle.emit("task_progress", "Generating plan...")?;
         log::info!("Cognition: Generating plan for task: '{}'", task_description);
-        // let plan = self.cognition.lock().unwrap().generate_plan(&task_description)?; // Real implementation
-        tokio::time::sleep(std::time::Duration::from_secs(1)).await; // Placeholder for async work
-
-        // 2. Iterate through plan and execute steps
-        log::info!("Tooling: Executing plan steps...");
-        for i in 1..=3 {
-            self.app_handle.emit("task_progress", format!("Executing step {} of 3...", i))?;
-            // self.tooling.lock().unwrap().execute_step(step)?; // Real implementation
-            log::info!("Executing step {}", i);
-            tokio::time::sleep(std::time::Duration::from_millis(500)).await; // Placeholder
+        if let Some(window) = &window {
+            log::info!("Cognition: Focused window is {}", window.describe());
         }
+        let planner = self.planner.clone();
+        let app_handle = self.app_handle.clone();
+        let run = tokio::task::spawn_blocking(move || -> Result<(), String> {
+            let plan = planner.plan(&task_description, window.as_ref())?;
 
-        // 3. Log to Knowledge Base
-        log::info!("Knowledge: Logging execution results...");
-        // self.knowledge.lock().unwrap().log_task(result)?; // Real implementation
+            // 2. Execute the plan's steps
+            log::info!("Tooling: Executing {} plan step(s)...", plan.steps.len());
+            app_handle
+                .emit("task_progress", format!("Executing {} step(s)...", plan.steps.len()))
+                .map_err(|e| e.to_string())?;
+            let load_macro = move |name: &str| macro_engine::load_macro(name, &app_handle);
+            tooling::Tooling::new()
+                .with_ui_map(plan.ui_map)
+                .execute_plan(&plan.steps, &mut LiveInput, &load_macro)
+                .map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| OrchestratorError::CognitionError(format!("Task join error: {}", e)))?;
 
-        // 4. Return to IDLE
-        self.stop()?;
+        // 3. Log to the trajectory log and return to IDLE
+        let result = match run {
+            Ok(()) => TaskResult { success: true, message: "Task completed successfully!".to_string() },
+            Err(e) => TaskResult { success: false, message: e },
+        };
+        self.stop_executing(result.success, &result.message)?;
 
-        Ok(TaskResult {
-            success: true,
-            message: "Task completed successfully!".to_string(),
-        })
+        Ok(result)
     }
 
     // This function will be called by the event processor task
@@ -243,14 +529,54 @@ impl Orchestrator {
             }
         }
 
+        if event.event_type == rdev::EventType::KeyPress(SECURE_INPUT_TOGGLE_KEY) {
+            self.last_recorded_event_type = Some(event.event_type);
+            self.toggle_secure_input();
+            return;
+        }
+        if event.event_type == rdev::EventType::KeyRelease(SECURE_INPUT_TOGGLE_KEY) {
+            self.last_recorded_event_type = Some(event.event_type);
+            return;
+        }
+
         let now = Instant::now();
         let time_since_previous = self
             .last_event_time
             .map_or(Duration::ZERO, |last_time| now.duration_since(last_time));
 
+        if self.secure_input && self.capture_secret_keystroke(&event, time_since_previous) {
+            self.last_recorded_event_type = Some(event.event_type);
+            self.last_event_time = Some(now);
+            return;
+        }
+
+        // Keep the character the key produced so typing can be replayed as text.
+        let text = match event.event_type {
+            rdev::EventType::KeyPress(_) => event.unicode.as_ref().and_then(|info| info.name.clone()),
+            _ => None,
+        };
+        let window = match event.event_type {
+            rdev::EventType::ButtonPress(_) => self.sample_window(),
+            rdev::EventType::KeyPress(_) if self.starts_key_burst(time_since_previous) => self.sample_window(),
+            _ => None,
+        };
+        let target = match event.event_type {
+            rdev::EventType::MouseMove { x, y } => {
+                self.last_cursor = Some((x, y));
+                None
+            }
+            rdev::EventType::ButtonPress(_) if self.ai_assisted => {
+                self.capture_click_target(window.as_ref().map(|window| window.title.clone()))
+            }
+            _ => None,
+        };
+
         self.recording_buffer.push(TimedEvent {
-            event_type: event.event_type.clone(),
+            event: MacroEvent::Input(event.event_type),
             time_since_previous,
+            text,
+            target,
+            window,
         });
 
         self.last_recorded_event_type = Some(event.event_type.clone());
@@ -260,6 +586,294 @@ impl Orchestrator {
             log::info!("Recorded {} events so far...", self.recording_buffer.len());
         }
     }
+
+    /// Whether a key press is the first of a typing burst: no key was recorded just before it.
+    fn starts_key_burst(&self, time_since_previous: Duration) -> bool {
+        let after_key = matches!(
+            self.recording_buffer.last().map(|last| &last.event),
+            Some(MacroEvent::Input(rdev::EventType::KeyPress(_) | rdev::EventType::KeyRelease(_)))
+        );
+        !after_key || time_since_previous > KEY_BURST_GAP
+    }
+
+    /// Grabs the screen around the cursor for a click in the window titled `window_title`.
+    /// The capture runs on its own thread so event handling is not held up, and text
+    /// recognition runs on the crops when the recording is saved.
+    fn capture_click_target(&mut self, window_title: Option<String>) -> Option<ClickTarget> {
+        let original_xy = self.last_cursor?;
+        let screen = self.screen.clone();
+        let capture = std::thread::spawn(move || {
+            let (width, height) = screen.screen_size()?;
+            let rect = Rect::centered_on(original_xy.0, original_xy.1, CLICK_CROP_WIDTH, CLICK_CROP_HEIGHT)
+                .clamp_to(width, height)
+                .ok_or_else(|| perception::PerceptionError::Capture("Cursor is off screen".to_string()))?;
+            Ok((rect, screen.capture_region(rect)?))
+        });
+
+        let target_crop_path = format!("clicks/{}-{}.png", self.recording_id, self.pending_click_crops.len() + 1);
+        self.pending_click_crops.push((target_crop_path.clone(), capture));
+        Some(ClickTarget {
+            target_description: perception::describe_click_target("", window_title.as_deref(), original_xy),
+            target_crop_path,
+            original_xy,
+            // Clamped to the screen once the capture finishes.
+            crop_rect: Rect::centered_on(original_xy.0, original_xy.1, CLICK_CROP_WIDTH, CLICK_CROP_HEIGHT),
+            window_title,
+            target_text: None,
+        })
+    }
+
+    fn toggle_secure_input(&mut self) {
+        if self.secure_input {
+            self.flush_secret();
+        }
+        self.secure_input = !self.secure_input;
+        log::info!("Secure input {}", if self.secure_input { "enabled" } else { "disabled" });
+        if let Err(e) = self.app_handle.emit("secure_input_changed", self.secure_input) {
+            log::error!("Failed to emit secure input change: {}", e);
+        }
+    }
+
+    /// Feeds a keystroke into the secret buffer while secure input is on. Returns false
+    /// for events that should be recorded normally, flushing the pending secret first.
+    fn capture_secret_keystroke(&mut self, event: &rdev::Event, time_since_previous: Duration) -> bool {
+        let consumed = match event.event_type {
+            rdev::EventType::KeyPress(Key::Backspace) => {
+                self.secret_buffer.pop();
+                self.secret_keys_down.push(Key::Backspace);
+                true
+            }
+            rdev::EventType::KeyPress(key) => {
+                let text = event
+                    .unicode
+                    .as_ref()
+                    .and_then(|info| info.name.as_deref())
+                    .filter(|text| !text.is_empty() && !text.chars().any(char::is_control));
+                if let Some(text) = text {
+                    self.secret_buffer.push_str(text);
+                } else if !matches!(key, Key::ShiftLeft | Key::ShiftRight | Key::AltGr | Key::CapsLock) {
+                    self.flush_secret();
+                    return false;
+                }
+                self.secret_keys_down.push(key);
+                true
+            }
+            rdev::EventType::KeyRelease(key) if self.secret_keys_down.contains(&key) => {
+                self.secret_keys_down.retain(|k| *k != key);
+                true
+            }
+            _ => {
+                self.flush_secret();
+                return false;
+            }
+        };
+
+        if consumed && self.secret_delay.is_none() {
+            self.secret_delay = Some(time_since_previous);
+            self.secret_window = self.sample_window();
+        }
+        consumed
+    }
+
+    /// Replaces the typed secret so far with a `SecretRef` step in the recording.
+    fn flush_secret(&mut self) {
+        let time_since_previous = self.secret_delay.take().unwrap_or(Duration::ZERO);
+        let window = self.secret_window.take();
+        if self.secret_buffer.is_empty() {
+            return;
+        }
+        let id = format!("secret-{}", self.pending_secrets.len() + 1);
+        self.recording_buffer.push(TimedEvent {
+            event: MacroEvent::Secret(SecretRef { id: id.clone() }),
+            time_since_previous,
+            text: None,
+            target: None,
+            window,
+        });
+        self.pending_secrets.push((id, std::mem::take(&mut self.secret_buffer)));
+        log::info!("Captured a secret input segment ({} secret(s) so far)", self.pending_secrets.len());
+    }
+}
+
+/// Screen capture around a click, running on its own thread.
+type ClickCapture = std::thread::JoinHandle<Result<(Rect, RgbaImage), perception::PerceptionError>>;
+
+/// A stopped recording waiting to be saved.
+pub struct FinishedRecording {
+    macro_data: Macro,
+    secrets: Vec<(String, String)>,
+    click_crops: Vec<(String, ClickCapture)>,
+    app_handle: tauri::AppHandle,
+}
+
+impl FinishedRecording {
+    /// Reads the text near each click, stores the secrets and saves the macro. Blocks
+    /// on text recognition, so call it from a blocking task.
+    pub fn save(mut self) -> Result<(), OrchestratorError> {
+        let name = self.macro_data.name.clone();
+        self.finish_click_targets()?;
+
+        for (id, value) in &self.secrets {
+            macro_secrets::store_secret(&name, id, value)
+                .map_err(|e| OrchestratorError::SecretStorageError(e.to_string()))?;
+        }
+
+        let warnings = macro_secrets::detect_sensitive_input(&self.macro_data.events);
+        if !warnings.is_empty() {
+            log::warn!(
+                "Macro '{}' may contain {} password(s) typed without secure input",
+                name,
+                warnings.len()
+            );
+            self.app_handle.emit("sensitive_input_warning", warnings)?;
+        }
+
+        macro_engine::save_macro(&self.macro_data, &self.app_handle)
+            .map_err(|e| OrchestratorError::FileSystemError(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Describes each captured click from the text near it and writes the crops into
+    /// the macro's assets folder.
+    fn finish_click_targets(&mut self) -> Result<(), OrchestratorError> {
+        if self.click_crops.is_empty() {
+            return Ok(());
+        }
+        let assets_dir = macro_engine::macro_assets_dir(&self.macro_data.name, &self.app_handle)
+            .map_err(|e| OrchestratorError::FileSystemError(e.to_string()))?;
+
+        for (crop_path, capture) in std::mem::take(&mut self.click_crops) {
+            let position = self
+                .macro_data
+                .events
+                .iter()
+                .position(|event| event.target.as_ref().is_some_and(|target| target.target_crop_path == crop_path));
+            let (crop_rect, crop) = match capture.join() {
+                Ok(Ok(capture)) => capture,
+                Ok(Err(e)) => {
+                    log::warn!("Could not capture click context: {}", e);
+                    if let Some(index) = position {
+                        self.macro_data.events[index].target = None;
+                    }
+                    continue;
+                }
+                Err(_) => {
+                    log::error!("Click capture thread for {} panicked", crop_path);
+                    if let Some(index) = position {
+                        self.macro_data.events[index].target = None;
+                    }
+                    continue;
+                }
+            };
+
+            let words = perception::read_words(&crop).unwrap_or_else(|e| {
+                log::warn!("Could not read text near click {}: {}", crop_path, e);
+                Vec::new()
+            });
+            if let Some(target) = position.and_then(|index| self.macro_data.events[index].target.as_mut()) {
+                target.crop_rect = crop_rect;
+                let click_in_crop = (
+                    target.original_xy.0 - target.crop_rect.x as f64,
+                    target.original_xy.1 - target.crop_rect.y as f64,
+                );
+                target.target_text = perception::text_near(&words, click_in_crop);
+                target.target_description = perception::describe_click_target(
+                    target.target_text.as_deref().unwrap_or(""),
+                    target.window_title.as_deref(),
+                    target.original_xy,
+                );
+            }
+
+            let file_path = assets_dir.join(&crop_path);
+            if let Some(parent) = file_path.parent() {
+                std::fs::create_dir_all(parent).map_err(|e| OrchestratorError::FileSystemError(e.to_string()))?;
+            }
+            let png = perception::encode_png(&crop).map_err(|e| OrchestratorError::FileSystemError(e.to_string()))?;
+            persistence::write_atomic(&file_path, &png)
+                .map_err(|e| OrchestratorError::FileSystemError(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+/// A plan and the UI map its element ids refer to.
+pub struct TaskPlan {
+    pub steps: Vec<ToolCall>,
+    pub ui_map: UiMap,
+}
+
+/// Plans a task for the window the user was working in. Called on a blocking thread.
+pub trait TaskPlanner: Send + Sync {
+    fn plan(&self, task: &str, window: Option<&WindowContext>) -> Result<TaskPlan, String>;
+}
+
+/// Plans with Cognition from the current UI map and screen.
+pub struct CognitionPlanner {
+    app_handle: tauri::AppHandle,
+}
+
+impl CognitionPlanner {
+    pub fn new(app_handle: tauri::AppHandle) -> Self {
+        Self { app_handle }
+    }
+}
+
+impl TaskPlanner for CognitionPlanner {
+    fn plan(&self, task: &str, window: Option<&WindowContext>) -> Result<TaskPlan, String> {
+        // The focused application may be Nyx itself, so the map is of the task's window.
+        let ui_map = perception::Perception::new()
+            .application_ui_map(window.and_then(|window| window.pid), || capture_without_nyx(&self.app_handle))
+            .map_err(|e| e.to_string())?;
+        let steps = plan_with_cognition(&self.app_handle, task, &ui_map, window)?;
+        Ok(TaskPlan { steps, ui_map })
+    }
+}
+
+/// Asks Cognition for a plan for `window`, with a screenshot taken while Nyx's own window
+/// is hidden. Blocks, so it must run on a blocking thread of the Tokio runtime.
+pub fn plan_with_cognition(
+    app_handle: &tauri::AppHandle,
+    task: &str,
+    ui_map: &UiMap,
+    window: Option<&WindowContext>,
+) -> Result<Vec<ToolCall>, String> {
+    let settings = CognitionSettings::load(app_handle);
+    // A screenshot whose text cannot be read cannot be redacted, so it is not sent.
+    let screen = if settings.image_token_budget == 0 {
+        None
+    } else {
+        capture_without_nyx(app_handle)
+            .and_then(|image| {
+                let words = perception::read_words(&image)?;
+                Ok((image, words))
+            })
+            .map_err(|e| log::warn!("Planning without a screenshot: {}", e))
+            .ok()
+    };
+    let screen = screen.as_ref().map(|(image, words)| (image, words.as_slice()));
+    let redactor = Redactor::load(app_handle);
+    tokio::runtime::Handle::current()
+        .block_on(cognition::generate_plan(task, ui_map, window, screen, &redactor, &Gemini::new(settings)))
+        .map_err(|e| e.to_string())
+}
+
+/// Captures the screen with Nyx's always-on-top window hidden, so the screenshot shows
+/// the window the task is about.
+fn capture_without_nyx(app_handle: &tauri::AppHandle) -> Result<RgbaImage, perception::PerceptionError> {
+    let nyx = app_handle.get_webview_window("main").filter(|window| window.is_visible().unwrap_or(false));
+    if let Some(window) = &nyx {
+        if let Err(e) = window.hide() {
+            log::warn!("Could not hide Nyx for the screenshot: {}", e);
+        }
+        std::thread::sleep(NYX_HIDE_SETTLE);
+    }
+    let image = perception::Perception::new().capture_screen();
+    if let Some(window) = &nyx {
+        if let Err(e) = window.show() {
+            log::warn!("Could not show Nyx again: {}", e);
+        }
+    }
+    image
 }
 
 #[cfg(test)]
@@ -312,23 +926,76 @@ mod tests {
         let task = "test task".to_string();
         assert!(orchestrator.start_executing(task.clone()).is_ok());
         assert_eq!(orchestrator.state, AppState::EXECUTING);
-        assert_eq!(orchestrator.session_context, Some(task));
+        assert_eq!(orchestrator.session_context().map(|context| context.task.clone()), Some(task));
     }
 
     #[test]
     fn test_stop_returns_to_idle() {
         let mut orchestrator = create_orchestrator_for_test();
         orchestrator.state = AppState::EXECUTING;
-        orchestrator.session_context = Some("a task".to_string());
+        orchestrator.session_context = Some(SessionContext { task: "a task".to_string(), window: None });
         
         assert!(orchestrator.stop().is_ok());
         assert_eq!(orchestrator.state, AppState::IDLE);
         assert!(orchestrator.session_context.is_none());
     }
 
+    #[test]
+    fn test_push_to_talk() {
+        let mut orchestrator = create_orchestrator_for_test();
+        orchestrator.voice_settings.push_to_talk_key = Some("F9".to_string());
+        assert!(orchestrator.handle_push_to_talk(&rdev::EventType::KeyPress(Key::F10)).is_none());
+
+        let (stop, held) = orchestrator.handle_push_to_talk(&rdev::EventType::KeyPress(Key::F9)).unwrap();
+        assert_eq!(orchestrator.state, AppState::LISTENING);
+        // Key repeats while held start nothing new.
+        assert!(orchestrator.handle_push_to_talk(&rdev::EventType::KeyPress(Key::F9)).is_none());
+
+        // Releasing ends the utterance; the listener returns to IDLE once it is transcribed.
+        orchestrator.handle_push_to_talk(&rdev::EventType::KeyRelease(Key::F9));
+        assert!(!held.load(Ordering::SeqCst));
+        assert!(!stop.load(Ordering::SeqCst));
+        orchestrator.stop().unwrap();
+        assert!(stop.load(Ordering::SeqCst));
+    }
+
+    /// Returns an empty plan and records the windows it was asked to plan for.
+    #[derive(Default)]
+    struct RecordingPlanner {
+        windows: Mutex<Vec<Option<WindowContext>>>,
+    }
+
+    impl TaskPlanner for RecordingPlanner {
+        fn plan(&self, _task: &str, window: Option<&WindowContext>) -> Result<TaskPlan, String> {
+            self.windows.lock().unwrap().push(window.cloned());
+            Ok(TaskPlan { steps: Vec::new(), ui_map: UiMap::default() })
+        }
+    }
+
+    #[test]
+    fn test_stale_voice_listener_is_ignored() {
+        let mut orchestrator = create_orchestrator_for_test();
+        let stale = orchestrator.start_voice_input().unwrap();
+        orchestrator.stop().unwrap();
+        let current = orchestrator.start_voice_input().unwrap();
+        let app_handle = orchestrator.app_handle.clone();
+        let orchestrator_state = Arc::new(tokio::sync::Mutex::new(orchestrator));
+
+        // The old listener neither runs its command nor stops the new session.
+        run_voice_command(&orchestrator_state, &app_handle, &stale, Some("an old task".to_string()));
+        run_voice_command(&orchestrator_state, &app_handle, &stale, None);
+        assert_eq!(orchestrator_state.blocking_lock().state, AppState::LISTENING);
+        assert!(!current.load(Ordering::SeqCst));
+
+        run_voice_command(&orchestrator_state, &app_handle, &current, None);
+        assert_eq!(orchestrator_state.blocking_lock().state, AppState::IDLE);
+        assert!(current.load(Ordering::SeqCst));
+    }
+
     #[tokio::test]
     async fn test_cognitive_loop_flow() {
         let mut orchestrator = create_orchestrator_for_test();
+        orchestrator.planner = Arc::new(RecordingPlanner::default());
         let task = "a trivial plan".to_string();
         
         let result = orchestrator.execute_task(task).await;
@@ -338,6 +1005,25 @@ mod tests {
         assert!(task_result.success);
         assert_eq!(orchestrator.state, AppState::IDLE);
     }
+
+    #[tokio::test]
+    async fn test_task_is_planned_for_window_focused_before_nyx() {
+        let mut orchestrator = create_orchestrator_for_test();
+        let planner = Arc::new(RecordingPlanner::default());
+        orchestrator.planner = planner.clone();
+        orchestrator.focus = FocusTracker::default();
+        let editor = WindowContext { title: "report.odt".to_string(), class: "libreoffice".to_string(), pid: Some(1) };
+        orchestrator.focus.observe(Some(editor.clone()));
+        orchestrator.focus.observe(Some(WindowContext {
+            title: "Nyx".to_string(),
+            class: "nyx".to_string(),
+            pid: Some(std::process::id()),
+        }));
+
+        orchestrator.execute_task("summarize this document".to_string()).await.unwrap();
+
+        assert_eq!(*planner.windows.lock().unwrap(), vec![Some(editor)]);
+    }
 }
 
 pub async fn event_processor_task(
@@ -346,7 +1032,253 @@ pub async fn event_processor_task(
 ) {
     for event in receiver {
         let mut orchestrator = orchestrator_state.lock().await;
+        if let Some((stop, held)) = orchestrator.handle_push_to_talk(&event.event_type) {
+            let app_handle = orchestrator.app_handle.clone();
+            match orchestrator.voice_settings.whisper_model_path(&app_handle) {
+                Ok(model) => {
+                    let orchestrator_state = orchestrator_state.clone();
+                    std::thread::spawn(move || {
+                        voice_listener(orchestrator_state, app_handle, VoiceInput::Microphone, model, stop, Some(held))
+                    });
+                }
+                Err(e) => {
+                    report_voice_error(&app_handle, &e);
+                    if let Err(e) = orchestrator.stop() {
+                        log::error!("Failed to stop listening: {}", e);
+                    }
+                }
+            }
+        }
         orchestrator.handle_event(event);
     }
 }
 
+fn report_voice_error(app_handle: &tauri::AppHandle, error: &VoiceError) {
+    log::error!("Voice input failed: {}", error);
+    if let Err(e) = app_handle.emit("voice_error", error.to_string()) {
+        log::error!("Failed to emit voice error: {}", e);
+    }
+}
+
+/// Listens on `source` until a command is spoken, emitting `transcript_partial` events
+/// while it is. Returns `None` if listening stopped or timed out first.
+fn listen_for_command(
+    app_handle: &tauri::AppHandle,
+    source: &mut dyn AudioSource,
+    transcriber: &mut dyn Transcriber,
+    stop: &AtomicBool,
+) -> Result<Option<String>, VoiceError> {
+    let mut command = None;
+    // Ends listening without setting `stop`, which tells the session apart from one stopped
+    // by the user.
+    let finished = Cell::new(false);
+    let should_stop = || finished.get() || stop.load(Ordering::SeqCst);
+    voice::listen(source, transcriber, &should_stop, &ListenTimeouts::default(), &mut |event| match event {
+        TranscriptEvent::Partial(text) => {
+            if let Err(e) = app_handle.emit("transcript_partial", &text) {
+                log::error!("Failed to emit partial transcript: {}", e);
+            }
+        }
+        TranscriptEvent::Final(text) => {
+            finished.set(true);
+            command = Some(text);
+        }
+    })?;
+    Ok(command)
+}
+
+/// Executes a spoken command and says how it went, or goes back to IDLE if none was heard.
+/// Does nothing unless `stop` is the flag of the voice session still running.
+fn run_voice_command(
+    orchestrator_state: &Arc<Mutex<Orchestrator>>,
+    app_handle: &tauri::AppHandle,
+    stop: &Arc<AtomicBool>,
+    command: Option<String>,
+) {
+    let response = tauri::async_runtime::block_on(async {
+        let mut orchestrator = orchestrator_state.lock().await;
+        // Listening may have been stopped, or a new session started, while the command
+        // was spoken.
+        if orchestrator.state != AppState::LISTENING || !orchestrator.is_current_voice_session(stop) {
+            return None;
+        }
+        let Some(task) = command else {
+            if let Err(e) = orchestrator.stop() {
+                log::error!("Failed to stop listening: {}", e);
+            }
+            return None;
+        };
+        log::info!("Voice command: '{}'", task);
+        if let Err(e) = app_handle.emit("transcript_final", &task) {
+            log::error!("Failed to emit transcript: {}", e);
+        }
+        let message = match orchestrator.execute_task(task).await {
+            Ok(result) => result.message,
+            Err(e) => {
+                log::error!("Voice command failed: {}", e);
+                format!("The task failed. {}", e)
+            }
+        };
+        Some((orchestrator.voice_settings.speech.clone(), message))
+    });
+    // Spoken once the orchestrator is released, so the agent is not blocked meanwhile.
+    if let Some((speech, message)) = response {
+        if let Err(e) = speech.speak(&message) {
+            log::error!("Could not speak the result: {}", e);
+        }
+    }
+}
+
+/// Listens for one spoken command and executes it. With `hold`, listening ends when it is
+/// cleared, as push-to-talk does. Runs on its own thread, since the microphone stream
+/// cannot move between threads.
+pub fn voice_listener(
+    orchestrator_state: Arc<Mutex<Orchestrator>>,
+    app_handle: tauri::AppHandle,
+    input: VoiceInput,
+    model: PathBuf,
+    stop: Arc<AtomicBool>,
+    hold: Option<Arc<AtomicBool>>,
+) {
+    let run = || -> Result<Option<String>, VoiceError> {
+        let mut source = voice::open_source(&input)?;
+        if let Some(held) = hold {
+            source = Box::new(HoldSource::new(source, held));
+        }
+        let mut transcriber = WhisperTranscriber::new(&model)?;
+        listen_for_command(&app_handle, source.as_mut(), &mut transcriber, &stop)
+    };
+    let command = run().unwrap_or_else(|e| {
+        report_voice_error(&app_handle, &e);
+        None
+    });
+    run_voice_command(&orchestrator_state, &app_handle, &stop, command);
+}
+
+/// Spots the wake word on the microphone and, when the agent is idle, listens for one
+/// command and executes it, emitting `wake_word_detected` first. Runs until `stop` is set.
+pub fn wake_word_listener(
+    orchestrator_state: Arc<Mutex<Orchestrator>>,
+    app_handle: tauri::AppHandle,
+    model: WakeWordModel,
+    whisper_model: PathBuf,
+    stop: Arc<AtomicBool>,
+) {
+    let run = || -> Result<(), VoiceError> {
+        let mut source = voice::open_source(&VoiceInput::Microphone)?;
+        let mut transcriber = WhisperTranscriber::new(&whisper_model)?;
+        let mut detector = WakeWordDetector::new(model);
+        log::info!("Listening for the wake word '{}'", detector.model().name);
+        while !stop.load(Ordering::SeqCst) {
+            let Some(samples) = source.next_samples()? else {
+                break;
+            };
+            if !detector.push(&samples) {
+                continue;
+            }
+            // Only an idle agent starts listening.
+            let listen_stop = tauri::async_runtime::block_on(async {
+                orchestrator_state.lock().await.start_voice_input().ok()
+            });
+            let Some(listen_stop) = listen_stop else {
+                continue;
+            };
+            log::info!("Heard the wake word '{}'", detector.model().name);
+            if let Err(e) = app_handle.emit("wake_word_detected", &detector.model().name) {
+                log::error!("Failed to emit wake word detection: {}", e);
+            }
+            // A failed transcription ends this command, not the wake word listener.
+            let command = listen_for_command(&app_handle, source.as_mut(), &mut transcriber, &listen_stop)
+                .unwrap_or_else(|e| {
+                    report_voice_error(&app_handle, &e);
+                    None
+                });
+            run_voice_command(&orchestrator_state, &app_handle, &listen_stop, command);
+            // Skip what the microphone heard while the command ran.
+            source.skip_pending();
+        }
+        Ok(())
+    };
+    if let Err(e) = run() {
+        report_voice_error(&app_handle, &e);
+    }
+}
+
+/// Polls the scheduler once a second and runs whatever job is due.
+pub async fn scheduler_task(orchestrator_state: Arc<Mutex<Orchestrator>>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let due_job = {
+            let mut orchestrator = orchestrator_state.lock().await;
+            let state = orchestrator.state.clone();
+            orchestrator.scheduler.poll(&state)
+        };
+        if let Some(job) = due_job {
+            run_scheduled_job(&orchestrator_state, job).await;
+        }
+    }
+}
+
+async fn run_scheduled_job(orchestrator_state: &Arc<Mutex<Orchestrator>>, job: ScheduledJob) {
+    let started_at = orchestrator_state.lock().await.scheduler.now();
+    log::info!("Running scheduled job {} '{}'", job.id, job.name);
+
+    let outcome = match &job.action {
+        JobAction::Task { description } => {
+            let mut orchestrator = orchestrator_state.lock().await;
+            match orchestrator.execute_task(description.clone()).await {
+                Ok(result) if result.success => RunOutcome::Success,
+                Ok(result) => RunOutcome::Failed { message: result.message },
+                Err(e) => RunOutcome::Failed { message: e.to_string() },
+            }
+        }
+        JobAction::Macro { name } => run_scheduled_macro(orchestrator_state, name).await,
+    };
+
+    let mut orchestrator = orchestrator_state.lock().await;
+    let record = orchestrator.scheduler.record_run(&job, started_at, outcome);
+    if let Err(e) = orchestrator.app_handle.emit("scheduled_job_finished", record) {
+        log::error!("Failed to emit scheduled job result: {}", e);
+    }
+}
+
+async fn run_scheduled_macro(orchestrator_state: &Arc<Mutex<Orchestrator>>, name: &str) -> RunOutcome {
+    let app_handle = {
+        let mut orchestrator = orchestrator_state.lock().await;
+        if let Err(e) = orchestrator.start_executing(format!("Playing macro: {}", name)) {
+            return RunOutcome::Failed { message: e.to_string() };
+        }
+        orchestrator.app_handle.clone()
+    };
+
+    let macro_name = name.to_string();
+    let play_result = tokio::task::spawn_blocking(move || {
+        let macro_data = macro_engine::load_macro(&macro_name, &app_handle)?;
+        let result = self_correction::play_macro_self_correcting(
+            &macro_data,
+            &app_handle,
+            self_correction::DEFAULT_CONFIDENCE_THRESHOLD,
+            false,
+        );
+        if result.is_ok() {
+            macro_index::record_play(&macro_name, &app_handle);
+        }
+        result
+    })
+    .await;
+
+    let outcome = match play_result {
+        Ok(Ok(_)) => RunOutcome::Success,
+        Ok(Err(e)) => RunOutcome::Failed { message: e.to_string() },
+        Err(e) => RunOutcome::Failed { message: format!("Task join error: {}", e) },
+    };
+    let (success, message) = match &outcome {
+        RunOutcome::Failed { message } => (false, message.clone()),
+        _ => (true, format!("Played macro: {}", name)),
+    };
+    if let Err(e) = orchestrator_state.lock().await.stop_executing(success, &message) {
+        log::error!("Failed to return to IDLE after scheduled macro: {}", e);
+    }
+    outcome
+}
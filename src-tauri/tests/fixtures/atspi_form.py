@@ -0,0 +1,27 @@
+#!/usr/bin/env python3
+# A small GTK window for the accessibility tests: a text field, a checkbox and a button
+# that copies the field into a status label.
+import gi
+
+gi.require_version("Gtk", "3.0")
+from gi.repository import GLib, Gtk
+
+GLib.set_prgname("atspi_form")
+
+window = Gtk.Window(title="Invoices - Nyx")
+grid = Gtk.Grid(column_spacing=8, row_spacing=8, margin=16)
+window.add(grid)
+
+grid.attach(Gtk.Label(label="Customer:"), 0, 0, 1, 1)
+entry = Gtk.Entry()
+grid.attach(entry, 1, 0, 1, 1)
+grid.attach(Gtk.CheckButton(label="Save a copy"), 0, 1, 2, 1)
+status = Gtk.Label(label="Not saved")
+grid.attach(status, 0, 2, 2, 1)
+button = Gtk.Button(label="Save as")
+button.connect("clicked", lambda _: status.set_text("Saved: " + entry.get_text()))
+grid.attach(button, 1, 3, 1, 1)
+
+window.connect("destroy", Gtk.main_quit)
+window.show_all()
+Gtk.main()
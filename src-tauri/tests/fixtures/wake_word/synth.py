@@ -0,0 +1,131 @@
+#!/usr/bin/env python3
+"""Generates the wake word test clips with a small formant synthesizer.
+
+The clips are synthetic so they can be regenerated anywhere: "hey nyx" spoken at
+different pitches and speeds for enrollment and detection, and other phrases that
+must not trigger it. Run from this folder: `python3 synth.py`.
+"""
+
+import math
+import random
+import struct
+import wave
+
+RATE = 16000
+
+# Phoneme targets: (kind, duration in ms, F1, F2, F3, amplitude). "voiced" sounds use a
+# glottal pulse train, "noise" sounds white noise, "silence" is a stop closure.
+PHONEMES = {
+    "h": ("noise", 70, 530, 1840, 2480, 0.25),
+    "ey": ("voiced", 210, 530, 1840, 2480, 1.0),
+    "ey_end": ("voiced", 0, 400, 2100, 2700, 1.0),
+    "n": ("voiced", 70, 250, 1700, 2600, 0.35),
+    "ih": ("voiced", 120, 390, 1990, 2550, 0.9),
+    "k": ("silence", 50, 0, 0, 0, 0.0),
+    "s": ("noise", 130, 4500, 6000, 7000, 0.35),
+    "ow": ("voiced", 180, 500, 900, 2400, 1.0),
+    "t": ("silence", 40, 0, 0, 0, 0.0),
+    "aa": ("voiced", 180, 730, 1090, 2440, 1.0),
+    "p": ("silence", 60, 0, 0, 0, 0.0),
+    "l": ("voiced", 70, 360, 1300, 2700, 0.6),
+    "m": ("voiced", 80, 280, 1000, 2200, 0.35),
+    "y": ("voiced", 60, 280, 2250, 2900, 0.7),
+    "uw": ("voiced", 170, 300, 870, 2240, 1.0),
+    "z": ("noise", 90, 4000, 5500, 6800, 0.25),
+}
+
+PHRASES = {
+    "hey nyx": ["h", "ey", "ey_end", "n", "ih", "k", "s"],
+    "okay stop": ["ow", "k", "ey", "ey_end", "s", "t", "aa", "p"],
+    "play music": ["p", "l", "ey", "ey_end", "m", "y", "uw", "z", "ih", "k"],
+}
+
+
+def resonate(signal, formants, bandwidth):
+    """Runs `signal` through a two-pole resonator whose frequency follows `formants`."""
+    out, y1, y2 = [], 0.0, 0.0
+    for x, freq in zip(signal, formants):
+        c = -math.exp(-2 * math.pi * bandwidth / RATE)
+        b = 2 * math.exp(-math.pi * bandwidth / RATE) * math.cos(2 * math.pi * freq / RATE)
+        y = (1 - b - c) * x + b * y1 + c * y2
+        out.append(y)
+        y1, y2 = y, y1
+    return out
+
+
+def speak(phrase, pitch, speed, rng):
+    """Synthesizes a phrase at a base pitch in Hz, `speed` times faster than normal."""
+    segments = [PHONEMES[name] for name in PHRASES[phrase]]
+    kinds, tracks, amplitudes = [], [[], [], []], []
+    for index, (kind, millis, f1, f2, f3, amplitude) in enumerate(segments):
+        count = int(millis / speed * RATE / 1000)
+        following = segments[index + 1] if index + 1 < len(segments) else segments[index]
+        # Glide halfway into the next sound so vowels turn into diphthongs.
+        glide = following[0] == "voiced" and kind == "voiced"
+        for i in range(count):
+            t = i / max(count, 1) if glide else 0.0
+            kinds.append(kind)
+            for track, start, end in zip(tracks, (f1, f2, f3), following[2:5]):
+                track.append(start + (end - start) * t * 0.5 if end else start)
+            amplitudes.append(amplitude)
+
+    source, phase = [], 0.0
+    for i, kind in enumerate(kinds):
+        # Pitch falls a little over the phrase, as in a statement.
+        f0 = pitch * (1.1 - 0.2 * i / len(kinds)) * (1 + rng.uniform(-0.01, 0.01))
+        phase += f0 / RATE
+        if kind == "voiced":
+            pulse = 1.0 if phase >= 1.0 else 0.0
+            source.append(pulse * 8.0 + rng.gauss(0, 0.02))
+        elif kind == "noise":
+            source.append(rng.gauss(0, 1.0))
+        else:
+            source.append(0.0)
+        phase %= 1.0
+
+    voice = [0.0] * len(source)
+    for track, bandwidth, gain in zip(tracks, (90, 110, 170), (1.0, 0.6, 0.3)):
+        for i, value in enumerate(resonate(source, track, bandwidth)):
+            voice[i] += value * gain
+    peak = max(abs(v) for v in voice) or 1.0
+    return [v / peak * 0.5 * a for v, a in zip(voice, amplitudes)]
+
+
+def silence(seconds):
+    return [0.0] * int(seconds * RATE)
+
+
+def write(name, samples, noise, rng):
+    with wave.open(name, "wb") as wav:
+        wav.setnchannels(1)
+        wav.setsampwidth(2)
+        wav.setframerate(RATE)
+        frames = b"".join(
+            struct.pack("<h", max(-32768, min(32767, int((s + rng.gauss(0, noise)) * 32767)))) for s in samples
+        )
+        wav.writeframes(frames)
+
+
+def main():
+    rng = random.Random(7)
+    # Enrollment: three speakers at different pitches and speeds, in a quiet room.
+    for index, (pitch, speed) in enumerate([(110, 0.95), (150, 1.0), (200, 1.1)], start=1):
+        clip = silence(0.15) + speak("hey nyx", pitch, speed, rng) + silence(0.15)
+        write(f"enroll_{index}.wav", clip, 0.002, rng)
+    # A fourth voice says the wake word and then a command, over background noise.
+    clip = (
+        silence(0.6)
+        + speak("hey nyx", 130, 1.05, rng)
+        + silence(0.4)
+        + speak("play music", 130, 1.0, rng)
+        + silence(0.8)
+    )
+    write("wake_then_command.wav", clip, 0.01, rng)
+    # Phrases that share sounds with the wake word but must not trigger it.
+    clip = silence(0.5) + speak("okay stop", 140, 1.0, rng) + silence(0.5) + speak("play music", 170, 1.0, rng)
+    write("other_phrases.wav", clip + silence(0.5), 0.01, rng)
+    write("background_noise.wav", silence(2.0), 0.03, rng)
+
+
+if __name__ == "__main__":
+    main()